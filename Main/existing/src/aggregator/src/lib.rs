@@ -0,0 +1,234 @@
+// aggregator/src/lib.rs - collects MetricsSummary pushes from miners and
+// serves fleet-wide totals, so the fleet isn't polled miner by miner
+use candid::{CandidType, Deserialize, Principal};
+use canister_auth::audit::AuditEntry;
+use canister_state::{Migration, StateError};
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk::api::time;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Miners that haven't reported for this long don't count towards the
+/// fleet hashrate
+const STALE_AFTER_NS: u64 = 10 * 60 * 1_000_000_000;
+
+// ------------------------------------------------------------
+// Types (mirror the miner's MetricsSummary)
+// ------------------------------------------------------------
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MetricsSummary {
+    pub total_chunks: u64,
+    pub total_hashes: u64,
+    pub solutions_found: u64,
+    pub cache_hit_rate: f64,
+    pub early_termination_rate: f64,
+    pub avg_time_per_chunk_ms: u64,
+    pub avg_hashes_per_chunk: u64,
+    pub avg_instructions_per_hash: u64,
+    pub hashes_per_second: u64,
+    pub recent_hashes_per_second: u64,
+    pub hashrate_window_secs: u64,
+    pub p50_chunk_time_ms: u64,
+    pub p95_chunk_time_ms: u64,
+    pub p99_chunk_time_ms: u64,
+    pub p50_chunk_instructions: u64,
+    pub p95_chunk_instructions: u64,
+    pub p99_chunk_instructions: u64,
+    pub total_cycles: u128,
+    pub avg_cycles_per_chunk: u128,
+    pub cycles_per_hash: u128,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerReport {
+    pub miner: Principal,
+    pub received_at: u64,
+    pub summary: MetricsSummary,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct FleetMetrics {
+    pub miners: u64,
+    /// Reported within the last 10 minutes
+    pub active_miners: u64,
+    pub total_chunks: u64,
+    pub total_hashes: u64,
+    pub solutions_found: u64,
+    /// Sum over active miners
+    pub hashes_per_second: u64,
+    pub total_cycles: u128,
+    pub cycles_per_hash: u128,
+    /// Worst p95 chunk time across active miners
+    pub max_p95_chunk_time_ms: u64,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+struct State {
+    owner: Option<Principal>,
+    /// Only these may push reports
+    reporters: BTreeSet<Principal>,
+    reports: BTreeMap<Principal, MinerReport>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn require_owner() {
+    if STATE.with(|s| s.borrow().owner) != Some(caller()) {
+        ic_cdk::trap("caller is not the owner");
+    }
+}
+
+// ------------------------------------------------------------
+// Init / upgrades
+// ------------------------------------------------------------
+
+/// The installer becomes the owner; `reporters` are the miners allowed to push
+#[init]
+fn init(reporters: Vec<Principal>) {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.owner = Some(caller());
+        st.reporters = reporters.into_iter().collect();
+    });
+}
+
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (State, Option<Vec<AuditEntry>>);
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let saved: Saved = (STATE.with(|s| s.borrow().clone()), Some(canister_auth::audit::snapshot()));
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved) {
+        ic_cdk::trap(&format!("failed to save aggregator state: {}", e));
+    }
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => Ok(saved),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<Saved>(),
+        Err(e) => Err(e.to_string()),
+    };
+    match restored {
+        Ok((state, audit)) => {
+            STATE.with(|s| *s.borrow_mut() = state);
+            canister_auth::audit::restore(audit.unwrap_or_default());
+        }
+        Err(e) => ic_cdk::trap(&format!("failed to restore aggregator state: {}", e)),
+    }
+}
+
+// ------------------------------------------------------------
+// Reporters (owner only)
+// ------------------------------------------------------------
+
+/// Owner actions, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+#[update]
+pub fn add_reporter(miner: Principal) {
+    require_owner();
+    canister_auth::audit::record("add_reporter", format!("{:?}", miner));
+    STATE.with(|s| s.borrow_mut().reporters.insert(miner));
+}
+
+/// Also drops the miner's last report
+#[update]
+pub fn remove_reporter(miner: Principal) -> bool {
+    require_owner();
+    canister_auth::audit::record("remove_reporter", format!("{:?}", miner));
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.reports.remove(&miner);
+        st.reporters.remove(&miner)
+    })
+}
+
+#[query]
+pub fn get_reporters() -> Vec<Principal> {
+    STATE.with(|s| s.borrow().reporters.iter().copied().collect())
+}
+
+// ------------------------------------------------------------
+// Push API (registered miners)
+// ------------------------------------------------------------
+
+/// Replace the calling miner's summary with a fresh one
+#[update]
+pub fn report_metrics(summary: MetricsSummary) {
+    let miner = caller();
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        if !st.reporters.contains(&miner) {
+            ic_cdk::trap("caller is not a registered reporter");
+        }
+        st.reports.insert(miner, MinerReport { miner, received_at: time(), summary });
+    });
+}
+
+// ------------------------------------------------------------
+// Read API
+// ------------------------------------------------------------
+
+#[query]
+pub fn get_fleet_metrics() -> FleetMetrics {
+    let now = time();
+
+    STATE.with(|s| {
+        let st = s.borrow();
+        let reports = st.reports.values();
+
+        let mut fleet = FleetMetrics {
+            miners: st.reports.len() as u64,
+            active_miners: 0,
+            total_chunks: 0,
+            total_hashes: 0,
+            solutions_found: 0,
+            hashes_per_second: 0,
+            total_cycles: 0,
+            cycles_per_hash: 0,
+            max_p95_chunk_time_ms: 0,
+        };
+
+        for r in reports {
+            let m = &r.summary;
+            fleet.total_chunks += m.total_chunks;
+            fleet.total_hashes += m.total_hashes;
+            fleet.solutions_found += m.solutions_found;
+            fleet.total_cycles += m.total_cycles;
+
+            if now.saturating_sub(r.received_at) <= STALE_AFTER_NS {
+                fleet.active_miners += 1;
+                fleet.hashes_per_second += m.recent_hashes_per_second;
+                fleet.max_p95_chunk_time_ms = fleet.max_p95_chunk_time_ms.max(m.p95_chunk_time_ms);
+            }
+        }
+        fleet.cycles_per_hash = fleet
+        .total_cycles
+        .checked_div(fleet.total_hashes as u128)
+        .unwrap_or(0);
+        fleet
+    })
+}
+
+#[query]
+pub fn get_miner_reports() -> Vec<MinerReport> {
+    STATE.with(|s| s.borrow().reports.values().cloned().collect())
+}
@@ -0,0 +1,150 @@
+// archive/src/lib.rs - cold storage for old chain_controller headers
+use candid::{CandidType, Deserialize, Principal};
+use canister_state::{Migration, StateError};
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+const MAX_BLOCKS_PER_QUERY: u64 = 1_000;
+
+// ------------------------------------------------------------
+// Types (mirror chain_controller's BlockHeader)
+// ------------------------------------------------------------
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub block_hash: String,
+    pub prev_hash: String,
+    pub difficulty: u32,
+    pub timestamp_ns: u64,
+    pub cumulative_work: u128,
+    pub miner: Option<Principal>,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+struct State {
+    /// The chain_controller allowed to append
+    writer: Option<Principal>,
+    /// Height of blocks[0]
+    first_height: u64,
+    blocks: Vec<BlockHeader>,
+    by_hash: HashMap<String, u64>,
+}
+
+impl State {
+    fn get(&self, height: u64) -> Option<&BlockHeader> {
+        let idx = height.checked_sub(self.first_height)?;
+        self.blocks.get(idx as usize)
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+// ------------------------------------------------------------
+// Init / upgrades
+// ------------------------------------------------------------
+
+#[init]
+fn init(writer: Principal) {
+    STATE.with(|s| s.borrow_mut().writer = Some(writer));
+}
+
+/// Bump when `State` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = STATE.with(|s| s.borrow().clone());
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &state) {
+        ic_cdk::trap(&format!("failed to save archive: {}", e));
+    }
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let restored = match canister_state::load::<State>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(state) => Ok(state),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<(State,)>().map(|(state,)| state),
+        Err(e) => Err(e.to_string()),
+    };
+    match restored {
+        Ok(state) => STATE.with(|s| *s.borrow_mut() = state),
+        Err(e) => ic_cdk::trap(&format!("failed to restore archive: {}", e)),
+    }
+}
+
+// ------------------------------------------------------------
+// Write API (chain_controller only)
+// ------------------------------------------------------------
+
+/// Append a contiguous run of headers that continues where the archive ends
+#[update]
+pub fn append_blocks(blocks: Vec<BlockHeader>) {
+    let caller = caller();
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+
+        if st.writer != Some(caller) {
+            ic_cdk::trap("only the chain controller can append");
+        }
+
+        for b in blocks {
+            let expected = st.first_height + st.blocks.len() as u64;
+            if st.blocks.is_empty() {
+                st.first_height = b.height;
+            } else if b.height != expected {
+                ic_cdk::trap(&format!("expected height {}, got {}", expected, b.height));
+            }
+
+            st.by_hash.insert(b.block_hash.clone(), b.height);
+            st.blocks.push(b);
+        }
+    });
+}
+
+// ------------------------------------------------------------
+// Read API
+// ------------------------------------------------------------
+
+#[query]
+pub fn get_block(height: u64) -> Option<BlockHeader> {
+    STATE.with(|s| s.borrow().get(height).cloned())
+}
+
+/// Headers for heights `from..=to`, at most 1000 per call
+#[query]
+pub fn get_blocks(from: u64, to: u64) -> Vec<BlockHeader> {
+    if to < from {
+        return Vec::new();
+    }
+    let to = to.min(from.saturating_add(MAX_BLOCKS_PER_QUERY - 1));
+
+    STATE.with(|s| {
+        let st = s.borrow();
+        (from..=to).filter_map(|h| st.get(h).cloned()).collect()
+    })
+}
+
+#[query]
+pub fn get_block_by_hash(block_hash: String) -> Option<BlockHeader> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        let height = *st.by_hash.get(&block_hash)?;
+        st.get(height).cloned()
+    })
+}
+
+/// (first_height, block_count)
+#[query]
+pub fn get_range() -> (u64, u64) {
+    STATE.with(|s| {
+        let st = s.borrow();
+        (st.first_height, st.blocks.len() as u64)
+    })
+}
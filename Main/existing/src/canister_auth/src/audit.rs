@@ -0,0 +1,87 @@
+// audit.rs - log of the latest privileged calls, for post-incident review
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+use ic_cdk::caller;
+use sha2::{Digest, Sha256};
+
+/// Entries per get_audit_log reply
+const MAX_PAGE: u64 = 500;
+/// Oldest entries are dropped past this
+const MAX_ENTRIES: usize = 10_000;
+/// Longer argument renderings are cut off here
+const MAX_ARGS_LEN: usize = 1_024;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    /// Endpoint name
+    pub action: String,
+    /// Debug rendering of the call's arguments
+    pub args: String,
+}
+
+thread_local! {
+    static LOG: RefCell<VecDeque<AuditEntry>> = const { RefCell::new(VecDeque::new()) };
+    static NEXT_SEQ: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Log the current call; do this once its guard has passed. Render large
+/// payloads with `digest` rather than in full.
+pub fn record(action: &str, mut args: String) {
+    if args.len() > MAX_ARGS_LEN {
+        let mut end = MAX_ARGS_LEN;
+        while !args.is_char_boundary(end) {
+            end -= 1;
+        }
+        args.truncate(end);
+        args.push('…');
+    }
+
+    let seq = NEXT_SEQ.with(|n| n.replace(n.get() + 1));
+    LOG.with(|l| {
+        let mut l = l.borrow_mut();
+        if l.len() >= MAX_ENTRIES {
+            l.pop_front();
+        }
+        l.push_back(AuditEntry { seq, caller: caller(), timestamp: time(), action: action.to_string(), args });
+    });
+}
+
+/// Length and SHA-256 of a payload, for the log
+pub fn digest(data: &[u8]) -> String {
+    format!("{} bytes, sha256 {}", data.len(), hex::encode(Sha256::digest(data)))
+}
+
+/// Oldest first from sequence number `offset`, at most MAX_PAGE entries.
+/// Only the latest MAX_ENTRIES are kept, so a page can start later.
+pub fn page(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    LOG.with(|l| {
+        let l = l.borrow();
+        let first = l.front().map_or(0, |e| e.seq);
+        l.iter()
+        .skip(offset.saturating_sub(first) as usize)
+        .take(limit.min(MAX_PAGE) as usize)
+        .cloned()
+        .collect()
+    })
+}
+
+/// Entries ever recorded, i.e. the next sequence number
+pub fn len() -> u64 {
+    NEXT_SEQ.with(|n| n.get())
+}
+
+pub fn snapshot() -> Vec<AuditEntry> {
+    LOG.with(|l| l.borrow().iter().cloned().collect())
+}
+
+pub fn restore(mut entries: Vec<AuditEntry>) {
+    entries.drain(..entries.len().saturating_sub(MAX_ENTRIES));
+    NEXT_SEQ.with(|n| n.set(entries.last().map_or(0, |e| e.seq + 1)));
+    LOG.with(|l| *l.borrow_mut() = entries.into());
+}
@@ -0,0 +1,100 @@
+// canister_auth - the owner/admin model shared by every canister
+//
+// Each canister links its own copy of this state. The owner is fixed at
+// install time (or handed over with `transfer_ownership`), is always an
+// admin and can't be removed; admins may call every mutating endpoint.
+pub mod audit;
+pub mod rate_limit;
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use candid::Principal;
+use ic_cdk::caller;
+
+thread_local! {
+    static OWNER: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static ADMINS: RefCell<BTreeSet<Principal>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// ------------------------------------------------------------
+// Setup
+// ------------------------------------------------------------
+
+/// Install the owner plus any extra admins
+pub fn init(owner: Principal, admins: Vec<Principal>) {
+    OWNER.with(|o| *o.borrow_mut() = Some(owner));
+    ADMINS.with(|a| {
+        let mut a = a.borrow_mut();
+        a.clear();
+        a.insert(owner);
+        a.extend(admins);
+    });
+}
+
+/// (owner, admins) for stable storage
+pub fn snapshot() -> (Option<Principal>, Vec<Principal>) {
+    (owner(), list_admins())
+}
+
+pub fn restore(owner: Option<Principal>, admins: Vec<Principal>) {
+    OWNER.with(|o| *o.borrow_mut() = owner);
+    ADMINS.with(|a| {
+        let mut a = a.borrow_mut();
+        *a = admins.into_iter().collect();
+        a.extend(owner);
+    });
+}
+
+// ------------------------------------------------------------
+// Guards
+// ------------------------------------------------------------
+
+pub fn owner() -> Option<Principal> {
+    OWNER.with(|o| *o.borrow())
+}
+
+pub fn is_admin(p: &Principal) -> bool {
+    ADMINS.with(|a| a.borrow().contains(p))
+}
+
+/// Trap unless the caller is an admin
+pub fn require_admin() {
+    if !is_admin(&caller()) {
+        ic_cdk::trap("caller is not an admin");
+    }
+}
+
+/// Trap unless the caller is the owner
+pub fn require_owner() {
+    if owner() != Some(caller()) {
+        ic_cdk::trap("caller is not the owner");
+    }
+}
+
+// ------------------------------------------------------------
+// Admin set (callers check require_admin / require_owner first)
+// ------------------------------------------------------------
+
+pub fn add_admin(p: Principal) {
+    ADMINS.with(|a| a.borrow_mut().insert(p));
+}
+
+/// The owner can't be removed; returns false if `p` wasn't removable
+pub fn remove_admin(p: Principal) -> bool {
+    if owner() == Some(p) {
+        return false;
+    }
+    ADMINS.with(|a| a.borrow_mut().remove(&p))
+}
+
+pub fn list_admins() -> Vec<Principal> {
+    ADMINS.with(|a| a.borrow().iter().copied().collect())
+}
+
+/// Make `new_owner` the owner; the previous owner stays an admin until
+/// removed
+pub fn transfer_ownership(new_owner: Principal) {
+    OWNER.with(|o| *o.borrow_mut() = Some(new_owner));
+    add_admin(new_owner);
+}
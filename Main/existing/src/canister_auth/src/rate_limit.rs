@@ -0,0 +1,123 @@
+// rate_limit.rs - per-caller token buckets for expensive updates
+//
+// Each caller gets `burst` calls back to back, refilled at `per_minute`.
+// Admins are never limited. Buckets are only kept in the heap, so an upgrade
+// hands everyone a full bucket again.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+use ic_cdk::caller;
+
+/// One call, in thousandths of a token
+const TOKEN: u64 = 1_000;
+const NS_PER_MINUTE: u128 = 60_000_000_000;
+/// Callers tracked at once. Past this, idle (full) buckets are dropped,
+/// then the least recently used one.
+const MAX_BUCKETS: usize = 10_000;
+
+const DEFAULT_LIMIT: RateLimit = RateLimit { burst: 20, per_minute: 60 };
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct RateLimit {
+    /// Calls a caller may make back to back
+    pub burst: u32,
+    /// Sustained calls per minute
+    pub per_minute: u32,
+}
+
+/// Returned instead of running the call
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct RateLimited {
+    pub retry_after_ms: u64,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limited, retry after {} ms", self.retry_after_ms)
+    }
+}
+
+struct Bucket {
+    millitokens: u64,
+    updated_ns: u64,
+    /// The caller's latest call, for evicting the least recently used
+    last_call_ns: u64,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: u64) {
+        let elapsed = now.saturating_sub(self.updated_ns) as u128;
+        let gained = elapsed * limit.per_minute as u128 * TOKEN as u128 / NS_PER_MINUTE;
+        if gained == 0 {
+            // Keep the partial progress for the next call
+            return;
+        }
+        let cap = limit.burst as u64 * TOKEN;
+        self.millitokens = (self.millitokens as u128 + gained).min(cap as u128) as u64;
+        self.updated_ns = now;
+    }
+}
+
+thread_local! {
+    static LIMIT: Cell<Option<RateLimit>> = const { Cell::new(Some(DEFAULT_LIMIT)) };
+    static BUCKETS: RefCell<HashMap<Principal, Bucket>> = RefCell::new(HashMap::new());
+}
+
+/// None turns limiting off. Every bucket starts full under the new limit.
+pub fn set_limit(limit: Option<RateLimit>) {
+    if matches!(limit, Some(l) if l.burst == 0 || l.per_minute == 0) {
+        ic_cdk::trap("burst and per_minute must be at least 1");
+    }
+    LIMIT.with(|l| l.set(limit));
+    BUCKETS.with(|b| b.borrow_mut().clear());
+}
+
+pub fn limit() -> Option<RateLimit> {
+    LIMIT.with(|l| l.get())
+}
+
+/// Spend one call from the caller's bucket
+pub fn check() -> Result<(), RateLimited> {
+    let caller = caller();
+    let Some(limit) = limit() else {
+        return Ok(());
+    };
+    if crate::is_admin(&caller) {
+        return Ok(());
+    }
+
+    let now = time();
+    BUCKETS.with(|b| {
+        let mut b = b.borrow_mut();
+        if b.len() >= MAX_BUCKETS && !b.contains_key(&caller) {
+            b.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.millitokens < limit.burst as u64 * TOKEN
+            });
+            if b.len() >= MAX_BUCKETS {
+                if let Some(oldest) = b.iter().min_by_key(|(_, bucket)| bucket.last_call_ns).map(|(p, _)| *p) {
+                    b.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = b.entry(caller).or_insert(Bucket {
+            millitokens: limit.burst as u64 * TOKEN,
+            updated_ns: now,
+            last_call_ns: now,
+        });
+        bucket.refill(limit, now);
+        bucket.last_call_ns = now;
+        if bucket.millitokens >= TOKEN {
+            bucket.millitokens -= TOKEN;
+            return Ok(());
+        }
+
+        let missing = (TOKEN - bucket.millitokens) as u128;
+        let wait_ns = (missing * NS_PER_MINUTE).div_ceil(limit.per_minute as u128 * TOKEN as u128);
+        Err(RateLimited { retry_after_ms: (wait_ns as u64).div_ceil(1_000_000) })
+    })
+}
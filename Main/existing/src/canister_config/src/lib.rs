@@ -0,0 +1,178 @@
+// canister_config - the global parameters held by the config canister, and
+// the cached copy each consuming canister reads them from
+//
+// A consumer points itself at the config canister with `connect`, which
+// subscribes it to changes and fetches the current version. Until then, or
+// if the config canister is unreachable, `current()` returns the defaults,
+// which match the values the canisters used to hard-code.
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call;
+
+/// Consumers export this update; the config canister calls it with the new
+/// VersionedConfig
+pub const UPDATE_METHOD: &str = "on_config_update";
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct GlobalConfig {
+    /// What difficulty retargeting aims for
+    pub target_block_time_secs: u64,
+    /// Most the difficulty moves in one adjustment, in leading-zero bits
+    pub max_difficulty_step: u32,
+    /// Bounds for the chunk sizes the coordinator hands out; the miner
+    /// refuses chunks over the maximum
+    pub min_chunk_size: u64,
+    pub max_chunk_size: u64,
+    /// A miner that hasn't answered an assignment within this is reclaimed
+    pub assign_timeout_secs: u64,
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        GlobalConfig {
+            target_block_time_secs: 60,
+            max_difficulty_step: 2,
+            min_chunk_size: 1_000,
+            max_chunk_size: 1_000_000,
+            assign_timeout_secs: 10,
+        }
+    }
+}
+
+impl GlobalConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.target_block_time_secs == 0 {
+            return Err("target_block_time_secs must be at least 1".to_string());
+        }
+        if self.max_difficulty_step == 0 {
+            return Err("max_difficulty_step must be at least 1".to_string());
+        }
+        if self.min_chunk_size == 0 || self.min_chunk_size > self.max_chunk_size {
+            return Err("chunk sizes need 1 <= min_chunk_size <= max_chunk_size".to_string());
+        }
+        if self.assign_timeout_secs == 0 {
+            return Err("assign_timeout_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Version 0 is the built-in defaults; the config canister starts at 1
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct VersionedConfig {
+    pub version: u64,
+    pub updated_at: u64,
+    pub config: GlobalConfig,
+}
+
+/// What a consumer keeps across upgrades
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    source: Option<Principal>,
+    cached: Option<VersionedConfig>,
+}
+
+thread_local! {
+    static SOURCE: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static CACHED: RefCell<Option<VersionedConfig>> = const { RefCell::new(None) };
+}
+
+// ------------------------------------------------------------
+// Reads
+// ------------------------------------------------------------
+
+pub fn current() -> GlobalConfig {
+    CACHED.with(|c| c.borrow().as_ref().map(|v| v.config.clone()).unwrap_or_default())
+}
+
+/// The config fetched from the config canister; None while on the defaults
+pub fn fetched() -> Option<GlobalConfig> {
+    CACHED.with(|c| c.borrow().as_ref().map(|v| v.config.clone()))
+}
+
+pub fn cached() -> VersionedConfig {
+    CACHED.with(|c| c.borrow().clone()).unwrap_or(VersionedConfig {
+        version: 0,
+        updated_at: 0,
+        config: GlobalConfig::default(),
+    })
+}
+
+pub fn source() -> Option<Principal> {
+    SOURCE.with(|s| *s.borrow())
+}
+
+// ------------------------------------------------------------
+// Keeping the cache current
+// ------------------------------------------------------------
+
+/// Take a newer version; older or invalid ones are ignored
+fn apply(update: VersionedConfig) -> bool {
+    if update.config.validate().is_err() {
+        return false;
+    }
+    CACHED.with(|c| {
+        let mut c = c.borrow_mut();
+        if c.as_ref().is_some_and(|v| v.version >= update.version) {
+            return false;
+        }
+        ic_cdk::println!("⚙️ Config version {}", update.version);
+        *c = Some(update);
+        true
+    })
+}
+
+/// Follow `source` (None goes back to the defaults): subscribe to its
+/// changes and fetch its current version, which is returned
+pub async fn connect(source: Option<Principal>) -> Result<u64, String> {
+    SOURCE.with(|s| *s.borrow_mut() = source);
+    CACHED.with(|c| *c.borrow_mut() = None);
+    let Some(canister) = source else {
+        return Ok(0);
+    };
+
+    let me = ic_cdk::id();
+    call::<(Principal, Option<String>), (bool,)>(canister, "subscribe", (me, Some(UPDATE_METHOD.to_string())))
+    .await
+    .map_err(|(code, msg)| format!("subscribe: {:?} {}", code, msg))?;
+    refresh().await
+}
+
+/// Fetch the source's config if it is newer than the cached one; returns
+/// the cached version afterwards
+pub async fn refresh() -> Result<u64, String> {
+    let canister = source().ok_or("no config canister set")?;
+    let have = cached().version;
+    let (newer,) = call::<(u64,), (Option<VersionedConfig>,)>(canister, "get_config_if_newer", (have,))
+    .await
+    .map_err(|(code, msg)| format!("get_config_if_newer: {:?} {}", code, msg))?;
+    if let Some(update) = newer {
+        apply(update);
+    }
+    Ok(cached().version)
+}
+
+/// Body of the consumer's `on_config_update`: only the source may push
+pub fn accept_update(update: VersionedConfig) -> bool {
+    if source() != Some(ic_cdk::caller()) {
+        ic_cdk::trap("only the config canister may push config");
+    }
+    apply(update)
+}
+
+// ------------------------------------------------------------
+// Upgrades
+// ------------------------------------------------------------
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        source: source(),
+        cached: CACHED.with(|c| c.borrow().clone()),
+    }
+}
+
+pub fn restore(snapshot: Snapshot) {
+    SOURCE.with(|s| *s.borrow_mut() = snapshot.source);
+    CACHED.with(|c| *c.borrow_mut() = snapshot.cached);
+}
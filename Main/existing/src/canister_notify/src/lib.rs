@@ -0,0 +1,298 @@
+// canister_notify - topic-based pub/sub between canisters
+//
+// Subscribers register a (canister, method) per topic. Each publish encodes
+// its Candid arguments once and calls every subscriber; a call that is
+// rejected or traps is retried with exponential backoff, and after
+// `max_attempts` it lands on a bounded dead-letter list an admin can replay.
+// Each canister links its own copy of this state.
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use candid::utils::ArgumentEncoder;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call_raw;
+
+/// Oldest dead letters are dropped past this
+const MAX_DEAD_LETTERS: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Subscriber {
+    pub topic: String,
+    pub canister: Principal,
+    pub method: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RetryPolicy {
+    /// Calls per delivery, including the first
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubles after each failure
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+/// A delivery that ran out of attempts
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DeadLetter {
+    pub id: u64,
+    pub topic: String,
+    pub canister: Principal,
+    pub method: String,
+    /// Candid-encoded arguments, as they were sent
+    pub args: Vec<u8>,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct Delivery {
+    id: u64,
+    topic: String,
+    canister: Principal,
+    method: String,
+    args: Vec<u8>,
+    attempts: u32,
+}
+
+/// Everything that has to survive an upgrade
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    subscribers: Vec<Subscriber>,
+    policy: Option<RetryPolicy>,
+    /// Deliveries waiting for a retry; rescheduled on restore
+    pending: Vec<Delivery>,
+    dead_letters: Vec<DeadLetter>,
+    next_id: u64,
+}
+
+thread_local! {
+    static SUBSCRIBERS: RefCell<Vec<Subscriber>> = const { RefCell::new(Vec::new()) };
+    static POLICY: RefCell<RetryPolicy> = RefCell::new(RetryPolicy::default());
+    static PENDING: RefCell<BTreeMap<u64, Delivery>> = const { RefCell::new(BTreeMap::new()) };
+    static DEAD_LETTERS: RefCell<VecDeque<DeadLetter>> = const { RefCell::new(VecDeque::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+// ------------------------------------------------------------
+// Subscribers
+// ------------------------------------------------------------
+
+/// Returns false if the (topic, canister, method) triple is already there
+pub fn subscribe(topic: &str, canister: Principal, method: String) -> bool {
+    let sub = Subscriber { topic: topic.to_string(), canister, method };
+    SUBSCRIBERS.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.contains(&sub) {
+            return false;
+        }
+        s.push(sub);
+        true
+    })
+}
+
+/// Drop `canister`'s subscriptions to `topic`, or to every topic if None;
+/// false if there were none
+pub fn unsubscribe(topic: Option<&str>, canister: Principal) -> bool {
+    SUBSCRIBERS.with(|s| {
+        let mut s = s.borrow_mut();
+        let before = s.len();
+        s.retain(|sub| sub.canister != canister || topic.is_some_and(|t| sub.topic != t));
+        s.len() != before
+    })
+}
+
+/// Subscribers of `topic`, or of every topic if None
+pub fn subscribers(topic: Option<&str>) -> Vec<Subscriber> {
+    SUBSCRIBERS.with(|s| {
+        s.borrow()
+        .iter()
+        .filter(|sub| topic.is_none_or(|t| sub.topic == t))
+        .cloned()
+        .collect()
+    })
+}
+
+// ------------------------------------------------------------
+// Publishing
+// ------------------------------------------------------------
+
+/// Call every subscriber of `topic` with `args`. Deliveries run in the
+/// background; this never fails the caller.
+pub fn publish<A: ArgumentEncoder>(topic: &str, args: A) {
+    let subs = subscribers(Some(topic));
+    if subs.is_empty() {
+        return;
+    }
+    let args = match candid::encode_args(args) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            ic_cdk::println!("❌ Can't encode {} notification: {}", topic, e);
+            return;
+        }
+    };
+
+    for sub in subs {
+        let id = NEXT_ID.with(|n| n.replace(n.get() + 1));
+        PENDING.with(|p| {
+            p.borrow_mut().insert(id, Delivery {
+                id,
+                topic: sub.topic,
+                canister: sub.canister,
+                method: sub.method,
+                args: args.clone(),
+                attempts: 0,
+            })
+        });
+        ic_cdk::spawn(deliver(id));
+    }
+}
+
+async fn deliver(id: u64) {
+    let Some(delivery) = PENDING.with(|p| p.borrow().get(&id).cloned()) else {
+        return;
+    };
+    let result = call_raw(delivery.canister, &delivery.method, &delivery.args, 0).await;
+    let attempts = delivery.attempts + 1;
+
+    let error = match result {
+        Ok(_) => {
+            PENDING.with(|p| p.borrow_mut().remove(&id));
+            return;
+        }
+        Err((code, msg)) => format!("{:?} {}", code, msg),
+    };
+
+    let policy = policy();
+    if attempts >= policy.max_attempts {
+        ic_cdk::println!(
+            "❌ Notify {}.{} ({}) gave up after {} attempts: {}",
+            delivery.canister, delivery.method, delivery.topic, attempts, error
+        );
+        PENDING.with(|p| p.borrow_mut().remove(&id));
+        push_dead_letter(DeadLetter {
+            id,
+            topic: delivery.topic,
+            canister: delivery.canister,
+            method: delivery.method,
+            args: delivery.args,
+            attempts,
+            last_error: error,
+            failed_at: ic_cdk::api::time(),
+        });
+        return;
+    }
+
+    PENDING.with(|p| {
+        if let Some(d) = p.borrow_mut().get_mut(&id) {
+            d.attempts = attempts;
+        }
+    });
+    schedule_retry(id, backoff(&policy, attempts));
+}
+
+/// Wait before the retry that follows `attempts` failed calls
+fn backoff(policy: &RetryPolicy, attempts: u32) -> Duration {
+    let ms = policy
+    .initial_backoff_ms
+    .saturating_mul(1u64.checked_shl(attempts.saturating_sub(1)).unwrap_or(u64::MAX))
+    .min(policy.max_backoff_ms);
+    Duration::from_millis(ms)
+}
+
+fn schedule_retry(id: u64, delay: Duration) {
+    canister_timers::set_timer(delay, move || ic_cdk::spawn(deliver(id)));
+}
+
+fn push_dead_letter(letter: DeadLetter) {
+    DEAD_LETTERS.with(|d| {
+        let mut d = d.borrow_mut();
+        if d.len() >= MAX_DEAD_LETTERS {
+            d.pop_front();
+        }
+        d.push_back(letter);
+    });
+}
+
+// ------------------------------------------------------------
+// Policy and dead letters
+// ------------------------------------------------------------
+
+pub fn set_policy(policy: RetryPolicy) {
+    POLICY.with(|p| *p.borrow_mut() = policy);
+}
+
+pub fn policy() -> RetryPolicy {
+    POLICY.with(|p| p.borrow().clone())
+}
+
+/// Oldest first
+pub fn dead_letters() -> Vec<DeadLetter> {
+    DEAD_LETTERS.with(|d| d.borrow().iter().cloned().collect())
+}
+
+/// Send every dead letter again with a fresh set of attempts; returns how
+/// many were requeued
+pub fn retry_dead_letters() -> u64 {
+    let letters: Vec<DeadLetter> = DEAD_LETTERS.with(|d| d.borrow_mut().drain(..).collect());
+    for letter in &letters {
+        PENDING.with(|p| {
+            p.borrow_mut().insert(letter.id, Delivery {
+                id: letter.id,
+                topic: letter.topic.clone(),
+                canister: letter.canister,
+                method: letter.method.clone(),
+                args: letter.args.clone(),
+                attempts: 0,
+            })
+        });
+        ic_cdk::spawn(deliver(letter.id));
+    }
+    letters.len() as u64
+}
+
+/// Returns how many were dropped
+pub fn clear_dead_letters() -> u64 {
+    DEAD_LETTERS.with(|d| d.borrow_mut().drain(..).count() as u64)
+}
+
+// ------------------------------------------------------------
+// Upgrades
+// ------------------------------------------------------------
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        subscribers: subscribers(None),
+        policy: Some(policy()),
+        pending: PENDING.with(|p| p.borrow().values().cloned().collect()),
+        dead_letters: dead_letters(),
+        next_id: NEXT_ID.with(|n| n.get()),
+    }
+}
+
+/// Reinstall saved state and reschedule deliveries that were waiting for a
+/// retry. Call from post_upgrade.
+pub fn restore(snapshot: Snapshot) {
+    SUBSCRIBERS.with(|s| *s.borrow_mut() = snapshot.subscribers);
+    set_policy(snapshot.policy.unwrap_or_default());
+    DEAD_LETTERS.with(|d| *d.borrow_mut() = snapshot.dead_letters.into());
+    NEXT_ID.with(|n| n.set(snapshot.next_id));
+
+    let policy = policy();
+    for delivery in snapshot.pending {
+        let (id, attempts) = (delivery.id, delivery.attempts);
+        PENDING.with(|p| p.borrow_mut().insert(id, delivery));
+        schedule_retry(id, backoff(&policy, attempts.max(1)));
+    }
+}
@@ -0,0 +1,103 @@
+// canister_state - versioned upgrade state with registered migrations
+//
+// A canister saves its state as `(schema_version, bytes)`, where `bytes` is
+// the candid encoding of that version's state type. On upgrade the saved
+// bytes are passed through each registered migration from the saved version
+// up to the current one before they are decoded. Bump the version whenever
+// the saved type changes and register a migration from the old version.
+use std::fmt;
+
+use candid::{CandidType, Decode, Deserialize, Encode};
+use serde::de::DeserializeOwned;
+
+pub mod transfer;
+
+/// Turns version `n` bytes into version `n + 1` bytes
+pub type Migration = fn(Vec<u8>) -> Result<Vec<u8>, String>;
+
+#[derive(CandidType, Deserialize)]
+struct Envelope {
+    schema_version: u32,
+    bytes: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    /// Stable memory holds no versioned state (fresh install, or saved
+    /// before this framework)
+    Unversioned,
+    /// Saved by a newer build; downgrades aren't supported
+    Newer { saved: u32, current: u32 },
+    /// No migration is registered from this version
+    MissingMigration(u32),
+    Migration { from: u32, error: String },
+    Decode(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Unversioned => write!(f, "no versioned state saved"),
+            StateError::Newer { saved, current } => {
+                write!(f, "state schema {} is newer than this build's {}", saved, current)
+            }
+            StateError::MissingMigration(v) => write!(f, "no migration from schema {}", v),
+            StateError::Migration { from, error } => write!(f, "migration from schema {} failed: {}", from, error),
+            StateError::Decode(e) => write!(f, "failed to decode state: {}", e),
+        }
+    }
+}
+
+/// Envelope bytes for `state` at `version`, for canisters that keep their
+/// own stable structures
+pub fn encode<T: CandidType>(version: u32, state: &T) -> Vec<u8> {
+    let bytes = Encode!(state).expect("failed to encode state");
+    Encode!(&Envelope { schema_version: version, bytes }).expect("failed to encode state envelope")
+}
+
+/// Migrate envelope bytes up to `current` and decode them. `migrations`
+/// holds (from_version, migration) pairs.
+pub fn decode<T>(envelope: &[u8], current: u32, migrations: &[(u32, Migration)]) -> Result<T, StateError>
+where
+    T: CandidType + DeserializeOwned,
+{
+    let env = Decode!(envelope, Envelope).map_err(|_| StateError::Unversioned)?;
+    migrate_and_decode(env, current, migrations)
+}
+
+/// Save `state` at `version` in stable memory (pre_upgrade)
+pub fn save<T: CandidType>(version: u32, state: &T) -> Result<(), String> {
+    let bytes = Encode!(state).map_err(|e| e.to_string())?;
+    ic_cdk::storage::stable_save((Envelope { schema_version: version, bytes },)).map_err(|e| e.to_string())
+}
+
+/// Load state saved with `save`, migrated up to `current` (post_upgrade)
+pub fn load<T>(current: u32, migrations: &[(u32, Migration)]) -> Result<T, StateError>
+where
+    T: CandidType + DeserializeOwned,
+{
+    let (env,) = ic_cdk::storage::stable_restore::<(Envelope,)>().map_err(|_| StateError::Unversioned)?;
+    migrate_and_decode(env, current, migrations)
+}
+
+fn migrate_and_decode<T>(env: Envelope, current: u32, migrations: &[(u32, Migration)]) -> Result<T, StateError>
+where
+    T: CandidType + DeserializeOwned,
+{
+    let Envelope { schema_version: mut version, mut bytes } = env;
+    if version > current {
+        return Err(StateError::Newer { saved: version, current });
+    }
+
+    while version < current {
+        let step = migrations
+        .iter()
+        .find(|(from, _)| *from == version)
+        .map(|(_, m)| *m)
+        .ok_or(StateError::MissingMigration(version))?;
+        bytes = step(bytes).map_err(|error| StateError::Migration { from: version, error })?;
+        version += 1;
+    }
+
+    Decode!(&bytes, T).map_err(|e| StateError::Decode(e.to_string()))
+}
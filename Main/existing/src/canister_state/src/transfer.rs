@@ -0,0 +1,49 @@
+// transfer.rs - chunked export/import of a canister's versioned state, for
+// backups and for rebuilding a canister elsewhere with identical state
+use std::cell::RefCell;
+
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+
+use crate::{Migration, StateError};
+
+/// Bytes per export page; keeps replies well under the 2MB message limit
+const MAX_PAGE: u64 = 1_000_000;
+
+thread_local! {
+    // Envelope taken at offset 0, so later pages come from the same snapshot
+    static EXPORT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    // Chunks staged by import until the last one arrives
+    static IMPORT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// `(page, total_len)` of the exported envelope. Offset 0 takes a fresh
+/// snapshot of `state`; later offsets page through that snapshot.
+pub fn export_page<T: CandidType>(version: u32, state: impl FnOnce() -> T, offset: u64, limit: u64) -> (Vec<u8>, u64) {
+    EXPORT.with(|e| {
+        let mut e = e.borrow_mut();
+        if offset == 0 {
+            *e = crate::encode(version, &state());
+        }
+        let start = (offset as usize).min(e.len());
+        let end = start.saturating_add(limit.min(MAX_PAGE) as usize).min(e.len());
+        (e[start..end].to_vec(), e.len() as u64)
+    })
+}
+
+/// Stage `chunk`; once `last` is set, decode everything staged (migrating it
+/// up to `current`) and clear the stage. Ok(None) while chunks are pending.
+pub fn import_chunk<T>(chunk: Vec<u8>, last: bool, current: u32, migrations: &[(u32, Migration)]) -> Result<Option<T>, StateError>
+where
+    T: CandidType + DeserializeOwned,
+{
+    IMPORT.with(|i| {
+        let mut i = i.borrow_mut();
+        i.extend_from_slice(&chunk);
+        if !last {
+            return Ok(None);
+        }
+        let bytes = std::mem::take(&mut *i);
+        crate::decode(&bytes, current, migrations).map(Some)
+    })
+}
@@ -0,0 +1,141 @@
+// canister_timers - one-shot and interval timers on top of the global timer
+//
+// Every canister has a single `canister_global_timer` entry point. This crate
+// owns it and multiplexes any number of timers onto it, so canisters that link
+// it must not export their own global timer.
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use ic_cdk::api::{set_global_timer, time};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+enum Task {
+    Once(Box<dyn FnOnce()>),
+    Repeated {
+        func: Box<dyn FnMut()>,
+        interval_ns: u64,
+    },
+}
+
+thread_local! {
+    static TASKS: RefCell<BTreeMap<u64, Task>> = const { RefCell::new(BTreeMap::new()) };
+    // (deadline_ns, timer id), earliest first
+    static QUEUE: RefCell<BTreeSet<(u64, u64)>> = const { RefCell::new(BTreeSet::new()) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    // Set when the task currently executing clears itself
+    static RUNNING: Cell<Option<u64>> = const { Cell::new(None) };
+    static CLEARED_WHILE_RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+// ------------------------------------------------------------
+// Public API
+// ------------------------------------------------------------
+
+/// Run `func` once after `delay`.
+pub fn set_timer(delay: Duration, func: impl FnOnce() + 'static) -> TimerId {
+    schedule(delay_ns(delay), Task::Once(Box::new(func)))
+}
+
+/// Run `func` every `interval`, starting one interval from now.
+pub fn set_timer_interval(interval: Duration, func: impl FnMut() + 'static) -> TimerId {
+    let interval_ns = delay_ns(interval).max(1);
+    schedule(
+        interval_ns,
+        Task::Repeated {
+            func: Box::new(func),
+            interval_ns,
+        },
+    )
+}
+
+/// Cancel a timer. Clearing an unknown or already fired timer is a no-op.
+pub fn clear_timer(id: TimerId) {
+    if RUNNING.with(|r| r.get()) == Some(id.0) {
+        CLEARED_WHILE_RUNNING.with(|c| c.set(true));
+    }
+
+    TASKS.with(|t| t.borrow_mut().remove(&id.0));
+    QUEUE.with(|q| q.borrow_mut().retain(|(_, i)| *i != id.0));
+    rearm();
+}
+
+// ------------------------------------------------------------
+// Internals
+// ------------------------------------------------------------
+
+fn delay_ns(d: Duration) -> u64 {
+    d.as_nanos().min(u64::MAX as u128) as u64
+}
+
+fn schedule(delay_ns: u64, task: Task) -> TimerId {
+    let id = NEXT_ID.with(|n| {
+        let id = n.get();
+        n.set(id + 1);
+        id
+    });
+
+    let deadline = time().saturating_add(delay_ns);
+
+    TASKS.with(|t| t.borrow_mut().insert(id, task));
+    QUEUE.with(|q| q.borrow_mut().insert((deadline, id)));
+    rearm();
+
+    TimerId(id)
+}
+
+fn rearm() {
+    let next = QUEUE.with(|q| q.borrow().first().map(|(deadline, _)| *deadline));
+    // Zero deactivates the global timer
+    set_global_timer(next.unwrap_or(0));
+}
+
+fn pop_due(now: u64) -> Option<(u64, Task)> {
+    let id = QUEUE.with(|q| {
+        let mut q = q.borrow_mut();
+        let first = *q.first()?;
+        if first.0 > now {
+            return None;
+        }
+        q.remove(&first);
+        Some(first.1)
+    })?;
+
+    // A queued id without a task was cleared; keep draining
+    match TASKS.with(|t| t.borrow_mut().remove(&id)) {
+        Some(task) => Some((id, task)),
+        None => pop_due(now),
+    }
+}
+
+#[export_name = "canister_global_timer"]
+extern "C" fn global_timer() {
+    ic_cdk::setup();
+
+    let now = time();
+
+    while let Some((id, task)) = pop_due(now) {
+        RUNNING.with(|r| r.set(Some(id)));
+        CLEARED_WHILE_RUNNING.with(|c| c.set(false));
+
+        match task {
+            Task::Once(func) => func(),
+            Task::Repeated { mut func, interval_ns } => {
+                func();
+
+                if !CLEARED_WHILE_RUNNING.with(|c| c.get()) {
+                    TASKS.with(|t| {
+                        t.borrow_mut().insert(id, Task::Repeated { func, interval_ns })
+                    });
+                    QUEUE.with(|q| q.borrow_mut().insert((now.saturating_add(interval_ns), id)));
+                }
+            }
+        }
+
+        RUNNING.with(|r| r.set(None));
+    }
+
+    rearm();
+}
@@ -0,0 +1,132 @@
+// archive.rs - move old main-chain headers to the archive canister and
+// read them back through it
+use std::cell::Cell;
+use std::collections::HashSet;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call;
+
+use crate::{BlockHeader, STATE};
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ArchiveConfig {
+    pub canister: Principal,
+    /// Start archiving once more main-chain headers than this are held
+    pub max_local_blocks: u64,
+    /// Headers moved per archive call
+    pub batch_size: u64,
+}
+
+thread_local! {
+    // One transfer at a time
+    static ARCHIVING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn config() -> Option<ArchiveConfig> {
+    STATE.with(|s| s.borrow().as_ref().and_then(|st| st.archive.clone()))
+}
+
+pub fn canister() -> Option<Principal> {
+    config().map(|c| c.canister)
+}
+
+pub fn first_local_height() -> u64 {
+    STATE.with(|s| s.borrow().as_ref().map(|st| st.first_height).unwrap_or(0))
+}
+
+/// Called after each accepted block
+pub fn maybe_archive() {
+    if ARCHIVING.with(|a| a.get()) {
+        return;
+    }
+
+    let batch = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref()?;
+        let cfg = st.archive.as_ref()?;
+        if (st.main_chain.len() as u64) <= cfg.max_local_blocks {
+            return None;
+        }
+
+        let headers: Vec<BlockHeader> = (st.first_height..st.first_height + cfg.batch_size)
+        .filter_map(|h| st.header_at(h).cloned())
+        .collect();
+        Some((cfg.canister, headers))
+    });
+
+    if let Some((canister, headers)) = batch {
+        ARCHIVING.with(|a| a.set(true));
+        ic_cdk::spawn(transfer(canister, headers));
+    }
+}
+
+async fn transfer(canister: Principal, headers: Vec<BlockHeader>) {
+    let count = headers.len() as u64;
+    let result = call::<(Vec<BlockHeader>,), ()>(canister, "append_blocks", (headers,)).await;
+
+    match result {
+        Ok(()) => {
+            STATE.with(|s| {
+                if let Some(st) = s.borrow_mut().as_mut() {
+                    drop_archived(st, count);
+                }
+            });
+            ic_cdk::println!("🗄️ Archived {} headers to {}", count, canister);
+        }
+        Err((code, msg)) => ic_cdk::println!("❌ Archiving to {} failed: {:?} {}", canister, code, msg),
+    }
+
+    ARCHIVING.with(|a| a.set(false));
+}
+
+/// Forget the oldest `count` main-chain blocks, plus every side-branch block
+/// that no longer connects to what is left
+fn drop_archived(st: &mut crate::State, count: u64) {
+    let count = count.min(st.main_chain.len() as u64 - 1);
+    st.main_chain.drain(..count as usize);
+    st.first_height += count;
+
+    let first = st.first_height;
+    let root = st.main_chain[0].clone();
+    st.blocks.retain(|_, b| b.header.height >= first);
+
+    let mut by_height: Vec<(u64, String, String)> = st
+    .blocks
+    .values()
+    .map(|b| (b.header.height, b.header.block_hash.clone(), b.header.prev_hash.clone()))
+    .collect();
+    by_height.sort();
+
+    let mut kept: HashSet<String> = HashSet::from([root.clone()]);
+    for (_, hash, prev) in by_height {
+        if hash != root && kept.contains(&prev) {
+            kept.insert(hash);
+        }
+    }
+    st.blocks.retain(|hash, _| kept.contains(hash));
+}
+
+// ------------------------------------------------------------
+// Reads proxied to the archive canister
+// ------------------------------------------------------------
+
+pub async fn fetch_block(canister: Principal, height: u64) -> Option<BlockHeader> {
+    call::<(u64,), (Option<BlockHeader>,)>(canister, "get_block", (height,))
+    .await
+    .ok()
+    .and_then(|(b,)| b)
+}
+
+pub async fn fetch_blocks(canister: Principal, from: u64, to: u64) -> Vec<BlockHeader> {
+    call::<(u64, u64), (Vec<BlockHeader>,)>(canister, "get_blocks", (from, to))
+    .await
+    .map(|(b,)| b)
+    .unwrap_or_default()
+}
+
+pub async fn fetch_block_by_hash(canister: Principal, block_hash: String) -> Option<BlockHeader> {
+    call::<(String,), (Option<BlockHeader>,)>(canister, "get_block_by_hash", (block_hash,))
+    .await
+    .ok()
+    .and_then(|(b,)| b)
+}
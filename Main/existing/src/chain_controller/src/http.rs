@@ -0,0 +1,124 @@
+// http.rs - read-only JSON explorer over http_request: /tip, /stats and
+// /block/{height or hash}, so a plain web page or curl can browse the chain
+use candid::{CandidType, Deserialize};
+use serde_json::{json, Value};
+
+use crate::{BlockHeader, State, STATE};
+
+/// Block times averaged for /stats
+const STATS_WINDOW: u64 = 100;
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+fn respond(status_code: u16, body: Value) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body: body.to_string().into_bytes(),
+    }
+}
+
+fn error(status_code: u16, message: String) -> HttpResponse {
+    respond(status_code, json!({ "error": message }))
+}
+
+pub fn handle(req: HttpRequest) -> HttpResponse {
+    if req.method != "GET" {
+        return error(405, format!("{} not allowed", req.method));
+    }
+    let path = req.url.split('?').next().unwrap_or("").trim_end_matches('/');
+
+    STATE.with(|s| {
+        let st = s.borrow();
+        let Some(st) = st.as_ref() else {
+            return error(503, "chain not initialized".to_string());
+        };
+        match path.split('/').collect::<Vec<_>>().as_slice() {
+            ["", "tip"] => respond(200, tip(st)),
+            ["", "stats"] => respond(200, stats(st)),
+            ["", "block", id] => block(st, id),
+            _ => error(404, format!("no route {}", path)),
+        }
+    })
+}
+
+// ------------------------------------------------------------
+// Routes
+// ------------------------------------------------------------
+
+fn tip(st: &State) -> Value {
+    json!({
+        "height": st.tip.height,
+        "block_hash": st.tip.block_hash,
+        "difficulty": st.tip.difficulty,
+        "last_update_ns": st.tip.last_update_ns,
+        "paused": st.tip.paused,
+    })
+}
+
+/// A decimal id is a height, anything else a block hash
+fn block(st: &State, id: &str) -> HttpResponse {
+    let header = match id.parse::<u64>() {
+        Ok(height) if height < st.first_height => {
+            return error(404, format!("height {} is archived; read it from the archive canister", height));
+        }
+        Ok(height) => st.header_at(height),
+        Err(_) => st.blocks.get(id).map(|b| &b.header),
+    };
+    match header {
+        Some(h) => respond(200, block_json(st, h)),
+        None => error(404, format!("unknown block {}", id)),
+    }
+}
+
+fn block_json(st: &State, h: &BlockHeader) -> Value {
+    let on_main = st.main_hash_at(h.height) == Some(&h.block_hash);
+    let confirmations = if on_main { st.confirmations(h.height) } else { 0 };
+    json!({
+        "height": h.height,
+        "block_hash": h.block_hash,
+        "prev_hash": h.prev_hash,
+        "difficulty": h.difficulty,
+        "timestamp_ns": h.timestamp_ns,
+        // u128 doesn't fit a JSON number losslessly
+        "cumulative_work": h.cumulative_work.to_string(),
+        "miner": h.miner.map(|m| m.to_text()),
+        "main_chain": on_main,
+        "confirmations": confirmations,
+        "final": on_main && confirmations >= st.finality_depth,
+    })
+}
+
+fn stats(st: &State) -> Value {
+    let times = st.recent_block_times(STATS_WINDOW);
+    let avg_block_time = (!times.is_empty()).then(|| times.iter().sum::<u64>() as f64 / times.len() as f64);
+    json!({
+        "height": st.tip.height,
+        "difficulty": st.tip.difficulty,
+        "cumulative_work": st.tip_work().to_string(),
+        "avg_block_time_secs": avg_block_time,
+        "known_blocks": st.blocks.len(),
+        "side_branch_blocks": st.blocks.len().saturating_sub(st.main_chain.len()),
+        "reorgs": st.reorgs.len(),
+        "first_local_height": st.first_height,
+        "finality_depth": st.finality_depth,
+        "pending_payloads": crate::mempool::pending().len(),
+        "paused": st.tip.paused,
+    })
+}
@@ -1,8 +1,33 @@
 use candid::{CandidType, Deserialize};
-use ic_cdk::{query, update};
+use ic_cdk::{init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk::api::call::call;
 use ic_cdk::api::caller;
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
 use std::cell::RefCell;
-use candid::Principal;
+use std::collections::HashMap;
+use candid::{Nat, Principal};
+use canister_auth::audit::AuditEntry;
+use canister_notify::{DeadLetter, RetryPolicy};
+use canister_state::{Migration, StateError};
+
+mod archive;
+mod http;
+mod mempool;
+mod rewards;
+mod search;
+mod subscriptions;
+
+use crate::archive::ArchiveConfig;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::mempool::{Mempool, Payload};
+use crate::rewards::{RewardConfig, RewardPayout, RewardStatus, Rewards};
+use crate::search::{BlockFilter, BlockPage};
+use crate::subscriptions::Subscription;
+
+/// Cap on headers returned by one `get_blocks` call
+const MAX_BLOCKS_PER_QUERY: u64 = 1_000;
+
+const DEFAULT_FINALITY_DEPTH: u64 = 6;
 
 
 // ------------------------------------------------------------
@@ -15,16 +40,166 @@ pub struct ChainTip {
     pub block_hash: String,
     pub difficulty: u32,
     pub last_update_ns: u64,
+    /// No blocks are accepted while set
+    pub paused: bool,
+}
+
+/// Header of an accepted block; height 0 is genesis
+#[derive(Clone, CandidType, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub block_hash: String,
+    pub prev_hash: String,
+    pub difficulty: u32,
+    pub timestamp_ns: u64,
+    /// Sum of 2^difficulty from genesis up to and including this block
+    pub cumulative_work: u128,
+    /// Who found it, if the submitter said
+    pub miner: Option<Principal>,
+}
+
+/// The tip moved to a heavier branch that doesn't extend the old tip
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ReorgEvent {
+    pub timestamp_ns: u64,
+    /// Last height both branches share
+    pub fork_height: u64,
+    pub old_tip: String,
+    pub new_tip: String,
+    /// Main-chain heights whose blocks were replaced
+    pub rolled_back: Vec<u64>,
+}
+
+/// Recompute difficulty every `interval_blocks` main-chain blocks by asking
+/// the validator canister's `calculate_difficulty_adjustment`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RetargetConfig {
+    pub interval_blocks: u64,
+    pub target_block_time_secs: u64,
+    pub validator_canister: Principal,
 }
 
 // ------------------------------------------------------------
 // Internal state
 // ------------------------------------------------------------
 
-#[derive(Clone)]
+#[derive(Clone, CandidType, Deserialize)]
+struct StoredBlock {
+    header: BlockHeader,
+    /// Difficulty a child of this block must be mined at
+    next_difficulty: u32,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
 struct State {
     tip: ChainTip,
     validator: Principal,
+    /// Mirrors the auth owner so older saves still carry it
+    owner: Principal,
+    genesis_hash: String,
+    /// Every known block still held here, main chain and side branches
+    blocks: HashMap<String, StoredBlock>,
+    /// Main-chain block hashes from `first_height` up to the tip
+    main_chain: Vec<String>,
+    /// Heights below this have been moved to the archive canister
+    first_height: u64,
+    reorgs: Vec<ReorgEvent>,
+    retarget: Option<RetargetConfig>,
+    archive: Option<ArchiveConfig>,
+    /// Confirmations after which a block counts as final
+    finality_depth: u64,
+}
+
+impl State {
+    fn main_hash_at(&self, height: u64) -> Option<&String> {
+        let idx = height.checked_sub(self.first_height)?;
+        self.main_chain.get(idx as usize)
+    }
+
+    fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        let hash = self.main_hash_at(height)?;
+        self.blocks.get(hash).map(|b| &b.header)
+    }
+
+    /// Seconds between each of the last `count` main-chain blocks and its parent
+    fn recent_block_times(&self, count: u64) -> Vec<u64> {
+        let from = self.tip.height.saturating_sub(count);
+        let headers: Vec<&BlockHeader> = (from..=self.tip.height)
+        .filter_map(|h| self.header_at(h))
+        .collect();
+
+        headers
+        .windows(2)
+        .map(|w| w[1].timestamp_ns.saturating_sub(w[0].timestamp_ns) / 1_000_000_000)
+        .collect()
+    }
+
+    /// 1 for the tip, 0 for a block off the main chain
+    fn confirmations(&self, height: u64) -> u64 {
+        self.tip.height.checked_sub(height).map(|d| d + 1).unwrap_or(0)
+    }
+
+    /// Archived blocks are always final
+    fn is_final(&self, height: u64) -> bool {
+        height < self.first_height || (height <= self.tip.height && self.confirmations(height) >= self.finality_depth)
+    }
+
+    /// Archived heights only ever held main-chain blocks
+    fn on_main(&self, height: u64, hash: &str) -> bool {
+        height < self.first_height || self.main_hash_at(height).is_some_and(|h| h == hash)
+    }
+
+    /// Send the rewards of blocks that just became final and cancel those
+    /// of blocks that were rolled back first
+    fn settle_rewards(&self) {
+        rewards::settle(|height, hash| self.on_main(height, hash), |height| self.is_final(height));
+    }
+
+    fn tip_work(&self) -> u128 {
+        self.blocks
+        .get(&self.tip.block_hash)
+        .map(|b| b.header.cumulative_work)
+        .unwrap_or(0)
+    }
+
+    /// Make `hash` the tip, rewriting the main-chain index back to where
+    /// the branch joins it. Returns the replaced heights.
+    fn switch_tip(&mut self, hash: &str) -> (u64, Vec<u64>) {
+        let mut branch = Vec::new();
+        let mut cursor = hash.to_string();
+
+        // Walk back until we reach a block that is already on the main chain
+        loop {
+            let block = &self.blocks[&cursor];
+            if self.main_hash_at(block.header.height) == Some(&cursor) {
+                break;
+            }
+            branch.push(cursor.clone());
+            cursor = block.header.prev_hash.clone();
+        }
+
+        let fork_height = self.blocks[&cursor].header.height;
+        let old_height = self.tip.height;
+        let rolled_back: Vec<u64> = (fork_height + 1..=old_height).collect();
+
+        self.main_chain.truncate((fork_height - self.first_height) as usize + 1);
+        self.main_chain.extend(branch.into_iter().rev());
+
+        let tip = &self.blocks[hash];
+        self.tip = ChainTip {
+            height: tip.header.height,
+            block_hash: hash.to_string(),
+            difficulty: tip.next_difficulty,
+            last_update_ns: ic_cdk::api::time(),
+            paused: self.tip.paused,
+        };
+
+        (fork_height, rolled_back)
+    }
+}
+
+fn block_work(difficulty: u32) -> u128 {
+    1u128.checked_shl(difficulty).unwrap_or(u128::MAX)
 }
 
 // ------------------------------------------------------------
@@ -37,29 +212,228 @@ thread_local! {
 // Init
 // ------------------------------------------------------------
 
-#[update]
-pub fn init_chain(
-    genesis_hash: String,
-    initial_difficulty: u32,
-    validator: Principal,
-) {
+#[derive(Clone, CandidType, Deserialize)]
+pub struct GenesisArgs {
+    pub genesis_hash: String,
+    pub initial_difficulty: u32,
+    pub validator: Principal,
+    pub owner: Principal,
+    /// Extra admins alongside the owner
+    pub admins: Option<Vec<Principal>>,
+}
+
+fn genesis_state(args: GenesisArgs) -> State {
     let now = ic_cdk::api::time();
 
     let tip = ChainTip {
         height: 0,
-        block_hash: genesis_hash,
-        difficulty: initial_difficulty,
+        block_hash: args.genesis_hash.clone(),
+        difficulty: args.initial_difficulty,
         last_update_ns: now,
+        paused: false,
+    };
+
+    let genesis = StoredBlock {
+        header: BlockHeader {
+            height: 0,
+            block_hash: args.genesis_hash.clone(),
+            prev_hash: String::new(),
+            difficulty: args.initial_difficulty,
+            timestamp_ns: now,
+            cumulative_work: 0,
+            miner: None,
+        },
+        next_difficulty: args.initial_difficulty,
     };
 
+    State {
+        tip,
+        validator: args.validator,
+        owner: args.owner,
+        genesis_hash: args.genesis_hash.clone(),
+        blocks: HashMap::from([(args.genesis_hash.clone(), genesis)]),
+        main_chain: vec![args.genesis_hash],
+        first_height: 0,
+        reorgs: Vec::new(),
+        retarget: None,
+        archive: None,
+        finality_depth: DEFAULT_FINALITY_DEPTH,
+    }
+}
+
+/// Genesis is fixed at install; there is no way to re-initialize later
+#[init]
+fn init(args: GenesisArgs) {
+    canister_auth::init(args.owner, args.admins.clone().unwrap_or_default());
+    STATE.with(|s| *s.borrow_mut() = Some(genesis_state(args)));
+}
+
+/// Bump when `Saved` (or anything in it, like ChainTip) changes, and register
+/// a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Auth = (Option<Principal>, Vec<Principal>);
+type Saved = (
+    Option<State>,
+    Vec<Subscription>,
+    Mempool,
+    Option<Auth>,
+    Option<Vec<AuditEntry>>,
+    Option<Rewards>,
+    Option<canister_notify::Snapshot>,
+);
+
+fn saved_state() -> Saved {
+    let state = STATE.with(|s| s.borrow().clone());
+    let auth = Some(canister_auth::snapshot());
+    let audit = Some(canister_auth::audit::snapshot());
+    let notify = Some(canister_notify::snapshot());
+    (state, subscriptions::list(), mempool::snapshot(), auth, audit, Some(rewards::snapshot()), notify)
+}
+
+/// Everything but the chain itself, which the caller places
+fn restore_state((state, subs, pool, auth, audit, paid, notify): Saved) -> Option<State> {
+    match notify {
+        Some(n) => canister_notify::restore(n),
+        None => subscriptions::restore(subs),
+    }
+    mempool::restore(pool);
+    rewards::restore(paid.unwrap_or_default());
+    canister_auth::audit::restore(audit.unwrap_or_default());
+    if let Some((owner, admins)) = auth {
+        canister_auth::restore(owner, admins);
+    }
+    state
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved_state()) {
+        ic_cdk::trap(&format!("failed to save chain state: {}", e));
+    }
+}
+
+/// Upgrades keep the stored chain. Genesis args are only used if nothing was
+/// saved, and are rejected if they name a different genesis. Their admins
+/// are added to the saved set.
+#[post_upgrade]
+fn post_upgrade(args: Option<GenesisArgs>) {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => Ok(saved),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<Saved>(),
+        Err(e) => ic_cdk::trap(&format!("failed to restore chain state: {}", e)),
+    };
+    let saved = match restored {
+        Ok(saved) => restore_state(saved),
+        Err(e) => {
+            ic_cdk::println!("No saved chain state restored: {}", e);
+            None
+        }
+    };
+
+    let extra_admins = args.as_ref().and_then(|a| a.admins.clone()).unwrap_or_default();
+    let state = match (saved, args) {
+        (Some(st), Some(args)) if st.genesis_hash != args.genesis_hash => {
+            ic_cdk::trap("chain already initialized with a different genesis")
+        }
+        (Some(st), _) => st,
+        (None, Some(args)) => genesis_state(args),
+        (None, None) => ic_cdk::trap("no saved chain state; genesis args required"),
+    };
+
+    if canister_auth::owner().is_none() {
+        canister_auth::init(state.owner, Vec::new());
+    }
+    extra_admins.into_iter().for_each(canister_auth::add_admin);
+
+    STATE.with(|s| *s.borrow_mut() = Some(state));
+}
+
+#[query]
+pub fn get_owner() -> Principal {
+    canister_auth::owner().expect("chain not initialized")
+}
+
+// ------------------------------------------------------------
+// Owner / admins (admins may call every owner-gated update)
+// ------------------------------------------------------------
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+/// Hand the chain to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
     STATE.with(|s| {
-        *s.borrow_mut() = Some(State {
-            tip,
-            validator,
-        });
+        s.borrow_mut().as_mut().expect("chain not initialized").owner = new_owner;
     });
 }
 
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// `(page, total_len)` of a versioned snapshot of the chain, mempool,
+/// subscriptions and admins; offset 0 takes the snapshot, then page until
+/// offset reaches total_len (admin only)
+#[update]
+pub fn export_state(offset: u64, limit: u64) -> (Vec<u8>, u64) {
+    canister_auth::require_admin();
+    if offset == 0 {
+        canister_auth::audit::record("export_state", String::new());
+    }
+    canister_state::transfer::export_page(SCHEMA_VERSION, saved_state, offset, limit)
+}
+
+/// Replace all state, chain included, with an export_state snapshot sent in
+/// order with `last` set on the final chunk. The caller stays an admin
+/// (admin only).
+#[update]
+pub fn import_state(chunk: Vec<u8>, last: bool) -> Result<(), String> {
+    canister_auth::require_admin();
+    let admin = ic_cdk::caller();
+    let imported = canister_state::transfer::import_chunk::<Saved>(chunk, last, SCHEMA_VERSION, MIGRATIONS)
+    .map_err(|e| e.to_string())?;
+    if let Some(saved) = imported {
+        if saved.0.is_none() {
+            return Err("snapshot holds no chain".to_string());
+        }
+        let state = restore_state(saved);
+        STATE.with(|s| *s.borrow_mut() = state);
+        canister_auth::add_admin(admin);
+        canister_auth::audit::record("import_state", String::new());
+    }
+    Ok(())
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
 // ------------------------------------------------------------
 // Read API (used by coordinator / monitoring)
 // ------------------------------------------------------------
@@ -97,14 +471,97 @@ pub fn get_height() -> u64 {
     })
 }
 
+/// The tip with the validator's verdict on its block
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ValidatedTip {
+    pub tip: ChainTip,
+    pub validation: ValidationResult,
+    pub validator: Principal,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ValidatedBlock {
+    pub header: BlockHeader,
+    pub validation: ValidationResult,
+}
+
+/// The validator canister to ask: the one the retarget config names, else
+/// the registered submitter (which submit_block also treats as one)
+fn validator_canister(st: &State) -> Principal {
+    st.retarget
+    .as_ref()
+    .map(|r| r.validator_canister)
+    .unwrap_or(st.validator)
+}
+
+/// A main-chain header links to `parent` (when that is still held here) and,
+/// per the validator's `check_difficulty_level`, its hash meets its
+/// difficulty. Genesis always passes.
+async fn validate_header(validator: Principal, header: &BlockHeader, parent: Option<String>) -> ValidationResult {
+    let verdict = |reason: Option<String>| ValidationResult { valid: reason.is_none(), reason };
+    if header.height == 0 {
+        return verdict(None);
+    }
+    if parent.is_some_and(|p| p != header.prev_hash) {
+        return verdict(Some(format!("prev_hash doesn't match the block at height {}", header.height - 1)));
+    }
+
+    let args = (header.block_hash.clone(), header.difficulty);
+    match call::<(String, u32), (bool,)>(validator, "check_difficulty_level", args).await {
+        Ok((true,)) => verdict(None),
+        Ok((false,)) => verdict(Some(format!("hash does not meet difficulty {}", header.difficulty))),
+        Err((code, msg)) => verdict(Some(format!("check_difficulty_level failed: {:?} {}", code, msg))),
+    }
+}
+
+/// Main-chain hash at `height - 1`, if held here
+fn parent_hash(st: &State, height: u64) -> Option<String> {
+    st.main_hash_at(height.checked_sub(1)?).cloned()
+}
+
+/// The tip and the validator's verdict on it in one read, instead of a
+/// get_tip followed by a call to the validator. The validator must be on
+/// this subnet.
+#[query(composite = true)]
+pub async fn get_tip_with_validation() -> ValidatedTip {
+    let (tip, header, parent, validator) = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref().expect("chain not initialized");
+        let header = st.header_at(st.tip.height).cloned().expect("tip header missing");
+        (st.tip.clone(), header, parent_hash(st, st.tip.height), validator_canister(st))
+    });
+
+    let validation = validate_header(validator, &header, parent).await;
+    ValidatedTip { tip, validation, validator }
+}
+
+/// get_block plus the validator's verdict; None if there's no such block
+#[query(composite = true)]
+pub async fn get_block_with_validation(height: u64) -> Option<ValidatedBlock> {
+    let header = get_block(height).await?;
+    let (parent, validator) = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref().expect("chain not initialized");
+        (parent_hash(st, height), validator_canister(st))
+    });
+
+    let validation = validate_header(validator, &header, parent).await;
+    Some(ValidatedBlock { header, validation })
+}
+
 // ------------------------------------------------------------
 // Write API (validator only)
 // ------------------------------------------------------------
 
+/// Accept a block on top of `prev_hash` (the tip if omitted). A block on a
+/// side branch becomes the tip once its branch has more cumulative work.
+/// `miner` is rewarded once the block is on the main chain.
 #[update]
 pub fn submit_valid_block(
     new_block_hash: String,
     new_difficulty: Option<u32>,
+    prev_hash: Option<String>,
+    miner: Option<Principal>,
 ) {
     let caller = caller();
 
@@ -115,16 +572,667 @@ pub fn submit_valid_block(
         if caller != st.validator {
             ic_cdk::trap("only validator can submit blocks");
         }
+        if st.tip.paused {
+            ic_cdk::trap("chain is paused");
+        }
+
+        accept_block(st, new_block_hash, new_difficulty, prev_hash, miner);
+    });
+
+    archive::maybe_archive();
+}
+
+/// Store a block under its parent and move the tip if its branch is now the
+/// heaviest
+fn accept_block(
+    st: &mut State,
+    new_block_hash: String,
+    new_difficulty: Option<u32>,
+    prev_hash: Option<String>,
+    miner: Option<Principal>,
+) {
+    if st.blocks.contains_key(&new_block_hash) {
+        ic_cdk::trap("block already known");
+    }
+
+    let prev_hash = prev_hash.unwrap_or_else(|| st.tip.block_hash.clone());
+    let parent = match st.blocks.get(&prev_hash) {
+        Some(p) => p,
+        None => ic_cdk::trap("unknown parent block"),
+    };
 
-        st.tip.height += 1;
-        st.tip.block_hash = new_block_hash;
+    let now = ic_cdk::api::time();
+    // The block was mined at the difficulty its parent set
+    let difficulty = parent.next_difficulty;
+    let header = BlockHeader {
+        height: parent.header.height + 1,
+        block_hash: new_block_hash.clone(),
+        prev_hash: prev_hash.clone(),
+        difficulty,
+        timestamp_ns: now,
+        cumulative_work: parent.header.cumulative_work.saturating_add(block_work(difficulty)),
+        miner,
+    };
+    let work = header.cumulative_work;
+
+    st.blocks.insert(new_block_hash.clone(), StoredBlock {
+        header,
+        next_difficulty: new_difficulty.unwrap_or(difficulty),
+    });
+
+    if work <= st.tip_work() {
+        ic_cdk::println!("Stored side-branch block {}", new_block_hash);
+        return;
+    }
+
+    let old_tip = st.tip.block_hash.clone();
+    let (fork_height, rolled_back) = st.switch_tip(&new_block_hash);
 
-        if let Some(d) = new_difficulty {
-            st.tip.difficulty = d;
+    // Queue a reward for every block that just joined the main chain, which
+    // after a reorg can be more than the new tip. Only final blocks are paid;
+    // rolled-back ones that were still immature are cancelled here.
+    for height in fork_height + 1..=st.tip.height {
+        if let Some(h) = st.header_at(height)
+            && let Some(miner) = h.miner
+        {
+            rewards::reward_block(&h.block_hash, height, miner);
         }
+    }
+    st.settle_rewards();
 
-        st.tip.last_update_ns = ic_cdk::api::time();
+    // An explicit difficulty from the caller wins over the schedule
+    let due = st
+    .retarget
+    .clone()
+    .filter(|c| new_difficulty.is_none() && st.tip.height.is_multiple_of(c.interval_blocks));
+    if let Some(cfg) = due {
+        let times = st.recent_block_times(cfg.interval_blocks);
+        ic_cdk::spawn(retarget(cfg, new_block_hash.clone(), st.tip.difficulty, times));
+    }
+
+    subscriptions::publish_new_block(st.tip.height, &st.tip.block_hash, st.tip.difficulty);
+
+    if !rolled_back.is_empty() {
+        ic_cdk::println!(
+            "🔀 Reorg at height {}: {} -> {} ({} blocks rolled back)",
+            fork_height, old_tip, new_block_hash, rolled_back.len()
+        );
+        st.reorgs.push(ReorgEvent {
+            timestamp_ns: now,
+            fork_height,
+            old_tip,
+            new_tip: new_block_hash,
+            rolled_back,
+        });
+    }
+}
+
+// ------------------------------------------------------------
+// Open submission (validated against the current tip)
+// ------------------------------------------------------------
+
+/// Mirrors the validator's `Block`
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Block {
+    pub height: u64,
+    pub prev_hash: String,
+    pub block_data: String,
+    pub nonce: u64,
+    pub difficulty: u32,
+    pub hash: String,
+    pub timestamp: u64,
+    pub miner: Option<Principal>,
+    /// Cycles bid by the block's payloads, as committed in block_data
+    pub total_fee: Option<u64>,
+    /// Checked by the validator against its emission schedule
+    pub coinbase: Option<u64>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+/// Anyone may submit a block; the validator canister checks its PoW and it
+/// must extend the current tip at the current difficulty
+#[update]
+pub async fn submit_block(block: Block) -> ChainTip {
+    let validator = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref().expect("chain not initialized");
+        check_extends_tip(st, &block);
+        st.validator
+    });
+    if let Some(Err(e)) = block.total_fee.map(|fee| mempool::check_fee(&block.block_data, fee)) {
+        ic_cdk::trap(&e);
+    }
+
+    let result = call::<(Block,), (ValidationResult,)>(validator, "verify_block", (block.clone(),)).await;
+    match result {
+        Ok((v,)) if v.valid => {}
+        Ok((v,)) => ic_cdk::trap(&format!(
+            "rejected by validator: {}",
+            v.reason.unwrap_or_default()
+        )),
+        Err((code, msg)) => ic_cdk::trap(&format!("verify_block failed: {:?} {}", code, msg)),
+    }
+
+    let tip = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let st = st.as_mut().expect("chain not initialized");
+        // The tip may have moved while we waited on the validator
+        check_extends_tip(st, &block);
+
+        accept_block(st, block.hash, None, Some(block.prev_hash), block.miner);
+        mempool::remove_included(&block.block_data);
+        st.tip.clone()
     });
+
+    archive::maybe_archive();
+    tip
+}
+
+fn check_extends_tip(st: &State, block: &Block) {
+    if st.tip.paused {
+        ic_cdk::trap("chain is paused");
+    }
+    if block.prev_hash != st.tip.block_hash {
+        ic_cdk::trap("prev_hash is not the current tip");
+    }
+    if block.height != st.tip.height + 1 {
+        ic_cdk::trap(&format!("expected height {}, got {}", st.tip.height + 1, block.height));
+    }
+    if block.difficulty != st.tip.difficulty {
+        ic_cdk::trap(&format!("expected difficulty {}, got {}", st.tip.difficulty, block.difficulty));
+    }
+}
+
+// ------------------------------------------------------------
+// Mempool and block templates
+// ------------------------------------------------------------
+
+/// What to mine next: `block_data` commits to the height, parent and the
+/// selected payloads, which leave the mempool once `submit_block` accepts it
+#[derive(Clone, CandidType, Deserialize)]
+pub struct BlockTemplate {
+    pub height: u64,
+    pub prev_hash: String,
+    pub difficulty: u32,
+    pub payload_ids: Vec<u64>,
+    pub block_data: String,
+    /// Sum of the selected payloads' fees; submit it as the block's total_fee
+    pub total_fee: u64,
+}
+
+/// Queue data for inclusion in a future block; returns its id. Cycles
+/// attached to the call are accepted as its fee bid. A full mempool evicts
+/// its lowest bid per byte for a higher one; the bids of evicted payloads
+/// and of those not mined within a day can be claimed back.
+#[update]
+pub fn submit_payload(data: String) -> u64 {
+    let fee = ic_cdk::api::call::msg_cycles_accept(u64::MAX);
+    match mempool::submit(data, caller(), fee) {
+        Ok(id) => id,
+        Err(e) => ic_cdk::trap(&e),
+    }
+}
+
+#[query]
+pub fn get_pending_payloads() -> Vec<Payload> {
+    mempool::pending()
+}
+
+/// Cycles owed to `submitter` for evicted or expired payloads
+#[query]
+pub fn get_payload_refund(submitter: Principal) -> u64 {
+    mempool::refund_owed(submitter)
+}
+
+/// Deposit the bids owed to the calling canister for evicted or expired
+/// payloads back into it; returns the cycles sent
+#[update]
+pub async fn claim_payload_refund() -> Result<u64, String> {
+    let submitter = caller();
+    let amount = mempool::take_refund(submitter);
+    if amount == 0 {
+        return Err("no payload refund owed".to_string());
+    }
+
+    match deposit_cycles(CanisterIdRecord { canister_id: submitter }, amount as u128).await {
+        Ok(()) => Ok(amount),
+        Err((code, msg)) => {
+            mempool::restore_refund(submitter, amount);
+            Err(format!("refund of {} cycles failed: {:?} {}", amount, code, msg))
+        }
+    }
+}
+
+#[query]
+pub fn build_block_template() -> BlockTemplate {
+    let tip = get_tip();
+    let payloads = mempool::select();
+    let height = tip.height + 1;
+
+    BlockTemplate {
+        height,
+        block_data: mempool::encode_block_data(height, &tip.block_hash, &payloads),
+        prev_hash: tip.block_hash,
+        difficulty: tip.difficulty,
+        payload_ids: payloads.iter().map(|p| p.id).collect(),
+        total_fee: mempool::total_fee(&payloads),
+    }
+}
+
+// ------------------------------------------------------------
+// Automatic retargeting
+// ------------------------------------------------------------
+
+async fn retarget(cfg: RetargetConfig, at_hash: String, current: u32, times: Vec<u64>) {
+    let result = call::<(u32, u64, Vec<u64>), (u32,)>(
+        cfg.validator_canister,
+        "calculate_difficulty_adjustment",
+        (current, cfg.target_block_time_secs, times),
+    )
+    .await;
+
+    let new_difficulty = match result {
+        Ok((d,)) => d,
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ Retarget at {} failed: {:?} {}", at_hash, code, msg);
+            return;
+        }
+    };
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let Some(st) = st.as_mut() else { return };
+
+        if let Some(block) = st.blocks.get_mut(&at_hash) {
+            block.next_difficulty = new_difficulty;
+        }
+        // Only the live tip's difficulty is what miners see next
+        if st.tip.block_hash == at_hash {
+            st.tip.difficulty = new_difficulty;
+        }
+    });
+
+    ic_cdk::println!("🎯 Retarget at {}: difficulty {} -> {}", at_hash, current, new_difficulty);
+}
+
+/// None turns automatic retargeting off (validator or admin)
+#[update]
+pub fn set_retarget_config(config: Option<RetargetConfig>) {
+    let caller = caller();
+
+    if matches!(&config, Some(c) if c.interval_blocks == 0) {
+        ic_cdk::trap("interval_blocks must be at least 1");
+    }
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let st = st.as_mut().expect("chain not initialized");
+
+        if caller != st.validator && !canister_auth::is_admin(&caller) {
+            ic_cdk::trap("only validator or an admin can change retargeting");
+        }
+        canister_auth::audit::record("set_retarget_config", format!("{:?}", config));
+
+        st.retarget = config;
+    });
+}
+
+#[query]
+pub fn get_retarget_config() -> Option<RetargetConfig> {
+    STATE.with(|s| s.borrow().as_ref().and_then(|st| st.retarget.clone()))
+}
+
+// ------------------------------------------------------------
+// New-block notifications
+// ------------------------------------------------------------
+
+/// Have `canister` notified with (height, block_hash, difficulty) whenever
+/// the tip moves; `method` defaults to `on_new_block`. A canister may
+/// subscribe itself; anyone else must be the validator or an admin.
+#[update]
+pub fn subscribe(canister: Principal, method: Option<String>) -> bool {
+    require_self_or_validator(canister);
+
+    let method = method.unwrap_or_else(|| subscriptions::DEFAULT_METHOD.to_string());
+    subscriptions::subscribe(canister, method)
+}
+
+#[update]
+pub fn unsubscribe(canister: Principal) -> bool {
+    require_self_or_validator(canister);
+
+    subscriptions::unsubscribe(canister)
+}
+
+#[query]
+pub fn get_subscriptions() -> Vec<Subscription> {
+    subscriptions::list()
+}
+
+/// Retries with backoff for notifications subscribers fail to take
+/// (admin only)
+#[update]
+pub fn set_notify_policy(policy: RetryPolicy) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_notify_policy", format!("{:?}", policy));
+    if policy.max_attempts == 0 {
+        ic_cdk::trap("max_attempts must be at least 1");
+    }
+
+    canister_notify::set_policy(policy);
+}
+
+#[query]
+pub fn get_notify_policy() -> RetryPolicy {
+    canister_notify::policy()
+}
+
+/// Notifications that ran out of retries, oldest first
+#[query]
+pub fn get_dead_letters() -> Vec<DeadLetter> {
+    canister_notify::dead_letters()
+}
+
+/// Resend every dead letter; returns how many (admin only)
+#[update]
+pub fn retry_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_dead_letters", String::new());
+    canister_notify::retry_dead_letters()
+}
+
+/// Returns how many were dropped (admin only)
+#[update]
+pub fn clear_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("clear_dead_letters", String::new());
+    canister_notify::clear_dead_letters()
+}
+
+fn require_self_or_validator(canister: Principal) {
+    let caller = caller();
+    if caller != canister && caller != get_validator() && !canister_auth::is_admin(&caller) {
+        ic_cdk::trap("only the canister itself, the validator or an admin can change its subscription");
+    }
+}
+
+// ------------------------------------------------------------
+// Block history (light clients / explorer)
+// ------------------------------------------------------------
+
+/// Main-chain header at `height`; archived heights are fetched from the
+/// archive canister
+#[query(composite = true)]
+pub async fn get_block(height: u64) -> Option<BlockHeader> {
+    let (local, archive) = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref()?;
+        Some((st.header_at(height).cloned(), st.archive.as_ref().map(|a| a.canister)))
+    })?;
+
+    match (local, archive) {
+        (Some(h), _) => Some(h),
+        (None, Some(canister)) if height < archive::first_local_height() => {
+            archive::fetch_block(canister, height).await
+        }
+        _ => None,
+    }
+}
+
+/// Main-chain headers for heights `from..=to`, at most 1000 per call,
+/// stitched from the archive and local storage
+#[query(composite = true)]
+pub async fn get_blocks(from: u64, to: u64) -> Vec<BlockHeader> {
+    if to < from {
+        return Vec::new();
+    }
+    let to = to.min(from.saturating_add(MAX_BLOCKS_PER_QUERY - 1));
+
+    let first_local = archive::first_local_height();
+    let mut out = Vec::new();
+
+    if from < first_local && let Some(canister) = archive::canister() {
+        out = archive::fetch_blocks(canister, from, to.min(first_local - 1)).await;
+    }
+
+    STATE.with(|s| {
+        if let Some(st) = s.borrow().as_ref() {
+            out.extend((from.max(first_local)..=to).map_while(|h| st.header_at(h).cloned()));
+        }
+    });
+    out
+}
+
+/// Any known block, including ones on side branches and archived ones
+#[query(composite = true)]
+pub async fn get_block_by_hash(block_hash: String) -> Option<BlockHeader> {
+    let local = STATE.with(|s| {
+        s.borrow()
+        .as_ref()
+        .and_then(|st| st.blocks.get(&block_hash).map(|b| b.header.clone()))
+    });
+    if local.is_some() {
+        return local;
+    }
+
+    match archive::canister() {
+        Some(canister) => archive::fetch_block_by_hash(canister, block_hash).await,
+        None => None,
+    }
+}
+
+#[query]
+pub fn get_reorgs() -> Vec<ReorgEvent> {
+    STATE.with(|s| {
+        s.borrow()
+        .as_ref()
+        .map(|st| st.reorgs.clone())
+        .unwrap_or_default()
+    })
+}
+
+/// Locally held main-chain blocks matching `filter`, newest first. Start
+/// with no cursor and pass back `next_cursor` until it is None; a page may
+/// come back short (even empty) when the scan limit is reached first.
+#[query]
+pub fn search_blocks(filter: BlockFilter, cursor: Option<u64>, limit: Option<u64>) -> BlockPage {
+    STATE.with(|s| {
+        let st = s.borrow();
+        search::search(st.as_ref().expect("chain not initialized"), &filter, cursor, limit)
+    })
+}
+
+/// The latest `n` main-chain headers (at most 1000), newest first
+#[query]
+pub fn recent_blocks(n: u64) -> Vec<BlockHeader> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        search::recent(st.as_ref().expect("chain not initialized"), n.min(MAX_BLOCKS_PER_QUERY))
+    })
+}
+
+/// JSON explorer: GET /tip, /stats, /block/{height} or /block/{hash}
+#[query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    http::handle(req)
+}
+
+// ------------------------------------------------------------
+// Confirmations / finality
+// ------------------------------------------------------------
+
+/// Blocks on top of and including `block_hash` on the main chain; 0 for a
+/// side-branch block, None if the block is unknown
+#[query(composite = true)]
+pub async fn get_confirmations(block_hash: String) -> Option<u64> {
+    let local = STATE.with(|s| {
+        let st = s.borrow();
+        let st = st.as_ref()?;
+        let header = &st.blocks.get(&block_hash)?.header;
+        let on_main = st.main_hash_at(header.height) == Some(&block_hash);
+        Some(if on_main { st.confirmations(header.height) } else { 0 })
+    });
+    if local.is_some() {
+        return local;
+    }
+
+    // Only main-chain blocks are archived
+    let archived = get_block_by_hash(block_hash).await?;
+    Some(STATE.with(|s| s.borrow().as_ref().map(|st| st.confirmations(archived.height)).unwrap_or(0)))
+}
+
+/// True once the main-chain block at `height` has at least finality_depth
+/// confirmations; archived blocks are always final
+#[query]
+pub fn is_final(height: u64) -> bool {
+    STATE.with(|s| s.borrow().as_ref().is_some_and(|st| st.is_final(height)))
+}
+
+#[update]
+pub fn set_finality_depth(depth: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_finality_depth", format!("{:?}", depth));
+    if depth == 0 {
+        ic_cdk::trap("finality depth must be at least 1");
+    }
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let st = st.as_mut().expect("chain not initialized");
+        st.finality_depth = depth;
+        st.settle_rewards();
+    });
+}
+
+#[query]
+pub fn get_finality_depth() -> u64 {
+    STATE.with(|s| {
+        s.borrow()
+        .as_ref()
+        .expect("chain not initialized")
+        .finality_depth
+    })
+}
+
+// ------------------------------------------------------------
+// Archiving (admin only)
+// ------------------------------------------------------------
+
+/// Once more than `max_local_blocks` main-chain headers are held here, move
+/// the oldest `batch_size` to the archive canister. Blocks that deep can no
+/// longer be reorged, since their side branches are dropped with them.
+#[update]
+pub fn set_archive_config(config: Option<ArchiveConfig>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_archive_config", format!("{:?}", config));
+
+    if matches!(&config, Some(c) if c.batch_size == 0 || c.batch_size >= c.max_local_blocks) {
+        ic_cdk::trap("batch_size must be between 1 and max_local_blocks - 1");
+    }
+
+    STATE.with(|s| {
+        s.borrow_mut().as_mut().expect("chain not initialized").archive = config;
+    });
+}
+
+#[query]
+pub fn get_archive_config() -> Option<ArchiveConfig> {
+    archive::config()
+}
+
+/// Lowest height still stored locally
+#[query]
+pub fn get_first_local_height() -> u64 {
+    archive::first_local_height()
+}
+
+// ------------------------------------------------------------
+// Block rewards (ICRC-1)
+// ------------------------------------------------------------
+
+/// Pay `amount`, or the schedule's reward at the block's height, from this
+/// canister's ledger account to the miner of every block that joins the
+/// main chain, once it is final (None stops rewarding; queued rewards are
+/// still sent). Admin only.
+#[update]
+pub fn set_reward_config(config: Option<RewardConfig>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_reward_config", format!("{:?}", config));
+    match config.as_ref().and_then(|c| c.schedule.as_ref()) {
+        Some(s) => {
+            if let Err(e) = s.validate() {
+                ic_cdk::trap(&e);
+            }
+        }
+        None if matches!(&config, Some(c) if c.amount == 0u32) => ic_cdk::trap("reward amount must be positive"),
+        None => {}
+    }
+    rewards::set_config(config);
+}
+
+#[query]
+pub fn get_reward_config() -> Option<RewardConfig> {
+    rewards::config()
+}
+
+#[query]
+pub fn get_block_reward(block_hash: String) -> Option<RewardPayout> {
+    rewards::payout(&block_hash)
+}
+
+/// Every queued reward, lowest height first, optionally for one miner
+#[query]
+pub fn get_rewards(miner: Option<Principal>) -> Vec<RewardPayout> {
+    rewards::payouts(miner)
+}
+
+/// Resend every reward the ledger hasn't confirmed; the ledger drops any
+/// that already went through. Returns how many were resent. Admin only.
+#[update]
+pub fn retry_rewards() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_rewards", String::new());
+    rewards::retry_unpaid()
+}
+
+/// Settle an unreconciled reward after looking its memo up on the ledger:
+/// `paid_in` is the ledger block that paid it, or None if none did, which
+/// sends it again under a fresh created_at_time. Admin only.
+#[update]
+pub fn reconcile_reward(block_hash: String, paid_in: Option<Nat>) -> RewardStatus {
+    canister_auth::require_admin();
+    canister_auth::audit::record("reconcile_reward", format!("{:?}", (&block_hash, &paid_in)));
+    rewards::reconcile(&block_hash, paid_in, ic_cdk::api::time()).unwrap_or_else(|e| ic_cdk::trap(&e))
+}
+
+// ------------------------------------------------------------
+// Emergency pause (admin only)
+// ------------------------------------------------------------
+
+/// Circuit breaker: reject every block submission until resumed
+#[update]
+pub fn pause_chain() {
+    set_paused(true);
+}
+
+#[update]
+pub fn resume_chain() {
+    set_paused(false);
+}
+
+fn set_paused(paused: bool) {
+    canister_auth::require_admin();
+    canister_auth::audit::record(if paused { "pause_chain" } else { "resume_chain" }, format!("{:?}", paused));
+
+    STATE.with(|s| {
+        s.borrow_mut().as_mut().expect("chain not initialized").tip.paused = paused;
+    });
+    ic_cdk::println!("{} chain", if paused { "⏸️ Paused" } else { "▶️ Resumed" });
 }
 
 // ------------------------------------------------------------
@@ -139,9 +1247,10 @@ pub fn set_validator(new_validator: Principal) {
         let mut st = s.borrow_mut();
         let st = st.as_mut().expect("chain not initialized");
 
-        if caller != st.validator {
-            ic_cdk::trap("only current validator can change validator");
+        if caller != st.validator && !canister_auth::is_admin(&caller) {
+            ic_cdk::trap("only current validator or an admin can change validator");
         }
+        canister_auth::audit::record("set_validator", format!("{:?}", new_validator));
 
         st.validator = new_validator;
     });
@@ -160,3 +1269,17 @@ pub fn get_validator() -> Principal {
         .validator
     })
 }
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
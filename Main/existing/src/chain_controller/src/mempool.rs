@@ -0,0 +1,381 @@
+// mempool.rs - payloads waiting to be included in a block. Each payload may
+// bid a fee in cycles; templates take the best-paying bytes first and commit
+// to the total fee in block_data, so the validator can check it. A full pool
+// evicts its lowest bid per byte for a better one. Bids of evicted or
+// expired payloads are owed back to their submitters.
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use sha2::{Digest, Sha256};
+
+/// Payloads larger than this are refused
+pub const MAX_PAYLOAD_BYTES: usize = 4 * 1024;
+const MAX_PENDING: usize = 10_000;
+/// Payloads not mined within this long expire
+pub const PAYLOAD_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// Payloads per block template
+const MAX_PAYLOADS_PER_BLOCK: usize = 100;
+/// Payload bytes per block template
+pub const MAX_BLOCK_PAYLOAD_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Payload {
+    pub id: u64,
+    pub data: String,
+    pub submitted_by: Principal,
+    pub submitted_at: u64,
+    /// Cycles bid for inclusion; None for payloads queued before fees
+    pub fee: Option<u64>,
+}
+
+impl Payload {
+    fn fee(&self) -> u64 {
+        self.fee.unwrap_or(0)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.submitted_at) >= PAYLOAD_TTL_NS
+    }
+}
+
+/// Better-paying first: a.fee / a.len against b.fee / b.len without
+/// dividing (an empty payload counts as one byte), then oldest first
+fn by_density(a: &Payload, b: &Payload) -> Ordering {
+    let lhs = a.fee() as u128 * b.data.len().max(1) as u128;
+    let rhs = b.fee() as u128 * a.data.len().max(1) as u128;
+    rhs.cmp(&lhs).then(a.id.cmp(&b.id))
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Mempool {
+    pending: Vec<Payload>,
+    next_id: u64,
+    /// Cycles owed back to submitters of evicted or expired payloads; None
+    /// in pools saved before refunds
+    refunds: Option<BTreeMap<Principal, u64>>,
+}
+
+impl Mempool {
+    fn owe(&mut self, p: &Payload) {
+        if p.fee() > 0 {
+            let owed = self.refunds.get_or_insert_with(BTreeMap::new).entry(p.submitted_by).or_default();
+            *owed = owed.saturating_add(p.fee());
+        }
+    }
+
+    /// Drop expired payloads, owing their bids back
+    fn expire(&mut self, now: u64) {
+        let (expired, live): (Vec<Payload>, Vec<Payload>) =
+            std::mem::take(&mut self.pending).into_iter().partition(|p| p.is_expired(now));
+        self.pending = live;
+        for p in &expired {
+            self.owe(p);
+        }
+    }
+
+    /// Queue a payload; a full pool evicts its lowest bid per byte if the
+    /// new payload bids more
+    fn admit(&mut self, payload: Payload) -> Result<(), String> {
+        if self.pending.len() >= MAX_PENDING {
+            let (lowest, _) = self
+            .pending
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| by_density(a, b))
+            .ok_or("mempool is full")?;
+            if by_density(&payload, &self.pending[lowest]) != Ordering::Less {
+                return Err("mempool is full; bid more cycles per byte than the lowest pending payload".to_string());
+            }
+            let evicted = self.pending.remove(lowest);
+            self.owe(&evicted);
+        }
+        self.pending.push(payload);
+        Ok(())
+    }
+}
+
+thread_local! {
+    static MEMPOOL: RefCell<Mempool> = RefCell::new(Mempool::default());
+}
+
+pub fn submit(data: String, submitted_by: Principal, fee: u64) -> Result<u64, String> {
+    if data.len() > MAX_PAYLOAD_BYTES {
+        return Err(format!("payload exceeds {} bytes", MAX_PAYLOAD_BYTES));
+    }
+
+    let now = ic_cdk::api::time();
+    MEMPOOL.with(|m| {
+        let mut m = m.borrow_mut();
+        m.expire(now);
+
+        let id = m.next_id;
+        m.admit(Payload {
+            id,
+            data,
+            submitted_by,
+            submitted_at: now,
+            fee: Some(fee),
+        })?;
+        m.next_id += 1;
+        Ok(id)
+    })
+}
+
+/// Payloads that haven't expired yet
+pub fn pending() -> Vec<Payload> {
+    let now = ic_cdk::api::time();
+    MEMPOOL.with(|m| m.borrow().pending.iter().filter(|p| !p.is_expired(now)).cloned().collect())
+}
+
+/// Take what `owner` is owed for evicted or expired payloads
+pub fn take_refund(owner: Principal) -> u64 {
+    MEMPOOL.with(|m| {
+        m.borrow_mut()
+        .refunds
+        .as_mut()
+        .and_then(|r| r.remove(&owner))
+        .unwrap_or(0)
+    })
+}
+
+/// Owe `amount` back to `owner` again after a refund failed
+pub fn restore_refund(owner: Principal, amount: u64) {
+    MEMPOOL.with(|m| {
+        let mut m = m.borrow_mut();
+        let owed = m.refunds.get_or_insert_with(BTreeMap::new).entry(owner).or_default();
+        *owed = owed.saturating_add(amount);
+    });
+}
+
+pub fn refund_owed(owner: Principal) -> u64 {
+    MEMPOOL.with(|m| m.borrow().refunds.as_ref().and_then(|r| r.get(&owner).copied()).unwrap_or(0))
+}
+
+/// The payloads the next template will carry
+pub fn select() -> Vec<Payload> {
+    select_from(pending())
+}
+
+/// Highest fee per byte first (oldest first among equals), skipping any that
+/// would take the block past MAX_BLOCK_PAYLOAD_BYTES. They keep that order
+/// in the block.
+fn select_from(mut by_density_order: Vec<Payload>) -> Vec<Payload> {
+    by_density_order.sort_by(by_density);
+
+    let mut bytes = 0;
+    let mut selected = Vec::new();
+    for p in by_density_order {
+        if selected.len() == MAX_PAYLOADS_PER_BLOCK {
+            break;
+        }
+        if bytes + p.data.len() <= MAX_BLOCK_PAYLOAD_BYTES {
+            bytes += p.data.len();
+            selected.push(p);
+        }
+    }
+    selected
+}
+
+pub fn total_fee(payloads: &[Payload]) -> u64 {
+    payloads.iter().map(Payload::fee).fold(0, u64::saturating_add)
+}
+
+/// SHA-256 over the SHA-256 of each payload, in order
+pub fn payload_root(payloads: &[Payload]) -> String {
+    let mut h = Sha256::new();
+    for p in payloads {
+        h.update(Sha256::digest(p.data.as_bytes()));
+    }
+    hex::encode(h.finalize())
+}
+
+/// `height:prev_hash:id,id,...:total_fee:payload_root`
+pub fn encode_block_data(height: u64, prev_hash: &str, payloads: &[Payload]) -> String {
+    let ids: Vec<String> = payloads.iter().map(|p| p.id.to_string()).collect();
+    format!(
+        "{}:{}:{}:{}:{}",
+        height,
+        prev_hash,
+        ids.join(","),
+        total_fee(payloads),
+        payload_root(payloads)
+    )
+}
+
+/// (ids, total_fee, payload_root) of template-shaped block_data
+fn decode_block_data(block_data: &str) -> Option<(Vec<u64>, u64, &str)> {
+    let parts: Vec<&str> = block_data.rsplitn(4, ':').collect();
+    match parts.as_slice() {
+        [root, fee, ids, _] => {
+            let ids = ids.split(',').filter_map(|id| id.parse().ok()).collect();
+            Some((ids, fee.parse().ok()?, *root))
+        }
+        _ => None,
+    }
+}
+
+/// The payloads a block's block_data commits to, if it came from a template
+/// and every one of them is still pending with the same root
+fn included(m: &Mempool, block_data: &str) -> Option<Vec<Payload>> {
+    let (ids, _, root) = decode_block_data(block_data)?;
+    if ids.is_empty() {
+        return None;
+    }
+    let included: Vec<Payload> = ids
+    .iter()
+    .filter_map(|id| m.pending.iter().find(|p| p.id == *id).cloned())
+    .collect();
+
+    (included.len() == ids.len() && payload_root(&included) == root).then_some(included)
+}
+
+/// Check a block's claimed total fee against block_data and, for payloads we
+/// still hold, against the fees they bid
+pub fn check_fee(block_data: &str, claimed: u64) -> Result<(), String> {
+    MEMPOOL.with(|m| check_fee_in(&m.borrow(), block_data, claimed))
+}
+
+fn check_fee_in(m: &Mempool, block_data: &str, claimed: u64) -> Result<(), String> {
+    let committed = match decode_block_data(block_data) {
+        Some((_, fee, _)) => fee,
+        None => return Err("total_fee given but block_data isn't from a template".to_string()),
+    };
+    if committed != claimed {
+        return Err(format!("total_fee {} but block_data commits to {}", claimed, committed));
+    }
+    match included(m, block_data) {
+        Some(payloads) if total_fee(&payloads) != committed => Err(format!(
+            "block_data commits to fee {} but its payloads bid {}",
+            committed,
+            total_fee(&payloads)
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Drop the payloads a mined block carried, if its block_data came from a
+/// template and its root still matches what we hold, and expire stale ones
+pub fn remove_included(block_data: &str) {
+    let now = ic_cdk::api::time();
+    MEMPOOL.with(|m| {
+        let mut m = m.borrow_mut();
+        if let Some(payloads) = included(&m, block_data) {
+            m.pending.retain(|p| !payloads.iter().any(|i| i.id == p.id));
+        }
+        m.expire(now);
+    });
+}
+
+pub fn snapshot() -> Mempool {
+    MEMPOOL.with(|m| m.borrow().clone())
+}
+
+pub fn restore(mempool: Mempool) {
+    MEMPOOL.with(|m| *m.borrow_mut() = mempool);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(id: u64, len: usize, fee: u64) -> Payload {
+        Payload {
+            id,
+            data: "x".repeat(len),
+            submitted_by: Principal::from_slice(&[id as u8]),
+            submitted_at: 0,
+            fee: Some(fee),
+        }
+    }
+
+    fn ids(payloads: &[Payload]) -> Vec<u64> {
+        payloads.iter().map(|p| p.id).collect()
+    }
+
+    #[test]
+    fn selects_by_fee_density_within_the_byte_limit() {
+        let pool = vec![
+            payload(0, 100, 100),
+            payload(1, 100, 500),
+            payload(2, 10, 100),
+            payload(3, MAX_BLOCK_PAYLOAD_BYTES, 1_000_000),
+            payload(4, 100, 500),
+            payload(5, 0, 0),
+        ];
+        // 3 pays the most per byte but leaves no room; ties go to the older id
+        let selected = select_from(pool);
+        assert_eq!(ids(&selected), vec![3, 5]);
+
+        let pool = vec![payload(0, 100, 100), payload(1, 100, 500), payload(2, 10, 100), payload(4, 100, 500)];
+        assert_eq!(ids(&select_from(pool)), vec![2, 1, 4, 0]);
+    }
+
+    #[test]
+    fn caps_payloads_per_block() {
+        let pool: Vec<Payload> = (0..MAX_PAYLOADS_PER_BLOCK as u64 + 5).map(|id| payload(id, 1, 1)).collect();
+        assert_eq!(select_from(pool).len(), MAX_PAYLOADS_PER_BLOCK);
+    }
+
+    #[test]
+    fn block_data_round_trips() {
+        let payloads = vec![payload(7, 10, 3), payload(9, 20, 4)];
+        let block_data = encode_block_data(5, "prev:with:colons", &payloads);
+        let (ids, fee, root) = decode_block_data(&block_data).unwrap();
+        assert_eq!(ids, vec![7, 9]);
+        assert_eq!(fee, 7);
+        assert_eq!(root, payload_root(&payloads));
+
+        let (ids, fee, _) = decode_block_data(&encode_block_data(1, "prev", &[])).unwrap();
+        assert!(ids.is_empty());
+        assert_eq!(fee, 0);
+        assert!(decode_block_data("not a template").is_none());
+    }
+
+    #[test]
+    fn checks_claimed_fee_against_block_data_and_bids() {
+        let payloads = vec![payload(0, 10, 3), payload(1, 20, 4)];
+        let block_data = encode_block_data(1, "prev", &payloads);
+        let m = Mempool { pending: payloads.clone(), next_id: 2, refunds: None };
+        assert!(check_fee_in(&m, &block_data, 7).is_ok());
+        assert!(check_fee_in(&m, &block_data, 8).is_err());
+        assert!(check_fee_in(&m, "plain block data", 0).is_err());
+
+        // block_data that commits to more than its payloads bid
+        let inflated = block_data.replacen(":7:", ":9:", 1);
+        assert!(check_fee_in(&m, &inflated, 9).is_err());
+        // Payloads we no longer hold can't be checked against their bids
+        assert!(check_fee_in(&Mempool::default(), &inflated, 9).is_ok());
+    }
+
+    #[test]
+    fn full_pool_evicts_the_lowest_bid_for_a_higher_one() {
+        let mut m = Mempool::default();
+        for id in 0..MAX_PENDING as u64 {
+            m.admit(payload(id, 100, if id == 42 { 0 } else { 100 })).unwrap();
+        }
+        assert!(m.admit(payload(20_000, 100, 0)).is_err());
+
+        m.admit(payload(20_001, 100, 1)).unwrap();
+        assert_eq!(m.pending.len(), MAX_PENDING);
+        assert!(!m.pending.iter().any(|p| p.id == 42));
+        // The evicted payload bid nothing, so nothing is owed
+        assert!(m.refunds.is_none());
+
+        // Equal density doesn't evict
+        assert!(m.admit(payload(20_002, 100, 1)).is_err());
+        m.admit(payload(20_003, 100, 101)).unwrap();
+        assert_eq!(m.refunds.as_ref().unwrap().get(&Principal::from_slice(&[0x21])), Some(&1));
+    }
+
+    #[test]
+    fn expired_payloads_are_owed_back() {
+        let mut m = Mempool::default();
+        m.admit(payload(0, 10, 5)).unwrap();
+        m.admit(Payload { submitted_at: PAYLOAD_TTL_NS, ..payload(1, 10, 6) }).unwrap();
+        m.expire(PAYLOAD_TTL_NS);
+        assert_eq!(ids(&m.pending), vec![1]);
+        assert_eq!(m.refunds.unwrap().get(&Principal::from_slice(&[0])), Some(&5));
+    }
+}
@@ -0,0 +1,400 @@
+// rewards.rs - pay block finders from an ICRC-1 ledger. A block's reward is
+// queued when it joins the main chain and only sent once the block is final;
+// a block rolled back before that is never paid. Every transfer for a block
+// carries the same memo and created_at_time, so the ledger dedupes retries
+// instead of paying twice. Once that window has passed, a reward nothing
+// could have paid is sent under a fresh created_at_time; one that may have
+// gone through waits for an admin to reconcile it.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::call;
+use pow_core::emission::EmissionSchedule;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RewardConfig {
+    pub ledger: Principal,
+    /// Token units paid per block that joins the main chain, unless
+    /// `schedule` is set
+    pub amount: Nat,
+    /// None lets the ledger charge its default fee
+    pub fee: Option<Nat>,
+    /// This canister's subaccount the rewards are paid from
+    pub from_subaccount: Option<Vec<u8>>,
+    /// Pay each block its height's reward from this schedule instead of
+    /// `amount`; keep it the same as the validator's
+    pub schedule: Option<EmissionSchedule>,
+}
+
+impl RewardConfig {
+    /// What the block at `height` is paid
+    pub fn amount_at(&self, height: u64) -> Nat {
+        match &self.schedule {
+            Some(s) => Nat::from(s.reward_at(height)),
+            None => self.amount.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RewardStatus {
+    /// Waiting for the block to become final; nothing sent yet
+    Immature,
+    /// The block left the main chain before it was final; never sent
+    Orphaned,
+    /// Not yet confirmed by the ledger (includes transfers in flight)
+    Pending,
+    Paid { block_index: Nat },
+    Failed { error: String },
+    /// The dedup window passed after an attempt that may have gone through.
+    /// Nothing is sent until reconcile records what the ledger holds under
+    /// its memo.
+    Unreconciled,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RewardPayout {
+    pub block_hash: String,
+    pub height: u64,
+    pub miner: Principal,
+    pub ledger: Principal,
+    /// Fixed when the block was accepted, whatever the config says later
+    pub amount: Nat,
+    /// Set once the block is final and sent with every attempt; the
+    /// ledger's dedup key along with the memo
+    pub created_at_time: u64,
+    pub status: RewardStatus,
+    /// An attempt ended without a definite answer, so it may have paid.
+    /// None on payouts saved before this was tracked, which counts as maybe.
+    pub maybe_sent: Option<bool>,
+}
+
+impl RewardPayout {
+    fn may_have_paid(&self) -> bool {
+        self.maybe_sent != Some(false)
+    }
+
+    /// Apply the ledger's answer to one attempt
+    fn record(&mut self, attempt: Attempt, now: u64) {
+        // Never let a late failure overwrite a confirmed payment
+        if matches!(self.status, RewardStatus::Paid { .. }) {
+            return;
+        }
+        self.status = match attempt {
+            Attempt::Paid(block_index) => RewardStatus::Paid { block_index },
+            Attempt::Rejected(error) => RewardStatus::Failed { error },
+            // A fresh created_at_time is only safe if no attempt could have
+            // gone through; otherwise the ledger would pay it twice
+            Attempt::TooOld if self.may_have_paid() => RewardStatus::Unreconciled,
+            Attempt::TooOld => {
+                self.created_at_time = now;
+                RewardStatus::Failed { error: "TooOld".to_string() }
+            }
+            Attempt::Unknown(error) => {
+                self.maybe_sent = Some(true);
+                RewardStatus::Failed { error }
+            }
+        };
+    }
+}
+
+/// What one icrc1_transfer call says about the payout
+enum Attempt {
+    Paid(Nat),
+    /// The ledger refused this attempt, so it moved nothing
+    Rejected(String),
+    /// Past the ledger's dedup window; this attempt moved nothing, but the
+    /// ledger can no longer tell whether an earlier one did
+    TooOld,
+    /// The call failed or the ledger was busy; it may have gone through
+    Unknown(String),
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Rewards {
+    config: Option<RewardConfig>,
+    /// Keyed by block hash, so a block is only ever queued once
+    payouts: BTreeMap<String, RewardPayout>,
+}
+
+// ------------------------------------------------------------
+// ICRC-1 ledger interface
+// ------------------------------------------------------------
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+thread_local! {
+    static REWARDS: RefCell<Rewards> = RefCell::new(Rewards::default());
+}
+
+pub fn snapshot() -> Rewards {
+    REWARDS.with(|r| r.borrow().clone())
+}
+
+pub fn restore(rewards: Rewards) {
+    REWARDS.with(|r| *r.borrow_mut() = rewards);
+}
+
+pub fn set_config(config: Option<RewardConfig>) {
+    REWARDS.with(|r| r.borrow_mut().config = config);
+}
+
+pub fn config() -> Option<RewardConfig> {
+    REWARDS.with(|r| r.borrow().config.clone())
+}
+
+pub fn payout(block_hash: &str) -> Option<RewardPayout> {
+    REWARDS.with(|r| r.borrow().payouts.get(block_hash).cloned())
+}
+
+/// Oldest block first, optionally only one miner's
+pub fn payouts(miner: Option<Principal>) -> Vec<RewardPayout> {
+    REWARDS.with(|r| {
+        let mut out: Vec<RewardPayout> = r
+        .borrow()
+        .payouts
+        .values()
+        .filter(|p| miner.is_none_or(|m| p.miner == m))
+        .cloned()
+        .collect();
+        out.sort_by_key(|p| p.height);
+        out
+    })
+}
+
+// ------------------------------------------------------------
+// Paying out
+// ------------------------------------------------------------
+
+/// Queue the reward for a block that just joined the main chain; settle()
+/// sends it once the block is final. An orphaned block that rejoins is
+/// queued again. No-op without a config, if the block was already queued,
+/// or once the schedule pays nothing at its height.
+pub fn reward_block(block_hash: &str, height: u64, miner: Principal) {
+    REWARDS.with(|r| {
+        let mut r = r.borrow_mut();
+        if let Some(p) = r.payouts.get_mut(block_hash) {
+            if matches!(p.status, RewardStatus::Orphaned) {
+                p.status = RewardStatus::Immature;
+            }
+            return;
+        }
+        let Some(cfg) = r.config.clone() else { return };
+        let amount = cfg.amount_at(height);
+        if amount == 0u32 {
+            return;
+        }
+        r.payouts.insert(block_hash.to_string(), RewardPayout {
+            block_hash: block_hash.to_string(),
+            height,
+            miner,
+            ledger: cfg.ledger,
+            amount,
+            created_at_time: 0,
+            status: RewardStatus::Immature,
+            maybe_sent: Some(false),
+        });
+    });
+}
+
+/// Cancel immature rewards whose block has left the main chain and send
+/// those whose block is now final
+pub fn settle(on_main: impl Fn(u64, &str) -> bool, is_final: impl Fn(u64) -> bool) {
+    let due: Vec<String> = REWARDS.with(|r| {
+        let mut due = Vec::new();
+        let now = ic_cdk::api::time();
+        for p in r.borrow_mut().payouts.values_mut() {
+            if !matches!(p.status, RewardStatus::Immature) {
+                continue;
+            }
+            if !on_main(p.height, &p.block_hash) {
+                ic_cdk::println!("Cancelled reward for rolled-back block {}", p.block_hash);
+                p.status = RewardStatus::Orphaned;
+            } else if is_final(p.height) {
+                p.status = RewardStatus::Pending;
+                p.created_at_time = now;
+                p.maybe_sent = Some(false);
+                due.push(p.block_hash.clone());
+            }
+        }
+        due
+    });
+    for hash in due {
+        ic_cdk::spawn(pay(hash));
+    }
+}
+
+/// Resend every sent reward the ledger hasn't confirmed; returns how many
+pub fn retry_unpaid() -> u64 {
+    let unpaid: Vec<String> = REWARDS.with(|r| {
+        r.borrow()
+        .payouts
+        .values()
+        .filter(|p| matches!(p.status, RewardStatus::Pending | RewardStatus::Failed { .. }))
+        .map(|p| p.block_hash.clone())
+        .collect()
+    });
+    for hash in &unpaid {
+        ic_cdk::spawn(pay(hash.clone()));
+    }
+    unpaid.len() as u64
+}
+
+async fn pay(block_hash: String) {
+    let Some((payout, cfg)) = REWARDS.with(|r| {
+        let r = r.borrow();
+        let payout = r.payouts.get(&block_hash)?;
+        // Only rewards of final blocks are ever sent
+        if !matches!(payout.status, RewardStatus::Pending | RewardStatus::Failed { .. }) {
+            return None;
+        }
+        Some((payout.clone(), r.config.clone()))
+    }) else {
+        return;
+    };
+
+    let arg = TransferArg {
+        from_subaccount: cfg.as_ref().and_then(|c| c.from_subaccount.clone()),
+        to: Account { owner: payout.miner, subaccount: None },
+        amount: payout.amount.clone(),
+        fee: cfg.and_then(|c| c.fee),
+        memo: Some(Sha256::digest(block_hash.as_bytes()).to_vec()),
+        created_at_time: Some(payout.created_at_time),
+    };
+    let result = call::<(TransferArg,), (Result<Nat, TransferError>,)>(payout.ledger, "icrc1_transfer", (arg,)).await;
+
+    let attempt = match result {
+        Ok((Ok(block_index),)) => Attempt::Paid(block_index),
+        // An earlier attempt already went through
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => Attempt::Paid(duplicate_of),
+        Ok((Err(TransferError::TemporarilyUnavailable),)) => Attempt::Unknown("ledger temporarily unavailable".to_string()),
+        Ok((Err(TransferError::TooOld),)) => Attempt::TooOld,
+        Ok((Err(e),)) => Attempt::Rejected(format!("{:?}", e)),
+        Err((code, msg)) => Attempt::Unknown(format!("icrc1_transfer: {:?} {}", code, msg)),
+    };
+
+    let now = ic_cdk::api::time();
+    REWARDS.with(|r| {
+        let mut r = r.borrow_mut();
+        let Some(p) = r.payouts.get_mut(&block_hash) else { return };
+        p.record(attempt, now);
+        match &p.status {
+            RewardStatus::Paid { block_index } => {
+                ic_cdk::println!("💰 Rewarded {} for block {} (ledger block {})", p.miner, block_hash, block_index)
+            }
+            RewardStatus::Failed { error } => ic_cdk::println!("❌ Reward for block {} failed: {}", block_hash, error),
+            RewardStatus::Unreconciled => ic_cdk::println!("❌ Reward for block {} needs reconciling", block_hash),
+            _ => {}
+        }
+    });
+}
+
+/// Settle an unreconciled reward from what the ledger holds under its memo:
+/// the block that paid it, or None to send it again under a fresh
+/// created_at_time
+pub fn reconcile(block_hash: &str, paid_in: Option<Nat>, now: u64) -> Result<RewardStatus, String> {
+    let status = REWARDS.with(|r| {
+        let mut r = r.borrow_mut();
+        let p = r.payouts.get_mut(block_hash).ok_or(format!("no reward for block {}", block_hash))?;
+        reconcile_payout(p, paid_in, now)
+    })?;
+    if matches!(status, RewardStatus::Pending) {
+        ic_cdk::spawn(pay(block_hash.to_string()));
+    }
+    Ok(status)
+}
+
+fn reconcile_payout(p: &mut RewardPayout, paid_in: Option<Nat>, now: u64) -> Result<RewardStatus, String> {
+    if !matches!(p.status, RewardStatus::Unreconciled) {
+        return Err(format!("reward for block {} is not unreconciled", p.block_hash));
+    }
+    p.status = match paid_in {
+        Some(block_index) => RewardStatus::Paid { block_index },
+        None => {
+            p.created_at_time = now;
+            p.maybe_sent = Some(false);
+            RewardStatus::Pending
+        }
+    };
+    Ok(p.status.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent() -> RewardPayout {
+        RewardPayout {
+            block_hash: "b".to_string(),
+            height: 1,
+            miner: Principal::anonymous(),
+            ledger: Principal::anonymous(),
+            amount: Nat::from(10u32),
+            created_at_time: 100,
+            status: RewardStatus::Pending,
+            maybe_sent: Some(false),
+        }
+    }
+
+    #[test]
+    fn too_old_after_definite_answers_resends_under_a_fresh_time() {
+        let mut p = sent();
+        p.record(Attempt::Rejected("InsufficientFunds".to_string()), 150);
+        p.record(Attempt::TooOld, 200);
+        assert!(matches!(p.status, RewardStatus::Failed { .. }));
+        assert_eq!(p.created_at_time, 200);
+    }
+
+    #[test]
+    fn too_old_after_an_unknown_attempt_waits_for_reconciling() {
+        let mut p = sent();
+        p.record(Attempt::Unknown("timeout".to_string()), 150);
+        p.record(Attempt::TooOld, 200);
+        assert!(matches!(p.status, RewardStatus::Unreconciled));
+        assert_eq!(p.created_at_time, 100);
+
+        // Nothing paid it, so it goes out again with a fresh time
+        assert!(matches!(reconcile_payout(&mut p, None, 300), Ok(RewardStatus::Pending)));
+        assert_eq!((p.created_at_time, p.maybe_sent), (300, Some(false)));
+        assert!(reconcile_payout(&mut p, None, 400).is_err());
+    }
+
+    #[test]
+    fn payouts_saved_before_tracking_count_as_maybe_sent() {
+        let mut p = RewardPayout { maybe_sent: None, ..sent() };
+        p.record(Attempt::TooOld, 200);
+        assert!(matches!(p.status, RewardStatus::Unreconciled));
+
+        assert!(matches!(reconcile_payout(&mut p, Some(Nat::from(7u32)), 300), Ok(RewardStatus::Paid { .. })));
+        p.record(Attempt::Rejected("late".to_string()), 400);
+        assert!(matches!(p.status, RewardStatus::Paid { .. }));
+    }
+}
@@ -0,0 +1,84 @@
+// search.rs - explorer queries over the locally held main chain, newest
+// first, paged with a height cursor
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::{BlockHeader, State};
+
+/// Blocks returned per page unless the caller asks for fewer
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 500;
+/// Heights examined per call, so a sparse filter can't run out of
+/// instructions; the cursor picks up where the scan stopped
+const MAX_SCAN: u64 = 20_000;
+
+/// Every field set must match; an empty filter matches every block
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct BlockFilter {
+    pub miner: Option<Principal>,
+    /// Inclusive difficulty bounds
+    pub min_difficulty: Option<u32>,
+    pub max_difficulty: Option<u32>,
+    /// Inclusive timestamp bounds, nanoseconds
+    pub from_ns: Option<u64>,
+    pub to_ns: Option<u64>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct BlockPage {
+    pub blocks: Vec<BlockHeader>,
+    /// Pass back to continue; None once every local height was scanned
+    pub next_cursor: Option<u64>,
+}
+
+impl BlockFilter {
+    fn matches(&self, h: &BlockHeader) -> bool {
+        self.miner.is_none_or(|m| h.miner == Some(m))
+        && self.min_difficulty.is_none_or(|d| h.difficulty >= d)
+        && self.max_difficulty.is_none_or(|d| h.difficulty <= d)
+        && self.from_ns.is_none_or(|t| h.timestamp_ns >= t)
+        && self.to_ns.is_none_or(|t| h.timestamp_ns <= t)
+    }
+}
+
+/// Matching main-chain headers from `cursor` (default: the tip) downwards
+pub fn search(st: &State, filter: &BlockFilter, cursor: Option<u64>, limit: Option<u64>) -> BlockPage {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let start = cursor.unwrap_or(st.tip.height).min(st.tip.height);
+    if start < st.first_height {
+        return BlockPage { blocks: Vec::new(), next_cursor: None };
+    }
+
+    // Timestamps only grow along the chain, so nothing below the first
+    // block older than from_ns can match
+    let mut blocks = Vec::new();
+    let mut height = start;
+    let floor = start.saturating_sub(MAX_SCAN - 1).max(st.first_height);
+    while let Some(h) = st.header_at(height) {
+        if filter.from_ns.is_some_and(|t| h.timestamp_ns < t) {
+            return BlockPage { blocks, next_cursor: None };
+        }
+        if filter.matches(h) {
+            blocks.push(h.clone());
+            if blocks.len() == limit {
+                break;
+            }
+        }
+        if height == floor {
+            break;
+        }
+        height -= 1;
+    }
+
+    let next_cursor = height.checked_sub(1).filter(|h| *h >= st.first_height);
+    BlockPage { blocks, next_cursor }
+}
+
+/// The latest `n` main-chain headers, newest first
+pub fn recent(st: &State, n: u64) -> Vec<BlockHeader> {
+    let from = st.tip.height.saturating_sub(n.saturating_sub(1)).max(st.first_height);
+    (from..=st.tip.height)
+    .rev()
+    .take(n as usize)
+    .filter_map(|h| st.header_at(h).cloned())
+    .collect()
+}
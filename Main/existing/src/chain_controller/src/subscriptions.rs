@@ -0,0 +1,43 @@
+// subscriptions.rs - tell subscribed canisters when the tip moves, through
+// canister_notify's new_block topic
+use candid::{CandidType, Deserialize, Principal};
+
+pub const DEFAULT_METHOD: &str = "on_new_block";
+const TOPIC: &str = "new_block";
+
+#[derive(Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Subscription {
+    pub canister: Principal,
+    /// Called with (height, block_hash, difficulty)
+    pub method: String,
+}
+
+/// Returns false if the (canister, method) pair is already subscribed
+pub fn subscribe(canister: Principal, method: String) -> bool {
+    canister_notify::subscribe(TOPIC, canister, method)
+}
+
+/// Drop every subscription of `canister`; false if it had none
+pub fn unsubscribe(canister: Principal) -> bool {
+    canister_notify::unsubscribe(Some(TOPIC), canister)
+}
+
+pub fn list() -> Vec<Subscription> {
+    canister_notify::subscribers(Some(TOPIC))
+    .into_iter()
+    .map(|s| Subscription { canister: s.canister, method: s.method })
+    .collect()
+}
+
+/// Reinstall subscriptions from a save that predates canister_notify
+pub fn restore(subs: Vec<Subscription>) {
+    for sub in subs {
+        subscribe(sub.canister, sub.method);
+    }
+}
+
+/// `(height, block_hash, difficulty)` to every subscriber, retried until
+/// delivered or dead-lettered
+pub fn publish_new_block(height: u64, block_hash: &str, difficulty: u32) {
+    canister_notify::publish(TOPIC, (height, block_hash.to_string(), difficulty));
+}
@@ -0,0 +1,234 @@
+// config/src/lib.rs - one place for the parameters the miner, coordinator
+// and validator share. Every change gets a new version and is pushed to the
+// subscribed canisters, which cache it (see canister_config).
+use candid::Principal;
+use canister_auth::audit::AuditEntry;
+use canister_config::{GlobalConfig, VersionedConfig, UPDATE_METHOD};
+use canister_notify::{DeadLetter, Subscriber};
+use canister_state::{Migration, StateError};
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Past versions kept for get_config_version
+const MAX_HISTORY: usize = 100;
+const TOPIC: &str = "config";
+
+thread_local! {
+    /// Newest last; never empty once initialized
+    static HISTORY: RefCell<VecDeque<VersionedConfig>> = const { RefCell::new(VecDeque::new()) };
+}
+
+fn latest() -> VersionedConfig {
+    HISTORY.with(|h| h.borrow().back().cloned().expect("config not initialized"))
+}
+
+// ------------------------------------------------------------
+// Init / upgrades
+// ------------------------------------------------------------
+
+/// Starts at version 1 with `config`, or the built-in defaults
+#[init]
+fn init(config: Option<GlobalConfig>, admins: Option<Vec<Principal>>) {
+    canister_auth::init(caller(), admins.unwrap_or_default());
+    let config = config.unwrap_or_default();
+    if let Err(e) = config.validate() {
+        ic_cdk::trap(&e);
+    }
+    HISTORY.with(|h| {
+        h.borrow_mut().push_back(VersionedConfig {
+            version: 1,
+            updated_at: ic_cdk::api::time(),
+            config,
+        })
+    });
+}
+
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (Vec<VersionedConfig>, (Option<Principal>, Vec<Principal>), Vec<AuditEntry>, canister_notify::Snapshot);
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let saved: Saved = (
+        HISTORY.with(|h| h.borrow().iter().cloned().collect()),
+        canister_auth::snapshot(),
+        canister_auth::audit::snapshot(),
+        canister_notify::snapshot(),
+    );
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved) {
+        ic_cdk::trap(&format!("failed to save config: {}", e));
+    }
+}
+
+/// Upgrade args are ignored; change the config with set_config
+#[post_upgrade]
+fn post_upgrade() {
+    let saved = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => saved,
+        Err(StateError::Unversioned) => ic_cdk::trap("config state was never versioned"),
+        Err(e) => ic_cdk::trap(&format!("failed to restore config: {}", e)),
+    };
+    let (history, (owner, admins), audit, notify) = saved;
+    HISTORY.with(|h| *h.borrow_mut() = history.into());
+    canister_auth::restore(owner, admins);
+    canister_auth::audit::restore(audit);
+    canister_notify::restore(notify);
+}
+
+// ------------------------------------------------------------
+// Admin set
+// ------------------------------------------------------------
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+// ------------------------------------------------------------
+// Config
+// ------------------------------------------------------------
+
+#[query]
+pub fn get_config() -> VersionedConfig {
+    latest()
+}
+
+/// The latest config if its version is above `version`; what consumers poll
+#[query]
+pub fn get_config_if_newer(version: u64) -> Option<VersionedConfig> {
+    Some(latest()).filter(|c| c.version > version)
+}
+
+/// One of the last 100 versions
+#[query]
+pub fn get_config_version(version: u64) -> Option<VersionedConfig> {
+    HISTORY.with(|h| h.borrow().iter().find(|c| c.version == version).cloned())
+}
+
+/// Store `config` as a new version and push it to every subscriber; returns
+/// the version (admin only)
+#[update]
+pub fn set_config(config: GlobalConfig) -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_config", format!("{:?}", config));
+    if let Err(e) = config.validate() {
+        ic_cdk::trap(&e);
+    }
+
+    let update = VersionedConfig {
+        version: latest().version + 1,
+        updated_at: ic_cdk::api::time(),
+        config,
+    };
+    HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        if h.len() >= MAX_HISTORY {
+            h.pop_front();
+        }
+        h.push_back(update.clone());
+    });
+
+    let version = update.version;
+    canister_notify::publish(TOPIC, (update,));
+    version
+}
+
+// ------------------------------------------------------------
+// Subscribers
+// ------------------------------------------------------------
+
+/// Have `canister.method` (default on_config_update) called with each new
+/// VersionedConfig. A canister may subscribe itself; anyone else must be an
+/// admin.
+#[update]
+pub fn subscribe(canister: Principal, method: Option<String>) -> bool {
+    if caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("subscribe", format!("{:?}", (&canister, &method)));
+    }
+
+    canister_notify::subscribe(TOPIC, canister, method.unwrap_or_else(|| UPDATE_METHOD.to_string()))
+}
+
+#[update]
+pub fn unsubscribe(canister: Principal) -> bool {
+    if caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("unsubscribe", format!("{:?}", canister));
+    }
+
+    canister_notify::unsubscribe(Some(TOPIC), canister)
+}
+
+#[query]
+pub fn get_subscribers() -> Vec<Subscriber> {
+    canister_notify::subscribers(Some(TOPIC))
+}
+
+/// Pushes that ran out of retries, oldest first
+#[query]
+pub fn get_dead_letters() -> Vec<DeadLetter> {
+    canister_notify::dead_letters()
+}
+
+/// Resend every dead letter; returns how many (admin only)
+#[update]
+pub fn retry_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_dead_letters", String::new());
+    canister_notify::retry_dead_letters()
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
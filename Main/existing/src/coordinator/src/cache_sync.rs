@@ -0,0 +1,129 @@
+// cache_sync.rs - share solution caches across the fleet so a block solved
+// by one miner short-circuits the same job on every other miner
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
+use futures::future::join_all;
+use ic_cdk::api::call::call;
+
+use crate::scheduler::get_miner_stats;
+
+/// Must match the miner's CacheEntry
+#[derive(Clone, CandidType, Deserialize)]
+pub struct CacheEntry {
+    pub block_digest: Vec<u8>,
+    pub nonce: u64,
+    pub hash: String,
+    pub difficulty: u32,
+    pub hits: u64,
+    pub created_at: u64,
+    pub last_accessed: u64,
+    pub expires_at: Option<u64>,
+}
+
+thread_local! {
+    /// Next export_cache_entries cursor per miner: the last entry's
+    /// created_at and cache key
+    static WATERMARKS: RefCell<HashMap<Principal, (u64, Vec<u8>)>> = RefCell::new(HashMap::new());
+    static SYNC_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    static SYNC_INTERVAL_SECS: Cell<Option<u64>> = const { Cell::new(None) };
+    static SYNCING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Sync every `secs` (None stops periodic syncing)
+pub fn set_interval(secs: Option<u64>) {
+    if let Some(id) = SYNC_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+    SYNC_INTERVAL_SECS.with(|i| i.set(secs));
+
+    if let Some(secs) = secs {
+        let id = set_timer_interval(Duration::from_secs(secs), || {
+            ic_cdk::spawn(async {
+                sync().await;
+            })
+        });
+        SYNC_TIMER.with(|t| t.set(Some(id)));
+    }
+}
+
+pub fn get_interval() -> Option<u64> {
+    SYNC_INTERVAL_SECS.with(|i| i.get())
+}
+
+/// Pull new entries from every known miner and push each miner the ones it
+/// didn't produce. The coordinator must be a miner admin (import is
+/// admin-only). Returns how many entries were newly imported.
+pub async fn sync() -> u64 {
+    if SYNCING.with(|s| s.replace(true)) {
+        return 0;
+    }
+
+    let miners: Vec<Principal> = get_miner_stats().into_iter().map(|m| m.miner).collect();
+
+    let exports = join_all(miners.iter().map(|&miner| export(miner))).await;
+    let exported: Vec<(Principal, Vec<CacheEntry>)> = miners.iter().copied().zip(exports).collect();
+
+    let imports = miners.iter().map(|&miner| {
+        let entries: Vec<CacheEntry> = exported
+        .iter()
+        .filter(|(from, _)| *from != miner)
+        .flat_map(|(_, entries)| entries.iter().cloned())
+        .collect();
+        import(miner, entries)
+    });
+    let imported: u64 = join_all(imports).await.into_iter().sum();
+
+    if imported > 0 {
+        ic_cdk::println!("🗄️ Cache sync imported {} entries across {} miners", imported, miners.len());
+    }
+    SYNCING.with(|s| s.set(false));
+    imported
+}
+
+impl CacheEntry {
+    /// The miner's cache key: block_digest ++ difficulty (big-endian)
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.block_digest.clone();
+        key.extend_from_slice(&self.difficulty.to_be_bytes());
+        key
+    }
+}
+
+async fn export(miner: Principal) -> Vec<CacheEntry> {
+    let (since, after) = match WATERMARKS.with(|w| w.borrow().get(&miner).cloned()) {
+        Some((created_at, key)) => (created_at, Some(key)),
+        None => (0, None),
+    };
+
+    match call::<(u64, Option<Vec<u8>>), (Vec<CacheEntry>,)>(miner, "export_cache_entries", (since, after)).await {
+        Ok((entries,)) => {
+            // Oldest first, so the last entry is where the next page starts
+            if let Some(last) = entries.last() {
+                WATERMARKS.with(|w| w.borrow_mut().insert(miner, (last.created_at, last.key())));
+            }
+            entries
+        }
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ export_cache_entries on {} failed: {:?} {}", miner, code, msg);
+            Vec::new()
+        }
+    }
+}
+
+async fn import(miner: Principal, entries: Vec<CacheEntry>) -> u64 {
+    if entries.is_empty() {
+        return 0;
+    }
+
+    match call::<(Vec<CacheEntry>,), (u64,)>(miner, "import_cache_entries", (entries,)).await {
+        Ok((added,)) => added,
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ import_cache_entries on {} failed: {:?} {}", miner, code, msg);
+            0
+        }
+    }
+}
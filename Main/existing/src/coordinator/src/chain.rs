@@ -0,0 +1,140 @@
+// chain.rs - hand solved blocks to the chain controller along with a
+// difficulty retarget computed by the validator from observed solve times
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call;
+use ic_cdk::spawn;
+
+use crate::events::{self, EventKind};
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChainLink {
+    pub chain_controller: Principal,
+    pub validator: Principal,
+    pub target_block_time_secs: u64,
+    /// How many recent solve times the validator averages over
+    pub window: u32,
+}
+
+/// Mirrors chain_controller's `ChainTip`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChainTip {
+    pub height: u64,
+    pub block_hash: String,
+    pub difficulty: u32,
+    pub last_update_ns: u64,
+    pub paused: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+/// Mirrors chain_controller's `ValidatedTip`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct ValidatedTip {
+    tip: ChainTip,
+    validation: ValidationResult,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChainStatus {
+    pub tip: ChainTip,
+    /// The validator's verdict on the tip block
+    pub validation: ValidationResult,
+    /// What the validator would retarget to from the recent solve times
+    pub next_difficulty: u32,
+}
+
+thread_local! {
+    static LINK: RefCell<Option<ChainLink>> = const { RefCell::new(None) };
+    static SOLVE_TIMES: RefCell<VecDeque<u64>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// chain_controller only accepts blocks from its registered validator, so
+/// this coordinator must be set as that validator for submissions to land
+pub fn set_link(link: Option<ChainLink>) {
+    LINK.with(|l| *l.borrow_mut() = link);
+}
+
+pub fn get_link() -> Option<ChainLink> {
+    LINK.with(|l| l.borrow().clone())
+}
+
+pub fn recent_solve_times() -> Vec<u64> {
+    SOLVE_TIMES.with(|t| t.borrow().iter().copied().collect())
+}
+
+/// Record how long the job took and, if a chain is linked, submit its hash
+/// with the validator's recommended difficulty, naming `miner` as the finder
+pub fn report_block(job_id: u64, hash: String, difficulty: u32, solve_secs: u64, miner: Principal) {
+    let Some(link) = get_link() else { return };
+
+    let times = SOLVE_TIMES.with(|t| {
+        let mut t = t.borrow_mut();
+        t.push_back(solve_secs);
+        while t.len() > link.window.max(1) as usize {
+            t.pop_front();
+        }
+        t.iter().copied().collect::<Vec<u64>>()
+    });
+
+    spawn(async move {
+        let result = submit(&link, &hash, difficulty, times, miner).await;
+        let (new_difficulty, error) = match result {
+            Ok(d) => {
+                ic_cdk::println!("⛓️ Submitted block {} (difficulty {} -> {})", hash, difficulty, d);
+                (Some(d), None)
+            }
+            Err(e) => {
+                ic_cdk::println!("❌ Block submission for job {} failed: {}", job_id, e);
+                (None, Some(e))
+            }
+        };
+        events::record(job_id, EventKind::BlockSubmitted { hash, difficulty, new_difficulty, error });
+    });
+}
+
+async fn submit(link: &ChainLink, hash: &str, difficulty: u32, times: Vec<u64>, miner: Principal) -> Result<u32, String> {
+    let (new_difficulty,): (u32,) = call(
+        link.validator,
+        "calculate_difficulty_adjustment",
+        (difficulty, link.target_block_time_secs, times),
+    )
+    .await
+    .map_err(|(code, msg)| format!("calculate_difficulty_adjustment: {:?} {}", code, msg))?;
+
+    call::<(String, Option<u32>, Option<String>, Option<Principal>), ()>(
+        link.chain_controller,
+        "submit_valid_block",
+        (hash.to_string(), Some(new_difficulty), None, Some(miner)),
+    )
+    .await
+    .map_err(|(code, msg)| format!("submit_valid_block: {:?} {}", code, msg))?;
+
+    Ok(new_difficulty)
+}
+
+/// The linked chain's tip, validated, and the next difficulty, without any
+/// update calls. Only works from a composite query, with all three
+/// canisters on one subnet.
+pub async fn status(link: &ChainLink) -> Result<ChainStatus, String> {
+    let (validated,): (ValidatedTip,) = call(link.chain_controller, "get_tip_with_validation", ())
+    .await
+    .map_err(|(code, msg)| format!("get_tip_with_validation: {:?} {}", code, msg))?;
+
+    let args = (validated.tip.difficulty, link.target_block_time_secs, recent_solve_times());
+    let (next_difficulty,): (u32,) = call(link.validator, "calculate_difficulty_adjustment", args)
+    .await
+    .map_err(|(code, msg)| format!("calculate_difficulty_adjustment: {:?} {}", code, msg))?;
+
+    Ok(ChainStatus {
+        tip: validated.tip,
+        validation: validated.validation,
+        next_difficulty,
+    })
+}
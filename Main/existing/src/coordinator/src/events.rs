@@ -0,0 +1,242 @@
+// events.rs - append-only scheduler event log (kept across upgrades). Once
+// it grows past COMPACT_THRESHOLD, the lease and share events of finished
+// jobs are folded into one Compacted event per job, so the log stays bounded
+// by the jobs still running.
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+
+use crate::verify::PowAlgorithm;
+
+const MAX_PAGE_SIZE: u64 = 500;
+/// Log length that triggers compacting finished jobs
+const COMPACT_THRESHOLD: usize = 50_000;
+
+#[derive(Clone, CandidType, Deserialize)]
+pub enum EventKind {
+    /// Everything needed to rebuild the job's nonce space on replay
+    JobStarted {
+        block_data: String,
+        difficulty: u32,
+        start_nonce: u64,
+        end_nonce: u64,
+        chunk_size: u64,
+        weight: u32,
+        deadline_ns: Option<u64>,
+        max_total_attempts: Option<u64>,
+        /// None for jobs logged before algorithms were selectable (SHA-256)
+        algorithm: Option<PowAlgorithm>,
+        /// 32 bytes, big-endian; None for jobs logged before targets (they
+        /// use `difficulty`)
+        target: Option<Vec<u8>>,
+        /// 32 bytes, big-endian; None if the job takes no shares
+        share_target: Option<Vec<u8>>,
+    },
+    JobResumed {
+        requeued_ranges: u64,
+        next_nonce: u64,
+    },
+    Assigned {
+        miner: Principal,
+        lease_id: u64,
+        start: u64,
+        size: u64,
+    },
+    Completed {
+        miner: Principal,
+        lease_id: u64,
+        attempts: u64,
+    },
+    TimedOut {
+        miner: Principal,
+        lease_id: u64,
+    },
+    Failed {
+        miner: Principal,
+        lease_id: u64,
+        error: String,
+    },
+    Solution {
+        miner: Principal,
+        nonce: u64,
+        hash: String,
+    },
+    StopBroadcast {
+        miners: u64,
+    },
+    Expired {
+        reason: String,
+        total_attempts: u64,
+    },
+    Rejected {
+        miner: Principal,
+        nonce: u64,
+        reason: String,
+    },
+    /// A verified share, worth 2^difficulty expected hashes
+    Share {
+        miner: Principal,
+        nonce: u64,
+        hash: String,
+        difficulty: u32,
+    },
+    ShareRejected {
+        miner: Principal,
+        nonce: u64,
+        reason: String,
+    },
+    /// The job's reward split over the last `window` shares (PPLNS)
+    PayoutsSettled {
+        finder: Principal,
+        hash: String,
+        window: u64,
+    },
+    /// Solved block handed to the chain controller
+    BlockSubmitted {
+        hash: String,
+        difficulty: u32,
+        new_difficulty: Option<u32>,
+        error: Option<String>,
+    },
+    /// Stands in for a finished job's lease, share and rejection events,
+    /// dropped to keep the log bounded
+    Compacted {
+        events: u64,
+        shares: u64,
+    },
+}
+
+impl EventKind {
+    /// Lease and share traffic, only needed while the job can be resumed
+    fn is_compactable(&self) -> bool {
+        matches!(
+            self,
+            EventKind::Assigned { .. }
+            | EventKind::Completed { .. }
+            | EventKind::TimedOut { .. }
+            | EventKind::Failed { .. }
+            | EventKind::Rejected { .. }
+            | EventKind::Share { .. }
+            | EventKind::ShareRejected { .. }
+        )
+    }
+
+    fn finishes_job(&self) -> bool {
+        matches!(self, EventKind::Solution { .. } | EventKind::Expired { .. } | EventKind::StopBroadcast { .. })
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct SchedulerEvent {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub job_id: u64,
+    pub kind: EventKind,
+}
+
+thread_local! {
+    static EVENTS: RefCell<Vec<SchedulerEvent>> = const { RefCell::new(Vec::new()) };
+    /// Seqs keep counting across compactions
+    static NEXT_SEQ: Cell<u64> = const { Cell::new(0) };
+    static COMPACT_AT: Cell<usize> = const { Cell::new(COMPACT_THRESHOLD) };
+}
+
+pub fn record(job_id: u64, kind: EventKind) {
+    let len = EVENTS.with(|e| {
+        let mut e = e.borrow_mut();
+        e.push(SchedulerEvent {
+            seq: NEXT_SEQ.with(|n| n.replace(n.get() + 1)),
+            timestamp: time(),
+            job_id,
+            kind,
+        });
+        e.len()
+    });
+
+    if len >= COMPACT_AT.with(|c| c.get()) {
+        let compacted = compact();
+        // Running jobs alone may hold this much; wait for the log to double
+        // before scanning it again
+        let len = EVENTS.with(|e| e.borrow().len());
+        COMPACT_AT.with(|c| c.set(COMPACT_THRESHOLD.max(len.saturating_mul(2))));
+        ic_cdk::println!("Compacted {} finished jobs, {} events left", compacted, len);
+    }
+}
+
+/// Replace the compactable events of every finished job with one Compacted
+/// event; returns how many jobs were compacted
+fn compact() -> usize {
+    let dropped = EVENTS.with(|e| {
+        let mut e = e.borrow_mut();
+        let finished: BTreeSet<u64> = e.iter().filter(|ev| ev.kind.finishes_job()).map(|ev| ev.job_id).collect();
+
+        let mut dropped: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+        e.retain(|ev| {
+            if !finished.contains(&ev.job_id) || !ev.kind.is_compactable() {
+                return true;
+            }
+            let d = dropped.entry(ev.job_id).or_default();
+            d.0 += 1;
+            d.1 += matches!(ev.kind, EventKind::Share { .. }) as u64;
+            false
+        });
+
+        let now = time();
+        for (&job_id, &(events, shares)) in &dropped {
+            e.push(SchedulerEvent {
+                seq: NEXT_SEQ.with(|n| n.replace(n.get() + 1)),
+                timestamp: now,
+                job_id,
+                kind: EventKind::Compacted { events, shares },
+            });
+        }
+        dropped
+    });
+
+    for &job_id in dropped.keys() {
+        crate::shares::compacted(job_id);
+    }
+    dropped.len()
+}
+
+/// Events `offset..offset+limit` of the retained log in append order (limit
+/// capped at 500)
+pub fn get_events(offset: u64, limit: u64) -> Vec<SchedulerEvent> {
+    let limit = limit.min(MAX_PAGE_SIZE) as usize;
+
+    EVENTS.with(|e| {
+        e.borrow()
+        .iter()
+        .skip(offset as usize)
+        .take(limit)
+        .cloned()
+        .collect()
+    })
+}
+
+pub fn event_count() -> u64 {
+    EVENTS.with(|e| e.borrow().len() as u64)
+}
+
+/// All events of one job, in append order
+pub fn for_job(job_id: u64) -> Vec<SchedulerEvent> {
+    EVENTS.with(|e| e.borrow().iter().filter(|ev| ev.job_id == job_id).cloned().collect())
+}
+
+/// Visit every event in append order without copying the log
+pub fn each(mut f: impl FnMut(&SchedulerEvent)) {
+    EVENTS.with(|e| e.borrow().iter().for_each(&mut f));
+}
+
+pub fn snapshot() -> Vec<SchedulerEvent> {
+    EVENTS.with(|e| e.borrow().clone())
+}
+
+pub fn restore(events: Vec<SchedulerEvent>) {
+    NEXT_SEQ.with(|n| n.set(events.last().map_or(0, |ev| ev.seq + 1)));
+    // A log saved before compaction existed is compacted on the next event
+    COMPACT_AT.with(|c| c.set(COMPACT_THRESHOLD));
+    EVENTS.with(|e| *e.borrow_mut() = events);
+}
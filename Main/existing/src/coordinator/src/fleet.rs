@@ -0,0 +1,328 @@
+// fleet.rs - autoscaling: provision extra miner canisters when the fleet is
+// too slow for the running jobs, and decommission them again when idle
+use std::cell::{Cell, RefCell};
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call;
+use ic_cdk::api::management_canister::main::{
+    create_canister, delete_canister, install_code, start_canister, stop_canister,
+    CanisterIdRecord, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use ic_cdk::api::{id, time};
+use ic_cdk::spawn;
+
+use crate::scheduler;
+
+/// Cycles a miner keeps when refunding, enough to reply to the refund call
+const REFUND_KEEP_CYCLES: u128 = 10_000_000_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FleetConfig {
+    pub enabled: bool,
+    /// Scale up while the expected time-to-solution is above this
+    pub target_solve_secs: u64,
+    /// Upper bound on miners this coordinator creates
+    pub max_provisioned: u32,
+    /// Cycles attached to each create_canister call
+    pub cycles_per_miner: u128,
+    /// Provisioned miners kept running when there is nothing to mine
+    pub warm_pool_min: u32,
+    /// Delete scaled-down miners (after reclaiming their cycles) instead of
+    /// just stopping them
+    pub delete_on_scale_down: bool,
+}
+
+impl Default for FleetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_solve_secs: 60,
+            max_provisioned: 10,
+            cycles_per_miner: 500_000_000_000,
+            warm_pool_min: 0,
+            delete_on_scale_down: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ProvisionedStatus {
+    /// Created, but the miner module isn't installed yet; the install is
+    /// retried before any new canister is created
+    Installing,
+    Running,
+    Stopped,
+    Deleted,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ProvisionedMiner {
+    pub canister_id: Principal,
+    pub created_at: u64,
+    pub status: ProvisionedStatus,
+    pub reclaimed_cycles: u128,
+}
+
+/// Kept across upgrades, so created canisters can still be torn down
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    config: FleetConfig,
+    miner_wasm: Vec<u8>,
+    provisioned: Vec<ProvisionedMiner>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<FleetConfig> = RefCell::new(FleetConfig::default());
+    static MINER_WASM: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static PROVISIONED: RefCell<Vec<ProvisionedMiner>> = const { RefCell::new(Vec::new()) };
+    // One provisioning round at a time
+    static SCALING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        config: get_config(),
+        miner_wasm: MINER_WASM.with(|w| w.borrow().clone()),
+        provisioned: list_provisioned(),
+    }
+}
+
+pub fn restore(snapshot: Snapshot) {
+    CONFIG.with(|c| *c.borrow_mut() = snapshot.config);
+    MINER_WASM.with(|w| *w.borrow_mut() = snapshot.miner_wasm);
+    PROVISIONED.with(|p| *p.borrow_mut() = snapshot.provisioned);
+}
+
+pub fn set_config(config: FleetConfig) {
+    CONFIG.with(|c| *c.borrow_mut() = config);
+}
+
+pub fn get_config() -> FleetConfig {
+    CONFIG.with(|c| c.borrow().clone())
+}
+
+/// The miner module installed into new canisters (existing_backend.wasm)
+pub fn set_miner_wasm(wasm: Vec<u8>) {
+    MINER_WASM.with(|w| *w.borrow_mut() = wasm);
+}
+
+pub fn miner_wasm_len() -> u64 {
+    MINER_WASM.with(|w| w.borrow().len() as u64)
+}
+
+pub fn list_provisioned() -> Vec<ProvisionedMiner> {
+    PROVISIONED.with(|p| p.borrow().clone())
+}
+
+fn with_status(status: ProvisionedStatus) -> Vec<Principal> {
+    PROVISIONED.with(|p| {
+        p.borrow()
+        .iter()
+        .filter(|m| m.status == status)
+        .map(|m| m.canister_id)
+        .collect()
+    })
+}
+
+fn set_status(canister_id: Principal, status: ProvisionedStatus, reclaimed: u128) {
+    PROVISIONED.with(|p| {
+        if let Some(m) = p.borrow_mut().iter_mut().find(|m| m.canister_id == canister_id) {
+            m.status = status;
+            m.reclaimed_cycles += reclaimed;
+        }
+    });
+}
+
+// ------------------------------------------------------------
+// Scale-up / scale-down (called from the health timer)
+// ------------------------------------------------------------
+
+pub fn autoscale() {
+    spawn(async {
+        scale_up_if_needed().await;
+        scale_down_if_needed().await;
+    });
+}
+
+/// Called when the last job finishes
+pub fn scale_down() {
+    spawn(async {
+        scale_down_if_needed().await;
+    });
+}
+
+async fn scale_up_if_needed() {
+    let config = get_config();
+    if !config.enabled || SCALING.with(|s| s.get()) {
+        return;
+    }
+
+    let installing = with_status(ProvisionedStatus::Installing);
+    let live = with_status(ProvisionedStatus::Running).len() + installing.len();
+    if live >= config.max_provisioned as usize {
+        return;
+    }
+
+    match scheduler::estimated_solve_secs() {
+        Some(secs) if secs > config.target_solve_secs => {
+            ic_cdk::println!(
+                "Expected solve time {}s > target {}s, provisioning a miner",
+                secs, config.target_solve_secs
+            );
+        }
+        _ => return,
+    }
+
+    SCALING.with(|s| s.set(true));
+    // A half-provisioned or stopped miner is cheaper to bring back than a
+    // new one
+    match (installing.first(), with_status(ProvisionedStatus::Stopped).first()) {
+        (Some(&canister_id), _) if miner_wasm_len() > 0 => install_miner(canister_id).await,
+        (_, Some(&canister_id)) => restart_miner(canister_id).await,
+        (None, None) if miner_wasm_len() > 0 => provision_miner(config.cycles_per_miner).await,
+        _ => {}
+    }
+    SCALING.with(|s| s.set(false));
+}
+
+/// With nothing running, shrink to the warm pool; while jobs run, drop one
+/// miner per round if the fleet is over twice as fast as the target needs
+async fn scale_down_if_needed() {
+    let config = get_config();
+    if !config.enabled || SCALING.with(|s| s.get()) {
+        return;
+    }
+
+    let running = with_status(ProvisionedStatus::Running);
+    let keep = if !scheduler::is_running() {
+        config.warm_pool_min as usize
+    } else {
+        match scheduler::estimated_solve_secs() {
+            Some(secs) if secs.saturating_mul(2) < config.target_solve_secs => running.len().saturating_sub(1),
+            _ => return,
+        }
+    }
+    .max(config.warm_pool_min as usize);
+
+    if running.len() <= keep {
+        return;
+    }
+    let excess = running.len() - keep;
+
+    // Newest first, skipping miners that are mid-chunk
+    let surplus: Vec<Principal> = running
+    .into_iter()
+    .rev()
+    .filter(|m| !scheduler::is_miner_busy(*m))
+    .take(excess)
+    .collect();
+
+    SCALING.with(|s| s.set(true));
+    for canister_id in surplus {
+        if let Err(e) = decommission(canister_id, config.delete_on_scale_down).await {
+            ic_cdk::println!("❌ Decommissioning {} failed: {}", canister_id, e);
+        }
+    }
+    SCALING.with(|s| s.set(false));
+}
+
+async fn restart_miner(canister_id: Principal) {
+    match start_canister(CanisterIdRecord { canister_id }).await {
+        Ok(()) => {
+            set_status(canister_id, ProvisionedStatus::Running, 0);
+            scheduler::add_miner(canister_id);
+            ic_cdk::println!("✅ Restarted miner {}", canister_id);
+        }
+        Err((code, msg)) => ic_cdk::println!("❌ start_canister {} failed: {:?} {}", canister_id, code, msg),
+    }
+}
+
+/// Take a miner out of the fleet and stop it. With `delete`, its cycles are
+/// pulled back to the coordinator first and the canister is deleted.
+pub async fn decommission(canister_id: Principal, delete: bool) -> Result<(), String> {
+    scheduler::remove_miner(canister_id);
+
+    let mut reclaimed = 0;
+    if delete {
+        match call::<(u128,), (u128,)>(canister_id, "refund_cycles", (REFUND_KEEP_CYCLES,)).await {
+            Ok((amount,)) => reclaimed = amount,
+            Err((code, msg)) => ic_cdk::println!("Refund from {} failed: {:?} {}", canister_id, code, msg),
+        }
+    }
+
+    stop_canister(CanisterIdRecord { canister_id })
+    .await
+    .map_err(|(code, msg)| format!("stop_canister: {:?} {}", code, msg))?;
+    set_status(canister_id, ProvisionedStatus::Stopped, reclaimed);
+
+    if delete {
+        delete_canister(CanisterIdRecord { canister_id })
+        .await
+        .map_err(|(code, msg)| format!("delete_canister: {:?} {}", code, msg))?;
+        set_status(canister_id, ProvisionedStatus::Deleted, 0);
+    }
+
+    ic_cdk::println!("🧊 Decommissioned miner {} (reclaimed {} cycles)", canister_id, reclaimed);
+    Ok(())
+}
+
+pub fn is_provisioned(canister_id: Principal) -> bool {
+    PROVISIONED.with(|p| {
+        p.borrow()
+        .iter()
+        .any(|m| m.canister_id == canister_id && m.status != ProvisionedStatus::Deleted)
+    })
+}
+
+/// Create a canister controlled by this coordinator and install the miner.
+/// The canister is recorded as Installing as soon as it exists so a failed
+/// install can be retried or torn down later.
+async fn provision_miner(cycles: u128) {
+    let settings = CanisterSettings {
+        controllers: Some(vec![id()]),
+        ..Default::default()
+    };
+
+    let canister_id = match create_canister(CreateCanisterArgument { settings: Some(settings) }, cycles).await {
+        Ok((record,)) => record.canister_id,
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ create_canister failed: {:?} {}", code, msg);
+            return;
+        }
+    };
+
+    PROVISIONED.with(|p| {
+        p.borrow_mut().push(ProvisionedMiner {
+            canister_id,
+            created_at: time(),
+            status: ProvisionedStatus::Installing,
+            reclaimed_cycles: 0,
+        })
+    });
+    install_miner(canister_id).await;
+}
+
+/// Install the miner module into a provisioned canister; it only joins the
+/// fleet once that succeeds
+async fn install_miner(canister_id: Principal) {
+    let wasm_module = MINER_WASM.with(|w| w.borrow().clone());
+    let installed = install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id,
+        wasm_module,
+        arg: candid::encode_args(()).unwrap_or_default(),
+    })
+    .await;
+
+    match installed {
+        Ok(()) => {
+            ic_cdk::println!("✅ Provisioned miner {}", canister_id);
+            set_status(canister_id, ProvisionedStatus::Running, 0);
+            scheduler::add_miner(canister_id);
+        }
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ install_code on {} failed: {:?} {}", canister_id, code, msg);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// http.rs - Prometheus scrape endpoint (GET /metrics) over http_request,
+// with one series per miner and per job
+use std::fmt::Write;
+
+use candid::{CandidType, Deserialize};
+
+use crate::events;
+use crate::scheduler::{get_miner_stats, list_jobs, MinerStats, SchedulerStats};
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+pub fn handle(req: HttpRequest) -> HttpResponse {
+    let path = req.url.split('?').next().unwrap_or("");
+    if req.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"not found".to_vec(),
+        };
+    }
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+        body: render().into_bytes(),
+    }
+}
+
+/// Writes one metric family: (label value, sample) pairs under `label`
+fn family(out: &mut String, name: &str, kind: &str, help: &str, label: &str, samples: &[(String, u128)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (value, sample) in samples {
+        let _ = writeln!(out, "{}{{{}=\"{}\"}} {}", name, label, value, sample);
+    }
+}
+
+fn render() -> String {
+    let miners = get_miner_stats();
+    let jobs = list_jobs();
+    let mut out = String::new();
+
+    let per_miner = |f: &dyn Fn(&MinerStats) -> u128| -> Vec<(String, u128)> {
+        miners.iter().map(|m| (m.miner.to_text(), f(m))).collect()
+    };
+    family(&mut out, "pow_miner_hashrate", "gauge", "Estimated hashes per second", "miner",
+           &per_miner(&|m| m.estimated_hashrate as u128));
+    family(&mut out, "pow_miner_hashes_total", "counter", "Hashes reported by the miner", "miner",
+           &per_miner(&|m| m.total_attempts as u128));
+    family(&mut out, "pow_miner_chunks_completed_total", "counter", "Chunks completed", "miner",
+           &per_miner(&|m| m.chunks_completed as u128));
+    family(&mut out, "pow_miner_solutions_total", "counter", "Solutions found", "miner",
+           &per_miner(&|m| m.solutions_found as u128));
+    family(&mut out, "pow_miner_invalid_solutions_total", "counter", "Solutions that failed verification", "miner",
+           &per_miner(&|m| m.invalid_solutions as u128));
+    family(&mut out, "pow_miner_failures", "gauge", "Consecutive failed calls", "miner",
+           &per_miner(&|m| m.failures as u128));
+
+    let per_job = |f: &dyn Fn(&SchedulerStats) -> u128| -> Vec<(String, u128)> {
+        jobs.iter().map(|j| (j.job_id.to_string(), f(j))).collect()
+    };
+    family(&mut out, "pow_job_hashes_total", "counter", "Hashes searched for the job", "job",
+           &per_job(&|j| j.total_attempts as u128));
+    family(&mut out, "pow_job_running", "gauge", "1 while the job is running", "job",
+           &per_job(&|j| j.running as u128));
+    family(&mut out, "pow_job_busy_miners", "gauge", "Miners working on the job", "job",
+           &per_job(&|j| j.busy_miners as u128));
+
+    let _ = writeln!(out, "# HELP pow_hashes_total Hashes reported by all miners");
+    let _ = writeln!(out, "# TYPE pow_hashes_total counter");
+    let _ = writeln!(out, "pow_hashes_total {}", miners.iter().map(|m| m.total_attempts as u128).sum::<u128>());
+    let _ = writeln!(out, "# HELP pow_hashrate Estimated fleet hashes per second");
+    let _ = writeln!(out, "# TYPE pow_hashrate gauge");
+    let _ = writeln!(out, "pow_hashrate {}", miners.iter().map(|m| m.estimated_hashrate as u128).sum::<u128>());
+    let _ = writeln!(out, "# HELP pow_events_total Scheduler events logged");
+    let _ = writeln!(out, "# TYPE pow_events_total counter");
+    let _ = writeln!(out, "pow_events_total {}", events::event_count());
+    let _ = writeln!(out, "# HELP pow_cycles_balance Coordinator cycle balance");
+    let _ = writeln!(out, "# TYPE pow_cycles_balance gauge");
+    let _ = writeln!(out, "pow_cycles_balance {}", ic_cdk::api::canister_balance128());
+
+    out
+}
@@ -1,72 +1,931 @@
+mod cache_sync;
+mod chain;
+mod events;
+mod fleet;
+mod http;
+mod payouts;
+mod protocol;
+mod replay;
+mod reputation;
 mod scheduler;
+mod session;
+mod shares;
+mod stake;
+mod subscriptions;
+mod verify;
+mod vrf;
+mod work_source;
 
-use std::cell::RefCell;
+use std::cell::Cell;
+use std::time::Duration;
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::{update, heartbeat, query};  // Added query here
+use canister_auth::audit::AuditEntry;
+use canister_config::VersionedConfig;
+use canister_notify::{DeadLetter, RetryPolicy};
+use canister_state::{Migration, StateError};
+use ic_cdk::{init, post_upgrade, pre_upgrade, update, query};  // Added query here
 use ic_cdk::api::call::call;
-use sha2::{Digest, Sha256};
+use futures::future::select_all;
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
+use pow_core::target;
 
-use crate::scheduler::{start_scheduler, stop_scheduler, tick};
+use crate::chain::{ChainLink, ChainStatus};
+use crate::events::{EventKind, SchedulerEvent};
+use crate::payouts::{PayoutBreakdown, MAX_PPLNS_WINDOW};
+use crate::replay::JobReplay;
+use crate::reputation::{MinerReputation, ReputationOverride, ReputationPolicy};
+use crate::shares::ShareTally;
+use crate::stake::{Bond, StakePolicy};
+use crate::fleet::{FleetConfig, ProvisionedMiner};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::subscriptions::Subscription;
+use crate::verify::PowAlgorithm;
+use crate::vrf::{offset_for_miner, vrf_seed, VrfRound};
+use crate::work_source::{WorkSource, WorkSourceStatus};
+use crate::protocol::{JobNotify, JobSubmit, MAX_BATCH_CHUNKS, PROTOCOL_VERSION};
+use crate::scheduler::{start_scheduler, stop_scheduler, stop_job as stop_one_job, tick, is_running};
+use crate::scheduler::{set_job_weight as set_weight, list_jobs as job_list, JobLimits};
+use crate::scheduler::{restore_job, next_job_id, restore_next_job_id};
+use crate::scheduler::{health_check, reset_miner_failures as reset_slot_failures};
+use crate::scheduler::{add_miner as add_slot, remove_miner as remove_slot};
+use crate::scheduler::{set_backoff_policy as set_policy, get_backoff_policy as backoff_policy, BackoffPolicy};
+use crate::scheduler::{set_redundancy as set_replicas, get_redundancy as replicas};
+use crate::scheduler::{set_batch_size as set_batch, get_batch_size as batch_size};
+use crate::scheduler::{flush_cancels, has_pending_cancels, get_cancel_acks as cancel_acks, CancelAck};
 use crate::scheduler::{stats as scheduler_stats, SchedulerStats};
+use crate::scheduler::{get_job_cost as job_cost, JobCost, estimated_solve_secs};
+use crate::scheduler::{get_all_solutions as all_solutions, FoundSolution};
+use crate::scheduler::{get_unsearched_ranges as unsearched_ranges, NonceRange};
+use crate::scheduler::{get_miner_stats as miner_stats, get_miner_leaderboard as miner_leaderboard, MinerStats};
+
+const DEFAULT_LEADERBOARD_SIZE: u64 = 10;
+pub(crate) const DEFAULT_JOB_WEIGHT: u32 = 1;
+
+const DEFAULT_TICK_INTERVAL_MS: u64 = 1_000;
+const MIN_TICK_INTERVAL_MS: u64 = 100;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 // ------------------------------------------------------------
-// Target for heartbeat scheduler
+// Tick timer for the dynamic scheduler
 // ------------------------------------------------------------
 
 thread_local! {
-    static TARGET: RefCell<Option<(String, u32)>> = RefCell::new(None);
+    static TICK_INTERVAL_MS: Cell<u64> = const { Cell::new(DEFAULT_TICK_INTERVAL_MS) };
+    static TICK_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    static HEALTH_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+}
+
+// ------------------------------------------------------------
+// Init / admin management
+// ------------------------------------------------------------
+
+/// The installing principal becomes the owner; `admins` are added alongside
+#[init]
+fn init(admins: Option<Vec<Principal>>) {
+    canister_auth::init(ic_cdk::caller(), admins.unwrap_or_default());
+}
+
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (
+    Vec<SchedulerEvent>,
+    u64,
+    (Option<Principal>, Vec<Principal>),
+    Option<Vec<AuditEntry>>,
+    Option<canister_notify::Snapshot>,
+    Option<canister_config::Snapshot>,
+    Option<reputation::Snapshot>,
+    Option<stake::Snapshot>,
+    Option<fleet::Snapshot>,
+    Option<shares::Snapshot>,
+    Option<payouts::Snapshot>,
+    Option<work_source::Snapshot>,
+    Option<vrf::Snapshot>,
+);
+
+/// The event log, job counter and owner/admin set survive upgrades so jobs
+/// can be replayed, along with subscriptions, undelivered notifications,
+/// the cached global config, miner reputations, bonds, the provisioned
+/// fleet, the share and payout ledgers, the work source and the VRF rounds
+fn saved_state() -> Saved {
+    (
+        events::snapshot(),
+        next_job_id(),
+        canister_auth::snapshot(),
+        Some(canister_auth::audit::snapshot()),
+        Some(canister_notify::snapshot()),
+        Some(canister_config::snapshot()),
+        Some(reputation::snapshot()),
+        Some(stake::snapshot()),
+        Some(fleet::snapshot()),
+        Some(shares::snapshot()),
+        Some(payouts::snapshot()),
+        Some(work_source::snapshot()),
+        Some(vrf::snapshot()),
+    )
+}
+
+fn restore_state(
+    (log, next_id, (owner, admins), audit, notify, global_config, reputations, bonds, fleet, tally, pplns, source, rounds): Saved,
+) {
+    fleet::restore(fleet.unwrap_or_default());
+    reputation::restore(reputations.unwrap_or_default());
+    stake::restore(bonds.unwrap_or_default());
+    canister_notify::restore(notify.unwrap_or_default());
+    canister_config::restore(global_config.unwrap_or_default());
+    events::restore(log);
+    // Saves from before the ledgers were kept rebuild them from the log
+    match tally {
+        Some(t) => shares::restore(t),
+        None => shares::rebuild(),
+    }
+    match pplns {
+        Some(p) => payouts::restore(p),
+        None => payouts::rebuild(),
+    }
+    restore_next_job_id(next_id);
+    work_source::restore(source.unwrap_or_default());
+    vrf::restore(rounds.unwrap_or_default());
+    canister_auth::restore(owner, admins);
+    canister_auth::audit::restore(audit.unwrap_or_default());
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved_state()) {
+        ic_cdk::trap(&format!("failed to save event log: {}", e));
+    }
+}
+
+/// `admins` are added to the restored set; a canister saved before the set
+/// was persisted falls back to the upgrader as owner
+#[post_upgrade]
+fn post_upgrade(admins: Option<Vec<Principal>>) {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => Ok(saved),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<Saved>(),
+        Err(e) => ic_cdk::trap(&format!("failed to restore coordinator state: {}", e)),
+    };
+    match restored {
+        Ok(saved) => restore_state(saved),
+        Err(e) => ic_cdk::println!("No saved event log restored: {}", e),
+    }
+
+    if canister_auth::owner().is_none() {
+        init(admins);
+    } else {
+        admins.unwrap_or_default().into_iter().for_each(canister_auth::add_admin);
+    }
+}
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+/// Hand the canister to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// `(page, total_len)` of a versioned snapshot of the state kept across
+/// upgrades (event log, job counter, admins, audit log); offset 0 takes the
+/// snapshot, then page until offset reaches total_len (admin only)
+#[update]
+pub fn export_state(offset: u64, limit: u64) -> (Vec<u8>, u64) {
+    canister_auth::require_admin();
+    if offset == 0 {
+        canister_auth::audit::record("export_state", String::new());
+    }
+    canister_state::transfer::export_page(SCHEMA_VERSION, saved_state, offset, limit)
+}
+
+/// Replace that state with an export_state snapshot, sent in order with
+/// `last` set on the final chunk; jobs can then be replayed. The scheduler
+/// must be stopped, and the caller stays an admin (admin only).
+#[update]
+pub fn import_state(chunk: Vec<u8>, last: bool) -> Result<(), String> {
+    canister_auth::require_admin();
+    if is_running() {
+        return Err("stop the scheduler before importing state".to_string());
+    }
+    let admin = ic_cdk::caller();
+    let imported = canister_state::transfer::import_chunk::<Saved>(chunk, last, SCHEMA_VERSION, MIGRATIONS)
+    .map_err(|e| e.to_string())?;
+    if let Some(saved) = imported {
+        restore_state(saved);
+        canister_auth::add_admin(admin);
+        canister_auth::audit::record("import_state", String::new());
+    }
+    Ok(())
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+/// Alias of `list_admins` kept for existing callers
+#[query]
+pub fn get_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+// ------------------------------------------------------------
+// Solution subscriptions
+// ------------------------------------------------------------
+
+/// Have `canister.method` notified with (job_id, nonce, hash, miner) on every
+/// solution, and `expired_method` with (job_id, reason) when a job expires.
+/// A canister may subscribe itself; anyone else must be an admin.
+#[update]
+pub fn subscribe(canister: Principal, method: String, expired_method: Option<String>) -> bool {
+    if ic_cdk::caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("subscribe", format!("{:?}", (&canister, &method, &expired_method)));
+    }
+
+    subscriptions::subscribe(canister, method, expired_method)
+}
+
+#[update]
+pub fn unsubscribe(canister: Principal) -> bool {
+    if ic_cdk::caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("unsubscribe", format!("{:?}", canister));
+    }
+
+    subscriptions::unsubscribe(canister)
+}
+
+#[query]
+pub fn get_subscriptions() -> Vec<Subscription> {
+    subscriptions::list()
+}
+
+/// Retries with backoff for notifications subscribers fail to take
+/// (admin only)
+#[update]
+pub fn set_notify_policy(policy: RetryPolicy) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_notify_policy", format!("{:?}", policy));
+    if policy.max_attempts == 0 {
+        ic_cdk::trap("max_attempts must be at least 1");
+    }
+
+    canister_notify::set_policy(policy);
+}
+
+#[query]
+pub fn get_notify_policy() -> RetryPolicy {
+    canister_notify::policy()
+}
+
+/// Notifications that ran out of retries, oldest first
+#[query]
+pub fn get_dead_letters() -> Vec<DeadLetter> {
+    canister_notify::dead_letters()
+}
+
+/// Resend every dead letter; returns how many (admin only)
+#[update]
+pub fn retry_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_dead_letters", String::new());
+    canister_notify::retry_dead_letters()
+}
+
+/// Returns how many were dropped (admin only)
+#[update]
+pub fn clear_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("clear_dead_letters", String::new());
+    canister_notify::clear_dead_letters()
 }
 
 // ------------------------------------------------------------
 // Dynamic redistribution entrypoints
 // ------------------------------------------------------------
 
+/// Start a job alongside any running ones; `weight` (default 1) sets its
+/// share of the fleet relative to the other running jobs. The job is aborted
+/// once IC time passes `deadline_ns`, its miners report `max_total_attempts`
+/// or every nonce below `end_nonce` has been searched. `algorithm` defaults
+/// to SHA-256; a 32-byte big-endian `target` replaces `difficulty`.
+/// `share_difficulty`, below the job's, has miners send back shares that
+/// get_shares tallies per miner.
 #[update]
+#[allow(clippy::too_many_arguments)]
 pub fn start_dynamic_mining(
     miners: Vec<Principal>,
     block_data: String,
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
-) {
-    TARGET.with(|t| {
-        *t.borrow_mut() = Some((block_data.clone(), difficulty));
-    });
+    weight: Option<u32>,
+    deadline_ns: Option<u64>,
+    max_total_attempts: Option<u64>,
+    end_nonce: Option<u64>,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    share_difficulty: Option<u32>,
+) -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("start_dynamic_mining", format!("{:?}", (&miners, canister_auth::audit::digest(block_data.as_bytes()), &difficulty, &start_nonce, &chunk_size, &weight, &deadline_ns, &max_total_attempts, &end_nonce, &algorithm, target.as_deref().map(hex::encode), &share_difficulty)));
+
+    let weight = weight.unwrap_or(DEFAULT_JOB_WEIGHT);
+    if weight == 0 {
+        ic_cdk::trap("job weight must be at least 1");
+    }
+
+    let running = TICK_TIMER.with(|t| t.get()).is_some();
+    if end_nonce.is_some_and(|end| end <= start_nonce) {
+        ic_cdk::trap("end_nonce must be above start_nonce");
+    }
 
-    start_scheduler(miners, start_nonce, chunk_size);
+    let limits = JobLimits { deadline_ns, max_total_attempts, end_nonce };
+    let algorithm = algorithm.unwrap_or_default();
+    if matches!(algorithm, PowAlgorithm::Scrypt { log_n } if log_n > verify::MAX_SCRYPT_LOG_N) {
+        ic_cdk::trap("scrypt log_n can't exceed 15");
+    }
+    let target = match target {
+        Some(t) => target::parse(&t).unwrap_or_else(|e| ic_cdk::trap(&e)),
+        None => target::from_difficulty(difficulty),
+    };
+    let share_target = share_difficulty.map(target::from_difficulty);
+    if share_target.is_some_and(|t| t <= target) {
+        ic_cdk::trap("share_difficulty must be below the job's difficulty");
+    }
+    let job_id = start_scheduler(miners, block_data, difficulty, algorithm, target, share_target, start_nonce, chunk_size, weight, limits);
+    if !running {
+        arm_tick_timer();
+    }
+    job_id
 }
 
+/// Stops every job; the tick timer keeps running until miners ack the cancels
 #[update]
 pub fn stop_dynamic_mining() {
+    canister_auth::require_admin();
+    canister_auth::audit::record("stop_dynamic_mining", String::new());
     stop_scheduler();
+}
 
-    TARGET.with(|t| {
-        *t.borrow_mut() = None;
+/// Stop a single job, leaving the others running
+#[update]
+pub fn stop_job(job_id: u64) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("stop_job", format!("{:?}", job_id));
+    stop_one_job(job_id)
+}
+
+/// Which ranges went to which miners, what was confirmed searched and what
+/// is still open, rebuilt purely from the event log
+#[query]
+pub fn replay_job(job_id: u64) -> Option<JobReplay> {
+    replay::replay_job(job_id)
+}
+
+/// Restart an interrupted job (e.g. after an upgrade) from its event log.
+/// Unconfirmed ranges are re-issued first, then mining continues from the
+/// old frontier, so no nonce is skipped or searched twice.
+#[update]
+pub fn resume_job(job_id: u64, miners: Vec<Principal>) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("resume_job", format!("{:?}", (&job_id, &miners)));
+
+    let replay = match replay::replay_job(job_id) {
+        Some(r) => r,
+        None => ic_cdk::trap("no replayable event log for this job"),
+    };
+    if replay.finished {
+        ic_cdk::trap("job already finished");
+    }
+
+    if !restore_job(job_id, miners, replay::to_restored(&replay)) {
+        return false;
+    }
+
+    events::record(job_id, EventKind::JobResumed {
+        requeued_ranges: replay.unfinished.len() as u64,
+        next_nonce: replay.next_nonce,
     });
+    if TICK_TIMER.with(|t| t.get()).is_none() {
+        arm_tick_timer();
+    }
+    true
+}
+
+/// Change a job's share of the fleet
+#[update]
+pub fn set_job_weight(job_id: u64, weight: u32) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_job_weight", format!("{:?}", (&job_id, &weight)));
+    if weight == 0 {
+        ic_cdk::trap("job weight must be at least 1");
+    }
+
+    set_weight(job_id, weight)
+}
+
+/// Grow the fleet of a running scheduler
+#[update]
+pub fn add_miner(miner: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_miner", format!("{:?}", miner));
+
+    add_slot(miner)
+}
+
+/// Shrink the fleet; a busy miner is removed after its current chunk
+#[update]
+pub fn remove_miner(miner: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_miner", format!("{:?}", miner));
+
+    remove_slot(miner)
+}
+
+/// Change how often the scheduler ticks (takes effect immediately)
+#[update]
+pub fn set_tick_interval_ms(interval_ms: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_tick_interval_ms", format!("{:?}", interval_ms));
+    if interval_ms < MIN_TICK_INTERVAL_MS {
+        ic_cdk::trap(&format!("tick interval must be at least {}ms", MIN_TICK_INTERVAL_MS));
+    }
+
+    TICK_INTERVAL_MS.with(|i| i.set(interval_ms));
+
+    if TICK_TIMER.with(|t| t.get()).is_some() {
+        arm_tick_timer();
+    }
+}
+
+#[query]
+pub fn get_tick_interval_ms() -> u64 {
+    TICK_INTERVAL_MS.with(|i| i.get())
+}
+
+/// Configure how long failing miners are skipped
+#[update]
+pub fn set_backoff_policy(policy: BackoffPolicy) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_backoff_policy", format!("{:?}", policy));
+    if policy.base_ticks == 0 {
+        ic_cdk::trap("base_ticks must be at least 1");
+    }
+
+    set_policy(policy);
+}
+
+#[query]
+pub fn get_backoff_policy() -> BackoffPolicy {
+    backoff_policy()
+}
+
+/// Assign every range to `k` miners at once and accept the first completion;
+/// trades hashrate for latency on flaky fleets (1 turns it off)
+#[update]
+pub fn set_redundancy(k: u32) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_redundancy", format!("{:?}", k));
+    if k == 0 {
+        ic_cdk::trap("redundancy must be at least 1");
+    }
+
+    set_replicas(k);
+}
+
+#[query]
+pub fn get_redundancy() -> u32 {
+    replicas()
 }
 
-#[heartbeat]
-fn coordinator_heartbeat() {
-    TARGET.with(|t| {
-        if let Some((ref block, diff)) = *t.borrow() {
-            tick(block.clone(), diff);
+/// Send up to `n` chunks of a job per call to a miner (mine_chunks), so call
+/// overhead and block_data are paid once per batch; 1 turns it off. Ignored
+/// while redundancy is on.
+#[update]
+pub fn set_batch_size(n: u32) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_batch_size", format!("{:?}", n));
+    if n == 0 || n as usize > MAX_BATCH_CHUNKS {
+        ic_cdk::trap(&format!("batch size must be between 1 and {}", MAX_BATCH_CHUNKS));
+    }
+
+    set_batch(n);
+}
+
+#[query]
+pub fn get_batch_size() -> u32 {
+    batch_size()
+}
+
+/// Split each solved job's reward over the last `n` accepted shares
+/// (PPLNS); takes effect from the next solution
+#[update]
+pub fn set_pplns_window(n: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_pplns_window", format!("{:?}", n));
+    if n == 0 || n > MAX_PPLNS_WINDOW {
+        ic_cdk::trap(&format!("PPLNS window must be between 1 and {}", MAX_PPLNS_WINDOW));
+    }
+
+    payouts::set_window(n);
+}
+
+#[query]
+pub fn get_pplns_window() -> u64 {
+    payouts::window()
+}
+
+// ------------------------------------------------------------
+// Miner reputation
+// ------------------------------------------------------------
+
+/// Score deltas, and the score below which miners stop being scheduled
+#[update]
+pub fn set_reputation_policy(policy: ReputationPolicy) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_reputation_policy", format!("{:?}", policy));
+    if policy.ban_below.is_some_and(|t| t > reputation::MAX_SCORE) {
+        ic_cdk::trap(&format!("ban_below can't exceed {}", reputation::MAX_SCORE));
+    }
+
+    reputation::set_policy(policy);
+}
+
+#[query]
+pub fn get_reputation_policy() -> ReputationPolicy {
+    reputation::policy()
+}
+
+/// Ban or trust a miner whatever its score; None goes back to the score
+#[update]
+pub fn set_reputation_override(miner: Principal, pinned: Option<ReputationOverride>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_reputation_override", format!("{:?}", (&miner, &pinned)));
+
+    reputation::set_override(miner, pinned);
+}
+
+/// Put a miner back at the starting score; false if it has no record
+#[update]
+pub fn reset_reputation(miner: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("reset_reputation", format!("{:?}", miner));
+
+    reputation::reset(miner)
+}
+
+/// One miner's reputation, or every recorded miner's, lowest score first
+#[query]
+pub fn get_reputation(miner: Option<Principal>) -> Vec<MinerReputation> {
+    reputation::get_reputation(miner)
+}
+
+// ------------------------------------------------------------
+// Miner bonds
+// ------------------------------------------------------------
+
+/// Require miners to be bonded before they're scheduled; None turns it off
+/// and keeps existing bonds (admin only)
+#[update]
+pub fn set_stake_policy(policy: Option<StakePolicy>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_stake_policy", format!("{:?}", policy));
+    if policy.is_some_and(|p| p.required == 0) {
+        ic_cdk::trap("required bond must be positive; pass null to turn bonding off");
+    }
+
+    stake::set_policy(policy);
+}
+
+#[query]
+pub fn get_stake_policy() -> Option<StakePolicy> {
+    stake::policy()
+}
+
+/// Bond the attached cycles for `miner`. The miner itself or an admin opens
+/// the bond (or takes over an empty one) and becomes its operator, who may
+/// top it up and gets it back on withdraw_bond. Returns the bond's total.
+#[update]
+pub fn deposit_bond(miner: Principal) -> u64 {
+    let available = ic_cdk::api::call::msg_cycles_available();
+    if available == 0 {
+        ic_cdk::trap("attach cycles to bond");
+    }
+    let caller = ic_cdk::caller();
+    let may_open = caller == miner || canister_auth::is_admin(&caller);
+    match stake::deposit(miner, caller, available, may_open) {
+        Ok(total) => {
+            ic_cdk::api::call::msg_cycles_accept(available);
+            total
         }
+        Err(e) => ic_cdk::trap(&e),
+    }
+}
+
+/// Refund what's left of a bond to its operator once the miner has left
+/// the fleet (operator or admin). Returns the cycles sent; on an error the
+/// bond is kept.
+#[update]
+pub async fn withdraw_bond(miner: Principal) -> Result<u64, String> {
+    let caller = ic_cdk::caller();
+    let Some(bond) = stake::get_bond(miner) else {
+        ic_cdk::trap("miner has no bond");
+    };
+    if caller != bond.operator && !canister_auth::is_admin(&caller) {
+        ic_cdk::trap("only the operator or an admin can withdraw a bond");
+    }
+    if scheduler::has_miner(miner) {
+        ic_cdk::trap("remove the miner from the fleet first");
+    }
+    canister_auth::audit::record("withdraw_bond", format!("{:?}", miner));
+
+    // Not a trap on failure: that would also undo putting the bond back
+    stake::refund(miner).await
+}
+
+#[query]
+pub fn get_bond(miner: Principal) -> Option<Bond> {
+    stake::get_bond(miner)
+}
+
+/// Every bond, largest first
+#[query]
+pub fn get_bonds() -> Vec<Bond> {
+    stake::get_bonds()
+}
+
+/// Re-enable a miner that is backing off after repeated failures
+#[update]
+pub fn reset_miner_failures(miner: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("reset_miner_failures", format!("{:?}", miner));
+
+    reset_slot_failures(miner)
+}
+
+// ------------------------------------------------------------
+// Autoscaling - provision miners while jobs are too slow
+// ------------------------------------------------------------
+
+/// Upload the miner module used for new canisters. Ingress is capped at 2MB,
+/// so the wasm must be gzipped if it is larger than that.
+#[update]
+pub fn set_miner_wasm(wasm: Vec<u8>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_miner_wasm", format!("{} bytes", wasm.len()));
+    fleet::set_miner_wasm(wasm);
+}
+
+#[query]
+pub fn get_miner_wasm_len() -> u64 {
+    fleet::miner_wasm_len()
+}
+
+#[update]
+pub fn set_fleet_config(config: FleetConfig) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_fleet_config", format!("{:?}", config));
+    fleet::set_config(config);
+}
+
+#[query]
+pub fn get_fleet_config() -> FleetConfig {
+    fleet::get_config()
+}
+
+/// Miner canisters created by this coordinator
+#[query]
+pub fn get_provisioned_miners() -> Vec<ProvisionedMiner> {
+    fleet::list_provisioned()
+}
+
+/// Stop a provisioned miner now; with `delete`, reclaim its cycles and
+/// delete it
+#[update]
+pub async fn decommission_miner(canister_id: Principal, delete: bool) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("decommission_miner", format!("{:?}", (&canister_id, &delete)));
+    if !fleet::is_provisioned(canister_id) {
+        ic_cdk::trap("not a miner provisioned by this coordinator");
+    }
+
+    if let Err(e) = fleet::decommission(canister_id, delete).await {
+        ic_cdk::trap(&e);
+    }
+}
+
+/// Expected seconds to a solution for the slowest running job
+#[query]
+pub fn get_estimated_solve_secs() -> Option<u64> {
+    estimated_solve_secs()
+}
+
+// ------------------------------------------------------------
+// Chain link - submit solved blocks with a difficulty retarget
+// ------------------------------------------------------------
+
+/// Point the coordinator at a chain controller and validator (None unlinks).
+/// The chain controller must have this coordinator set as its validator.
+#[update]
+pub fn set_chain_link(link: Option<ChainLink>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_chain_link", format!("{:?}", link));
+    if matches!(&link, Some(l) if l.target_block_time_secs == 0) {
+        ic_cdk::trap("target_block_time_secs must be at least 1");
+    }
+    chain::set_link(link);
+}
+
+#[query]
+pub fn get_chain_link() -> Option<ChainLink> {
+    chain::get_link()
+}
+
+/// Solve times (seconds) fed to the last difficulty adjustment
+#[query]
+pub fn get_recent_solve_times() -> Vec<u64> {
+    chain::recent_solve_times()
+}
+
+/// The linked chain's tip with the validator's verdict and the difficulty it
+/// would retarget to, in one composite query. The chain controller and
+/// validator must be on this canister's subnet.
+#[query(composite = true)]
+pub async fn get_chain_status() -> Result<ChainStatus, String> {
+    let link = chain::get_link().ok_or("no chain link set")?;
+    chain::status(&link).await
+}
+
+// ------------------------------------------------------------
+// External work source - an off-chain pool fed over HTTPS outcalls
+// ------------------------------------------------------------
+
+/// Poll an HTTPS endpoint for work templates and mine each new one as a job,
+/// POSTing found nonces back (None stops polling). Outcalls are paid from
+/// this canister's cycles.
+#[update]
+pub fn set_work_source(source: Option<WorkSource>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_work_source", format!("{:?}", source));
+    if let Some(s) = &source {
+        if s.poll_interval_secs == 0 || s.chunk_size == 0 {
+            ic_cdk::trap("poll_interval_secs and chunk_size must be at least 1");
+        }
+        if s.weight == Some(0) {
+            ic_cdk::trap("job weight must be at least 1");
+        }
+        if !s.template_url.starts_with("https://") || !s.submit_url.starts_with("https://") {
+            ic_cdk::trap("work source URLs must be https");
+        }
+    }
+    work_source::set_source(source);
+}
+
+#[query]
+pub fn get_work_source() -> Option<WorkSource> {
+    work_source::get_source()
+}
+
+#[query]
+pub fn get_work_source_status() -> WorkSourceStatus {
+    work_source::status()
+}
+
+/// Fetch the template now; true if it carried new work
+#[update]
+pub async fn poll_work_source() -> Result<bool, String> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("poll_work_source", String::new());
+    work_source::poll().await
+}
+
+// ------------------------------------------------------------
+// Solution cache sync across the fleet
+// ------------------------------------------------------------
+
+/// Periodically copy every miner's newly cached solutions to the rest of
+/// the fleet (None stops it). The coordinator must control the miners.
+#[update]
+pub fn set_cache_sync_interval(secs: Option<u64>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_cache_sync_interval", format!("{:?}", secs));
+    if secs == Some(0) {
+        ic_cdk::trap("interval must be at least 1 second");
+    }
+    cache_sync::set_interval(secs);
+}
+
+#[query]
+pub fn get_cache_sync_interval() -> Option<u64> {
+    cache_sync::get_interval()
+}
+
+/// Run one sync now; returns how many entries were newly imported
+#[update]
+pub async fn sync_miner_caches() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("sync_miner_caches", String::new());
+    cache_sync::sync().await
+}
+
+// ------------------------------------------------------------
+// Tick timer - only armed while a job is running
+// ------------------------------------------------------------
+
+/// Arm the tick timer unless it is already running
+pub(crate) fn ensure_tick_timer() {
+    if TICK_TIMER.with(|t| t.get()).is_none() {
+        arm_tick_timer();
+    }
+}
+
+fn arm_tick_timer() {
+    disarm_tick_timer();
+
+    let interval = Duration::from_millis(TICK_INTERVAL_MS.with(|i| i.get()));
+    let id = set_timer_interval(interval, coordinator_tick);
+    let health = set_timer_interval(HEALTH_CHECK_INTERVAL, || {
+        health_check();
+        fleet::autoscale();
     });
+
+    TICK_TIMER.with(|t| t.set(Some(id)));
+    HEALTH_TIMER.with(|t| t.set(Some(health)));
+}
+
+fn disarm_tick_timer() {
+    if let Some(id) = TICK_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+    if let Some(id) = HEALTH_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+}
+
+fn coordinator_tick() {
+    if has_pending_cancels() {
+        flush_cancels();
+    }
+
+    if !is_running() {
+        if !has_pending_cancels() {
+            disarm_tick_timer();
+            fleet::scale_down();
+        }
+        return;
+    }
+
+    tick();
 }
 
 // ------------------------------------------------------------
 // Shared types (must match miner canister)
 // ------------------------------------------------------------
 
-#[derive(CandidType, Deserialize, Clone)]
-pub enum MiningStatus {
-    Found {
-        hash: String,
-        nonce: u64,
-    },
-    Continue {
-        next_nonce: u64,
-    },
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum MinerError {
+    RateLimited { retry_after_ms: u64 },
+    NotCoordinator,
+    InputTooLarge { field: String, value: u64, max: u64 },
+    InvalidTarget { len: u64 },
+    UnsupportedVersion { version: u32, supported: u32 },
+    /// The miner dropped the job; nothing was searched
+    Cancelled,
 }
 
 
@@ -77,33 +936,32 @@ pub struct MiningResult {
     pub hash: String,
 }
 
-// ------------------------------------------------------------
-// Deterministic VRF-like helpers
-// ------------------------------------------------------------
-
-fn vrf_seed(prev_block_hash: &str, round: u64) -> [u8; 32] {
-    let mut h = Sha256::new();
-    h.update(prev_block_hash.as_bytes());
-    h.update(round.to_le_bytes());
-    h.finalize().into()
-}
-
-fn offset_for_miner(seed: &[u8; 32], miner_index: u64) -> u64 {
-    let mut h = Sha256::new();
-    h.update(seed);
-    h.update(miner_index.to_le_bytes());
-    let out = h.finalize();
-
-    let mut buf = [0u8; 8];
-    buf.copy_from_slice(&out[0..8]);
-    u64::from_le_bytes(buf)
+/// Work handed out outside the scheduler goes out as job 0, which the
+/// scheduler never assigns
+fn one_off_job(block_data: String, difficulty: u32, prev_hash: Option<String>, start: u64, size: u64) -> JobNotify {
+    JobNotify {
+        version: PROTOCOL_VERSION,
+        job_id: 0,
+        prev_hash,
+        block_data,
+        extranonce_start: start,
+        extranonce_size: size,
+        target: target::from_difficulty(difficulty).to_vec(),
+        algorithm: None,
+        clean_jobs: false,
+        session_token: None,
+        share_target: None,
+    }
 }
 
 // ------------------------------------------------------------
 // VRF based parallel coordinator (single round fan-out)
 // ------------------------------------------------------------
 
+/// With `use_beacon`, raw_rand output is mixed into the seed so offsets can't
+/// be pre-computed; the beacon is kept in the round record (`get_vrf_round`)
 #[update]
+#[allow(clippy::too_many_arguments)]
 pub async fn start_vrf_parallel_mining(
     miner_canisters: Vec<Principal>,
     block_data: String,
@@ -112,9 +970,17 @@ pub async fn start_vrf_parallel_mining(
     round: u64,
     base_start: u64,
     range_per_miner: u64,
+    use_beacon: Option<bool>,
 ) -> Option<MiningResult> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("start_vrf_parallel_mining", format!("{:?}", (&miner_canisters, canister_auth::audit::digest(block_data.as_bytes()), &difficulty, &prev_block_hash, &round, &base_start, &range_per_miner, &use_beacon)));
 
-    let seed = vrf_seed(&prev_block_hash, round);
+    let beacon = match use_beacon {
+        Some(true) => Some(vrf::fetch_beacon().await),
+        _ => None,
+    };
+    let seed = vrf_seed(&prev_block_hash, round, beacon.as_deref());
+    let round_id = vrf::open_round(prev_block_hash.clone(), round, beacon, seed, miner_canisters.clone());
 
     let mut calls = Vec::new();
 
@@ -127,33 +993,29 @@ pub async fn start_vrf_parallel_mining(
         .wrapping_add(offset)
         .wrapping_add((i as u64) * range_per_miner);
 
-        let fut = call::<
-        (String, u32, u64, u64),
-        ((MiningStatus, u64),)
-        >(
-            *miner,
-          "mine_chunk_with_midstate",
-          (
-              block_data.clone(),
-           difficulty,
-           start,
-           range_per_miner,
-          ),
-        );
-
-        calls.push(fut);
-    }
-
-    // First valid solution wins
-    for fut in calls {
-        if let Ok(((status, _attempts),)) = fut.await {
-            if let MiningStatus::Found { nonce, hash } = status {
-                return Some(MiningResult {
-                    found: true,
-                    nonce,
-                    hash,
-                });
-            }
+        let job = one_off_job(block_data.clone(), difficulty, Some(prev_block_hash.clone()), start, range_per_miner);
+        let fut = call::<(JobNotify,), (Result<JobSubmit, MinerError>,)>(*miner, "mine_job", (job,));
+
+        calls.push(Box::pin(async move { (i, fut.await) }));
+    }
+
+    // First valid solution wins - race the calls instead of awaiting in order
+    while !calls.is_empty() {
+        let ((winner, res), _, rest) = select_all(calls).await;
+        calls = rest;
+
+        if let Ok((Ok(JobSubmit { found: true, nonce, hash, .. }),)) = res {
+            vrf::close_round(round_id, miner_canisters[winner], nonce, hash.clone());
+
+            // The losers aren't stopped: each `mine_job` runs in a single
+            // message, and cancelling the shared one-off job id would also
+            // refuse later rounds. Their replies are dropped with `calls`.
+
+            return Some(MiningResult {
+                found: true,
+                nonce,
+                hash,
+            });
         }
     }
 
@@ -172,36 +1034,155 @@ pub async fn assign_one_chunk(
     start_nonce: u64,
     chunk_size: u64,
 ) -> Option<MiningResult> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("assign_one_chunk", format!("{:?}", (&miner, canister_auth::audit::digest(block_data.as_bytes()), &difficulty, &start_nonce, &chunk_size)));
 
-    let res = call::<
-    (String, u32, u64, u64),
-    ((MiningStatus, u64),)
-    >(
-        miner,
-      "mine_chunk_with_midstate",
-      (
-          block_data,
-       difficulty,
-       start_nonce,
-       chunk_size,
-      ),
-    )
-    .await;
+    let job = one_off_job(block_data, difficulty, None, start_nonce, chunk_size);
+    let res = call::<(JobNotify,), (Result<JobSubmit, MinerError>,)>(miner, "mine_job", (job,)).await;
 
-    if let Ok(((status, _attempts),)) = res {
-        if let MiningStatus::Found { nonce, hash } = status {
-            return Some(MiningResult {
-                found: true,
-                nonce,
-                hash,
-            });
-        }
+    if let Ok((Ok(JobSubmit { found: true, nonce, hash, .. }),)) = res {
+        return Some(MiningResult {
+            found: true,
+            nonce,
+            hash,
+        });
     }
 
     None
 }
 
+/// Stats for `job_id`, or for the most recently started job
 #[query]
-pub fn get_scheduler_stats() -> Option<SchedulerStats> {
-    scheduler_stats()
+pub fn get_scheduler_stats(job_id: Option<u64>) -> Option<SchedulerStats> {
+    scheduler_stats(job_id)
+}
+
+/// Stats for every job started since install, oldest first
+#[query]
+pub fn list_jobs() -> Vec<SchedulerStats> {
+    job_list()
+}
+
+/// Every distinct verified solution for a job, lowest hash first
+#[query]
+pub fn get_all_solutions(job_id: u64) -> Vec<FoundSolution> {
+    all_solutions(job_id)
+}
+
+/// Verified shares per miner for a job started with a share_difficulty
+#[query]
+pub fn get_shares(job_id: u64) -> ShareTally {
+    shares::get_shares(job_id)
+}
+
+/// How a solved job's reward splits over the last shares (PPLNS); None
+/// until it's solved
+#[query]
+pub fn get_payouts(job_id: u64) -> Option<PayoutBreakdown> {
+    payouts::get_payouts(job_id)
+}
+
+/// Estimated cycles, instructions and chunks a job has consumed on miners
+#[query]
+pub fn get_job_cost(job_id: u64) -> Option<JobCost> {
+    job_cost(job_id)
+}
+
+/// Nonce ranges that are assigned but unconfirmed, or waiting to be retried
+#[query]
+pub fn get_unsearched_ranges(job_id: Option<u64>) -> Vec<NonceRange> {
+    unsearched_ranges(job_id)
+}
+
+#[query]
+pub fn get_miner_stats() -> Vec<MinerStats> {
+    miner_stats()
+}
+
+/// Top miners by estimated hashrate (defaults to 10 entries)
+#[query]
+pub fn get_miner_leaderboard(limit: Option<u64>) -> Vec<MinerStats> {
+    miner_leaderboard(limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE) as usize)
+}
+
+/// Per-miner acknowledgment of a job's cancellation (default: latest job)
+#[query]
+pub fn get_cancel_acks(job_id: Option<u64>) -> Vec<CancelAck> {
+    cancel_acks(job_id)
+}
+
+/// Audit record of a VRF round: seed inputs, beacon and winner
+#[query]
+pub fn get_vrf_round(round_id: u64) -> Option<VrfRound> {
+    vrf::get_round(round_id)
+}
+
+#[query]
+pub fn get_vrf_round_count() -> u64 {
+    vrf::round_count()
+}
+
+/// Scheduler events in append order, paginated (limit capped at 500)
+#[query]
+pub fn get_events(offset: u64, limit: u64) -> Vec<SchedulerEvent> {
+    events::get_events(offset, limit)
+}
+
+#[query]
+pub fn get_event_count() -> u64 {
+    events::event_count()
+}
+
+/// Prometheus text format at GET /metrics; served uncertified, so scrape
+/// through the raw domain
+#[query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    http::handle(req)
+}
+
+// ------------------------------------------------------------
+// Global config
+// ------------------------------------------------------------
+
+/// Follow the config canister's shared parameters (None goes back to the
+/// defaults); returns the version fetched (admin only)
+#[update]
+pub async fn set_config_canister(canister: Option<Principal>) -> Result<u64, String> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_config_canister", format!("{:?}", canister));
+    canister_config::connect(canister).await
+}
+
+#[query]
+pub fn get_config_canister() -> Option<Principal> {
+    canister_config::source()
+}
+
+/// The cached config; version 0 means the built-in defaults
+#[query]
+pub fn get_global_config() -> VersionedConfig {
+    canister_config::cached()
+}
+
+/// Pushed by the config canister on every change
+#[update]
+pub fn on_config_update(update: VersionedConfig) -> bool {
+    canister_config::accept_update(update)
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it. Types
+// from endpoints in submodules have to be in scope here.
+use ic_cdk::api::management_canister::http_request::{HttpResponse as OutcallResponse, TransformArgs};
+
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
 }
@@ -0,0 +1,228 @@
+// payouts.rs - Pay-Per-Last-N-Shares. When a job is solved its reward is
+// split over the last `window` shares the pool accepted, from any job, each
+// weighted by its work, so hopping in just before a block pays no better
+// than mining steadily. A settlement is logged with the window it used; the
+// share window and breakdowns are kept across upgrades.
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+
+use crate::events::{self, EventKind};
+
+pub const DEFAULT_PPLNS_WINDOW: u64 = 1_000;
+pub const MAX_PPLNS_WINDOW: u64 = 10_000;
+/// Each breakdown's `parts` sum to exactly this
+pub const PAYOUT_SCALE: u64 = 1_000_000_000;
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerPayout {
+    pub miner: Principal,
+    /// Shares of this miner inside the window
+    pub shares: u64,
+    pub work: f64,
+    /// Its cut of the reward, out of PAYOUT_SCALE
+    pub parts: u64,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct PayoutBreakdown {
+    pub job_id: u64,
+    pub block_hash: String,
+    pub finder: Principal,
+    pub window: u64,
+    /// Shares counted; below `window` until the pool has that many. With
+    /// none the finder gets everything.
+    pub shares: u64,
+    pub settled_at: u64,
+    /// Largest cut first
+    pub payouts: Vec<MinerPayout>,
+}
+
+thread_local! {
+    static WINDOW: Cell<u64> = const { Cell::new(DEFAULT_PPLNS_WINDOW) };
+    /// (miner, work) of the newest MAX_PPLNS_WINDOW accepted shares
+    static RECENT: RefCell<VecDeque<(Principal, f64)>> = const { RefCell::new(VecDeque::new()) };
+    static SETTLED: RefCell<BTreeMap<u64, PayoutBreakdown>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Kept across upgrades; the share events behind it may be compacted away
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    window: u64,
+    recent: VecDeque<(Principal, f64)>,
+    settled: BTreeMap<u64, PayoutBreakdown>,
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        window: window(),
+        recent: RECENT.with(|r| r.borrow().clone()),
+        settled: SETTLED.with(|s| s.borrow().clone()),
+    }
+}
+
+pub fn restore(snapshot: Snapshot) {
+    set_window(snapshot.window);
+    RECENT.with(|r| *r.borrow_mut() = snapshot.recent);
+    SETTLED.with(|s| *s.borrow_mut() = snapshot.settled);
+}
+
+pub fn set_window(n: u64) {
+    WINDOW.with(|w| w.set(n.clamp(1, MAX_PPLNS_WINDOW)));
+}
+
+pub fn window() -> u64 {
+    WINDOW.with(|w| w.get())
+}
+
+/// Split job `job_id`'s reward over the current window; its solution was
+/// found by `finder`. A job settles once.
+pub fn settle(job_id: u64, finder: Principal, hash: &str) {
+    if SETTLED.with(|s| s.borrow().contains_key(&job_id)) {
+        return;
+    }
+    let kind = EventKind::PayoutsSettled { finder, hash: hash.to_string(), window: window() };
+    apply(job_id, time(), &kind);
+    events::record(job_id, kind);
+}
+
+/// Fold one event into the share window or the settled breakdowns
+pub fn apply(job_id: u64, at: u64, kind: &EventKind) {
+    match kind {
+        EventKind::Share { miner, difficulty, .. } => RECENT.with(|r| {
+            let mut r = r.borrow_mut();
+            r.push_back((*miner, 2f64.powi(*difficulty as i32)));
+            if r.len() > MAX_PPLNS_WINDOW as usize {
+                r.pop_front();
+            }
+        }),
+        EventKind::PayoutsSettled { finder, hash, window } => {
+            let breakdown = breakdown(job_id, *finder, hash, *window, at);
+            SETTLED.with(|s| s.borrow_mut().insert(job_id, breakdown));
+        }
+        _ => {}
+    }
+}
+
+fn breakdown(job_id: u64, finder: Principal, hash: &str, window: u64, at: u64) -> PayoutBreakdown {
+    let mut per_miner: BTreeMap<Principal, (u64, f64)> = BTreeMap::new();
+    let counted = RECENT.with(|r| {
+        let r = r.borrow();
+        let n = (window as usize).min(r.len());
+        for &(miner, work) in r.iter().skip(r.len() - n) {
+            let e = per_miner.entry(miner).or_default();
+            e.0 += 1;
+            e.1 += work;
+        }
+        n as u64
+    });
+    if per_miner.is_empty() {
+        per_miner.insert(finder, (0, 0.0));
+    }
+
+    let weights: Vec<f64> = per_miner.values().map(|&(_, work)| work).collect();
+    let mut payouts: Vec<MinerPayout> = per_miner
+    .into_iter()
+    .zip(split(&weights))
+    .map(|((miner, (shares, work)), parts)| MinerPayout { miner, shares, work, parts })
+    .collect();
+    payouts.sort_by(|a, b| b.parts.cmp(&a.parts).then(a.miner.cmp(&b.miner)));
+
+    PayoutBreakdown {
+        job_id,
+        block_hash: hash.to_string(),
+        finder,
+        window,
+        shares: counted,
+        settled_at: at,
+        payouts,
+    }
+}
+
+/// PAYOUT_SCALE split in proportion to `weights` (evenly if they're all
+/// zero), the rounding remainder going to the largest fractions. The parts
+/// always sum to exactly PAYOUT_SCALE, even when float rounding overshoots.
+fn split(weights: &[f64]) -> Vec<u64> {
+    let total: f64 = weights.iter().sum();
+    let proportional = total > 0.0 && total.is_finite();
+    let exact: Vec<f64> = weights
+    .iter()
+    .map(|w| (if proportional { w / total } else { 1.0 / weights.len() as f64 }) * PAYOUT_SCALE as f64)
+    .collect();
+    let mut parts: Vec<u64> = exact.iter().map(|e| (e.floor() as u64).min(PAYOUT_SCALE)).collect();
+
+    let mut by_fraction: Vec<usize> = (0..parts.len()).collect();
+    by_fraction.sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    let given: u64 = parts.iter().sum();
+    let left = PAYOUT_SCALE.saturating_sub(given);
+    for &i in by_fraction.iter().cycle().take(left as usize) {
+        parts[i] += 1;
+    }
+    // Overshoot comes off the smallest fractions first
+    let mut excess = given.saturating_sub(PAYOUT_SCALE);
+    for &i in by_fraction.iter().rev().cycle() {
+        if excess == 0 {
+            break;
+        }
+        if parts[i] > 0 {
+            parts[i] -= 1;
+            excess -= 1;
+        }
+    }
+    parts
+}
+
+/// Rebuild the window and breakdowns from the event log of a canister saved
+/// before they were
+pub fn rebuild() {
+    RECENT.with(|r| r.borrow_mut().clear());
+    SETTLED.with(|s| s.borrow_mut().clear());
+    events::each(|ev| apply(ev.job_id, ev.timestamp, &ev.kind));
+}
+
+pub fn get_payouts(job_id: u64) -> Option<PayoutBreakdown> {
+    SETTLED.with(|s| s.borrow().get(&job_id).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_is_proportional_and_sums_to_scale() {
+        let parts = split(&[1.0, 3.0]);
+        assert_eq!(parts, vec![PAYOUT_SCALE / 4, PAYOUT_SCALE / 4 * 3]);
+
+        let parts = split(&[1.0, 1.0, 1.0]);
+        assert_eq!(parts.iter().sum::<u64>(), PAYOUT_SCALE);
+        assert!(parts.iter().all(|&p| p == PAYOUT_SCALE / 3 || p == PAYOUT_SCALE / 3 + 1));
+
+        assert_eq!(split(&[5.0]), vec![PAYOUT_SCALE]);
+    }
+
+    #[test]
+    fn split_evenly_without_weight() {
+        assert_eq!(split(&[0.0, 0.0]), vec![PAYOUT_SCALE / 2, PAYOUT_SCALE / 2]);
+        let parts = split(&[f64::INFINITY, 1.0]);
+        assert_eq!(parts.iter().sum::<u64>(), PAYOUT_SCALE);
+    }
+
+    #[test]
+    fn split_always_sums_to_exactly_scale() {
+        // Share work is 2^difficulty, so weights span many magnitudes
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for n in 1..200 {
+            let weights: Vec<f64> = (0..n)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                2f64.powi((seed % 64) as i32) * (1.0 + (seed >> 40) as f64 / (1u64 << 24) as f64)
+            })
+            .collect();
+            assert_eq!(split(&weights).iter().sum::<u64>(), PAYOUT_SCALE, "{} weights", n);
+        }
+    }
+}
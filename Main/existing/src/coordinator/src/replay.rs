@@ -0,0 +1,224 @@
+// replay.rs - rebuild a job's nonce-space ledger from the event log
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::events::{self, EventKind};
+use crate::scheduler::{JobLimits, Lease, LeaseStatus, NonceRange, RestoredJob};
+use pow_core::target;
+use crate::verify::PowAlgorithm;
+
+#[derive(Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum LeaseOutcome {
+    Pending,
+    Completed,
+    TimedOut,
+    Failed,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct ReplayedLease {
+    pub lease_id: u64,
+    pub miner: Principal,
+    pub start: u64,
+    pub size: u64,
+    pub outcome: LeaseOutcome,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct JobReplay {
+    pub job_id: u64,
+    pub block_data: String,
+    pub difficulty: u32,
+    pub algorithm: PowAlgorithm,
+    pub target: Vec<u8>,
+    pub share_target: Option<Vec<u8>>,
+    pub chunk_size: u64,
+    pub weight: u32,
+    pub start_nonce: u64,
+    pub end_nonce: u64,
+    pub deadline_ns: Option<u64>,
+    pub max_total_attempts: Option<u64>,
+    /// Every assignment in lease order, with its last recorded outcome
+    pub leases: Vec<ReplayedLease>,
+    /// Merged ranges some miner confirmed searching
+    pub searched: Vec<NonceRange>,
+    /// Gaps below `next_nonce` that were handed out but never confirmed
+    pub unfinished: Vec<NonceRange>,
+    /// Everything from here up was never assigned
+    pub next_nonce: u64,
+    /// Solved, expired or cancelled - nothing left to resume
+    pub finished: bool,
+    pub solution: Option<(u64, String)>,
+}
+
+/// Replay the job's events. Ranges count as searched only through a
+/// `Completed` event, so a lease that timed out and later completed is still
+/// searched, while one that only failed is not.
+pub fn replay_job(job_id: u64) -> Option<JobReplay> {
+    let mut log = events::for_job(job_id).into_iter();
+
+    let mut replay = match log.next()?.kind {
+        EventKind::JobStarted {
+            block_data,
+            difficulty,
+            start_nonce,
+            end_nonce,
+            chunk_size,
+            weight,
+            deadline_ns,
+            max_total_attempts,
+            algorithm,
+            target,
+            share_target,
+        } => JobReplay {
+            job_id,
+            block_data,
+            difficulty,
+            algorithm: algorithm.unwrap_or_default(),
+            target: target.unwrap_or_else(|| target::from_difficulty(difficulty).to_vec()),
+            share_target,
+            chunk_size,
+            weight,
+            start_nonce,
+            end_nonce,
+            deadline_ns,
+            max_total_attempts,
+            leases: Vec::new(),
+            searched: Vec::new(),
+            unfinished: Vec::new(),
+            next_nonce: start_nonce,
+            finished: false,
+            solution: None,
+        },
+        // Jobs started before JobStarted was logged can't be replayed
+        _ => return None,
+    };
+
+    let mut leases: BTreeMap<u64, ReplayedLease> = BTreeMap::new();
+
+    for ev in log {
+        match ev.kind {
+            EventKind::Assigned { miner, lease_id, start, size } => {
+                replay.next_nonce = replay.next_nonce.max(start.saturating_add(size));
+                leases.insert(lease_id, ReplayedLease {
+                    lease_id,
+                    miner,
+                    start,
+                    size,
+                    outcome: LeaseOutcome::Pending,
+                });
+            }
+            EventKind::Completed { lease_id, .. } => set_outcome(&mut leases, lease_id, LeaseOutcome::Completed),
+            EventKind::TimedOut { lease_id, .. } => set_outcome(&mut leases, lease_id, LeaseOutcome::TimedOut),
+            EventKind::Failed { lease_id, .. } => set_outcome(&mut leases, lease_id, LeaseOutcome::Failed),
+            EventKind::Solution { nonce, hash, .. } => {
+                replay.solution = Some((nonce, hash));
+                replay.finished = true;
+            }
+            EventKind::Expired { .. } | EventKind::StopBroadcast { .. } => replay.finished = true,
+            EventKind::JobStarted { .. }
+            | EventKind::JobResumed { .. }
+            | EventKind::Rejected { .. }
+            | EventKind::Share { .. }
+            | EventKind::ShareRejected { .. }
+            | EventKind::PayoutsSettled { .. }
+            | EventKind::BlockSubmitted { .. }
+            | EventKind::Compacted { .. } => {}
+        }
+    }
+
+    let mut searched: Vec<(u64, u64)> = leases
+    .values()
+    .filter(|l| l.outcome == LeaseOutcome::Completed)
+    .map(|l| (l.start, l.start.saturating_add(l.size)))
+    .collect();
+    searched.sort();
+
+    let merged = merge(searched);
+    replay.unfinished = gaps(&merged, replay.start_nonce, replay.next_nonce);
+    replay.searched = merged
+    .into_iter()
+    .map(|(start, end)| NonceRange { start, size: end - start })
+    .collect();
+    replay.leases = leases.into_values().collect();
+
+    Some(replay)
+}
+
+fn set_outcome(leases: &mut BTreeMap<u64, ReplayedLease>, lease_id: u64, outcome: LeaseOutcome) {
+    if let Some(l) = leases.get_mut(&lease_id) {
+        // A completion is final; a late failure report doesn't undo it
+        if l.outcome != LeaseOutcome::Completed {
+            l.outcome = outcome;
+        }
+    }
+}
+
+/// Merge sorted half-open intervals
+fn merge(sorted: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    let mut out: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in sorted {
+        match out.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => out.push((start, end)),
+        }
+    }
+    out
+}
+
+/// Parts of `[from, to)` not covered by the merged intervals
+fn gaps(merged: &[(u64, u64)], from: u64, to: u64) -> Vec<NonceRange> {
+    let mut out = Vec::new();
+    let mut cursor = from;
+    for &(start, end) in merged {
+        if start > cursor {
+            out.push(NonceRange { start: cursor, size: start.min(to) - cursor });
+        }
+        cursor = cursor.max(end);
+        if cursor >= to {
+            return out;
+        }
+    }
+    if cursor < to {
+        out.push(NonceRange { start: cursor, size: to - cursor });
+    }
+    out
+}
+
+/// Turn a replay into the scheduler's restore input
+pub fn to_restored(replay: &JobReplay) -> RestoredJob {
+    RestoredJob {
+        block_data: replay.block_data.clone(),
+        algorithm: replay.algorithm,
+        target: target::parse(&replay.target).unwrap_or_else(|_| target::from_difficulty(replay.difficulty)),
+        share_target: replay.share_target.as_deref().and_then(|t| target::parse(t).ok()),
+        chunk_size: replay.chunk_size,
+        weight: replay.weight,
+        limits: JobLimits {
+            deadline_ns: replay.deadline_ns,
+            max_total_attempts: replay.max_total_attempts,
+            end_nonce: Some(replay.end_nonce),
+        },
+        leases: replay
+        .leases
+        .iter()
+        .map(|l| {
+            let lease = Lease {
+                id: l.lease_id,
+                group: l.lease_id,
+                stolen_from: None,
+                miner: l.miner,
+                start: l.start,
+                size: l.size,
+                status: LeaseStatus::Completed,
+                assigned_at: 0,
+                session_token: None,
+            };
+            (lease, l.outcome == LeaseOutcome::Completed)
+        })
+        .collect(),
+        unfinished: replay.unfinished.clone(),
+        next_nonce: replay.next_nonce,
+    }
+}
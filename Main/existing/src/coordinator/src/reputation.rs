@@ -0,0 +1,203 @@
+// reputation.rs - a trust score per miner principal. Verified shares and
+// solutions raise it; invalid solutions, rejected shares, timeouts and
+// attempt counts larger than the range searched lower it. A low score
+// shrinks the miner's chunks, and below the policy's threshold the miner
+// isn't scheduled at all. Admins can pin a miner banned or trusted.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Principal};
+
+/// Where every miner starts, and the score at which chunks are full size
+pub const START_SCORE: u32 = 500;
+pub const MAX_SCORE: u32 = 1_000;
+/// The smallest chunk share a low score can shrink a miner to (per mille)
+const MIN_WEIGHT_PER_MILLE: u64 = 250;
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct ReputationPolicy {
+    pub share_bonus: u32,
+    pub solution_bonus: u32,
+    pub invalid_solution_penalty: u32,
+    pub rejected_share_penalty: u32,
+    pub timeout_penalty: u32,
+    pub inflated_attempts_penalty: u32,
+    /// Miners scoring below this aren't scheduled; None bans nobody
+    pub ban_below: Option<u32>,
+}
+
+impl Default for ReputationPolicy {
+    fn default() -> Self {
+        Self {
+            share_bonus: 1,
+            solution_bonus: 20,
+            invalid_solution_penalty: 200,
+            rejected_share_penalty: 25,
+            timeout_penalty: 10,
+            inflated_attempts_penalty: 100,
+            ban_below: None,
+        }
+    }
+}
+
+/// An admin decision that wins over the score
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ReputationOverride {
+    Banned,
+    /// Never banned and never shrunk, whatever the score
+    Trusted,
+}
+
+/// What a miner did that moves its score
+pub enum Conduct {
+    Shares(u64),
+    Solution,
+    InvalidSolution,
+    RejectedShares(u64),
+    Timeout,
+    InflatedAttempts,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+struct Entry {
+    score: u32,
+    pinned: Option<ReputationOverride>,
+    slashes: u64,
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        Self { score: START_SCORE, pinned: None, slashes: 0 }
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerReputation {
+    pub miner: Principal,
+    pub score: u32,
+    pub pinned: Option<ReputationOverride>,
+    /// Penalties applied so far
+    pub slashes: u64,
+    /// Whether the scheduler currently skips this miner
+    pub banned: bool,
+}
+
+/// Kept across upgrades
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    policy: ReputationPolicy,
+    miners: BTreeMap<Principal, Entry>,
+}
+
+thread_local! {
+    static STATE: RefCell<Snapshot> = RefCell::new(Snapshot::default());
+}
+
+pub fn snapshot() -> Snapshot {
+    STATE.with(|s| s.borrow().clone())
+}
+
+pub fn restore(snapshot: Snapshot) {
+    STATE.with(|s| *s.borrow_mut() = snapshot);
+}
+
+pub fn set_policy(policy: ReputationPolicy) {
+    STATE.with(|s| s.borrow_mut().policy = policy);
+}
+
+pub fn policy() -> ReputationPolicy {
+    STATE.with(|s| s.borrow().policy)
+}
+
+pub fn record(miner: Principal, conduct: Conduct) {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let p = s.policy;
+        let (bonus, penalty) = match conduct {
+            Conduct::Shares(n) => (p.share_bonus as u64 * n, 0),
+            Conduct::Solution => (p.solution_bonus as u64, 0),
+            Conduct::InvalidSolution => (0, p.invalid_solution_penalty as u64),
+            Conduct::RejectedShares(n) => (0, p.rejected_share_penalty as u64 * n),
+            Conduct::Timeout => (0, p.timeout_penalty as u64),
+            Conduct::InflatedAttempts => (0, p.inflated_attempts_penalty as u64),
+        };
+        if bonus == 0 && penalty == 0 {
+            return;
+        }
+
+        let e = s.miners.entry(miner).or_default();
+        let score = (e.score as u64 + bonus).saturating_sub(penalty);
+        e.score = score.min(MAX_SCORE as u64) as u32;
+        if penalty > 0 {
+            e.slashes += 1;
+        }
+    });
+}
+
+fn banned(policy: &ReputationPolicy, e: &Entry) -> bool {
+    match e.pinned {
+        Some(ReputationOverride::Banned) => true,
+        Some(ReputationOverride::Trusted) => false,
+        None => policy.ban_below.is_some_and(|t| e.score < t),
+    }
+}
+
+/// True if the scheduler must skip this miner
+pub fn is_banned(miner: Principal) -> bool {
+    STATE.with(|s| {
+        let s = s.borrow();
+        s.miners.get(&miner).is_some_and(|e| banned(&s.policy, e))
+    })
+}
+
+/// `quantum` shrunk in proportion to a score below START_SCORE, down to a
+/// quarter; untouched for trusted miners and scores at or above the start
+pub fn scale(miner: Principal, quantum: u64) -> u64 {
+    let per_mille = STATE.with(|s| {
+        match s.borrow().miners.get(&miner) {
+            Some(e) if e.pinned != Some(ReputationOverride::Trusted) => {
+                (e.score as u64 * 1_000 / START_SCORE as u64).clamp(MIN_WEIGHT_PER_MILLE, 1_000)
+            }
+            _ => 1_000,
+        }
+    });
+    (quantum as u128 * per_mille as u128 / 1_000) as u64
+}
+
+/// Pin a miner banned or trusted; None goes back to the score
+pub fn set_override(miner: Principal, pinned: Option<ReputationOverride>) {
+    STATE.with(|s| s.borrow_mut().miners.entry(miner).or_default().pinned = pinned);
+}
+
+/// Back to START_SCORE with no slashes; an override stays
+pub fn reset(miner: Principal) -> bool {
+    STATE.with(|s| match s.borrow_mut().miners.get_mut(&miner) {
+        Some(e) => {
+            e.score = START_SCORE;
+            e.slashes = 0;
+            true
+        }
+        None => false,
+    })
+}
+
+/// One miner, or every miner with a record (lowest score first)
+pub fn get_reputation(miner: Option<Principal>) -> Vec<MinerReputation> {
+    STATE.with(|s| {
+        let s = s.borrow();
+        let mut out: Vec<MinerReputation> = s
+        .miners
+        .iter()
+        .filter(|(id, _)| miner.is_none_or(|m| m == **id))
+        .map(|(id, e)| MinerReputation {
+            miner: *id,
+            score: e.score,
+            pinned: e.pinned,
+            slashes: e.slashes,
+            banned: banned(&s.policy, e),
+        })
+        .collect();
+        out.sort_by_key(|r| (r.score, r.miner));
+        out
+    })
+}
@@ -1,11 +1,36 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
 
 use candid::{CandidType, Deserialize, Principal};
+use futures::future::join_all;
 use ic_cdk::api::{call::call, time};
 use ic_cdk::spawn;
 
-const ASSIGN_TIMEOUT_NS: u64 = 10_000_000_000; // 10s
-const MAX_FAILURES: u32 = 3;
+use crate::chain;
+use crate::events::{self, EventKind};
+use crate::payouts;
+use crate::reputation::{self, Conduct};
+use crate::stake;
+use crate::protocol::{JobNotify, JobSubmit, PROTOCOL_VERSION};
+use crate::session;
+use crate::shares::{self, ShareCheck};
+use crate::subscriptions;
+use pow_core::target::{self, Target};
+use crate::verify::{self, PowAlgorithm};
+use crate::work_source;
+use crate::MinerError;
+
+const HEALTH_REHAB_SUCCESSES: u32 = 3;
+const MAX_CANCEL_ATTEMPTS: u32 = 5;
+/// Chunks are sized between chunk_size / 4 and chunk_size * 4
+const MAX_CHUNK_SCALE: u64 = 4;
+
+/// Leases smaller than twice this are not split for work stealing
+const MIN_STEAL_SIZE: u64 = 1_000;
+
+// Cycle fees on a 13-node application subnet
+const UPDATE_CALL_FEE: u128 = 590_000;
+const FEE_PER_10_INSTRUCTIONS: u128 = 4;
 
 #[derive(Clone)]
 pub struct MinerSlot {
@@ -13,71 +38,505 @@ pub struct MinerSlot {
     pub busy: bool,
     pub assigned_at: u64,
     pub failures: u32,
+    pub backoff_until_tick: u64,
     pub total_chunks: u64,
     pub successful_chunks: u64,
+    pub health_successes: u32,
+    /// (job_id, lease_id) of the chunk in flight
+    pub lease: Option<(u64, u64)>,
+    /// Further leases of the same job sent in the same call as `lease`
+    pub batch: Vec<u64>,
+    pub total_attempts: u64,
+    pub mining_ns: u64,
+    pub solutions_found: u64,
+    pub last_seen: u64,
+    pub draining: bool,
+    /// Nonces this miner is owed under deficit round-robin
+    pub deficit: u64,
+    /// Reported solutions that failed local re-verification
+    pub invalid_solutions: u64,
 }
 
-pub struct CoordinatorState {
-    pub miners: Vec<MinerSlot>,
+impl MinerSlot {
+    fn new(id: Principal) -> Self {
+        Self {
+            id,
+            busy: false,
+            assigned_at: 0,
+            failures: 0,
+            backoff_until_tick: 0,
+            total_chunks: 0,
+            successful_chunks: 0,
+            health_successes: 0,
+            lease: None,
+            batch: Vec::new(),
+            total_attempts: 0,
+            mining_ns: 0,
+            solutions_found: 0,
+            last_seen: 0,
+            draining: false,
+            deficit: 0,
+            invalid_solutions: 0,
+        }
+    }
+
+    fn job(&self) -> Option<u64> {
+        self.lease.map(|(job_id, _)| job_id)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, CandidType, Deserialize)]
+pub enum LeaseStatus {
+    Active,
+    Completed,
+    Requeued,
+    /// A replica that failed while another copy of the range was still out
+    Abandoned,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Lease {
+    pub id: u64,
+    /// Id of the first lease for this range; replicas share it
+    pub group: u64,
+    /// Set on a lease carved off the tail of another miner's lease
+    pub stolen_from: Option<u64>,
+    pub miner: Principal,
+    pub start: u64,
+    pub size: u64,
+    pub status: LeaseStatus,
+    pub assigned_at: u64,
+    /// Token issued with the call carrying this lease; the miner's reply
+    /// must echo it
+    pub session_token: Option<u64>,
+}
+
+#[derive(Clone, Copy, CandidType, Deserialize)]
+pub struct NonceRange {
+    pub start: u64,
+    pub size: u64,
+}
+
+/// A failing miner is skipped for `base_ticks * 2^failures` ticks, with the
+/// exponent capped at `max_exponent`
+#[derive(Clone, Debug, Copy, CandidType, Deserialize)]
+pub struct BackoffPolicy {
+    pub base_ticks: u64,
+    pub max_exponent: u32,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            base_ticks: 1,
+            max_exponent: 10,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    fn ticks_for(&self, failures: u32) -> u64 {
+        let exp = failures.min(self.max_exponent).min(63);
+        self.base_ticks.saturating_mul(1u64 << exp)
+    }
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct CancelAck {
+    pub miner: Principal,
+    pub acked: bool,
+    pub attempts: u32,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct FoundSolution {
+    pub nonce: u64,
+    pub hash: String,
+    pub miner: Principal,
+    pub found_at: u64,
+}
+
+/// One (block_data, target) pair and its nonce-space bookkeeping
+pub struct Job {
+    pub id: u64,
+    pub block_data: String,
+    pub algorithm: PowAlgorithm,
+    /// What solutions are checked against; the difficulty's target unless
+    /// the job was started with an explicit one
+    pub target: Target,
+    /// Easier target miners send shares against; None takes no shares
+    pub share_target: Option<Target>,
+    pub weight: u32,
+    pub leases: Vec<Lease>,
+    pub retry_pool: VecDeque<NonceRange>,
     pub next_nonce: u64,
+    /// Exclusive upper bound of the job's nonce space
+    pub end_nonce: u64,
     pub chunk_size: u64,
     pub running: bool,
-    pub rr_cursor: usize,
+    /// Lowest-hash entry of `solutions`
     pub solution_found: Option<(u64, String)>,
+    /// Every distinct verified solution, in arrival order
+    pub solutions: Vec<FoundSolution>,
     pub total_chunks_assigned: u64,
     pub started_at: u64,
+    pub cancels: Vec<CancelAck>,
+    pub cancel_in_flight: bool,
+    /// Absolute IC time after which the job is aborted
+    pub deadline_ns: Option<u64>,
+    pub max_total_attempts: Option<u64>,
+    pub total_attempts: u64,
+    pub expired: bool,
+    /// Instructions reported by miners across all completed chunks
+    pub instructions: u64,
+    pub chunks_completed: u64,
+}
+
+/// Optional abort conditions for a job
+#[derive(Clone, Copy, Default)]
+pub struct JobLimits {
+    pub deadline_ns: Option<u64>,
+    pub max_total_attempts: Option<u64>,
+    pub end_nonce: Option<u64>,
+}
+
+/// The miner fleet is shared; each idle miner goes to whichever running job
+/// is furthest below its weighted share
+#[derive(Default)]
+pub struct CoordinatorState {
+    pub miners: Vec<MinerSlot>,
+    pub jobs: BTreeMap<u64, Job>,
+    pub rr_cursor: usize,
+    pub tick: u64,
 }
 
 thread_local! {
-    static STATE: RefCell<Option<CoordinatorState>> = RefCell::new(None);
+    static STATE: RefCell<CoordinatorState> = RefCell::new(CoordinatorState::default());
+    static NEXT_JOB_ID: RefCell<u64> = const { RefCell::new(0) };
+    static BACKOFF: RefCell<BackoffPolicy> = RefCell::new(BackoffPolicy::default());
+    static REDUNDANCY: Cell<u32> = const { Cell::new(1) };
+    static BATCH_SIZE: Cell<u32> = const { Cell::new(1) };
 }
 
 // ------------------------------------------------------------
 // Public API
 // ------------------------------------------------------------
 
-pub fn start_scheduler(miners: Vec<Principal>, start_nonce: u64, chunk_size: u64) {
-    let slots = miners
-    .into_iter()
-    .map(|p| MinerSlot {
-        id: p,
-         busy: false,
-         assigned_at: 0,
-         failures: 0,
-         total_chunks: 0,
-         successful_chunks: 0,
-    })
-    .collect();
+/// Start a new job next to any already running ones. `miners` join the
+/// shared fleet if they are not in it yet.
+#[allow(clippy::too_many_arguments)]
+pub fn start_scheduler(
+    miners: Vec<Principal>,
+    block_data: String,
+    difficulty: u32,
+    algorithm: PowAlgorithm,
+    target: Target,
+    share_target: Option<Target>,
+    start_nonce: u64,
+    chunk_size: u64,
+    weight: u32,
+    limits: JobLimits,
+) -> u64 {
+    let job_id = NEXT_JOB_ID.with(|n| {
+        let mut n = n.borrow_mut();
+        *n += 1;
+        *n
+    });
+
+    let end_nonce = limits.end_nonce.unwrap_or(u64::MAX);
+    events::record(job_id, EventKind::JobStarted {
+        block_data: block_data.clone(),
+        difficulty,
+        start_nonce,
+        end_nonce,
+        chunk_size,
+        weight,
+        deadline_ns: limits.deadline_ns,
+        max_total_attempts: limits.max_total_attempts,
+        algorithm: Some(algorithm),
+        target: Some(target.to_vec()),
+        share_target: share_target.map(|t| t.to_vec()),
+    });
 
     STATE.with(|s| {
-        *s.borrow_mut() = Some(CoordinatorState {
-            miners: slots,
-            next_nonce: start_nonce,
+        let mut st = s.borrow_mut();
+        join_fleet(&mut st, miners);
+
+        let mut job = Job::new(job_id, block_data, algorithm, target, chunk_size, weight, limits);
+        job.share_target = share_target;
+        job.next_nonce = start_nonce;
+        job.end_nonce = end_nonce;
+        st.jobs.insert(job_id, job);
+    });
+
+    job_id
+}
+
+impl Job {
+    fn new(
+        id: u64,
+        block_data: String,
+        algorithm: PowAlgorithm,
+        target: Target,
+        chunk_size: u64,
+        weight: u32,
+        limits: JobLimits,
+    ) -> Self {
+        Self {
+            id,
+            block_data,
+            algorithm,
+            target,
+            share_target: None,
+            weight,
+            leases: Vec::new(),
+            retry_pool: VecDeque::new(),
+            next_nonce: 0,
+            end_nonce: u64::MAX,
             chunk_size,
             running: true,
-            rr_cursor: 0,
             solution_found: None,
+            solutions: Vec::new(),
             total_chunks_assigned: 0,
             started_at: time(),
-        });
-    });
+            cancels: Vec::new(),
+            cancel_in_flight: false,
+            deadline_ns: limits.deadline_ns,
+            max_total_attempts: limits.max_total_attempts,
+            total_attempts: 0,
+            expired: false,
+            instructions: 0,
+            chunks_completed: 0,
+        }
+    }
 }
 
-pub fn stop_scheduler() {
+fn join_fleet(st: &mut CoordinatorState, miners: Vec<Principal>) {
+    for miner in miners {
+        match st.miners.iter_mut().find(|m| m.id == miner) {
+            Some(slot) => slot.draining = false,
+            None => st.miners.push(MinerSlot::new(miner)),
+        }
+    }
+}
+
+/// Parameters of a job being rebuilt from its event log
+pub struct RestoredJob {
+    pub block_data: String,
+    pub algorithm: PowAlgorithm,
+    pub target: Target,
+    pub share_target: Option<Target>,
+    pub chunk_size: u64,
+    pub weight: u32,
+    pub limits: JobLimits,
+    /// Every lease ever issued, with whether its range was searched
+    pub leases: Vec<(Lease, bool)>,
+    pub unfinished: Vec<NonceRange>,
+    pub next_nonce: u64,
+}
+
+/// Re-create a job under its old id. Old leases keep their ids so new ones
+/// continue the sequence; unsearched ranges go to the retry pool.
+pub fn restore_job(job_id: u64, miners: Vec<Principal>, restored: RestoredJob) -> bool {
     STATE.with(|s| {
-        if let Some(st) = s.borrow_mut().as_mut() {
-            st.running = false;
+        let mut st = s.borrow_mut();
+        if st.jobs.get(&job_id).is_some_and(|j| j.running) {
+            return false;
         }
+        join_fleet(&mut st, miners);
+
+        let mut job = Job::new(
+            job_id,
+            restored.block_data,
+            restored.algorithm,
+            restored.target,
+            restored.chunk_size,
+            restored.weight,
+            restored.limits,
+        );
+        job.share_target = restored.share_target;
+        job.next_nonce = restored.next_nonce;
+        job.end_nonce = restored.limits.end_nonce.unwrap_or(u64::MAX);
+        job.total_chunks_assigned = restored.leases.len() as u64;
+        job.leases = restored
+        .leases
+        .into_iter()
+        .map(|(mut lease, searched)| {
+            lease.status = if searched { LeaseStatus::Completed } else { LeaseStatus::Abandoned };
+            lease
+        })
+        .collect();
+        job.retry_pool = restored.unfinished.into_iter().collect();
+
+        st.jobs.insert(job_id, job);
+        true
+    })
+}
+
+/// Keep job ids unique across upgrades
+pub fn next_job_id() -> u64 {
+    NEXT_JOB_ID.with(|n| *n.borrow())
+}
+
+pub fn restore_next_job_id(id: u64) {
+    NEXT_JOB_ID.with(|n| *n.borrow_mut() = id);
+}
+
+/// Stop one job; false if it is unknown or already stopped
+pub fn stop_job(job_id: u64) -> bool {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let st = &mut *st;
+        match st.jobs.get_mut(&job_id) {
+            Some(job) if job.running => {
+                job.running = false;
+                queue_cancels(job, &st.miners);
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Stop every running job
+pub fn stop_scheduler() {
+    let running: Vec<u64> = STATE.with(|s| {
+        s.borrow().jobs.values().filter(|j| j.running).map(|j| j.id).collect()
     });
+    for job_id in running {
+        stop_job(job_id);
+    }
+}
+
+/// Change a job's share of the fleet; false if the job is unknown
+pub fn set_job_weight(job_id: u64, weight: u32) -> bool {
+    STATE.with(|s| {
+        s.borrow_mut()
+        .jobs
+        .get_mut(&job_id)
+        .map(|job| job.weight = weight)
+        .is_some()
+    })
+}
+
+/// Add a miner to the shared fleet; re-adding a draining miner keeps it.
+/// Returns false if the miner is already active.
+pub fn add_miner(miner: Principal) -> bool {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+
+        if let Some(slot) = st.miners.iter_mut().find(|m| m.id == miner) {
+            let was_draining = slot.draining;
+            slot.draining = false;
+            return was_draining;
+        }
+
+        st.miners.push(MinerSlot::new(miner));
+        true
+    })
+}
+
+/// Remove a miner. An idle miner goes immediately; a busy one stops getting
+/// work and is dropped once its in-flight chunk completes, fails or times out.
+pub fn remove_miner(miner: Principal) -> bool {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+
+        match st.miners.iter_mut().find(|m| m.id == miner) {
+            Some(slot) => slot.draining = true,
+            None => return false,
+        }
+
+        drop_drained(&mut st);
+        true
+    })
+}
+
+pub fn is_miner_busy(miner: Principal) -> bool {
+    STATE.with(|s| s.borrow().miners.iter().any(|m| m.id == miner && m.busy))
+}
+
+/// True while the miner is in the fleet, draining included
+pub fn has_miner(miner: Principal) -> bool {
+    STATE.with(|s| s.borrow().miners.iter().any(|m| m.id == miner))
+}
+
+fn drop_drained(st: &mut CoordinatorState) {
+    st.miners.retain(|m| !m.draining || m.busy);
+}
+
+pub fn set_backoff_policy(policy: BackoffPolicy) {
+    BACKOFF.with(|b| *b.borrow_mut() = policy);
+}
+
+pub fn get_backoff_policy() -> BackoffPolicy {
+    BACKOFF.with(|b| *b.borrow())
+}
+
+/// Hand each range to `k` miners at once and take the first result (1 = off)
+pub fn set_redundancy(k: u32) {
+    REDUNDANCY.with(|r| r.set(k.max(1)));
+}
+
+pub fn get_redundancy() -> u32 {
+    REDUNDANCY.with(|r| r.get())
+}
+
+/// Send up to `n` chunks per call to a miner (1 = off). Only applies while
+/// redundancy is off; replicated and stolen ranges always go alone.
+pub fn set_batch_size(n: u32) {
+    BATCH_SIZE.with(|b| b.set(n));
+}
+
+pub fn get_batch_size() -> u32 {
+    BATCH_SIZE.with(|b| b.get())
+}
+
+/// True while at least one job is still being scheduled
+pub fn is_running() -> bool {
+    STATE.with(|s| s.borrow().jobs.values().any(|j| j.running))
+}
+
+/// The given job, or the most recently started one
+fn resolve_job(st: &CoordinatorState, job_id: Option<u64>) -> Option<&Job> {
+    match job_id {
+        Some(id) => st.jobs.get(&id),
+        None => st.jobs.values().next_back(),
+    }
+}
+
+/// Ranges that are handed out but not yet confirmed searched, plus ranges
+/// waiting in the retry pool. Everything from `next_nonce` up is untouched.
+pub fn get_unsearched_ranges(job_id: Option<u64>) -> Vec<NonceRange> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        let job = match resolve_job(&st, job_id) {
+            Some(job) => job,
+            None => return Vec::new(),
+        };
+
+        let mut ranges: Vec<NonceRange> = job
+        .leases
+        .iter()
+        .filter(|l| l.status == LeaseStatus::Active)
+        .map(|l| NonceRange { start: l.start, size: l.size })
+        .chain(job.retry_pool.iter().copied())
+        .collect();
+
+        // Replicated ranges appear once
+        ranges.sort_by_key(|r| (r.start, r.size));
+        ranges.dedup_by_key(|r| (r.start, r.size));
+        ranges
+    })
 }
 
 // ------------------------------------------------------------
-// Heartbeat tick - called every heartbeat
+// Timer tick - called on every scheduler tick
 // ------------------------------------------------------------
 
-pub fn tick(block_data: String, difficulty: u32) {
+pub fn tick() {
     spawn(async move {
-        schedule_once(block_data, difficulty).await;
+        schedule_once().await;
     });
 }
 
@@ -85,32 +544,78 @@ pub fn tick(block_data: String, difficulty: u32) {
 // Core scheduling logic
 // ------------------------------------------------------------
 
-async fn schedule_once(block_data: String, difficulty: u32) {
+/// Pick the running job with the fewest busy miners relative to its weight
+fn pick_job(st: &CoordinatorState) -> Option<u64> {
+    let busy_on = |job_id: u64| st.miners.iter().filter(|m| m.job() == Some(job_id)).count() as u64;
+
+    st.jobs
+    .values()
+    .filter(|j| j.running && j.solution_found.is_none() && j.weight > 0)
+    .map(|j| (j.id, busy_on(j.id), j.weight as u64))
+    .min_by(|a, b| (a.1 * b.2).cmp(&(b.1 * a.2)))
+    .map(|(id, _, _)| id)
+}
+
+/// Stop running jobs past their deadline or attempt cap; returns
+/// (job_id, reason, total_attempts) for each job expired by this call
+fn expire_overdue(st: &mut CoordinatorState, now: u64) -> Vec<(u64, String, u64)> {
+    let mut expired = Vec::new();
+
+    for job in st.jobs.values_mut().filter(|j| j.running) {
+        let reason = if job.deadline_ns.is_some_and(|d| now >= d) {
+            "deadline"
+        } else if job.max_total_attempts.is_some_and(|m| job.total_attempts >= m) {
+            "max_total_attempts"
+        } else if is_exhausted(job) {
+            "nonce space exhausted"
+        } else {
+            continue;
+        };
+
+        job.running = false;
+        job.expired = true;
+        queue_cancels(job, &st.miners);
+        expired.push((job.id, reason.to_string(), job.total_attempts));
+    }
+
+    expired
+}
+
+async fn schedule_once() {
     let now = time();
+    let backoff = get_backoff_policy();
+    let redundancy = get_redundancy();
+    let batch_size = get_batch_size();
+    session::ensure_secret().await;
 
-    // Stop if solution already found
-    let already_solved = STATE.with(|cell| {
-        cell.borrow()
-        .as_ref()
-        .and_then(|st| st.solution_found.as_ref())
-        .is_some()
-    });
-    if already_solved {
-        return;
+    let expired = STATE.with(|s| expire_overdue(&mut s.borrow_mut(), now));
+    for (job_id, reason, total_attempts) in expired {
+        ic_cdk::println!("⏱ Job {} expired ({})", job_id, reason);
+        events::record(job_id, EventKind::Expired { reason: reason.clone(), total_attempts });
+        subscriptions::publish_expired(job_id, &reason);
+        spawn(async move {
+            send_cancels(job_id).await;
+        });
     }
 
-    // Pick next idle miner
+    // Pick next idle miner and the job it should work on
     let picked = STATE.with(|cell| {
         let mut st = cell.borrow_mut();
-        let st = st.as_mut()?;
+        let st = &mut *st;
 
-        if !st.running || st.miners.is_empty() {
+        if !st.jobs.values().any(|j| j.running) || st.miners.is_empty() {
             return None;
         }
 
-        // Reclaim timed-out miners
+        st.tick += 1;
+        let tick = st.tick;
+
+        // Reclaim timed-out miners and requeue their ranges; a batch gets
+        // the timeout once per chunk
+        let assign_timeout_ns = canister_config::current().assign_timeout_secs.saturating_mul(1_000_000_000);
         for m in st.miners.iter_mut() {
-            if m.busy && now.saturating_sub(m.assigned_at) > ASSIGN_TIMEOUT_NS {
+            let timeout_ns = assign_timeout_ns.saturating_mul(1 + m.batch.len() as u64);
+            if m.busy && now.saturating_sub(m.assigned_at) > timeout_ns {
                 ic_cdk::println!(
                     "Miner {} timeout after {}s",
                     m.id,
@@ -118,11 +623,28 @@ async fn schedule_once(block_data: String, difficulty: u32) {
                 );
                 m.busy = false;
                 m.assigned_at = 0;
-                m.failures += 1;
+                record_failure(m, tick, &backoff);
+                reputation::record(m.id, Conduct::Timeout);
+
+                let batch = std::mem::take(&mut m.batch);
+                if let Some((job_id, lease_id)) = m.lease.take() {
+                    for lease_id in std::iter::once(lease_id).chain(batch) {
+                        events::record(job_id, EventKind::TimedOut { miner: m.id, lease_id });
+                        if let Some(job) = st.jobs.get_mut(&job_id) {
+                            requeue_lease(job, lease_id);
+                        }
+                    }
+                }
             }
         }
+        drop_drained(st);
+
+        let job_id = pick_job(st)?;
+        let mean_hashrate = mean_hashrate(&st.miners);
+        let rates: BTreeMap<Principal, u64> = st.miners.iter().map(|m| (m.id, m.hashrate())).collect();
 
-        // Round-robin selection
+        // Deficit round-robin: each visit credits the miner a quantum
+        // proportional to its hashrate and the chunk is cut from that credit
         let n = st.miners.len();
         for _ in 0..n {
             let i = st.rr_cursor % n;
@@ -130,119 +652,781 @@ async fn schedule_once(block_data: String, difficulty: u32) {
 
             let slot = &mut st.miners[i];
 
-            if slot.busy { continue; }
+            if slot.busy || slot.draining || reputation::is_banned(slot.id) || !stake::is_bonded(slot.id) { continue; }
 
-            if slot.failures >= MAX_FAILURES {
-                ic_cdk::println!("Miner {} disabled (failures={})", slot.id, slot.failures);
+            if tick < slot.backoff_until_tick {
                 continue;
             }
 
-            let start = st.next_nonce;
-            st.next_nonce += st.chunk_size;
-            st.total_chunks_assigned += 1;
+            let job = st.jobs.get_mut(&job_id)?;
+
+            let base = job.chunk_size.max(1);
+            let max_chunk = base.saturating_mul(MAX_CHUNK_SCALE);
+            slot.deficit = slot
+            .deficit
+            .saturating_add(reputation::scale(slot.id, quantum(slot.hashrate(), mean_hashrate, base)))
+            .min(max_chunk);
+
+            let lease_id = job.leases.len() as u64;
+
+            // Under-replicated ranges first, then retries, then fresh space,
+            // and once the space is used up, the tail of the slowest lease
+            let mut stolen_from = None;
+            let (group, range) = if let Some(v) = under_replicated(job, slot.id, redundancy) {
+                v
+            } else if let Some(r) = job.retry_pool.pop_front() {
+                (lease_id, r)
+            } else if job.next_nonce < job.end_nonce {
+                let wanted = slot.deficit.max(base / MAX_CHUNK_SCALE).max(1);
+                let size = wanted.min(job.end_nonce - job.next_nonce);
+                let r = NonceRange { start: job.next_nonce, size };
+                job.next_nonce += size;
+                (lease_id, r)
+            } else if let Some((victim, r)) = steal_tail(job, slot.id, &rates) {
+                stolen_from = Some(victim);
+                (lease_id, r)
+            } else {
+                return None;
+            };
+            slot.deficit = slot.deficit.saturating_sub(range.size);
+            open_lease(job, slot.id, group, stolen_from, range, now);
+
+            // Batching: more chunks of the same size for the same call, from
+            // retries and then fresh space
+            let mut extra = Vec::new();
+            if redundancy <= 1 && stolen_from.is_none() {
+                let mut batched = range.size;
+                while extra.len() + 1 < batch_size as usize {
+                    let r = if let Some(&r) = job.retry_pool.front() {
+                        r
+                    } else if job.next_nonce < job.end_nonce {
+                        let size = range.size.min(job.end_nonce - job.next_nonce);
+                        NonceRange { start: job.next_nonce, size }
+                    } else {
+                        break;
+                    };
+                    // Miners hold a whole batch to one chunk's budget
+                    if batched.saturating_add(r.size) > max_chunk {
+                        break;
+                    }
+                    if job.retry_pool.pop_front().is_none() {
+                        job.next_nonce += r.size;
+                    }
+                    batched += r.size;
+                    let id = job.leases.len() as u64;
+                    open_lease(job, slot.id, id, None, r, now);
+                    extra.push((id, r));
+                }
+            }
+
             slot.busy = true;
             slot.assigned_at = now;
-            slot.total_chunks += 1;
+            slot.total_chunks += 1 + extra.len() as u64;
+            slot.lease = Some((job_id, lease_id));
+            slot.batch = extra.iter().map(|&(id, _)| id).collect();
+            let token = session::issue(job_id, lease_id);
+            for id in std::iter::once(lease_id).chain(extra.iter().map(|&(id, _)| id)) {
+                job.leases[id as usize].session_token = Some(token);
+            }
 
-            return Some((i, slot.id, start, st.chunk_size));
+            return Some((
+                slot.id,
+                job_id,
+                lease_id,
+                token,
+                range.start,
+                range.size,
+                job.block_data.clone(),
+                job.algorithm,
+                job.target,
+                job.share_target,
+                extra,
+            ));
         }
         None
     });
 
-    let (slot_index, miner, start, size) = match picked {
+    let (miner, job_id, lease_id, token, start, size, block_data, algorithm, target, share_target, extra) = match picked {
         Some(v) => v,
         None => return,
     };
 
-    // Call mine_chunk_simple - returns (found, nonce, hash, attempts)
-    // Using primitive types avoids ALL Candid variant encoding issues
-    let result = call::<(String, u32, u64, u64), (bool, u64, String, u64)>(
-        miner,
-        "mine_chunk_simple",
-        (block_data.clone(), difficulty, start, size),
-    )
-    .await;
+    events::record(job_id, EventKind::Assigned { miner, lease_id, start, size });
+    for &(id, r) in &extra {
+        events::record(job_id, EventKind::Assigned { miner, lease_id: id, start: r.start, size: r.size });
+    }
+    let leases: Vec<u64> = std::iter::once(lease_id).chain(extra.iter().map(|&(id, _)| id)).collect();
+    let ranges: Vec<NonceRange> = std::iter::once(NonceRange { start, size }).chain(extra.iter().map(|&(_, r)| r)).collect();
 
-    match result {
-        Ok((found, nonce, hash, _attempts)) => {
-            if found {
-                ic_cdk::println!(
-                    "✅ SOLUTION FOUND by {} | nonce={} | hash={}",
-                    miner, nonce, hash
-                );
+    // Versioned records only gain optional fields, so either side can be
+    // upgraded first. A refused chunk (e.g. rate limited) is handled like a
+    // failed call. The notify is sent by reference and keeps the block for
+    // checking the reply.
+    let notify = JobNotify {
+        version: PROTOCOL_VERSION,
+        job_id,
+        prev_hash: None,
+        block_data,
+        extranonce_start: start,
+        extranonce_size: size,
+        target: target.to_vec(),
+        algorithm: Some(algorithm),
+        clean_jobs: false,
+        session_token: Some(token),
+        share_target: share_target.map(|t| t.to_vec()),
+    };
+    let replies = if extra.is_empty() {
+        call::<(&JobNotify,), (Result<JobSubmit, MinerError>,)>(miner, "mine_job", (&notify,))
+        .await
+        .map(|(reply,)| reply.map(|submit| vec![submit]))
+    } else {
+        let ranges: Vec<(u64, u64)> = ranges.iter().map(|r| (r.start, r.size)).collect();
+        let args = (job_id, ranges, &notify.block_data, &notify.target, notify.algorithm, notify.session_token, &notify.share_target);
+        call::<_, (Result<Vec<JobSubmit>, MinerError>,)>(miner, "mine_chunks", args)
+        .await
+        .map(|(reply,)| reply)
+    };
 
-                STATE.with(|s| {
-                    if let Some(st) = s.borrow_mut().as_mut() {
-                        st.solution_found = Some((nonce, hash.clone()));
-                        st.running = false;
-                        if let Some(slot) = st.miners.get_mut(slot_index) {
-                            slot.busy = false;
-                            slot.successful_chunks += 1;
-                        }
-                    }
-                });
+    // The miner dropped the job without searching anything; that's not its
+    // fault, so the ranges just go back to the pool
+    if let Ok(Err(MinerError::Cancelled)) = replies {
+        STATE.with(|s| {
+            if let Some(job) = s.borrow_mut().jobs.get_mut(&job_id) {
+                for &lease_id in &leases {
+                    requeue_lease(job, lease_id);
+                }
+            }
+        });
+        free_slot(job_id, lease_id, miner);
+        return;
+    }
 
-                broadcast_stop().await;
+    let result = replies
+    .map_err(|e| format!("{:?}", e))
+    .and_then(|reply| reply.map_err(|e| format!("{:?}", e)))
+    .and_then(|submits| {
+        // A reply that doesn't carry this lease's token isn't for this lease
+        let foreign = submits.iter().any(|s| s.job_id != job_id || s.session_token != Some(token));
+        if foreign || submits.len() > leases.len() || !issued_with(job_id, &leases, miner, token) {
+            return Err("session token mismatch".to_string());
+        }
+        Ok(submits)
+    });
 
-            } else {
-                // No solution found in this chunk - mark miner idle
-                STATE.with(|s| {
-                    if let Some(st) = s.borrow_mut().as_mut() {
-                        if let Some(slot) = st.miners.get_mut(slot_index) {
-                            slot.busy = false;
-                            slot.assigned_at = 0;
-                            slot.successful_chunks += 1;
-                        }
-                    }
-                });
+    let submits = match result {
+        Ok(submits) => submits,
+        Err(error) => {
+            ic_cdk::println!("❌ Miner {} call failed: {}", miner, error);
+            for &lease_id in &leases {
+                events::record(job_id, EventKind::Failed { miner, lease_id, error: error.clone() });
             }
+            release_failed(job_id, &leases, miner, false);
+            return;
         }
+    };
+
+    // A batch stops at its first solution; the ranges after it were never
+    // searched
+    let elapsed = time().saturating_sub(now) / submits.len().max(1) as u64;
+    STATE.with(|s| {
+        if let Some(job) = s.borrow_mut().jobs.get_mut(&job_id) {
+            for &unsearched in &leases[submits.len()..] {
+                requeue_lease(job, unsearched);
+            }
+        }
+    });
 
-        Err(e) => {
-            ic_cdk::println!("❌ Miner {} call failed: {:?}", miner, e);
+    let mut solved = None;
+    let replied = submits.len();
+    for (i, ((&lease_id, &range), submit)) in leases.iter().zip(&ranges).zip(submits).enumerate() {
+        let JobSubmit { found, nonce, hash, attempts, instructions, shares: submitted_shares, .. } = submit;
+
+        // Never let a miner end a job with a hash we can't reproduce. None
+        // of its remaining replies are trusted either, so their ranges go
+        // back to the pool with this one.
+        if found {
+            if let Err(reason) = verify::verify_solution(algorithm, &notify.block_data, &target, nonce, &hash) {
+                ic_cdk::println!("❌ Rejected solution from {}: {}", miner, reason);
+                events::record(job_id, EventKind::Rejected { miner, nonce, reason });
+                reputation::record(miner, Conduct::InvalidSolution);
+                stake::forfeit(miner);
+                release_failed(job_id, &leases[i..replied], miner, true);
+                return;
+            }
+        }
+
+        // A miner can't have tried more nonces than it was given; the
+        // excess counts toward neither its hashrate nor max_total_attempts
+        if attempts > range.size {
+            ic_cdk::println!("❌ Miner {} reported {} attempts for {} nonces", miner, attempts, range.size);
+            reputation::record(miner, Conduct::InflatedAttempts);
+        }
+        let attempts = attempts.min(range.size);
+
+        // Recorded only once the range is known to be searched; replay
+        // relies on this
+        events::record(job_id, EventKind::Completed { miner, lease_id, attempts });
+
+        if let (Some(share_target), Some(submitted)) = (&share_target, submitted_shares) {
+            let check = ShareCheck {
+                algorithm,
+                block_data: &notify.block_data,
+                target: &target,
+                share_target,
+            };
+            let (accepted, rejected) = shares::credit(job_id, miner, &check, range, submitted);
+            reputation::record(miner, Conduct::Shares(accepted));
+            reputation::record(miner, Conduct::RejectedShares(rejected));
+        }
+
+        // A late find for a job that was already solved does not count
+        let solved_started_at = if found {
             STATE.with(|s| {
-                if let Some(st) = s.borrow_mut().as_mut() {
-                    if let Some(slot) = st.miners.get_mut(slot_index) {
-                        slot.busy = false;
-                        slot.assigned_at = 0;
-                        slot.failures += 1;
-                    }
+                s.borrow()
+                .jobs
+                .get(&job_id)
+                .filter(|j| j.solution_found.is_none())
+                .map(|j| j.started_at)
+            })
+        } else {
+            None
+        };
+        let first = solved_started_at.is_some();
+
+        if first {
+            ic_cdk::println!(
+                "✅ SOLUTION FOUND for job {} by {} | nonce={} | hash={}",
+                job_id, miner, nonce, hash
+            );
+            events::record(job_id, EventKind::Solution { miner, nonce, hash: hash.clone() });
+            payouts::settle(job_id, miner, &hash);
+            reputation::record(miner, Conduct::Solution);
+            subscriptions::publish_solution(job_id, nonce, &hash, miner);
+        }
+
+        STATE.with(|s| {
+            let mut st = s.borrow_mut();
+            let st = &mut *st;
+
+            if let Some(job) = st.jobs.get_mut(&job_id) {
+                complete_lease(job, lease_id);
+                job.total_attempts += attempts;
+                job.instructions = job.instructions.saturating_add(instructions);
+                job.chunks_completed += 1;
+                if found {
+                    record_solution(job, nonce, &hash, miner);
                 }
-            });
+                if first {
+                    job.running = false;
+                    queue_cancels(job, &st.miners);
+                }
+            }
+
+            if let Some(slot) = st.miners.iter_mut().find(|m| m.id == miner) {
+                slot.successful_chunks += 1;
+                if first {
+                    slot.solutions_found += 1;
+                }
+                record_work(slot, attempts, elapsed);
+            }
+        });
+
+        if let Some(started_at) = solved_started_at {
+            solved = Some((started_at, nonce, hash));
+        }
+    }
+    free_slot(job_id, lease_id, miner);
+
+    if let Some((started_at, nonce, hash)) = solved {
+        let solve_secs = time().saturating_sub(started_at) / 1_000_000_000;
+        // The chain tracks leading-zero difficulty; an explicit target
+        // counts as the zero bits it guarantees
+        work_source::report_solution(job_id, nonce, &hash);
+        chain::report_block(job_id, hash, target::to_difficulty(&target), solve_secs, miner);
+        send_cancels(job_id).await;
+    }
+}
+
+fn open_lease(job: &mut Job, miner: Principal, group: u64, stolen_from: Option<u64>, range: NonceRange, now: u64) {
+    job.leases.push(Lease {
+        id: job.leases.len() as u64,
+        group,
+        stolen_from,
+        miner,
+        start: range.start,
+        size: range.size,
+        status: LeaseStatus::Active,
+        assigned_at: now,
+        session_token: None,
+    });
+    job.total_chunks_assigned += 1;
+}
+
+/// Mark a miner idle once the call for `lease_id` (the first of its batch)
+/// is done with
+fn free_slot(job_id: u64, lease_id: u64, miner: Principal) {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        if let Some(slot) = st.miners.iter_mut().find(|m| m.id == miner) {
+            if slot.lease == Some((job_id, lease_id)) {
+                slot.busy = false;
+                slot.assigned_at = 0;
+                slot.lease = None;
+                slot.batch.clear();
+            }
         }
+        drop_drained(&mut st);
+    });
+}
+
+/// Keep every distinct solution; the job's answer is the lowest hash seen
+fn record_solution(job: &mut Job, nonce: u64, hash: &str, miner: Principal) {
+    let hash = hash.to_ascii_lowercase();
+    if job.solutions.iter().any(|s| s.hash == hash) {
+        return;
+    }
+
+    let better = match &job.solution_found {
+        Some((_, best)) => hash < *best,
+        None => true,
+    };
+    if better {
+        job.solution_found = Some((nonce, hash.clone()));
+    }
+
+    job.solutions.push(FoundSolution { nonce, hash, miner, found_at: time() });
+}
+
+/// True when `token` is the one issued with each of `leases`, all of them
+/// `miner`'s. Checked against the leases rather than the miner's slot, so a
+/// reply that arrives after its lease timed out is still accepted.
+fn issued_with(job_id: u64, leases: &[u64], miner: Principal, token: u64) -> bool {
+    STATE.with(|s| {
+        s.borrow()
+        .jobs
+        .get(&job_id)
+        .is_some_and(|job| lease_tokens_match(job, leases, miner, token))
+    })
+}
+
+fn lease_tokens_match(job: &Job, leases: &[u64], miner: Principal, token: u64) -> bool {
+    leases.iter().all(|&id| {
+        job.leases
+        .get(id as usize)
+        .is_some_and(|l| l.miner == miner && l.session_token == Some(token))
+    })
+}
+
+/// Free a miner whose call failed or returned a bogus solution, back it off
+/// and put `leases` back up for grabs
+fn release_failed(job_id: u64, leases: &[u64], miner: Principal, invalid_solution: bool) {
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let st = &mut *st;
+        let tick = st.tick;
+
+        if let Some(slot) = st.miners.iter_mut().find(|m| m.id == miner) {
+            if invalid_solution {
+                slot.invalid_solutions += 1;
+            }
+            let ours = |lease: &u64| leases.contains(lease);
+            if slot.lease.is_some_and(|(j, l)| j == job_id && (ours(&l) || slot.batch.iter().any(ours))) {
+                slot.busy = false;
+                slot.assigned_at = 0;
+                slot.lease = None;
+                slot.batch.clear();
+                record_failure(slot, tick, &get_backoff_policy());
+            }
+        }
+        if let Some(job) = st.jobs.get_mut(&job_id) {
+            for &lease_id in leases {
+                requeue_lease(job, lease_id);
+            }
+        }
+        drop_drained(st);
+    });
+}
+
+/// Average hashrate over miners that have completed work (0 if none have)
+fn mean_hashrate(miners: &[MinerSlot]) -> u64 {
+    let rates: Vec<u64> = miners.iter().map(MinerSlot::hashrate).filter(|h| *h > 0).collect();
+    if rates.is_empty() {
+        return 0;
+    }
+    (rates.iter().map(|h| *h as u128).sum::<u128>() / rates.len() as u128) as u64
+}
+
+/// A miner's DRR quantum: `base` scaled by its hashrate over the fleet mean,
+/// kept inside the chunk size bounds of a global config fetched from the
+/// config canister (without one, the job's own size stands). Miners with no
+/// measurement yet get the plain chunk size.
+fn quantum(hashrate: u64, mean: u64, base: u64) -> u64 {
+    let bounds = canister_config::fetched().map(|c| (c.min_chunk_size, c.max_chunk_size));
+    let bounded = |size: u64| bounds.map_or(size, |(min, max)| size.clamp(min, max));
+    if hashrate == 0 || mean == 0 {
+        return bounded(base);
+    }
+    let scaled = base as u128 * hashrate as u128 / mean as u128;
+    bounded((scaled.min(u64::MAX as u128) as u64).clamp(base / MAX_CHUNK_SCALE, base.saturating_mul(MAX_CHUNK_SCALE)))
+}
+
+fn record_work(slot: &mut MinerSlot, attempts: u64, elapsed_ns: u64) {
+    slot.total_attempts += attempts;
+    slot.mining_ns += elapsed_ns;
+    slot.last_seen = time();
+
+    // A completed chunk clears the backoff
+    slot.failures = 0;
+    slot.backoff_until_tick = 0;
+}
+
+fn record_failure(slot: &mut MinerSlot, tick: u64, backoff: &BackoffPolicy) {
+    slot.failures += 1;
+    slot.backoff_until_tick = tick.saturating_add(backoff.ticks_for(slot.failures));
+    ic_cdk::println!(
+        "Miner {} backing off until tick {} (failures={})",
+        slot.id, slot.backoff_until_tick, slot.failures
+    );
+}
+
+impl MinerSlot {
+    fn is_backing_off(&self, tick: u64) -> bool {
+        tick < self.backoff_until_tick
     }
 }
 
 // ------------------------------------------------------------
-// Broadcast stop to all miners
+// Lease ledger
 // ------------------------------------------------------------
 
-async fn broadcast_stop() {
-    let miners = STATE.with(|s| {
-        s.borrow()
-        .as_ref()
-        .map(|st| st.miners.iter().map(|m| m.id).collect::<Vec<_>>())
-        .unwrap_or_default()
+fn group_leases(job: &mut Job, group: u64) -> impl Iterator<Item = &mut Lease> {
+    job.leases.iter_mut().skip(group as usize).filter(move |l| l.group == group)
+}
+
+/// First active range held by fewer than `k` miners, none of them `miner`
+fn under_replicated(job: &Job, miner: Principal, k: u32) -> Option<(u64, NonceRange)> {
+    if k <= 1 {
+        return None;
+    }
+
+    let mut groups: BTreeMap<u64, (u32, bool, NonceRange)> = BTreeMap::new();
+    for l in job.leases.iter().filter(|l| l.status == LeaseStatus::Active) {
+        let entry = groups
+        .entry(l.group)
+        .or_insert((0, false, NonceRange { start: l.start, size: l.size }));
+        entry.0 += 1;
+        entry.1 |= l.miner == miner;
+    }
+
+    groups
+    .into_iter()
+    .find(|(_, (active, has_miner, _))| *active < k && !has_miner)
+    .map(|(group, (_, _, range))| (group, range))
+}
+
+/// Drop a lease. The range goes back to the retry pool only if no other
+/// replica of it is still being mined; then so do tails stolen from the
+/// group that were abandoned while it was out.
+fn requeue_lease(job: &mut Job, lease_id: u64) {
+    let (group, start, size) = match job.leases.get(lease_id as usize) {
+        Some(l) if l.status == LeaseStatus::Active => (l.group, l.start, l.size),
+        _ => return,
+    };
+
+    let victim_active = job.leases[lease_id as usize]
+    .stolen_from
+    .and_then(|v| job.leases.get(v as usize))
+    .is_some_and(|v| v.status == LeaseStatus::Active);
+
+    let others_active = victim_active
+    || group_leases(job, group).any(|l| l.id != lease_id && l.status == LeaseStatus::Active);
+
+    let lease = &mut job.leases[lease_id as usize];
+    if others_active {
+        lease.status = LeaseStatus::Abandoned;
+    } else {
+        lease.status = LeaseStatus::Requeued;
+        job.retry_pool.push_back(NonceRange { start, size });
+        requeue_abandoned_tails(job, group);
+    }
+}
+
+/// A tail whose thief failed was abandoned on the promise that its victim
+/// still searches it. Once the victim's group is dropped too, nobody does.
+fn requeue_abandoned_tails(job: &mut Job, group: u64) {
+    let victims: Vec<u64> = group_leases(job, group).map(|l| l.id).collect();
+    let tails: Vec<u64> = job
+    .leases
+    .iter()
+    .filter(|l| l.status == LeaseStatus::Abandoned && l.stolen_from.is_some_and(|v| victims.contains(&v)))
+    .map(|l| l.id)
+    .collect();
+
+    for tail in tails {
+        let lease = &job.leases[tail as usize];
+        let (tail_group, range) = (lease.group, NonceRange { start: lease.start, size: lease.size });
+        // A replica of the tail may still be out, or already requeued it
+        if lease.status != LeaseStatus::Abandoned
+        || group_leases(job, tail_group).any(|l| l.status == LeaseStatus::Active)
+        {
+            continue;
+        }
+        for l in group_leases(job, tail_group).filter(|l| l.status == LeaseStatus::Abandoned) {
+            l.status = LeaseStatus::Requeued;
+        }
+        job.retry_pool.push_back(range);
+        requeue_abandoned_tails(job, tail_group);
+    }
+}
+
+/// Mark a range searched across all its replicas. A late result for a
+/// requeued range also pulls it back out of the retry pool so it is not
+/// mined twice.
+fn complete_lease(job: &mut Job, lease_id: u64) {
+    let (group, start, size) = match job.leases.get(lease_id as usize) {
+        Some(l) => (l.group, l.start, l.size),
+        None => return,
+    };
+
+    let mut requeued = false;
+    for lease in group_leases(job, group) {
+        requeued |= lease.status == LeaseStatus::Requeued;
+        lease.status = LeaseStatus::Completed;
+    }
+
+    if requeued {
+        if let Some(pos) = job.retry_pool.iter().position(|r| r.start == start && r.size == size) {
+            job.retry_pool.remove(pos);
+        }
+    }
+
+    // The miner searched its whole original range, including any tails that
+    // were stolen from it; those leases are now redundant
+    let tails: Vec<u64> = job
+    .leases
+    .iter()
+    .filter(|l| l.stolen_from == Some(lease_id) && l.status != LeaseStatus::Completed)
+    .map(|l| l.id)
+    .collect();
+    for tail in tails {
+        complete_lease(job, tail);
+    }
+}
+
+/// No fresh nonces left, nothing to retry and nothing in flight
+fn is_exhausted(job: &Job) -> bool {
+    job.next_nonce >= job.end_nonce
+    && job.retry_pool.is_empty()
+    && !job.leases.iter().any(|l| l.status == LeaseStatus::Active)
+}
+
+/// Split the active lease with the longest expected remaining time (size over
+/// its miner's hashrate) and return the upper half for `thief`. The victim
+/// keeps mining its full range; its lease shrinks to the lower half.
+fn steal_tail(job: &mut Job, thief: Principal, rates: &BTreeMap<Principal, u64>) -> Option<(u64, NonceRange)> {
+    let mut replicas: BTreeMap<u64, u32> = BTreeMap::new();
+    for l in job.leases.iter().filter(|l| l.status == LeaseStatus::Active) {
+        *replicas.entry(l.group).or_default() += 1;
+    }
+
+    let victim = job
+    .leases
+    .iter()
+    .filter(|l| {
+        l.status == LeaseStatus::Active
+        && l.miner != thief
+        && l.size >= 2 * MIN_STEAL_SIZE
+        && replicas.get(&l.group) == Some(&1)
+    })
+    .max_by_key(|l| {
+        let rate = rates.get(&l.miner).copied().unwrap_or(0).max(1);
+        l.size as u128 * 1_000_000 / rate as u128
+    })?
+    .id;
+
+    let lease = &mut job.leases[victim as usize];
+    let half = lease.size / 2;
+    let tail = NonceRange { start: lease.start + half, size: lease.size - half };
+    lease.size = half;
+
+    ic_cdk::println!(
+        "Stealing [{}, +{}) from {} for {}",
+        tail.start, tail.size, lease.miner, thief
+    );
+    Some((victim, tail))
+}
+
+// ------------------------------------------------------------
+// Health checks for disabled miners
+// ------------------------------------------------------------
+
+pub fn health_check() {
+    spawn(async {
+        probe_disabled_miners().await;
     });
+}
 
-    ic_cdk::println!("📢 Broadcasting stop to {} miners", miners.len());
+async fn probe_disabled_miners() {
+    let disabled = STATE.with(|s| {
+        let st = s.borrow();
+        st.miners
+        .iter()
+        .filter(|m| m.is_backing_off(st.tick))
+        .map(|m| m.id)
+        .collect::<Vec<_>>()
+    });
 
-    for miner in miners {
-        let _ = call::<(), ()>(miner, "stop_advanced_mining", ())
-        .await
-        .map_err(|e| {
-            ic_cdk::println!("Failed to stop miner {}: {:?}", miner, e);
+    for miner in disabled {
+        let healthy = matches!(call::<(), (bool,)>(miner, "health", ()).await, Ok((true,)));
+
+        STATE.with(|s| {
+            if let Some(slot) = s.borrow_mut().miners.iter_mut().find(|m| m.id == miner) {
+                if !healthy {
+                    slot.health_successes = 0;
+                    return;
+                }
+
+                slot.health_successes += 1;
+                slot.last_seen = time();
+                if slot.health_successes >= HEALTH_REHAB_SUCCESSES {
+                    ic_cdk::println!("Miner {} re-enabled after health checks", miner);
+                    slot.failures = 0;
+                    slot.backoff_until_tick = 0;
+                    slot.health_successes = 0;
+                }
+            }
         });
     }
 }
 
+/// Clear a miner's failure count so it is scheduled again; false if unknown
+pub fn reset_miner_failures(miner: Principal) -> bool {
+    STATE.with(|s| {
+        s.borrow_mut()
+        .miners
+        .iter_mut()
+        .find(|m| m.id == miner)
+        .map(|slot| {
+            slot.failures = 0;
+            slot.backoff_until_tick = 0;
+            slot.health_successes = 0;
+        })
+        .is_some()
+    })
+}
+
+// ------------------------------------------------------------
+// Cancel protocol - every miner must acknowledge the job is over
+// ------------------------------------------------------------
+
+fn queue_cancels(job: &mut Job, miners: &[MinerSlot]) {
+    job.cancels = miners
+    .iter()
+    .map(|m| CancelAck { miner: m.id, acked: false, attempts: 0 })
+    .collect();
+}
+
+fn cancels_pending(job: &Job) -> bool {
+    job.cancels.iter().any(|c| !c.acked && c.attempts < MAX_CANCEL_ATTEMPTS)
+}
+
+pub fn has_pending_cancels() -> bool {
+    STATE.with(|s| s.borrow().jobs.values().any(cancels_pending))
+}
+
+/// Retry un-acked cancels for every job (called from the tick timer)
+pub fn flush_cancels() {
+    let jobs: Vec<u64> = STATE.with(|s| {
+        s.borrow()
+        .jobs
+        .values()
+        .filter(|j| cancels_pending(j) && !j.cancel_in_flight)
+        .map(|j| j.id)
+        .collect()
+    });
+
+    for job_id in jobs {
+        spawn(async move {
+            send_cancels(job_id).await;
+        });
+    }
+}
+
+async fn send_cancels(job_id: u64) {
+    let pending = STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        let job = st.jobs.get_mut(&job_id)?;
+        if job.cancel_in_flight {
+            return None;
+        }
+
+        let miners: Vec<Principal> = job
+        .cancels
+        .iter_mut()
+        .filter(|c| !c.acked && c.attempts < MAX_CANCEL_ATTEMPTS)
+        .map(|c| {
+            c.attempts += 1;
+            c.miner
+        })
+        .collect();
+
+        job.cancel_in_flight = true;
+        Some(miners)
+    });
+
+    let miners = match pending {
+        Some(v) => v,
+        None => return,
+    };
+
+    ic_cdk::println!("📢 Cancelling job {} on {} miners", job_id, miners.len());
+    events::record(job_id, EventKind::StopBroadcast { miners: miners.len() as u64 });
+
+    let replies = join_all(miners.iter().map(|miner| {
+        call::<(u64,), (bool,)>(*miner, "cancel_assignment", (job_id,))
+    }))
+    .await;
+
+    STATE.with(|s| {
+        if let Some(job) = s.borrow_mut().jobs.get_mut(&job_id) {
+            for (miner, reply) in miners.iter().zip(replies) {
+                match reply {
+                    Ok((true,)) => {
+                        if let Some(c) = job.cancels.iter_mut().find(|c| c.miner == *miner) {
+                            c.acked = true;
+                        }
+                    }
+                    other => {
+                        ic_cdk::println!("Cancel not acked by {}: {:?}", miner, other.err());
+                    }
+                }
+            }
+            job.cancel_in_flight = false;
+        }
+    });
+}
+
+pub fn get_cancel_acks(job_id: Option<u64>) -> Vec<CancelAck> {
+    STATE.with(|s| {
+        resolve_job(&s.borrow(), job_id)
+        .map(|job| job.cancels.clone())
+        .unwrap_or_default()
+    })
+}
+
 // ------------------------------------------------------------
 // Stats
 // ------------------------------------------------------------
 
+/// Per-job stats. Miner counts cover the shared fleet, except `busy_miners`
+/// which only counts miners working on this job.
 #[derive(CandidType, Deserialize, Clone)]
 pub struct SchedulerStats {
+    pub job_id: u64,
+    pub weight: u32,
     pub running: bool,
+    pub expired: bool,
+    pub total_attempts: u64,
     pub total_miners: u64,
     pub idle_miners: u64,
     pub busy_miners: u64,
@@ -253,26 +1437,297 @@ pub struct SchedulerStats {
     pub uptime_seconds: u64,
 }
 
-pub fn get_scheduler_stats() -> Option<SchedulerStats> {
+fn job_stats(st: &CoordinatorState, job: &Job) -> SchedulerStats {
+    let now = time();
+    let uptime = (now - job.started_at) / 1_000_000_000;
+
+    SchedulerStats {
+        job_id: job.id,
+        weight: job.weight,
+        running: job.running,
+        expired: job.expired,
+        total_attempts: job.total_attempts,
+        total_miners: st.miners.len() as u64,
+        idle_miners: st.miners.iter().filter(|m| !m.busy).count() as u64,
+        busy_miners: st.miners.iter().filter(|m| m.job() == Some(job.id)).count() as u64,
+        failed_miners: st.miners.iter().filter(|m| m.is_backing_off(st.tick)).count() as u64,
+        total_chunks_assigned: job.total_chunks_assigned,
+        next_nonce: job.next_nonce,
+        solution: job.solution_found.clone(),
+        uptime_seconds: uptime,
+    }
+}
+
+/// Stats for one job, or the most recently started one
+pub fn get_scheduler_stats(job_id: Option<u64>) -> Option<SchedulerStats> {
     STATE.with(|s| {
         let st = s.borrow();
-        let st = st.as_ref()?;
-
-        let now = time();
-        let uptime = (now - st.started_at) / 1_000_000_000;
-
-        Some(SchedulerStats {
-            running: st.running,
-            total_miners: st.miners.len() as u64,
-             idle_miners: st.miners.iter().filter(|m| !m.busy).count() as u64,
-             busy_miners: st.miners.iter().filter(|m| m.busy).count() as u64,
-             failed_miners: st.miners.iter().filter(|m| m.failures >= MAX_FAILURES).count() as u64,
-             total_chunks_assigned: st.total_chunks_assigned,
-             next_nonce: st.next_nonce,
-             solution: st.solution_found.clone(),
-             uptime_seconds: uptime,
-        })
+        let job = resolve_job(&st, job_id)?;
+        Some(job_stats(&st, job))
+    })
+}
+
+/// All distinct solutions for a job, lowest hash first
+pub fn get_all_solutions(job_id: u64) -> Vec<FoundSolution> {
+    STATE.with(|s| {
+        let mut solutions = s
+        .borrow()
+        .jobs
+        .get(&job_id)
+        .map(|j| j.solutions.clone())
+        .unwrap_or_default();
+        solutions.sort_by(|a, b| a.hash.cmp(&b.hash).then(a.found_at.cmp(&b.found_at)));
+        solutions
+    })
+}
+
+pub fn list_jobs() -> Vec<SchedulerStats> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        st.jobs.values().map(|job| job_stats(&st, job)).collect()
     })
 }
 
 pub use get_scheduler_stats as stats;
+
+/// Expected seconds until the slowest running job finds a solution, given
+/// its weighted share of the measured fleet hashrate. A solution takes
+/// 2^256 / (target + 1) attempts on average.
+/// None if nothing is running or no miner has a hashrate yet.
+pub fn estimated_solve_secs() -> Option<u64> {
+    STATE.with(|s| {
+        let st = s.borrow();
+
+        let fleet_rate: u128 = st
+        .miners
+        .iter()
+        .filter(|m| !m.draining)
+        .map(|m| m.hashrate() as u128)
+        .sum();
+        if fleet_rate == 0 {
+            return None;
+        }
+
+        let running: Vec<&Job> = st.jobs.values().filter(|j| j.running).collect();
+        let total_weight: u128 = running.iter().map(|j| j.weight as u128).sum();
+
+        running
+        .iter()
+        .filter(|j| j.weight > 0)
+        .map(|j| {
+            let expected = target::expected_attempts(&j.target);
+            let rate = (fleet_rate * j.weight as u128 / total_weight).max(1);
+            (expected / rate as f64) as u64
+        })
+        .max()
+    })
+}
+
+// ------------------------------------------------------------
+// Per-job cost accounting
+// ------------------------------------------------------------
+
+/// Estimated miner-side cycles for a job: one update call per completed chunk
+/// plus the reported instructions at the subnet's execution fee
+#[derive(CandidType, Deserialize, Clone)]
+pub struct JobCost {
+    pub cycles: u128,
+    pub instructions: u64,
+    pub chunks: u64,
+}
+
+pub fn get_job_cost(job_id: u64) -> Option<JobCost> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        let job = st.jobs.get(&job_id)?;
+
+        let cycles = job.chunks_completed as u128 * UPDATE_CALL_FEE
+        + job.instructions as u128 * FEE_PER_10_INSTRUCTIONS / 10;
+
+        Some(JobCost {
+            cycles,
+            instructions: job.instructions,
+            chunks: job.chunks_completed,
+        })
+    })
+}
+
+// ------------------------------------------------------------
+// Per-miner stats
+// ------------------------------------------------------------
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MinerStats {
+    pub miner: Principal,
+    pub chunks_assigned: u64,
+    pub chunks_completed: u64,
+    pub solutions_found: u64,
+    pub failures: u32,
+    pub total_attempts: u64,
+    pub estimated_hashrate: u64,
+    pub last_seen: u64,
+    pub invalid_solutions: u64,
+}
+
+impl MinerSlot {
+    /// Hashes per second over the time spent on completed chunks
+    pub fn hashrate(&self) -> u64 {
+        if self.mining_ns == 0 {
+            return 0;
+        }
+        (self.total_attempts as u128 * 1_000_000_000 / self.mining_ns as u128) as u64
+    }
+
+    fn stats(&self) -> MinerStats {
+        MinerStats {
+            miner: self.id,
+            chunks_assigned: self.total_chunks,
+            chunks_completed: self.successful_chunks,
+            solutions_found: self.solutions_found,
+            failures: self.failures,
+            total_attempts: self.total_attempts,
+            estimated_hashrate: self.hashrate(),
+            last_seen: self.last_seen,
+            invalid_solutions: self.invalid_solutions,
+        }
+    }
+}
+
+pub fn get_miner_stats() -> Vec<MinerStats> {
+    STATE.with(|s| s.borrow().miners.iter().map(MinerSlot::stats).collect())
+}
+
+/// Miners ordered by estimated hashrate, then by solutions found
+pub fn get_miner_leaderboard(limit: usize) -> Vec<MinerStats> {
+    let mut stats = get_miner_stats();
+    stats.sort_by(|a, b| {
+        b.estimated_hashrate
+        .cmp(&a.estimated_hashrate)
+        .then(b.solutions_found.cmp(&a.solutions_found))
+    });
+    stats.truncate(limit);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_with_lease(miner: Principal, size: u64) -> Job {
+        let mut job = Job {
+            id: 1,
+            block_data: "block".to_string(),
+            algorithm: PowAlgorithm::Sha256,
+            target: target::from_difficulty(1),
+            share_target: None,
+            weight: 1,
+            leases: Vec::new(),
+            retry_pool: VecDeque::new(),
+            next_nonce: size,
+            end_nonce: size,
+            chunk_size: size,
+            running: true,
+            solution_found: None,
+            solutions: Vec::new(),
+            total_chunks_assigned: 0,
+            started_at: 0,
+            cancels: Vec::new(),
+            cancel_in_flight: false,
+            deadline_ns: None,
+            max_total_attempts: None,
+            total_attempts: 0,
+            expired: false,
+            instructions: 0,
+            chunks_completed: 0,
+        };
+        open_lease(&mut job, miner, 0, None, NonceRange { start: 0, size }, 0);
+        job
+    }
+
+    fn steal(job: &mut Job, thief: Principal) -> u64 {
+        let (victim, range) = steal_tail(job, thief, &BTreeMap::new()).unwrap();
+        let id = job.leases.len() as u64;
+        open_lease(job, thief, id, Some(victim), range, 0);
+        id
+    }
+
+    fn pooled(job: &Job) -> Vec<(u64, u64)> {
+        let mut out: Vec<(u64, u64)> = job.retry_pool.iter().map(|r| (r.start, r.size)).collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn failed_victim_requeues_its_abandoned_tail() {
+        let (victim, thief) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let mut job = job_with_lease(victim, 4 * MIN_STEAL_SIZE);
+        let tail = steal(&mut job, thief);
+
+        // The victim still covers the tail, so nothing is pooled yet
+        requeue_lease(&mut job, tail);
+        assert!(job.leases[tail as usize].status == LeaseStatus::Abandoned);
+        assert!(job.retry_pool.is_empty());
+
+        requeue_lease(&mut job, 0);
+        assert_eq!(pooled(&job), vec![(0, 2 * MIN_STEAL_SIZE), (2 * MIN_STEAL_SIZE, 2 * MIN_STEAL_SIZE)]);
+        assert!(job.leases.iter().all(|l| l.status == LeaseStatus::Requeued));
+        assert!(!is_exhausted(&job));
+    }
+
+    #[test]
+    fn failed_victim_requeues_nested_tails_once() {
+        let miners: Vec<Principal> = (1..=3).map(|i| Principal::from_slice(&[i])).collect();
+        let mut job = job_with_lease(miners[0], 8 * MIN_STEAL_SIZE);
+        let tail = steal(&mut job, miners[1]);
+        let nested = steal(&mut job, miners[2]);
+        assert_eq!(job.leases[nested as usize].stolen_from, Some(tail));
+
+        requeue_lease(&mut job, nested);
+        requeue_lease(&mut job, tail);
+        assert!(job.retry_pool.is_empty());
+
+        requeue_lease(&mut job, 0);
+        let searched: u64 = pooled(&job).iter().map(|&(_, size)| size).sum();
+        assert_eq!(searched, 8 * MIN_STEAL_SIZE);
+        assert_eq!(job.retry_pool.len(), 3);
+    }
+
+    #[test]
+    fn completed_victim_covers_its_abandoned_tail() {
+        let (victim, thief) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let mut job = job_with_lease(victim, 4 * MIN_STEAL_SIZE);
+        let tail = steal(&mut job, thief);
+
+        requeue_lease(&mut job, tail);
+        complete_lease(&mut job, 0);
+        assert!(job.leases.iter().all(|l| l.status == LeaseStatus::Completed));
+        assert!(is_exhausted(&job));
+    }
+
+    #[test]
+    fn late_reply_for_a_timed_out_lease_still_completes_it() {
+        let (miner, other) = (Principal::from_slice(&[1]), Principal::from_slice(&[2]));
+        let mut job = job_with_lease(miner, MIN_STEAL_SIZE);
+        job.leases[0].session_token = Some(7);
+
+        // The timeout sweep requeues the range; the token stays on the lease
+        requeue_lease(&mut job, 0);
+        assert_eq!(pooled(&job), vec![(0, MIN_STEAL_SIZE)]);
+        assert!(lease_tokens_match(&job, &[0], miner, 7));
+        assert!(!lease_tokens_match(&job, &[0], miner, 8));
+        assert!(!lease_tokens_match(&job, &[0], other, 7));
+
+        complete_lease(&mut job, 0);
+        assert!(job.retry_pool.is_empty());
+        assert!(is_exhausted(&job));
+    }
+
+    #[test]
+    fn quantum_keeps_the_job_chunk_size_without_a_fetched_config() {
+        // The defaults' bounds (1,000..=1,000,000) don't apply
+        assert_eq!(quantum(0, 0, 10), 10);
+        assert_eq!(quantum(0, 0, 5_000_000), 5_000_000);
+        assert_eq!(quantum(200, 100, 100), 200);
+        assert_eq!(quantum(1_000, 100, 100), 100 * MAX_CHUNK_SCALE);
+    }
+}
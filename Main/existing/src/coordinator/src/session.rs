@@ -0,0 +1,47 @@
+// session.rs - per-assignment tokens a miner must echo back with its result
+//
+// Tokens are SHA-256(secret || counter || job_id || lease_id), so a reply
+// carrying a token from an earlier assignment (or a made-up one) can't be
+// attributed to the lease currently in flight.
+use std::cell::{Cell, RefCell};
+
+use sha2::{Digest, Sha256};
+
+use crate::vrf;
+
+thread_local! {
+    /// Filled from raw_rand before the first assignment
+    static SECRET: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+    static COUNTER: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Fetch the secret once; later calls return without awaiting
+pub async fn ensure_secret() {
+    if SECRET.with(|s| s.borrow().is_some()) {
+        return;
+    }
+    let beacon = vrf::fetch_beacon().await;
+    SECRET.with(|s| {
+        s.borrow_mut().get_or_insert(beacon);
+    });
+}
+
+/// A fresh token for (job_id, lease_id)
+pub fn issue(job_id: u64, lease_id: u64) -> u64 {
+    let n = COUNTER.with(|c| {
+        let n = c.get();
+        c.set(n + 1);
+        n
+    });
+
+    let mut h = Sha256::new();
+    SECRET.with(|s| h.update(s.borrow().as_deref().unwrap_or_default()));
+    h.update(n.to_le_bytes());
+    h.update(job_id.to_le_bytes());
+    h.update(lease_id.to_le_bytes());
+    let out = h.finalize();
+
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&out[0..8]);
+    u64::from_le_bytes(buf)
+}
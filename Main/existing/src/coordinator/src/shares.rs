@@ -0,0 +1,183 @@
+// shares.rs - pool share accounting. A job started with a share target has
+// its miners send back the best hashes that meet it without solving the
+// job. Each one is re-verified, logged and credited to its miner with the
+// work it proves, so rewards can follow work instead of whoever happened
+// to find the block. The tally is kept across upgrades.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::time;
+
+use crate::events::{self, EventKind};
+use crate::payouts;
+use crate::protocol::MAX_SHARES_PER_CHUNK;
+use crate::scheduler::NonceRange;
+use crate::verify::{self, PowAlgorithm};
+use pow_core::target::{self, Target};
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerShares {
+    pub miner: Principal,
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Expected hashes behind the accepted shares
+    pub work: f64,
+    pub last_share_at: u64,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct ShareTally {
+    pub job_id: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub work: f64,
+    /// Most work first
+    pub miners: Vec<MinerShares>,
+}
+
+#[derive(Clone, Default, CandidType, Deserialize)]
+struct JobShares {
+    miners: BTreeMap<Principal, MinerShares>,
+    /// Credited nonces, so a replica's copy of a share counts once; dropped
+    /// once the job's events are compacted
+    nonces: BTreeSet<u64>,
+}
+
+/// Kept across upgrades; the share events behind it may be compacted away
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    jobs: BTreeMap<u64, JobShares>,
+}
+
+thread_local! {
+    static LEDGER: RefCell<BTreeMap<u64, JobShares>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot { jobs: LEDGER.with(|l| l.borrow().clone()) }
+}
+
+pub fn restore(snapshot: Snapshot) {
+    LEDGER.with(|l| *l.borrow_mut() = snapshot.jobs);
+}
+
+/// A finished job's share events were compacted; its tally stays
+pub fn compacted(job_id: u64) {
+    LEDGER.with(|l| {
+        if let Some(job) = l.borrow_mut().get_mut(&job_id) {
+            job.nonces.clear();
+        }
+    });
+}
+
+/// What a job's shares are checked against
+pub struct ShareCheck<'a> {
+    pub algorithm: PowAlgorithm,
+    pub block_data: &'a str,
+    pub target: &'a Target,
+    pub share_target: &'a Target,
+}
+
+/// Verify the shares a miner sent back for `range` and credit the good
+/// ones. Shares past MAX_SHARES_PER_CHUNK, outside the range or failing
+/// verification are rejected; one already credited to a replica of the
+/// range is skipped. Returns (accepted, rejected).
+pub fn credit(
+    job_id: u64,
+    miner: Principal,
+    check: &ShareCheck,
+    range: NonceRange,
+    shares: Vec<(u64, String)>,
+) -> (u64, u64) {
+    let difficulty = target::to_difficulty(check.share_target);
+    let (mut accepted, mut rejected) = (0, 0);
+
+    for (i, (nonce, hash)) in shares.into_iter().enumerate() {
+        let in_range = nonce >= range.start && nonce - range.start < range.size;
+        let verdict = if i >= MAX_SHARES_PER_CHUNK {
+            Err(format!("more than {} shares in one chunk", MAX_SHARES_PER_CHUNK))
+        } else if !in_range {
+            Err(format!("nonce {} is outside the assigned range", nonce))
+        } else {
+            verify::verify_share(check.algorithm, check.block_data, check.target, check.share_target, nonce, &hash)
+        };
+
+        let kind = match verdict {
+            Ok(()) if is_credited(job_id, nonce) => continue,
+            Ok(()) => {
+                accepted += 1;
+                EventKind::Share { miner, nonce, hash: hash.to_ascii_lowercase(), difficulty }
+            }
+            Err(reason) => {
+                rejected += 1;
+                EventKind::ShareRejected { miner, nonce, reason }
+            }
+        };
+        apply(job_id, time(), &kind);
+        payouts::apply(job_id, time(), &kind);
+        events::record(job_id, kind);
+    }
+    (accepted, rejected)
+}
+
+fn is_credited(job_id: u64, nonce: u64) -> bool {
+    LEDGER.with(|l| l.borrow().get(&job_id).is_some_and(|j| j.nonces.contains(&nonce)))
+}
+
+/// Fold one share event into the tally
+fn apply(job_id: u64, at: u64, kind: &EventKind) {
+    let (miner, nonce, work) = match kind {
+        EventKind::Share { miner, nonce, difficulty, .. } => (*miner, *nonce, Some(2f64.powi(*difficulty as i32))),
+        EventKind::ShareRejected { miner, nonce, .. } => (*miner, *nonce, None),
+        _ => return,
+    };
+
+    LEDGER.with(|l| {
+        let mut l = l.borrow_mut();
+        let job = l.entry(job_id).or_default();
+        let entry = job.miners.entry(miner).or_insert_with(|| MinerShares {
+            miner,
+            accepted: 0,
+            rejected: 0,
+            work: 0.0,
+            last_share_at: 0,
+        });
+        match work {
+            Some(work) => {
+                job.nonces.insert(nonce);
+                entry.accepted += 1;
+                entry.work += work;
+                entry.last_share_at = at;
+            }
+            None => entry.rejected += 1,
+        }
+    });
+}
+
+/// Rebuild the tally from the event log of a canister saved before the
+/// tally was
+pub fn rebuild() {
+    LEDGER.with(|l| l.borrow_mut().clear());
+    events::each(|ev| apply(ev.job_id, ev.timestamp, &ev.kind));
+}
+
+/// Per-miner shares of a job; all zeros if it has none
+pub fn get_shares(job_id: u64) -> ShareTally {
+    LEDGER.with(|l| {
+        let l = l.borrow();
+        let mut miners: Vec<MinerShares> = l
+        .get(&job_id)
+        .map(|j| j.miners.values().cloned().collect())
+        .unwrap_or_default();
+        miners.sort_by(|a, b| b.work.total_cmp(&a.work).then(a.miner.cmp(&b.miner)));
+
+        ShareTally {
+            job_id,
+            accepted: miners.iter().map(|m| m.accepted).sum(),
+            rejected: miners.iter().map(|m| m.rejected).sum(),
+            work: miners.iter().map(|m| m.work).sum(),
+            miners,
+        }
+    })
+}
@@ -0,0 +1,172 @@
+// stake.rs - optional miner bonds. With a stake policy set, a miner is only
+// scheduled once its operator has bonded at least `required` cycles on its
+// behalf. Each invalid solution forfeits part of the bond to the
+// coordinator; what's left goes back to the operator when the miner leaves
+// the fleet cleanly. Off by default, so admin-managed fleets need no bonds.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
+use ic_cdk::api::time;
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct StakePolicy {
+    /// Cycles a miner must have bonded to be scheduled
+    pub required: u64,
+    /// Cycles forfeited per invalid solution, capped at what's bonded
+    pub invalid_solution_forfeit: u64,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Bond {
+    pub miner: Principal,
+    /// Who deposited it, and who gets it back
+    pub operator: Principal,
+    pub amount: u64,
+    /// Taken for misbehavior so far
+    pub forfeited: u64,
+    pub updated_at: u64,
+}
+
+/// Kept across upgrades
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    policy: Option<StakePolicy>,
+    bonds: BTreeMap<Principal, Bond>,
+}
+
+thread_local! {
+    static STATE: RefCell<Snapshot> = RefCell::new(Snapshot::default());
+    /// Miners whose bond is on its way back to the operator
+    static REFUNDING: RefCell<BTreeSet<Principal>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+pub fn snapshot() -> Snapshot {
+    STATE.with(|s| s.borrow().clone())
+}
+
+pub fn restore(snapshot: Snapshot) {
+    STATE.with(|s| *s.borrow_mut() = snapshot);
+}
+
+pub fn set_policy(policy: Option<StakePolicy>) {
+    STATE.with(|s| s.borrow_mut().policy = policy);
+}
+
+pub fn policy() -> Option<StakePolicy> {
+    STATE.with(|s| s.borrow().policy)
+}
+
+/// Add `amount` cycles to `miner`'s bond. Its operator may top it up; with
+/// `may_open` (the miner itself or an admin) the caller opens the bond, or
+/// takes over one that is empty. Returns the new total.
+pub fn deposit(miner: Principal, operator: Principal, amount: u64, may_open: bool) -> Result<u64, String> {
+    let refunding = REFUNDING.with(|r| r.borrow().contains(&miner));
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let bond = match s.bonds.get_mut(&miner) {
+            Some(b) if b.operator == operator => b,
+            Some(b) if b.amount == 0 && may_open && !refunding => {
+                b.operator = operator;
+                b
+            }
+            Some(b) => return Err(format!("miner is bonded by {}", b.operator)),
+            None if may_open => s.bonds.entry(miner).or_insert(Bond {
+                miner,
+                operator,
+                amount: 0,
+                forfeited: 0,
+                updated_at: 0,
+            }),
+            None => return Err("only the miner or an admin can open its bond".to_string()),
+        };
+        bond.amount = bond.amount.saturating_add(amount);
+        bond.updated_at = time();
+        Ok(bond.amount)
+    })
+}
+
+/// True if the scheduler may give this miner work
+pub fn is_bonded(miner: Principal) -> bool {
+    STATE.with(|s| {
+        let s = s.borrow();
+        match s.policy {
+            Some(p) => s.bonds.get(&miner).is_some_and(|b| b.amount >= p.required),
+            None => true,
+        }
+    })
+}
+
+/// Take the policy's forfeit for an invalid solution; returns the cycles
+/// taken
+pub fn forfeit(miner: Principal) -> u64 {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let Some(p) = s.policy else { return 0 };
+        let Some(bond) = s.bonds.get_mut(&miner) else { return 0 };
+
+        let taken = p.invalid_solution_forfeit.min(bond.amount);
+        bond.amount -= taken;
+        bond.forfeited += taken;
+        bond.updated_at = time();
+        taken
+    })
+}
+
+/// Send what's left of `miner`'s bond back to its operator, which must be
+/// a canister that can receive cycles. The amount is added back if the
+/// deposit fails, on top of anything the operator deposited meanwhile; the
+/// bond is closed once it's empty. Returns the cycles refunded.
+pub async fn refund(miner: Principal) -> Result<u64, String> {
+    if !REFUNDING.with(|r| r.borrow_mut().insert(miner)) {
+        return Err("a refund of this bond is already in flight".to_string());
+    }
+    let taken = STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let bond = s.bonds.get_mut(&miner)?;
+        let amount = std::mem::take(&mut bond.amount);
+        Some((bond.operator, amount))
+    });
+    let Some((operator, amount)) = taken else {
+        REFUNDING.with(|r| r.borrow_mut().remove(&miner));
+        return Err("miner has no bond".to_string());
+    };
+
+    let result = if amount == 0 {
+        Ok(())
+    } else {
+        deposit_cycles(CanisterIdRecord { canister_id: operator }, amount as u128).await
+    };
+
+    REFUNDING.with(|r| r.borrow_mut().remove(&miner));
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        match &result {
+            Ok(()) if s.bonds.get(&miner).is_some_and(|b| b.amount == 0) => {
+                s.bonds.remove(&miner);
+            }
+            Ok(()) => {}
+            Err(_) => {
+                if let Some(b) = s.bonds.get_mut(&miner) {
+                    b.amount = b.amount.saturating_add(amount);
+                    b.updated_at = time();
+                }
+            }
+        }
+    });
+    result
+    .map(|()| amount)
+    .map_err(|(code, msg)| format!("refund of {} cycles failed: {:?} {}", amount, code, msg))
+}
+
+pub fn get_bond(miner: Principal) -> Option<Bond> {
+    STATE.with(|s| s.borrow().bonds.get(&miner).cloned())
+}
+
+/// Every bond, largest first
+pub fn get_bonds() -> Vec<Bond> {
+    let mut bonds: Vec<Bond> = STATE.with(|s| s.borrow().bonds.values().cloned().collect());
+    bonds.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.miner.cmp(&b.miner)));
+    bonds
+}
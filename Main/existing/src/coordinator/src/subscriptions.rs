@@ -0,0 +1,56 @@
+// subscriptions.rs - push solution and expiry notifications to subscribed
+// canisters through canister_notify's solution / job_expired topics
+use candid::{CandidType, Deserialize, Principal};
+
+const SOLUTION_TOPIC: &str = "solution";
+const EXPIRED_TOPIC: &str = "job_expired";
+
+#[derive(Clone, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Subscription {
+    pub canister: Principal,
+    pub method: String,
+    /// Called with (job_id, reason) when a job hits its deadline or attempt cap
+    pub expired_method: Option<String>,
+}
+
+/// Returns false if the (canister, method) pair is already subscribed
+pub fn subscribe(canister: Principal, method: String, expired_method: Option<String>) -> bool {
+    if !canister_notify::subscribe(SOLUTION_TOPIC, canister, method) {
+        return false;
+    }
+    if let Some(expired) = expired_method {
+        canister_notify::subscribe(EXPIRED_TOPIC, canister, expired);
+    }
+    true
+}
+
+/// Drop every subscription of `canister`; false if it had none
+pub fn unsubscribe(canister: Principal) -> bool {
+    let solutions = canister_notify::unsubscribe(Some(SOLUTION_TOPIC), canister);
+    let expiries = canister_notify::unsubscribe(Some(EXPIRED_TOPIC), canister);
+    solutions || expiries
+}
+
+/// One entry per solution method; `expired_method` is the canister's first
+/// expiry method
+pub fn list() -> Vec<Subscription> {
+    let expired = canister_notify::subscribers(Some(EXPIRED_TOPIC));
+    canister_notify::subscribers(Some(SOLUTION_TOPIC))
+    .into_iter()
+    .map(|s| Subscription {
+        canister: s.canister,
+        expired_method: expired.iter().find(|e| e.canister == s.canister).map(|e| e.method.clone()),
+        method: s.method,
+    })
+    .collect()
+}
+
+/// `(job_id, nonce, hash, miner)` to every subscriber
+pub fn publish_solution(job_id: u64, nonce: u64, hash: &str, miner: Principal) {
+    canister_notify::publish(SOLUTION_TOPIC, (job_id, nonce, hash.to_string(), miner));
+}
+
+/// `(job_id, reason)` to subscribers that asked for expiries
+pub fn publish_expired(job_id: u64, reason: &str) {
+    canister_notify::publish(EXPIRED_TOPIC, (job_id, reason.to_string()));
+}
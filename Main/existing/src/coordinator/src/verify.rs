@@ -0,0 +1,50 @@
+// verify.rs - recompute miner-reported solutions before trusting them
+use pow_core::{hash_to_hex, NonceEncoding};
+use pow_core::target::{self, Target};
+
+pub use pow_core::{pow_hash, PowAlgorithm, MAX_SCRYPT_LOG_N};
+
+/// Err with the reason if the reported hash is wrong or too weak
+pub fn verify_solution(
+    algorithm: PowAlgorithm,
+    block_data: &str,
+    target: &Target,
+    nonce: u64,
+    reported_hash: &str,
+) -> Result<(), String> {
+    // Jobs always append the nonce as little-endian bytes
+    let hash = pow_hash(algorithm, NonceEncoding::LeBytes, block_data, nonce);
+
+    if !reported_hash.eq_ignore_ascii_case(&hash_to_hex(&hash)) {
+        return Err(format!("hash mismatch for nonce {}", nonce));
+    }
+    if !target::meets(&hash, target) {
+        return Err(format!("hash does not meet target {}", hash_to_hex(target)));
+    }
+    Ok(())
+}
+
+/// Err with the reason unless the reported hash is right and meets
+/// `share_target` without meeting the job's `target` (that would be a
+/// solution, reported as found)
+pub fn verify_share(
+    algorithm: PowAlgorithm,
+    block_data: &str,
+    target: &Target,
+    share_target: &Target,
+    nonce: u64,
+    reported_hash: &str,
+) -> Result<(), String> {
+    let hash = pow_hash(algorithm, NonceEncoding::LeBytes, block_data, nonce);
+
+    if !reported_hash.eq_ignore_ascii_case(&hash_to_hex(&hash)) {
+        return Err(format!("hash mismatch for nonce {}", nonce));
+    }
+    if !target::meets(&hash, share_target) {
+        return Err(format!("hash does not meet share target {}", hash_to_hex(share_target)));
+    }
+    if target::meets(&hash, target) {
+        return Err(format!("nonce {} solves the job but was sent as a share", nonce));
+    }
+    Ok(())
+}
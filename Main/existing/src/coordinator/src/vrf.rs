@@ -0,0 +1,182 @@
+// vrf.rs - VRF-like seed derivation and the ledger of parallel rounds
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::time;
+use sha2::{Digest, Sha256};
+
+/// One `start_vrf_parallel_mining` call, kept so offsets can be audited
+#[derive(Clone, CandidType, Deserialize)]
+pub struct VrfRound {
+    pub id: u64,
+    pub prev_block_hash: String,
+    pub round: u64,
+    /// raw_rand output mixed into the seed, if the beacon was requested
+    pub beacon: Option<Vec<u8>>,
+    pub seed: Vec<u8>,
+    pub miners: Vec<Principal>,
+    pub started_at: u64,
+    pub winner: Option<Principal>,
+    pub solution: Option<(u64, String)>,
+}
+
+/// Only the latest rounds are kept; older ids read as unknown
+const MAX_ROUNDS: usize = 1_000;
+
+/// Kept across upgrades
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    rounds: VecDeque<VrfRound>,
+    next_id: u64,
+}
+
+impl Snapshot {
+    /// Append a round under the next id, dropping the oldest past MAX_ROUNDS
+    fn push(&mut self, mut round: VrfRound) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        round.id = id;
+        if self.rounds.len() >= MAX_ROUNDS {
+            self.rounds.pop_front();
+        }
+        self.rounds.push_back(round);
+        id
+    }
+
+    /// Ids are consecutive, so a round's place follows from the oldest kept
+    fn index(&self, id: u64) -> Option<usize> {
+        let first = self.rounds.front()?.id;
+        usize::try_from(id.checked_sub(first)?).ok().filter(|&i| i < self.rounds.len())
+    }
+}
+
+thread_local! {
+    static ROUNDS: RefCell<Snapshot> = const { RefCell::new(Snapshot { rounds: VecDeque::new(), next_id: 0 }) };
+}
+
+pub fn snapshot() -> Snapshot {
+    ROUNDS.with(|r| r.borrow().clone())
+}
+
+pub fn restore(snapshot: Snapshot) {
+    ROUNDS.with(|r| *r.borrow_mut() = snapshot);
+}
+
+// ------------------------------------------------------------
+// Deterministic VRF-like helpers
+// ------------------------------------------------------------
+
+/// Without a beacon the seed is predictable from (prev hash, round); with
+/// one, miners can't know their offsets before the round starts
+pub fn vrf_seed(prev_block_hash: &str, round: u64, beacon: Option<&[u8]>) -> [u8; 32] {
+    let mut h = Sha256::new();
+    h.update(prev_block_hash.as_bytes());
+    h.update(round.to_le_bytes());
+    if let Some(beacon) = beacon {
+        h.update(beacon);
+    }
+    h.finalize().into()
+}
+
+pub fn offset_for_miner(seed: &[u8; 32], miner_index: u64) -> u64 {
+    let mut h = Sha256::new();
+    h.update(seed);
+    h.update(miner_index.to_le_bytes());
+    let out = h.finalize();
+
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&out[0..8]);
+    u64::from_le_bytes(buf)
+}
+
+/// 32 bytes of randomness from the management canister
+pub async fn fetch_beacon() -> Vec<u8> {
+    match raw_rand().await {
+        Ok((bytes,)) => bytes,
+        Err((code, msg)) => ic_cdk::trap(&format!("raw_rand failed: {:?} {}", code, msg)),
+    }
+}
+
+// ------------------------------------------------------------
+// Round ledger
+// ------------------------------------------------------------
+
+pub fn open_round(
+    prev_block_hash: String,
+    round: u64,
+    beacon: Option<Vec<u8>>,
+    seed: [u8; 32],
+    miners: Vec<Principal>,
+) -> u64 {
+    ROUNDS.with(|r| {
+        r.borrow_mut().push(VrfRound {
+            id: 0,
+            prev_block_hash,
+            round,
+            beacon,
+            seed: seed.to_vec(),
+            miners,
+            started_at: time(),
+            winner: None,
+            solution: None,
+        })
+    })
+}
+
+pub fn close_round(id: u64, winner: Principal, nonce: u64, hash: String) {
+    ROUNDS.with(|r| {
+        let mut r = r.borrow_mut();
+        if let Some(round) = r.index(id).and_then(|i| r.rounds.get_mut(i)) {
+            round.winner = Some(winner);
+            round.solution = Some((nonce, hash));
+        }
+    });
+}
+
+pub fn get_round(id: u64) -> Option<VrfRound> {
+    ROUNDS.with(|r| {
+        let r = r.borrow();
+        r.index(id).map(|i| r.rounds[i].clone())
+    })
+}
+
+/// Rounds ever opened, including those no longer kept
+pub fn round_count() -> u64 {
+    ROUNDS.with(|r| r.borrow().next_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round() -> VrfRound {
+        VrfRound {
+            id: 0,
+            prev_block_hash: "prev".to_string(),
+            round: 0,
+            beacon: None,
+            seed: Vec::new(),
+            miners: Vec::new(),
+            started_at: 0,
+            winner: None,
+            solution: None,
+        }
+    }
+
+    #[test]
+    fn rounds_past_the_cap_drop_the_oldest() {
+        let mut rounds = Snapshot::default();
+        for _ in 0..MAX_ROUNDS + 2 {
+            rounds.push(round());
+        }
+        assert_eq!(rounds.rounds.len(), MAX_ROUNDS);
+        assert_eq!(rounds.next_id, MAX_ROUNDS as u64 + 2);
+        assert_eq!(rounds.index(1), None);
+        assert_eq!(rounds.index(2).map(|i| rounds.rounds[i].id), Some(2));
+        let last = MAX_ROUNDS as u64 + 1;
+        assert_eq!(rounds.index(last).map(|i| rounds.rounds[i].id), Some(last));
+        assert_eq!(rounds.index(last + 1), None);
+    }
+}
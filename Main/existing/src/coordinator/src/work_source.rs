@@ -0,0 +1,259 @@
+// work_source.rs - pull jobs from an off-chain pool over HTTPS outcalls (a
+// getblocktemplate-style JSON template) and post found nonces back, so the
+// fleet can act as that pool's hashing backend
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use candid::{CandidType, Deserialize, Principal};
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
+use ic_cdk::api::management_canister::http_request::{
+    self as outcall, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse as OutcallResponse,
+    TransformArgs, TransformContext,
+};
+use ic_cdk::query;
+
+use crate::scheduler::{self, JobLimits};
+use pow_core::target::{self, Target};
+use crate::verify::{PowAlgorithm, MAX_SCRYPT_LOG_N};
+
+/// Attached to each outcall; whatever the call doesn't use is refunded
+const OUTCALL_CYCLES: u128 = 2_000_000_000;
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct WorkSource {
+    /// GET returns the current template
+    pub template_url: String,
+    /// Found solutions are POSTed here
+    pub submit_url: String,
+    pub poll_interval_secs: u64,
+    /// Join the shared fleet, as with start_dynamic_mining
+    pub miners: Vec<Principal>,
+    pub chunk_size: u64,
+    /// Share of the fleet next to other running jobs (default 1)
+    pub weight: Option<u32>,
+    pub max_response_bytes: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct WorkSourceStatus {
+    /// Work id of the latest template and the job mining it
+    pub workid: Option<String>,
+    pub job_id: Option<u64>,
+    pub templates_fetched: u64,
+    pub jobs_started: u64,
+    pub solutions_submitted: u64,
+    pub last_error: Option<String>,
+}
+
+/// Kept across upgrades, so polling resumes and the running job still
+/// reports its solutions
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Snapshot {
+    source: Option<WorkSource>,
+    status: WorkSourceStatus,
+}
+
+/// What a template turns into: a new `workid` replaces the running job
+struct Template {
+    workid: String,
+    block_data: String,
+    target: Target,
+    algorithm: PowAlgorithm,
+}
+
+thread_local! {
+    static SOURCE: RefCell<Option<WorkSource>> = const { RefCell::new(None) };
+    static STATUS: RefCell<WorkSourceStatus> = RefCell::new(WorkSourceStatus::default());
+    static POLL_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    static POLLING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Replace the source (None stops polling; a running job keeps going)
+pub fn set_source(source: Option<WorkSource>) {
+    if let Some(id) = POLL_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+    if let Some(s) = &source {
+        let id = set_timer_interval(Duration::from_secs(s.poll_interval_secs), || {
+            ic_cdk::spawn(async {
+                let _ = poll().await;
+            })
+        });
+        POLL_TIMER.with(|t| t.set(Some(id)));
+    }
+    SOURCE.with(|s| *s.borrow_mut() = source);
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot { source: get_source(), status: status() }
+}
+
+/// Timers don't survive upgrades, so this re-arms polling
+pub fn restore(snapshot: Snapshot) {
+    STATUS.with(|s| *s.borrow_mut() = snapshot.status);
+    set_source(snapshot.source);
+}
+
+pub fn get_source() -> Option<WorkSource> {
+    SOURCE.with(|s| s.borrow().clone())
+}
+
+pub fn status() -> WorkSourceStatus {
+    STATUS.with(|s| s.borrow().clone())
+}
+
+fn record_error(error: &str) {
+    ic_cdk::println!("❌ Work source: {}", error);
+    STATUS.with(|s| s.borrow_mut().last_error = Some(error.to_string()));
+}
+
+// ------------------------------------------------------------
+// Templates
+// ------------------------------------------------------------
+
+/// Expects `workid`, `data` (or `block_data`), and either a 64-hex-digit
+/// `target` or a leading-zero `difficulty`. `algorithm` is one of sha256
+/// (default), blake3, sha256d, keccak256 or scrypt, which reads
+/// `scrypt_log_n`.
+fn parse_template(body: &[u8]) -> Result<Template, String> {
+    let json: serde_json::Value = serde_json::from_slice(body).map_err(|e| format!("bad template JSON: {}", e))?;
+    let text = |key: &str| json.get(key).and_then(|v| v.as_str());
+
+    let workid = text("workid").ok_or("template has no workid")?.to_string();
+    let block_data = text("data")
+    .or_else(|| text("block_data"))
+    .ok_or("template has no data")?
+    .to_string();
+
+    let target = match (text("target"), json.get("difficulty").and_then(|v| v.as_u64())) {
+        (Some(hex_target), _) => {
+            let bytes = hex::decode(hex_target).map_err(|e| format!("bad target hex: {}", e))?;
+            target::parse(&bytes)?
+        }
+        (None, Some(d)) if d <= 256 => target::from_difficulty(d as u32),
+        _ => return Err("template needs a target or a difficulty up to 256".to_string()),
+    };
+
+    let algorithm = match text("algorithm").unwrap_or("sha256") {
+        "sha256" => PowAlgorithm::Sha256,
+        "blake3" => PowAlgorithm::Blake3,
+        "sha256d" => PowAlgorithm::DoubleSha256,
+        "keccak256" => PowAlgorithm::Keccak256,
+        "scrypt" => match json.get("scrypt_log_n").and_then(|v| v.as_u64()) {
+            Some(log_n) if log_n <= MAX_SCRYPT_LOG_N as u64 => PowAlgorithm::Scrypt { log_n: log_n as u8 },
+            _ => return Err(format!("scrypt needs scrypt_log_n up to {}", MAX_SCRYPT_LOG_N)),
+        },
+        other => return Err(format!("unknown algorithm {}", other)),
+    };
+
+    Ok(Template { workid, block_data, target, algorithm })
+}
+
+/// Drop response headers so every replica sees the same response
+#[query]
+pub fn transform_work_source(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: args.response.body,
+    }
+}
+
+async fn fetch(source: &WorkSource, method: HttpMethod, url: &str, body: Option<Vec<u8>>) -> Result<Vec<u8>, String> {
+    let arg = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(source.max_response_bytes.unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)),
+        method,
+        headers: vec![HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() }],
+        body,
+        transform: Some(TransformContext::from_name("transform_work_source".to_string(), Vec::new())),
+    };
+    let (response,) = outcall::http_request(arg, OUTCALL_CYCLES)
+    .await
+    .map_err(|(code, msg)| format!("{} outcall: {:?} {}", url, code, msg))?;
+    if response.status != 200u32 {
+        return Err(format!("{} answered {}", url, response.status));
+    }
+    Ok(response.body)
+}
+
+/// Fetch the template once; Ok(true) if it carried new work and a job was
+/// started for it. Overlapping polls are skipped.
+pub async fn poll() -> Result<bool, String> {
+    let Some(source) = get_source() else {
+        return Err("no work source set".to_string());
+    };
+    if POLLING.with(|p| p.replace(true)) {
+        return Ok(false);
+    }
+    let result = fetch(&source, HttpMethod::GET, &source.template_url, None)
+    .await
+    .and_then(|body| parse_template(&body));
+    POLLING.with(|p| p.set(false));
+
+    let template = match result {
+        Ok(t) => t,
+        Err(e) => {
+            record_error(&e);
+            return Err(e);
+        }
+    };
+
+    let (previous, new_work) = STATUS.with(|s| {
+        let mut s = s.borrow_mut();
+        s.templates_fetched += 1;
+        (s.job_id, s.workid.as_deref() != Some(template.workid.as_str()))
+    });
+    if !new_work {
+        return Ok(false);
+    }
+
+    // Stale work is worthless to the pool
+    if let Some(job_id) = previous {
+        scheduler::stop_job(job_id);
+    }
+    let job_id = scheduler::start_scheduler(
+        source.miners.clone(),
+        template.block_data,
+        target::to_difficulty(&template.target),
+        template.algorithm,
+        template.target,
+        None,
+        0,
+        source.chunk_size,
+        source.weight.unwrap_or(crate::DEFAULT_JOB_WEIGHT),
+        JobLimits::default(),
+    );
+    crate::ensure_tick_timer();
+    ic_cdk::println!("🌐 Work source: job {} for work {}", job_id, template.workid);
+
+    STATUS.with(|s| {
+        let mut s = s.borrow_mut();
+        s.workid = Some(template.workid);
+        s.job_id = Some(job_id);
+        s.jobs_started += 1;
+        s.last_error = None;
+    });
+    Ok(true)
+}
+
+/// POST `{"workid", "nonce", "hash"}` to the pool if `job_id` is mining its
+/// latest template
+pub fn report_solution(job_id: u64, nonce: u64, hash: &str) {
+    let Some(source) = get_source() else { return };
+    let Some(workid) = STATUS.with(|s| {
+        let s = s.borrow();
+        s.workid.clone().filter(|_| s.job_id == Some(job_id))
+    }) else {
+        return;
+    };
+
+    let body = serde_json::json!({ "workid": workid, "nonce": nonce, "hash": hash }).to_string();
+    ic_cdk::spawn(async move {
+        match fetch(&source, HttpMethod::POST, &source.submit_url, Some(body.into_bytes())).await {
+            Ok(_) => STATUS.with(|s| s.borrow_mut().solutions_submitted += 1),
+            Err(e) => record_error(&e),
+        }
+    });
+}
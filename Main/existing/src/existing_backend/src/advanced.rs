@@ -7,13 +7,17 @@ use ic_cdk::{query, update};
 use ic_cdk::api::time;
 use ic_cdk::api::{canister_balance, instruction_counter};
 
-use crate::{mine_chunk_with_midstate, MiningStatus};
+use crate::{midstate_chunk, MinerError, MiningStatus, NonceEncoding, PowAlgorithm};
+use pow_core::target::{self, Target};
 
 use crate::cache;
 use crate::metrics;
 
-pub use cache::{get_cache_stats, clear_cache, is_cached};
-pub use metrics::{get_metrics, get_metrics_summary, reset_metrics, export_metrics_csv};
+pub use cache::{get_cache_stats, clear_cache, is_cached, set_cache_ttl};
+pub use cache::{export_cache_entries, import_cache_entries, export_cache, import_cache};
+pub use metrics::{get_metrics, get_metrics_summary, reset_metrics, export_metrics_csv, get_metrics_history};
+pub use metrics::{get_job_metrics, set_metrics_sink, get_metrics_sink, set_hashrate_window_secs};
+pub use metrics::{set_alert_rules, get_alert_rules, get_active_alerts};
 
 #[derive(Clone, CandidType, Deserialize)]
 pub struct AdvancedTask {
@@ -24,6 +28,12 @@ pub struct AdvancedTask {
     pub chunk_size: u64,
     pub total_attempts: u64,
     pub started_at: u64,
+    /// Overrides the cache's default TTL for this task's solution
+    pub cache_ttl_secs: Option<u64>,
+    /// None mines with SHA-256
+    pub algorithm: Option<PowAlgorithm>,
+    /// 32-byte big-endian target; None uses `difficulty`
+    pub target: Option<Vec<u8>>,
 }
 
 thread_local! {
@@ -40,19 +50,44 @@ pub fn start_advanced_mining(
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
-) {
-    // Check cache first
-    if let Some((cached_nonce, cached_hash)) = cache::cache_lookup(&block_data, difficulty) {
-        ic_cdk::println!(
-            "Cache hit! Block already mined: nonce={}, hash={}",
-            cached_nonce,
-            cached_hash
-        );
-        metrics::record_cache_hit();
-        return;
-    }
+    cache_ttl_secs: Option<u64>,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+) -> Result<(), MinerError> {
+    crate::require_coordinator()?;
+    canister_auth::rate_limit::check()?;
+    crate::limits::check(&block_data, difficulty, chunk_size, algorithm.unwrap_or_default())?;
+    crate::resolve_target(difficulty, target.clone())?;
+    canister_auth::audit::record(
+        "start_advanced_mining",
+        format!(
+            "{:?}",
+            (
+                canister_auth::audit::digest(block_data.as_bytes()),
+                &difficulty,
+                &start_nonce,
+                &chunk_size,
+                &cache_ttl_secs,
+                &algorithm,
+                target.as_deref().map(hex::encode),
+            )
+        ),
+    );
+
+    // Check cache first (it only holds SHA-256 solutions to plain difficulties)
+    if algorithm.unwrap_or_default() == PowAlgorithm::Sha256 && target.is_none() {
+        if let Some((cached_nonce, cached_hash)) = cache::cache_lookup(&block_data, difficulty) {
+            ic_cdk::println!(
+                "Cache hit! Block already mined: nonce={}, hash={}",
+                cached_nonce,
+                cached_hash
+            );
+            metrics::record_cache_hit();
+            return Ok(());
+        }
 
-    metrics::record_cache_miss();
+        metrics::record_cache_miss();
+    }
 
     let task = AdvancedTask {
         running: true,
@@ -62,13 +97,24 @@ pub fn start_advanced_mining(
         chunk_size,
         total_attempts: 0,
         started_at: time(),
+        cache_ttl_secs,
+        algorithm,
+        target,
     };
 
     TASK.with(|t| *t.borrow_mut() = Some(task));
+    Ok(())
 }
 
 #[update]
-pub fn stop_advanced_mining() {
+pub fn stop_advanced_mining() -> Result<(), MinerError> {
+    crate::require_coordinator()?;
+    canister_auth::audit::record("stop_advanced_mining", String::new());
+    stop_task();
+    Ok(())
+}
+
+pub(crate) fn stop_task() {
     TASK.with(|t| {
         if let Some(mut task) = t.borrow().clone() {
             task.running = false;
@@ -82,6 +128,12 @@ pub fn get_advanced_status() -> Option<AdvancedTask> {
     TASK.with(|t| t.borrow().clone())
 }
 
+/// Put back a task saved across an upgrade; the heartbeat resumes it if it
+/// was running
+pub(crate) fn restore_task(task: Option<AdvancedTask>) {
+    TASK.with(|t| *t.borrow_mut() = task);
+}
+
 // ------------------------------------------------------------
 // Heartbeat mining with cache and metrics
 // ------------------------------------------------------------
@@ -101,8 +153,10 @@ fn advanced_heartbeat() {
             return;
         }
 
+        let target = task_target(&task);
+
         // Adaptive chunk sizing
-        let chunk = adaptive_chunk_size(task.difficulty);
+        let chunk = adaptive_chunk_size(target::to_difficulty(&target));
 
         if chunk != task.chunk_size {
             metrics::record_adaptive_change(chunk);
@@ -113,11 +167,13 @@ fn advanced_heartbeat() {
         let t0 = time();
         let i0 = instruction_counter();
 
-        let (status, attempts) = mine_chunk_with_midstate(
-            task.block_data.clone(),
-                                                          task.difficulty,
+        let (status, attempts) = midstate_chunk(
+            &task.block_data,
+                                                          &target,
                                                           task.next_nonce,
                                                           chunk,
+                                                          task.algorithm.unwrap_or_default(),
+                                                          NonceEncoding::LeBytes,
         );
 
         let t1 = time();
@@ -126,13 +182,13 @@ fn advanced_heartbeat() {
         task.total_attempts += attempts;
 
         // Statistical early termination
-        let should_terminate = !should_continue_mining(task.total_attempts, task.difficulty);
+        let should_terminate = !should_continue_mining(task.total_attempts, &target);
 
         if should_terminate {
             ic_cdk::println!(
                 "Early termination after {} attempts (expected ~{})",
                              task.total_attempts,
-                             expected_attempts_for_difficulty(task.difficulty)
+                             expected_attempts_for_target(&target)
             );
 
             // Record metrics
@@ -158,12 +214,15 @@ fn advanced_heartbeat() {
                 );
 
                 // Store in cache
-                cache::cache_store(
-                    task.block_data.clone(),
-                                   task.difficulty,
-                                   nonce,
-                                   hash.clone(),
-                );
+                if task.algorithm.unwrap_or_default() == PowAlgorithm::Sha256 && task.target.is_none() {
+                    cache::cache_store(
+                        &task.block_data,
+                                       task.difficulty,
+                                       nonce,
+                                       hash.clone(),
+                                       task.cache_ttl_secs,
+                    );
+                }
 
                 // Record metrics
                 metrics::record_chunk_result(
@@ -232,15 +291,20 @@ fn adaptive_chunk_size(difficulty: u32) -> u64 {
 // Statistical early termination
 // ------------------------------------------------------------
 
-fn should_continue_mining(attempts_so_far: u64, difficulty: u32) -> bool {
-    let expected = expected_attempts_for_difficulty(difficulty);
+fn should_continue_mining(attempts_so_far: u64, target: &Target) -> bool {
+    let expected = expected_attempts_for_target(target);
     attempts_so_far <= expected.saturating_mul(3)
 }
 
-fn expected_attempts_for_difficulty(difficulty: u32) -> u64 {
-    if difficulty >= 64 {
-        u64::MAX
-    } else {
-        1u64 << difficulty
-    }
+/// Saturates at u64::MAX for targets too hard to ever finish
+fn expected_attempts_for_target(target: &Target) -> u64 {
+    target::expected_attempts(target) as u64
+}
+
+/// The task's explicit target (validated when it started), else its difficulty
+fn task_target(task: &AdvancedTask) -> Target {
+    task.target
+    .as_deref()
+    .and_then(|t| target::parse(t).ok())
+    .unwrap_or_else(|| target::from_difficulty(task.difficulty))
 }
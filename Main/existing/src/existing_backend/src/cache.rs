@@ -1,105 +1,369 @@
-// cache.rs - LRU cache for mined blocks
-use std::cell::RefCell;
-use std::collections::HashMap;
+// cache.rs - LRU cache for mined blocks, kept in stable memory so cached
+// solutions survive upgrades
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::time::Duration;
 use candid::Principal;
 
-use candid::{CandidType, Deserialize};
+use candid::{CandidType, Decode, Deserialize, Encode};
 use ic_cdk::{query, update};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
 
-const MAX_CACHE_SIZE: usize = 1000;
+use crate::metrics;
+
+const MAX_CACHE_SIZE: usize = 100_000;
+/// Entries per export_cache_entries / export_cache reply
+const MAX_EXPORT: usize = 1_000;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+/// Solutions go stale once the chain moves past their block
+const DEFAULT_TTL_SECS: u64 = 3600;
+const PRUNE_INTERVAL_SECS: u64 = 60;
+
+const ENTRIES_MEMORY: MemoryId = MemoryId::new(0);
+const LRU_MEMORY: MemoryId = MemoryId::new(1);
+const EXPIRY_MEMORY: MemoryId = MemoryId::new(2);
+// 3 is stable_state's
+const CREATED_MEMORY: MemoryId = MemoryId::new(4);
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+/// SHA-256(block_data) || difficulty (big-endian). Only the digest is kept,
+/// so large or private payloads never land in canister state.
+type CacheKey = [u8; 36];
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct CacheEntry {
+    /// SHA-256(block_data); with `difficulty` this is the cache key
+    pub block_digest: Vec<u8>,
     pub nonce: u64,
     pub hash: String,
     pub difficulty: u32,
     pub hits: u64,
     pub created_at: u64,
     pub last_accessed: u64,
+    /// None = never expires
+    pub expires_at: Option<u64>,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// An entry plus its position in the LRU index
+#[derive(Clone, CandidType, Deserialize)]
+struct Stored {
+    entry: CacheEntry,
+    seq: u64,
+}
+
+impl Storable for Stored {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode cache entry"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("failed to decode cache entry")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Running sums over every entry, so stats never walk the cache
+#[derive(Default)]
+struct Totals {
+    hits: u64,
+    created_at_sum: u128,
+    per_difficulty: BTreeMap<u32, u64>,
+}
+
+impl Totals {
+    fn add(&mut self, e: &CacheEntry) {
+        self.hits += e.hits;
+        self.created_at_sum += e.created_at as u128;
+        *self.per_difficulty.entry(e.difficulty).or_default() += 1;
+    }
+
+    fn sub(&mut self, e: &CacheEntry) {
+        self.hits = self.hits.saturating_sub(e.hits);
+        self.created_at_sum = self.created_at_sum.saturating_sub(e.created_at as u128);
+        if let Some(n) = self.per_difficulty.get_mut(&e.difficulty) {
+            *n -= 1;
+            if *n == 0 {
+                self.per_difficulty.remove(&e.difficulty);
+            }
+        }
+    }
 }
 
 pub struct LRUCache {
-    entries: HashMap<String, CacheEntry>,
-    access_order: Vec<String>, // LRU tracking
+    entries: StableBTreeMap<CacheKey, Stored, Memory>,
+    /// (access seq, key), least recently used first
+    lru: StableBTreeMap<(u64, CacheKey), (), Memory>,
+    /// (expires_at, key), soonest first
+    expiry: StableBTreeMap<(u64, CacheKey), (), Memory>,
+    /// (created_at, key), oldest first
+    created: StableBTreeMap<(u64, CacheKey), (), Memory>,
+    totals: Totals,
+    next_seq: u64,
+    evictions: u64,
+    expirations: u64,
 }
 
 impl LRUCache {
+    /// Walks the cache once to rebuild the totals (and the created_at index
+    /// of a cache stored before it existed), so call it from init and
+    /// post_upgrade rather than a query
     pub fn new() -> Self {
+        type Index = StableBTreeMap<(u64, CacheKey), (), Memory>;
+        let (entries, lru, expiry, mut created): (StableBTreeMap<CacheKey, Stored, Memory>, Index, Index, Index) =
+        MEMORY_MANAGER.with(|m| {
+            let m = m.borrow();
+            (
+                StableBTreeMap::init(m.get(ENTRIES_MEMORY)),
+             StableBTreeMap::init(m.get(LRU_MEMORY)),
+             StableBTreeMap::init(m.get(EXPIRY_MEMORY)),
+             StableBTreeMap::init(m.get(CREATED_MEMORY)),
+            )
+        });
+        // Carry on after the newest access recorded before an upgrade
+        let next_seq = lru.last_key_value().map(|((seq, _), ())| seq + 1).unwrap_or(0);
+
+        let mut totals = Totals::default();
+        let reindex = created.len() != entries.len();
+        if reindex {
+            created.clear_new();
+        }
+        for (key, stored) in entries.iter() {
+            totals.add(&stored.entry);
+            if reindex {
+                created.insert((stored.entry.created_at, key), ());
+            }
+        }
+
         Self {
-            entries: HashMap::new(),
-            access_order: Vec::new(),
+            entries,
+            lru,
+            expiry,
+            created,
+            totals,
+            next_seq,
+            evictions: 0,
+            expirations: 0,
         }
     }
 
     pub fn get(&mut self, block_data: &str, difficulty: u32) -> Option<CacheEntry> {
         let key = Self::make_key(block_data, difficulty);
+        let mut stored = self.entries.get(&key)?;
 
-        if let Some(entry) = self.entries.get_mut(&key) {
-            // Update access stats
-            entry.hits += 1;
-            entry.last_accessed = ic_cdk::api::time();
+        // Expired entries are dropped on access
+        let now = ic_cdk::api::time();
+        if stored.entry.is_expired(now) {
+            self.remove(&key);
+            self.expirations += 1;
+            metrics::record_cache_expirations(1);
+            return None;
+        }
 
-            // Move to end (most recently used)
-            if let Some(pos) = self.access_order.iter().position(|k| k == &key) {
-                self.access_order.remove(pos);
-            }
-            self.access_order.push(key.clone());
+        // Update access stats and move to the most recently used end
+        stored.entry.hits += 1;
+        self.totals.hits += 1;
+        stored.entry.last_accessed = now;
+        self.lru.remove(&(stored.seq, key));
+        stored.seq = self.bump_seq();
+        self.lru.insert((stored.seq, key), ());
+        self.entries.insert(key, stored.clone());
+
+        Some(stored.entry)
+    }
+
+    /// `ttl_secs` of 0 keeps the entry until it is evicted
+    pub fn insert(&mut self, block_data: &str, difficulty: u32, nonce: u64, hash: String, ttl_secs: u64) {
+        let key = Self::make_key(block_data, difficulty);
+        self.remove(&key);
+
+        self.evict_if_full();
+
+        let now = ic_cdk::api::time();
+        let expires_at = (ttl_secs > 0)
+        .then(|| now.saturating_add(ttl_secs.saturating_mul(NS_PER_SEC)));
 
-            return Some(entry.clone());
+        let entry = CacheEntry {
+            block_digest: key[..32].to_vec(),
+            nonce,
+            hash,
+            difficulty,
+            hits: 0,
+            created_at: now,
+            last_accessed: now,
+            expires_at,
+        };
+        self.add(key, entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear_new();
+        self.lru.clear_new();
+        self.expiry.clear_new();
+        self.created.clear_new();
+        self.totals = Totals::default();
+    }
+
+    /// Store a new entry under every index
+    fn add(&mut self, key: CacheKey, entry: CacheEntry) {
+        let seq = self.bump_seq();
+        self.lru.insert((seq, key), ());
+        if let Some(at) = entry.expires_at {
+            self.expiry.insert((at, key), ());
         }
+        self.created.insert((entry.created_at, key), ());
+        self.totals.add(&entry);
+        self.entries.insert(key, Stored { entry, seq });
+    }
 
-        None
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(stored) = self.entries.remove(key) {
+            self.lru.remove(&(stored.seq, *key));
+            if let Some(at) = stored.entry.expires_at {
+                self.expiry.remove(&(at, *key));
+            }
+            self.created.remove(&(stored.entry.created_at, *key));
+            self.totals.sub(&stored.entry);
+        }
     }
 
-    pub fn insert(&mut self, block_data: String, difficulty: u32, nonce: u64, hash: String) {
-        let key = Self::make_key(&block_data, difficulty);
+    /// Drop every expired entry; returns how many went
+    pub fn prune_expired(&mut self, now: u64) -> usize {
+        let expired: Vec<CacheKey> = self
+        .expiry
+        .range(..(now.saturating_add(1), [0u8; 36]))
+        .map(|((_, key), ())| key)
+        .collect();
+
+        for key in &expired {
+            self.remove(key);
+        }
+        self.expirations += expired.len() as u64;
+        metrics::record_cache_expirations(expired.len() as u64);
+        expired.len()
+    }
 
-        // Evict LRU if at capacity
-        if self.entries.len() >= MAX_CACHE_SIZE && !self.entries.contains_key(&key) {
-            if let Some(lru_key) = self.access_order.first().cloned() {
-                self.entries.remove(&lru_key);
-                self.access_order.remove(0);
+    /// Evict LRU if at capacity
+    fn evict_if_full(&mut self) {
+        if self.entries.len() as usize >= MAX_CACHE_SIZE {
+            if let Some(((_, lru_key), ())) = self.lru.first_key_value() {
+                self.remove(&lru_key);
+                self.evictions += 1;
+                metrics::record_cache_eviction();
             }
         }
+    }
 
-        let now = ic_cdk::api::time();
+    /// Unexpired entries after the `(created_at, key)` cursor, oldest first;
+    /// without a key, from the first entry created at `since`
+    pub fn export_since(&self, since: u64, after: Option<CacheKey>, now: u64) -> Vec<CacheEntry> {
+        let range = match after {
+            Some(key) => self.created.range((Excluded((since, key)), Unbounded)),
+            None => self.created.range((since, [0u8; 36])..),
+        };
+        range
+        .filter_map(|((_, key), ())| self.entries.get(&key))
+        .map(|s| s.entry)
+        .filter(|e| !e.is_expired(now))
+        .take(MAX_EXPORT)
+        .collect()
+    }
 
-        self.entries.insert(
-            key.clone(),
-                            CacheEntry {
-                                nonce,
-                                hash,
-                                difficulty,
-                                hits: 0,
-                                created_at: now,
-                                last_accessed: now,
-                            },
-        );
+    /// Adopt an entry found elsewhere; false if it is malformed, expired or
+    /// already cached here
+    pub fn import(&mut self, mut entry: CacheEntry, now: u64) -> bool {
+        let Some(key) = Self::entry_key(&entry) else {
+            return false;
+        };
+        entry.hits = 0;
+        entry.last_accessed = now;
+        self.adopt(key, entry, now)
+    }
 
-        self.access_order.push(key);
+    /// Up to `limit` (key, entry) pairs after `start_after`, in key order
+    pub fn export_page(&self, start_after: Option<CacheKey>, limit: usize) -> Vec<(CacheKey, CacheEntry)> {
+        let range = match start_after {
+            Some(k) => self.entries.range((Excluded(k), Unbounded)),
+            None => self.entries.range(..),
+        };
+        range.take(limit).map(|(k, s)| (k, s.entry)).collect()
     }
 
-    pub fn clear(&mut self) {
-        self.entries.clear();
-        self.access_order.clear();
+    /// Restore an entry from `export_page`, stats included; false if the key
+    /// doesn't match the entry, it has expired or the key is already cached
+    pub fn restore(&mut self, key: CacheKey, entry: CacheEntry, now: u64) -> bool {
+        if Self::entry_key(&entry) != Some(key) {
+            return false;
+        }
+        self.adopt(key, entry, now)
+    }
+
+    fn adopt(&mut self, key: CacheKey, entry: CacheEntry, now: u64) -> bool {
+        if entry.is_expired(now) || self.entries.contains_key(&key) {
+            return false;
+        }
+
+        self.evict_if_full();
+        self.add(key, entry);
+        true
+    }
+
+    fn bump_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
     }
 
-    fn make_key(block_data: &str, difficulty: u32) -> String {
-        format!("{}:{}", block_data, difficulty)
+    fn entry_key(entry: &CacheEntry) -> Option<CacheKey> {
+        let digest = <[u8; 32]>::try_from(entry.block_digest.as_slice()).ok()?;
+        let mut key = [0u8; 36];
+        key[..32].copy_from_slice(&digest);
+        key[32..].copy_from_slice(&entry.difficulty.to_be_bytes());
+        Some(key)
+    }
+
+    fn make_key(block_data: &str, difficulty: u32) -> CacheKey {
+        let mut key = [0u8; 36];
+        key[..32].copy_from_slice(&Sha256::digest(block_data.as_bytes()));
+        key[32..].copy_from_slice(&difficulty.to_be_bytes());
+        key
     }
 
     pub fn stats(&self) -> CacheStats {
-        let total_hits: u64 = self.entries.values().map(|e| e.hits).sum();
+        let now = ic_cdk::api::time();
+        let size = self.entries.len() as usize;
+        let total_hits = self.totals.hits;
+        // sum(now - created_at) without walking the entries
+        let total_age_ns = (now as u128 * size as u128).saturating_sub(self.totals.created_at_sum);
 
         CacheStats {
-            size: self.entries.len(),
+            size,
             capacity: MAX_CACHE_SIZE,
             total_hits,
-            hit_rate: if self.entries.is_empty() {
+            hit_rate: if size == 0 {
                 0.0
             } else {
-                total_hits as f64 / self.entries.len() as f64
+                total_hits as f64 / size as f64
             },
+            evictions: self.evictions,
+            expirations: self.expirations,
+            per_difficulty: self.totals.per_difficulty.iter().map(|(&d, &n)| (d, n)).collect(),
+            avg_entry_age_secs: total_age_ns
+            .checked_div(size as u128)
+            .map_or(0, |ns| (ns / NS_PER_SEC as u128) as u64),
+            default_ttl_secs: default_ttl_secs(),
         }
     }
 }
@@ -110,11 +374,48 @@ pub struct CacheStats {
     pub capacity: usize,
     pub total_hits: u64,
     pub hit_rate: f64,
+    /// Entries pushed out by the LRU at capacity (since the last upgrade)
+    pub evictions: u64,
+    /// Entries dropped because their TTL ran out (since the last upgrade)
+    pub expirations: u64,
+    /// (difficulty, entries)
+    pub per_difficulty: Vec<(u32, u64)>,
+    pub avg_entry_age_secs: u64,
+    pub default_ttl_secs: u64,
 }
 
-// Global cache instance
+// Global cache instance. The canister must not use ic_cdk::storage's
+// stable_save/stable_restore: stable memory belongs to the memory manager.
 thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+    RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
     static CACHE: RefCell<LRUCache> = RefCell::new(LRUCache::new());
+    static TTL_SECS: Cell<u64> = const { Cell::new(DEFAULT_TTL_SECS) };
+}
+
+/// A region of the shared memory manager, for state kept outside the cache
+pub(crate) fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// Open the cache, rebuilding its totals (from init and post_upgrade)
+pub fn load() {
+    CACHE.with(|_| {});
+}
+
+fn default_ttl_secs() -> u64 {
+    TTL_SECS.with(|t| t.get())
+}
+
+/// Sweep expired entries every PRUNE_INTERVAL_SECS (armed from init and
+/// post_upgrade)
+pub fn start_prune_timer() {
+    canister_timers::set_timer_interval(Duration::from_secs(PRUNE_INTERVAL_SECS), || {
+        let pruned = CACHE.with(|c| c.borrow_mut().prune_expired(ic_cdk::api::time()));
+        if pruned > 0 {
+            ic_cdk::println!("Pruned {} expired cache entries", pruned);
+        }
+    });
 }
 
 // ------------------------------------------------------------
@@ -130,10 +431,12 @@ pub fn cache_lookup(block_data: &str, difficulty: u32) -> Option<(u64, String)>
     })
 }
 
-/// Store successful mining result in cache
-pub fn cache_store(block_data: String, difficulty: u32, nonce: u64, hash: String) {
+/// Store successful mining result in cache; `ttl_secs` overrides the
+/// default TTL (0 = never expire)
+pub fn cache_store(block_data: &str, difficulty: u32, nonce: u64, hash: String, ttl_secs: Option<u64>) {
+    let ttl = ttl_secs.unwrap_or_else(default_ttl_secs);
     CACHE.with(|c| {
-        c.borrow_mut().insert(block_data, difficulty, nonce, hash);
+        c.borrow_mut().insert(block_data, difficulty, nonce, hash, ttl);
     });
 }
 
@@ -143,12 +446,97 @@ pub fn get_cache_stats() -> CacheStats {
     CACHE.with(|c| c.borrow().stats())
 }
 
+/// TTL for entries stored without an override; 0 = never expire
+#[update]
+pub fn set_cache_ttl(ttl_secs: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_cache_ttl", format!("{:?}", ttl_secs));
+    TTL_SECS.with(|t| t.set(ttl_secs));
+}
+
 /// Clear all cache entries
 #[update]
 pub fn clear_cache() {
+    canister_auth::require_admin();
+    canister_auth::audit::record("clear_cache", String::new());
     CACHE.with(|c| c.borrow_mut().clear());
 }
 
+/// Solutions cached here, oldest first and at most MAX_EXPORT per call,
+/// from the first created at `since_ts`. Page by passing the last entry's
+/// created_at and key (block_digest ++ difficulty, big-endian) back; the
+/// page starts after it, so entries sharing a created_at aren't resent.
+/// Used by the coordinator to sync caches across the fleet.
+#[query]
+pub fn export_cache_entries(since_ts: u64, after_key: Option<Vec<u8>>) -> Vec<CacheEntry> {
+    let after = after_key.map(|k| {
+        <CacheKey>::try_from(k.as_slice()).unwrap_or_else(|_| ic_cdk::trap("cache keys are 36 bytes"))
+    });
+    CACHE.with(|c| c.borrow().export_since(since_ts, after, ic_cdk::api::time()))
+}
+
+/// Adopt solutions exported by another miner (admins only, since the
+/// entries can't be re-verified without their block_data); returns how many
+/// were new
+#[update]
+pub fn import_cache_entries(entries: Vec<CacheEntry>) -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("import_cache_entries", format!("{} entries", entries.len()));
+
+    let now = ic_cdk::api::time();
+    CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        let mut added = 0;
+        for entry in entries {
+            if c.import(entry, now) {
+                added += 1;
+            }
+        }
+        added
+    })
+}
+
+/// Page through the whole cache as (key, entry) pairs in key order, e.g. to
+/// back it up or move it to a replacement miner. Pass the last key back as
+/// `start_after`; an empty page means the end. Controllers only.
+#[query]
+pub fn export_cache(start_after: Option<Vec<u8>>, limit: u64) -> Vec<(Vec<u8>, CacheEntry)> {
+    canister_auth::require_admin();
+
+    let start_after = start_after.map(|k| {
+        <CacheKey>::try_from(k.as_slice()).unwrap_or_else(|_| ic_cdk::trap("cache keys are 36 bytes"))
+    });
+    let limit = (limit as usize).min(MAX_EXPORT);
+    CACHE.with(|c| {
+        c.borrow()
+        .export_page(start_after, limit)
+        .into_iter()
+        .map(|(k, e)| (k.to_vec(), e))
+        .collect()
+    })
+}
+
+/// Load pages from `export_cache`, keeping hit counts and timestamps;
+/// returns how many entries were restored. Controllers only.
+#[update]
+pub fn import_cache(entries: Vec<(Vec<u8>, CacheEntry)>) -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("import_cache", format!("{} entries", entries.len()));
+
+    let now = ic_cdk::api::time();
+    CACHE.with(|c| {
+        let mut c = c.borrow_mut();
+        let mut restored = 0;
+        for (key, entry) in entries {
+            let Ok(key) = <CacheKey>::try_from(key.as_slice()) else { continue };
+            if c.restore(key, entry, now) {
+                restored += 1;
+            }
+        }
+        restored
+    })
+}
+
 /// Check if block is in cache (for testing)
 #[query]
 pub fn is_cached(block_data: String, difficulty: u32) -> bool {
@@ -0,0 +1,84 @@
+// http.rs - Prometheus scrape endpoint (GET /metrics) over http_request
+use std::fmt::Write;
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::query;
+
+use crate::cache::get_cache_stats;
+use crate::metrics::{get_metrics, get_metrics_summary};
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Served uncertified, so scrape through the raw domain
+#[query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    let path = req.url.split('?').next().unwrap_or("");
+    if req.method != "GET" || path != "/metrics" {
+        return HttpResponse {
+            status_code: 404,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"not found".to_vec(),
+        };
+    }
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+        body: render().into_bytes(),
+    }
+}
+
+fn render() -> String {
+    let m = get_metrics();
+    let summary = get_metrics_summary();
+    let cache = get_cache_stats();
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u128| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    };
+
+    metric("pow_chunks_total", "counter", "Chunks mined", m.total_chunks_mined as u128);
+    metric("pow_hashes_total", "counter", "Hashes computed", m.total_hashes_computed as u128);
+    metric("pow_solutions_total", "counter", "Solutions found", m.solutions_found as u128);
+    metric("pow_instructions_total", "counter", "Instructions spent mining", m.total_instructions as u128);
+    metric("pow_early_terminations_total", "counter", "Tasks abandoned early", m.early_terminations as u128);
+    metric("pow_cache_hits_total", "counter", "Solution cache hits", m.cache_hits as u128);
+    metric("pow_cache_misses_total", "counter", "Solution cache misses", m.cache_misses as u128);
+    metric("pow_cache_evictions_total", "counter", "Cache entries evicted by the LRU", m.cache_evictions as u128);
+    metric("pow_cache_expirations_total", "counter", "Cache entries expired", m.cache_expirations as u128);
+    metric("pow_cache_entries", "gauge", "Entries in the solution cache", cache.size as u128);
+    metric("pow_hashrate", "gauge", "Hashes per second over the recent window", summary.recent_hashes_per_second as u128);
+    metric(
+        "pow_hashrate_lifetime",
+        "gauge",
+        "Hashes per second of mining time since the last reset",
+        summary.hashes_per_second as u128,
+    );
+    metric(
+        "pow_instructions_per_hash",
+        "gauge",
+        "Average instructions per hash",
+        summary.avg_instructions_per_hash as u128,
+    );
+    metric("pow_cycles_burned_total", "counter", "Estimated cycles spent mining", m.total_cycles);
+    metric("pow_cycles_per_hash", "gauge", "Estimated cycles per hash", summary.cycles_per_hash);
+    metric("pow_cycles_balance", "gauge", "Canister cycle balance", ic_cdk::api::canister_balance128());
+
+    out
+}
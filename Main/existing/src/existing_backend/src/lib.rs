@@ -1,11 +1,27 @@
-use ic_cdk::{query, update};
-use ic_cdk::api::time;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use candid::Principal;
+use canister_auth::audit::AuditEntry;
+use canister_auth::rate_limit::{self, RateLimit, RateLimited};
+use canister_config::VersionedConfig;
+use ic_cdk::{init, post_upgrade, pre_upgrade, query, update};
+use ic_cdk::api::{performance_counter, time};
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
+use pow_core::target::{self, Target};
+use pow_core::{hash_preimage, hash_to_hex, meets_difficulty, pow_hash, HashMidState, Preimage};
+use pow_core::{NonceEncoding, PowAlgorithm, MAX_SCRYPT_LOG_N};
 use sha2::{Sha256, Digest};
 use sha2::digest::FixedOutput;
+use protocol::{JobNotify, JobSubmit, MAX_BATCH_CHUNKS, MAX_SHARES_PER_CHUNK, PROTOCOL_VERSION};
 
 mod cache;
 mod metrics;
 mod advanced;
+mod http;
+mod limits;
+mod protocol;
+mod stable_state;
 
 pub use advanced::{
     start_advanced_mining,
@@ -14,30 +30,146 @@ pub use advanced::{
     get_cache_stats,
     clear_cache,
     is_cached,
+    set_cache_ttl,
+    export_cache_entries,
+    import_cache_entries,
+    export_cache,
+    import_cache,
     get_metrics,
     get_metrics_summary,
     reset_metrics,
     export_metrics_csv,
+    get_metrics_history,
+    get_job_metrics,
+    set_metrics_sink,
+    get_metrics_sink,
+    set_hashrate_window_secs,
+    set_alert_rules,
+    get_alert_rules,
+    get_active_alerts,
 };
+pub use http::http_request;
+pub use limits::{get_input_limits, set_input_limits};
 
 // ------------------------------------------------------------
-// Internal helpers
+// Init / upgrades
 // ------------------------------------------------------------
 
-pub fn hash_to_hex(bytes: &[u8]) -> String {
-    hex::encode(bytes)
+/// The installing principal (normally the coordinator) becomes the owner;
+/// `admins` are added alongside
+#[init]
+fn init(admins: Option<Vec<Principal>>) {
+    canister_auth::init(ic_cdk::caller(), admins.unwrap_or_default());
+    cache::load();
+    cache::start_prune_timer();
+    metrics::start_snapshot_timer();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    stable_state::save(COORDINATOR.with(|c| c.get()));
 }
 
-pub fn meets_difficulty(hash: &[u8; 32], difficulty: u32) -> bool {
-    let mut remaining = difficulty;
-    for b in hash.iter() {
-        if remaining == 0 { return true; }
-        let z = b.leading_zeros();
-        if z >= remaining { return true; }
-        if z < 8 { return false; }
-        remaining -= 8;
+/// Upgrade args add admins; they never replace the saved owner
+#[post_upgrade]
+fn post_upgrade(admins: Option<Vec<Principal>>) {
+    match stable_state::load() {
+        Some(coordinator) => COORDINATOR.with(|c| c.set(coordinator)),
+        None => canister_auth::init(ic_cdk::caller(), Vec::new()),
+    }
+    admins.unwrap_or_default().into_iter().for_each(canister_auth::add_admin);
+    cache::load();
+    cache::start_prune_timer();
+    metrics::start_snapshot_timer();
+}
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+/// Hand the miner to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+/// Per-caller budget for the mining updates; admins bypass it and None
+/// turns it off (admin only)
+#[update]
+pub fn set_rate_limit(limit: Option<RateLimit>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_rate_limit", format!("{:?}", limit));
+    rate_limit::set_limit(limit);
+}
+
+#[query]
+pub fn get_rate_limit() -> Option<RateLimit> {
+    rate_limit::limit()
+}
+
+// ------------------------------------------------------------
+// Coordinator - the only caller allowed to hand out or stop work
+// ------------------------------------------------------------
+
+thread_local! {
+    static COORDINATOR: Cell<Option<Principal>> = const { Cell::new(None) };
+}
+
+/// Only `coordinator` may call mine_chunk_* / start_advanced_mining /
+/// stop_advanced_mining / cancel_assignment from now on (admin only)
+#[update]
+pub fn set_coordinator(coordinator: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_coordinator", format!("{:?}", coordinator));
+    COORDINATOR.with(|c| c.set(Some(coordinator)));
+}
+
+/// The configured coordinator, or the owner (normally the coordinator that
+/// installed this miner) until one is set
+#[query]
+pub fn get_coordinator() -> Option<Principal> {
+    COORDINATOR.with(|c| c.get()).or_else(canister_auth::owner)
+}
+
+pub(crate) fn require_coordinator() -> Result<(), MinerError> {
+    if get_coordinator() == Some(ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(MinerError::NotCoordinator)
     }
-    remaining == 0
 }
 
 // ------------------------------------------------------------
@@ -56,25 +188,36 @@ pub enum MiningStatus {
 }
 
 // ------------------------------------------------------------
-// SHA mid-state helper
+// Errors returned by the mining endpoints
 // ------------------------------------------------------------
 
-#[derive(Clone)]
-pub struct HashMidState {
-    hasher: Sha256,
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+pub enum MinerError {
+    /// This caller is over its call budget
+    RateLimited { retry_after_ms: u64 },
+    /// Only the configured coordinator may hand out or stop work
+    NotCoordinator,
+    /// `field` is over the admin-set limit (see get_input_limits)
+    InputTooLarge { field: String, value: u64, max: u64 },
+    /// An explicit target was not 32 bytes
+    InvalidTarget { len: u64 },
+    /// The message is newer than this miner's protocol
+    UnsupportedVersion { version: u32, supported: u32 },
+    /// The job was cancelled here; nothing was searched
+    Cancelled,
 }
 
-impl HashMidState {
-    pub fn new(block_data: &str) -> Self {
-        let mut hasher = Sha256::new();
-        hasher.update(block_data.as_bytes());
-        Self { hasher }
+/// An explicit 32-byte big-endian target overrides `difficulty`
+pub(crate) fn resolve_target(difficulty: u32, target: Option<Vec<u8>>) -> Result<Target, MinerError> {
+    match target {
+        Some(t) => target::parse(&t).map_err(|_| MinerError::InvalidTarget { len: t.len() as u64 }),
+        None => Ok(target::from_difficulty(difficulty)),
     }
+}
 
-    pub fn finalize_with_nonce(&self, nonce: u64) -> [u8; 32] {
-        let mut h = self.hasher.clone();
-        h.update(nonce.to_le_bytes());
-        h.finalize_fixed().into()
+impl From<RateLimited> for MinerError {
+    fn from(e: RateLimited) -> Self {
+        MinerError::RateLimited { retry_after_ms: e.retry_after_ms }
     }
 }
 
@@ -88,15 +231,51 @@ pub fn mine_chunk_with_midstate(
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> Result<(MiningStatus, u64), MinerError> {
+    require_coordinator()?;
+    rate_limit::check()?;
+    limits::check(&block_data, difficulty, chunk_size, algorithm.unwrap_or_default())?;
+    let target = resolve_target(difficulty, target)?;
+    Ok(midstate_chunk(&block_data, &target, start_nonce, chunk_size, algorithm.unwrap_or_default(), nonce_encoding.unwrap_or_default()))
+}
+
+#[update]
+pub fn mine_chunk_naive(
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> Result<(MiningStatus, u64), MinerError> {
+    require_coordinator()?;
+    rate_limit::check()?;
+    limits::check(&block_data, difficulty, chunk_size, algorithm.unwrap_or_default())?;
+    let target = resolve_target(difficulty, target)?;
+    Ok(naive_chunk(&block_data, &target, start_nonce, chunk_size, algorithm.unwrap_or_default(), nonce_encoding.unwrap_or_default()))
+}
+
+pub(crate) fn midstate_chunk(
+    block_data: &str,
+    target: &Target,
+    start_nonce: u64,
+    chunk_size: u64,
+    algorithm: PowAlgorithm,
+    encoding: NonceEncoding,
 ) -> (MiningStatus, u64) {
-    let mid = HashMidState::new(&block_data);
+    let mut mid = HashMidState::with_encoding(algorithm, encoding, block_data);
+    let check = target::Check::new(target);
     let mut nonce = start_nonce;
     let end = start_nonce.saturating_add(chunk_size);
     let mut attempts = 0u64;
 
     while nonce < end {
         let h = mid.finalize_with_nonce(nonce);
-        if meets_difficulty(&h, difficulty) {
+        if check.meets(&h) {
             return (MiningStatus::Found { hash: hash_to_hex(&h), nonce }, attempts);
         }
         nonce += 1;
@@ -105,24 +284,24 @@ pub fn mine_chunk_with_midstate(
     (MiningStatus::Continue { next_nonce: end }, attempts)
 }
 
-#[update]
-pub fn mine_chunk_naive(
-    block_data: String,
-    difficulty: u32,
+fn naive_chunk(
+    block_data: &str,
+    target: &Target,
     start_nonce: u64,
     chunk_size: u64,
+    algorithm: PowAlgorithm,
+    encoding: NonceEncoding,
 ) -> (MiningStatus, u64) {
+    let mut preimage = Preimage::new(block_data.as_bytes(), encoding);
+    let check = target::Check::new(target);
     let mut nonce = start_nonce;
     let end = start_nonce.saturating_add(chunk_size);
     let mut attempts = 0u64;
 
     while nonce < end {
-        let mut hasher = Sha256::new();
-        hasher.update(block_data.as_bytes());
-        hasher.update(nonce.to_le_bytes());
-        let hash: [u8; 32] = hasher.finalize_fixed().into();
+        let hash = hash_preimage(algorithm, preimage.with_nonce(nonce));
 
-        if meets_difficulty(&hash, difficulty) {
+        if check.meets(&hash) {
             return (MiningStatus::Found { hash: hash_to_hex(&hash), nonce }, attempts);
         }
         nonce += 1;
@@ -143,27 +322,289 @@ pub fn mine_chunk_simple(
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> Result<(bool, u64, String, u64), MinerError> {
+    require_coordinator()?;
+    rate_limit::check()?;
+    limits::check(&block_data, difficulty, chunk_size, algorithm.unwrap_or_default())?;
+    let target = resolve_target(difficulty, target)?;
+    Ok(simple_chunk(&block_data, &target, start_nonce, chunk_size, algorithm.unwrap_or_default(), nonce_encoding.unwrap_or_default(), None))
+}
+
+/// The lowest hashes of a chunk that met a share target but not the job's
+struct BestShares {
+    check: target::Check,
+    best: Vec<([u8; 32], u64)>,
+}
+
+impl BestShares {
+    fn new(share_target: &Target) -> Self {
+        Self { check: target::Check::new(share_target), best: Vec::new() }
+    }
+
+    fn offer(&mut self, hash: &[u8; 32], nonce: u64) {
+        if !self.check.meets(hash) {
+            return;
+        }
+        if self.best.len() == MAX_SHARES_PER_CHUNK {
+            if self.best.last().is_some_and(|(worst, _)| hash >= worst) {
+                return;
+            }
+            self.best.pop();
+        }
+        let at = self.best.partition_point(|(h, _)| h < hash);
+        self.best.insert(at, (*hash, nonce));
+    }
+
+    fn into_shares(self) -> Vec<(u64, String)> {
+        self.best.iter().map(|(h, nonce)| (*nonce, hash_to_hex(h))).collect()
+    }
+}
+
+fn simple_chunk(
+    block_data: &str,
+    target: &Target,
+    start_nonce: u64,
+    chunk_size: u64,
+    algorithm: PowAlgorithm,
+    encoding: NonceEncoding,
+    mut shares: Option<&mut BestShares>,
 ) -> (bool, u64, String, u64) {
-    let mid = HashMidState::new(&block_data);
+    let mut mid = HashMidState::with_encoding(algorithm, encoding, block_data);
+    let check = target::Check::new(target);
     let mut nonce = start_nonce;
     let end = start_nonce.saturating_add(chunk_size);
     let mut attempts = 0u64;
 
     while nonce < end {
         let h = mid.finalize_with_nonce(nonce);
-        if meets_difficulty(&h, difficulty) {
+        if check.meets(&h) {
             return (true, nonce, hash_to_hex(&h), attempts);
         }
+        if let Some(shares) = shares.as_deref_mut() {
+            shares.offer(&h, nonce);
+        }
         nonce += 1;
         attempts += 1;
     }
     (false, end, String::new(), attempts)
 }
 
+// ------------------------------------------------------------
+// Job-tagged mining and cancellation (coordinator protocol)
+// ------------------------------------------------------------
+
+const MAX_CANCELLED_JOBS: usize = 256;
+
+thread_local! {
+    static CANCELLED_JOBS: RefCell<VecDeque<u64>> = const { RefCell::new(VecDeque::new()) };
+    /// Jobs below this id were dropped by a clean_jobs notify
+    static OLDEST_LIVE_JOB: Cell<u64> = const { Cell::new(0) };
+}
+
+fn is_job_cancelled(job_id: u64) -> bool {
+    job_id < OLDEST_LIVE_JOB.with(|o| o.get()) || CANCELLED_JOBS.with(|c| c.borrow().contains(&job_id))
+}
+
+/// (found, nonce, hash, attempts, instructions, session_token)
+pub type JobChunkReply = (bool, u64, String, u64, u64, Option<u64>);
+
+/// Same as `mine_chunk_simple`, but refuses work for cancelled jobs (with
+/// `Cancelled`, so the range isn't taken as searched) and also
+/// reports the instructions the call executed, for the coordinator's billing.
+/// A cached solution (possibly synced from another miner) is returned
+/// without mining, even if its nonce lies outside the chunk. The
+/// coordinator's per-assignment `session_token` is echoed back unchanged.
+#[update]
+#[allow(clippy::too_many_arguments)]
+pub fn mine_chunk_for_job(
+    job_id: u64,
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+    session_token: Option<u64>,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+) -> Result<JobChunkReply, MinerError> {
+    job_chunk(job_id, block_data, difficulty, start_nonce, chunk_size, session_token, algorithm, target, None)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn job_chunk(
+    job_id: u64,
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+    session_token: Option<u64>,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    shares: Option<&mut BestShares>,
+) -> Result<JobChunkReply, MinerError> {
+    require_coordinator()?;
+    rate_limit::check()?;
+    let algorithm = algorithm.unwrap_or_default();
+    limits::check(&block_data, difficulty, chunk_size, algorithm)?;
+    // The cache is keyed by block and difficulty only, so it holds SHA-256
+    // solutions to plain difficulties
+    let cacheable = algorithm == PowAlgorithm::Sha256 && target.is_none();
+    let target = resolve_target(difficulty, target)?;
+    if is_job_cancelled(job_id) {
+        return Err(MinerError::Cancelled);
+    }
+    if cacheable {
+        if let Some((nonce, hash)) = cache::cache_lookup(&block_data, difficulty) {
+            metrics::record_job_cache_lookup(job_id, true);
+            return Ok((true, nonce, hash, 0, performance_counter(0), session_token));
+        }
+        metrics::record_job_cache_lookup(job_id, false);
+    }
+
+    let (found, nonce, hash, attempts) = simple_chunk(&block_data, &target, start_nonce, chunk_size, algorithm, NonceEncoding::LeBytes, shares);
+    if found && cacheable {
+        cache::cache_store(&block_data, difficulty, nonce, hash.clone(), None);
+    }
+
+    // IC time doesn't advance within a message, so only instructions count
+    let instructions = performance_counter(0);
+    metrics::record_job_chunk_result(job_id, attempts, 0, instructions, found);
+    Ok((found, nonce, hash, attempts, instructions, session_token))
+}
+
+/// `mine_chunk_for_job` as a versioned message. With `clean_jobs` set, every
+/// older job is dropped as if cancelled. A `share_target` also collects the
+/// best shares of the range.
+#[update]
+pub fn mine_job(job: JobNotify) -> Result<JobSubmit, MinerError> {
+    protocol::check_version(job.version)?;
+    require_coordinator()?;
+    if job.clean_jobs {
+        OLDEST_LIVE_JOB.with(|o| o.set(o.get().max(job.job_id)));
+    }
+    let share_target = job.share_target.map(|t| resolve_target(0, Some(t))).transpose()?;
+    let mut shares = share_target.as_ref().map(BestShares::new);
+    // Only used for the input limits; the target is what gets checked
+    let difficulty = target::parse(&job.target).map(|t| target::to_difficulty(&t)).unwrap_or(0);
+    let (found, nonce, hash, attempts, instructions, session_token) = job_chunk(
+        job.job_id,
+        job.block_data,
+        difficulty,
+        job.extranonce_start,
+        job.extranonce_size,
+        job.session_token,
+        job.algorithm,
+        Some(job.target),
+        shares.as_mut(),
+    )?;
+    Ok(JobSubmit {
+        version: PROTOCOL_VERSION,
+        job_id: job.job_id,
+        found,
+        nonce,
+        hash,
+        attempts,
+        instructions,
+        session_token,
+        shares: shares.map(BestShares::into_shares),
+    })
+}
+
+/// Several chunks of one job in a single call, so the call overhead and
+/// block_data are paid once. Ranges (start, size) are mined in order and
+/// each searched one gets a JobSubmit, stopping after the first solution; a
+/// cancelled job gets `Cancelled`. The first reply's instructions
+/// include the call's own overhead. A `share_target` collects each range's
+/// best shares. The ranges together are held to the chunk size limit.
+#[update]
+pub fn mine_chunks(
+    job_id: u64,
+    ranges: Vec<(u64, u64)>,
+    block_data: String,
+    target: Vec<u8>,
+    algorithm: Option<PowAlgorithm>,
+    session_token: Option<u64>,
+    share_target: Option<Vec<u8>>,
+) -> Result<Vec<JobSubmit>, MinerError> {
+    require_coordinator()?;
+    rate_limit::check()?;
+    if ranges.len() > MAX_BATCH_CHUNKS {
+        return Err(MinerError::InputTooLarge {
+            field: "ranges".to_string(),
+            value: ranges.len() as u64,
+            max: MAX_BATCH_CHUNKS as u64,
+        });
+    }
+    let algorithm = algorithm.unwrap_or_default();
+    let target = resolve_target(0, Some(target))?;
+    let difficulty = target::to_difficulty(&target);
+    let sizes: Vec<u64> = ranges.iter().map(|&(_, size)| size).collect();
+    limits::check_batch(&block_data, difficulty, &sizes, algorithm)?;
+    let share_target = share_target.map(|t| resolve_target(0, Some(t))).transpose()?;
+    if is_job_cancelled(job_id) {
+        return Err(MinerError::Cancelled);
+    }
+
+    let mut replies = Vec::with_capacity(ranges.len());
+    let mut counted = 0;
+    for (start, size) in ranges {
+        let mut shares = share_target.as_ref().map(BestShares::new);
+        let (found, nonce, hash, attempts) = simple_chunk(&block_data, &target, start, size, algorithm, NonceEncoding::LeBytes, shares.as_mut());
+        let instructions = performance_counter(0) - counted;
+        counted += instructions;
+        metrics::record_job_chunk_result(job_id, attempts, 0, instructions, found);
+        replies.push(JobSubmit {
+            version: PROTOCOL_VERSION,
+            job_id,
+            found,
+            nonce,
+            hash,
+            attempts,
+            instructions,
+            session_token,
+            shares: shares.map(BestShares::into_shares),
+        });
+        if found {
+            break;
+        }
+    }
+    Ok(replies)
+}
+
+/// Drop all further work for a job; returns true as the acknowledgment, or
+/// false if the caller isn't the coordinator
+#[update]
+pub fn cancel_assignment(job_id: u64) -> bool {
+    if require_coordinator().is_err() {
+        return false;
+    }
+    CANCELLED_JOBS.with(|c| {
+        let mut c = c.borrow_mut();
+        if !c.contains(&job_id) {
+            if c.len() >= MAX_CANCELLED_JOBS {
+                c.pop_front();
+            }
+            c.push_back(job_id);
+        }
+    });
+
+    advanced::stop_task();
+    true
+}
+
 // ------------------------------------------------------------
 // Benchmark functions
 // ------------------------------------------------------------
 
+/// Benchmarks keep their plain return types, so over-limit input traps
+fn check_bench_input(block_data: &str, difficulty: u32, chunk_size: u64, algorithm: PowAlgorithm) {
+    if let Err(e) = limits::check(block_data, difficulty, chunk_size, algorithm) {
+        ic_cdk::trap(&format!("{:?}", e));
+    }
+}
+
 #[update]
 pub fn benchmark_naive_chunk(
     block_data: String,
@@ -171,8 +612,9 @@ pub fn benchmark_naive_chunk(
     start_nonce: u64,
     chunk_size: u64,
 ) -> (MiningStatus, u64, u64) {
+    check_bench_input(&block_data, difficulty, chunk_size, PowAlgorithm::Sha256);
     let t0 = time();
-    let (status, attempts) = mine_chunk_naive(block_data, difficulty, start_nonce, chunk_size);
+    let (status, attempts) = naive_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, PowAlgorithm::Sha256, NonceEncoding::LeBytes);
     let t1 = time();
     (status, attempts, t1 - t0)
 }
@@ -184,8 +626,9 @@ pub fn benchmark_midstate_chunk(
     start_nonce: u64,
     chunk_size: u64,
 ) -> (MiningStatus, u64, u64) {
+    check_bench_input(&block_data, difficulty, chunk_size, PowAlgorithm::Sha256);
     let t0 = time();
-    let (status, attempts) = mine_chunk_with_midstate(block_data, difficulty, start_nonce, chunk_size);
+    let (status, attempts) = midstate_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, PowAlgorithm::Sha256, NonceEncoding::LeBytes);
     let t1 = time();
     (status, attempts, t1 - t0)
 }
@@ -197,27 +640,72 @@ pub fn benchmark_one_chunk(
     start_nonce: u64,
     chunk_size: u64,
 ) -> (u64, u64) {
+    check_bench_input(&block_data, difficulty, chunk_size, PowAlgorithm::Sha256);
     let t0 = time();
-    let (_status, attempts) = mine_chunk_with_midstate(block_data, difficulty, start_nonce, chunk_size);
+    let (_status, attempts) = midstate_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, PowAlgorithm::Sha256, NonceEncoding::LeBytes);
     let t1 = time();
     (attempts, t1 - t0)
 }
 
+// ------------------------------------------------------------
+// Health check (pinged by the coordinator)
+// ------------------------------------------------------------
+
+#[query]
+pub fn health() -> bool {
+    true
+}
+
+// ------------------------------------------------------------
+// Cycle refund (used by the coordinator before deleting a miner)
+// ------------------------------------------------------------
+
+/// Send everything above `keep` cycles back to the calling admin; returns
+/// the amount deposited
+#[update]
+pub async fn refund_cycles(keep: u128) -> u128 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("refund_cycles", format!("{:?}", keep));
+    let admin = ic_cdk::caller();
+
+    let amount = ic_cdk::api::canister_balance128().saturating_sub(keep);
+    if amount == 0 {
+        return 0;
+    }
+
+    let target = CanisterIdRecord { canister_id: admin };
+    match deposit_cycles(target, amount).await {
+        Ok(()) => amount,
+        Err((code, msg)) => ic_cdk::trap(&format!("deposit_cycles failed: {:?} {}", code, msg)),
+    }
+}
+
 // ------------------------------------------------------------
 // Hash test helpers
 // ------------------------------------------------------------
 
 #[query]
-pub fn test_naive_hash(block_data: String, nonce: u64) -> String {
-    let mut h = Sha256::new();
-    h.update(block_data.as_bytes());
-    h.update(nonce.to_le_bytes());
-    let arr: [u8; 32] = h.finalize_fixed().into(); hash_to_hex(&arr)
+pub fn test_naive_hash(
+    block_data: String,
+    nonce: u64,
+    algorithm: Option<PowAlgorithm>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> String {
+    let algorithm = algorithm.unwrap_or_default();
+    check_bench_input(&block_data, 0, 0, algorithm);
+    hash_to_hex(&pow_hash(algorithm, nonce_encoding.unwrap_or_default(), &block_data, nonce))
 }
 
 #[query]
-pub fn test_midstate_hash(block_data: String, nonce: u64) -> String {
-    let mid = HashMidState::new(&block_data);
+pub fn test_midstate_hash(
+    block_data: String,
+    nonce: u64,
+    algorithm: Option<PowAlgorithm>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> String {
+    let algorithm = algorithm.unwrap_or_default();
+    check_bench_input(&block_data, 0, 0, algorithm);
+    let mut mid = HashMidState::with_encoding(algorithm, nonce_encoding.unwrap_or_default(), &block_data);
     hash_to_hex(&mid.finalize_with_nonce(nonce))
 }
 
@@ -232,9 +720,12 @@ pub fn bench_naive_instructions(
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
+    algorithm: Option<PowAlgorithm>,
 ) -> (u64, u64) {
+    let algorithm = algorithm.unwrap_or_default();
+    check_bench_input(&block_data, difficulty, chunk_size, algorithm);
     let i0 = ic_cdk::api::instruction_counter();
-    let (_status, attempts) = mine_chunk_naive(block_data, difficulty, start_nonce, chunk_size);
+    let (_status, attempts) = naive_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, algorithm, NonceEncoding::LeBytes);
     let i1 = ic_cdk::api::instruction_counter();
     (attempts, i1 - i0)
 }
@@ -246,9 +737,239 @@ pub fn bench_midstate_instructions(
     difficulty: u32,
     start_nonce: u64,
     chunk_size: u64,
+    algorithm: Option<PowAlgorithm>,
 ) -> (u64, u64) {
+    let algorithm = algorithm.unwrap_or_default();
+    check_bench_input(&block_data, difficulty, chunk_size, algorithm);
     let i0 = ic_cdk::api::instruction_counter();
-    let (_status, attempts) = mine_chunk_with_midstate(block_data, difficulty, start_nonce, chunk_size);
+    let (_status, attempts) = midstate_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, algorithm, NonceEncoding::LeBytes);
     let i1 = ic_cdk::api::instruction_counter();
     (attempts, i1 - i0)
 }
+
+/// Midstate mining of the same chunk with every algorithm - returns
+/// (algorithm, attempts, instructions_used) per algorithm
+#[update]
+pub fn bench_algorithm_instructions(
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+) -> Vec<(PowAlgorithm, u64, u64)> {
+    check_bench_input(&block_data, difficulty, chunk_size, PowAlgorithm::Sha256);
+    PowAlgorithm::ALL
+    .iter()
+    .map(|&algorithm| {
+        let i0 = ic_cdk::api::instruction_counter();
+        let (_status, attempts) = midstate_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, algorithm, NonceEncoding::LeBytes);
+        let i1 = ic_cdk::api::instruction_counter();
+        (algorithm, attempts, i1 - i0)
+    })
+    .collect()
+}
+
+/// Instructions for one SHA-256 chunk through the hot loops as they were
+/// before buffers were reused (`*_before`) and as they are now (`*_after`)
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+pub struct HotLoopBench {
+    pub attempts: u64,
+    pub naive_before: u64,
+    pub naive_after: u64,
+    pub midstate_before: u64,
+    pub midstate_after: u64,
+}
+
+/// The SHA-256 loops as they used to be, kept only as the baseline for
+/// bench_hot_loop_instructions: the block is taken by value, naive builds a
+/// Sha256 per nonce and midstate clones the absorbed hasher per nonce
+fn legacy_sha256_chunk(block_data: String, target: &Target, start_nonce: u64, chunk_size: u64, midstate: bool) -> u64 {
+    let mut absorbed = Sha256::new();
+    absorbed.update(block_data.as_bytes());
+    let end = start_nonce.saturating_add(chunk_size);
+    let mut attempts = 0u64;
+
+    for nonce in start_nonce..end {
+        let mut h = if midstate {
+            absorbed.clone()
+        } else {
+            let mut h = Sha256::new();
+            h.update(block_data.as_bytes());
+            h
+        };
+        h.update(nonce.to_le_bytes());
+        let hash: [u8; 32] = h.finalize_fixed().into();
+        if target::meets(&hash, target) {
+            hash_to_hex(&hash);
+            break;
+        }
+        attempts += 1;
+    }
+    attempts
+}
+
+/// The same chunk through the old and new naive and midstate loops, each
+/// including the block_data copy its callers used to make
+#[update]
+pub fn bench_hot_loop_instructions(
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+) -> HotLoopBench {
+    check_bench_input(&block_data, difficulty, chunk_size, PowAlgorithm::Sha256);
+    let target = target::from_difficulty(difficulty);
+    let counter = ic_cdk::api::instruction_counter;
+
+    let i0 = counter();
+    legacy_sha256_chunk(block_data.clone(), &target, start_nonce, chunk_size, false);
+    let i1 = counter();
+    naive_chunk(&block_data, &target, start_nonce, chunk_size, PowAlgorithm::Sha256, NonceEncoding::LeBytes);
+    let i2 = counter();
+    legacy_sha256_chunk(block_data.clone(), &target, start_nonce, chunk_size, true);
+    let i3 = counter();
+    let (_status, attempts) = midstate_chunk(&block_data, &target, start_nonce, chunk_size, PowAlgorithm::Sha256, NonceEncoding::LeBytes);
+    let i4 = counter();
+
+    HotLoopBench {
+        attempts,
+        naive_before: i1 - i0,
+        naive_after: i2 - i1,
+        midstate_before: i3 - i2,
+        midstate_after: i4 - i3,
+    }
+}
+
+/// Instructions to check the same hashes against one difficulty with the
+/// per-byte leading_zeros loop, the 32-byte target compare, and the
+/// precomputed prefix mask; `hits` is how many met it
+#[derive(candid::CandidType, serde::Deserialize, Clone, Debug)]
+pub struct DifficultyCheckBench {
+    pub difficulty: u32,
+    pub hits: u64,
+    pub leading_zeros: u64,
+    pub target_compare: u64,
+    pub prefix_mask: u64,
+}
+
+/// Hashes `samples` nonces of `block_data` up front, then times only the
+/// difficulty checks over them at each difficulty from 16 to 32
+#[update]
+pub fn bench_difficulty_check_instructions(
+    block_data: String,
+    start_nonce: u64,
+    samples: u64,
+) -> Vec<DifficultyCheckBench> {
+    check_bench_input(&block_data, 32, samples, PowAlgorithm::Sha256);
+    let mut mid = HashMidState::for_algorithm(PowAlgorithm::Sha256, &block_data);
+    let hashes: Vec<[u8; 32]> = (start_nonce..start_nonce.saturating_add(samples))
+    .map(|nonce| mid.finalize_with_nonce(nonce))
+    .collect();
+    let counter = ic_cdk::api::instruction_counter;
+    let count = |meets: &dyn Fn(&[u8; 32]) -> bool| hashes.iter().filter(|h| meets(std::hint::black_box(h))).count() as u64;
+
+    (16..=32)
+    .map(|difficulty| {
+        let target = target::from_difficulty(difficulty);
+        let i0 = counter();
+        let hits = count(&|h| meets_difficulty(h, difficulty));
+        let i1 = counter();
+        count(&|h| target::meets(h, &target));
+        let i2 = counter();
+        let check = target::Check::new(&target);
+        count(&|h| check.meets(h));
+        let i3 = counter();
+        DifficultyCheckBench {
+            difficulty,
+            hits,
+            leading_zeros: i1 - i0,
+            target_compare: i2 - i1,
+            prefix_mask: i3 - i2,
+        }
+    })
+    .collect()
+}
+
+/// wasm memory never shrinks, so growth across a call is its peak heap use
+/// beyond what was already reserved
+fn heap_bytes() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * 65_536
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+/// Scrypt mining at N = 2^log_n - returns (attempts, instructions_used,
+/// scratchpad_bytes, heap_growth_bytes). Run it on a fresh canister, or
+/// with a larger log_n than before, to see the heap grow.
+#[update]
+pub fn bench_scrypt_memory(
+    block_data: String,
+    difficulty: u32,
+    start_nonce: u64,
+    chunk_size: u64,
+    log_n: u8,
+) -> (u64, u64, u64, u64) {
+    let algorithm = PowAlgorithm::Scrypt { log_n };
+    check_bench_input(&block_data, difficulty, chunk_size, algorithm);
+    let h0 = heap_bytes();
+    let i0 = ic_cdk::api::instruction_counter();
+    let (_status, attempts) = midstate_chunk(&block_data, &target::from_difficulty(difficulty), start_nonce, chunk_size, algorithm, NonceEncoding::LeBytes);
+    let i1 = ic_cdk::api::instruction_counter();
+    let h1 = heap_bytes();
+    (attempts, i1 - i0, 128u64 << log_n, h1 - h0)
+}
+
+// ------------------------------------------------------------
+// Global config
+// ------------------------------------------------------------
+
+/// Follow the config canister's shared parameters (None goes back to the
+/// defaults); returns the version fetched (admin only)
+#[update]
+pub async fn set_config_canister(canister: Option<Principal>) -> Result<u64, String> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_config_canister", format!("{:?}", canister));
+    canister_config::connect(canister).await
+}
+
+#[query]
+pub fn get_config_canister() -> Option<Principal> {
+    canister_config::source()
+}
+
+/// The cached config; version 0 means the built-in defaults
+#[query]
+pub fn get_global_config() -> VersionedConfig {
+    canister_config::cached()
+}
+
+/// Pushed by the config canister on every change
+#[update]
+pub fn on_config_update(update: VersionedConfig) -> bool {
+    canister_config::accept_update(update)
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it. Types
+// from endpoints in submodules have to be in scope here.
+use crate::advanced::AdvancedTask;
+use crate::cache::{CacheEntry, CacheStats};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::limits::InputLimits;
+use crate::metrics::{Alert, AlertRules, MetricsSink, MetricsSnapshot, MetricsSummary, MiningMetrics};
+
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
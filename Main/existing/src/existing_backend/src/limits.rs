@@ -0,0 +1,114 @@
+// limits.rs - admin-set ceilings on mining inputs, checked before any hashing
+use std::cell::Cell;
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::{query, update};
+
+use crate::{MinerError, PowAlgorithm, MAX_SCRYPT_LOG_N};
+
+/// Hashes per chunk stay well inside one message's instruction budget
+const DEFAULT_LIMITS: InputLimits = InputLimits {
+    max_chunk_size: 1_000_000,
+    max_block_data_len: 16 * 1024,
+    max_difficulty: 256,
+};
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct InputLimits {
+    pub max_chunk_size: u64,
+    /// Bytes
+    pub max_block_data_len: u64,
+    /// Leading zero bits; a SHA-256 hash has 256
+    pub max_difficulty: u32,
+}
+
+thread_local! {
+    static LIMITS: Cell<InputLimits> = const { Cell::new(DEFAULT_LIMITS) };
+}
+
+fn exceeds(field: &str, value: u64, max: u64) -> Result<(), MinerError> {
+    if value > max {
+        return Err(MinerError::InputTooLarge { field: field.to_string(), value, max });
+    }
+    Ok(())
+}
+
+/// The global config can only lower the local ceiling
+fn max_chunk_size(l: &InputLimits) -> u64 {
+    l.max_chunk_size.min(canister_config::current().max_chunk_size)
+}
+
+/// Reject a mining request that is over any limit
+pub fn check(block_data: &str, difficulty: u32, chunk_size: u64, algorithm: PowAlgorithm) -> Result<(), MinerError> {
+    let l = LIMITS.with(|l| l.get());
+    exceeds("chunk_size", chunk_size, max_chunk_size(&l))?;
+    exceeds("block_data", block_data.len() as u64, l.max_block_data_len)?;
+    exceeds("difficulty", difficulty as u64, l.max_difficulty as u64)?;
+    if let PowAlgorithm::Scrypt { log_n } = algorithm {
+        exceeds("scrypt_log_n", log_n as u64, MAX_SCRYPT_LOG_N as u64)?;
+    }
+    Ok(())
+}
+
+/// `check` for ranges mined in one message; together they get the budget
+/// of a single chunk
+pub fn check_batch(block_data: &str, difficulty: u32, sizes: &[u64], algorithm: PowAlgorithm) -> Result<(), MinerError> {
+    for &size in sizes {
+        check(block_data, difficulty, size, algorithm)?;
+    }
+    let total = sizes.iter().fold(0u64, |t, &size| t.saturating_add(size));
+    exceeds("total_chunk_size", total, max_chunk_size(&LIMITS.with(|l| l.get())))
+}
+
+// ------------------------------------------------------------
+// Public API
+// ------------------------------------------------------------
+
+/// Admin only
+#[update]
+pub fn set_input_limits(limits: InputLimits) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_input_limits", format!("{:?}", limits));
+    if limits.max_chunk_size == 0 || limits.max_block_data_len == 0 {
+        ic_cdk::trap("max_chunk_size and max_block_data_len must be at least 1");
+    }
+    if limits.max_difficulty > 256 {
+        ic_cdk::trap("max_difficulty can't exceed 256");
+    }
+    LIMITS.with(|l| l.set(limits));
+}
+
+#[query]
+pub fn get_input_limits() -> InputLimits {
+    LIMITS.with(|l| l.get())
+}
+
+/// Put back limits saved across an upgrade
+pub(crate) fn restore(limits: InputLimits) {
+    LIMITS.with(|l| l.set(limits));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_is_held_to_one_chunk_in_total() {
+        let max = DEFAULT_LIMITS.max_chunk_size;
+        assert!(check_batch("block", 1, &[max / 2, max / 2], PowAlgorithm::Sha256).is_ok());
+
+        // Every range is within the limit on its own
+        let err = check_batch("block", 1, &[max; 16], PowAlgorithm::Sha256).unwrap_err();
+        assert!(matches!(
+            err,
+            MinerError::InputTooLarge { ref field, value, max: m } if field == "total_chunk_size" && value == 16 * max && m == max
+        ));
+    }
+
+    #[test]
+    fn batch_still_checks_each_range() {
+        let max = DEFAULT_LIMITS.max_chunk_size;
+        let err = check_batch("block", 1, &[max + 1], PowAlgorithm::Sha256).unwrap_err();
+        assert!(matches!(err, MinerError::InputTooLarge { ref field, .. } if field == "chunk_size"));
+    }
+}
@@ -1,10 +1,87 @@
 // metrics.rs - Comprehensive performance metrics
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
 use candid::Principal;
 
 use candid::{CandidType, Deserialize};
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
 use ic_cdk::{query, update};
 
+/// One snapshot per minute, a day's worth kept
+const SNAPSHOT_INTERVAL_SECS: u64 = 60;
+const MAX_SNAPSHOTS: usize = 1440;
+
+const DEFAULT_HASHRATE_WINDOW_SECS: u64 = 300;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// Per-job metrics for the most recent job ids only
+const MAX_JOB_METRICS: usize = 100;
+
+// Execution pricing on a 13-node application subnet: every chunk runs in its
+// own message (a heartbeat or a mine_chunk_for_job call). Balance deltas
+// can't be used instead since execution is charged after the message ends.
+const UPDATE_BASE_FEE_CYCLES: u128 = 5_000_000;
+const CYCLES_PER_10_INSTRUCTIONS: u128 = 4;
+
+fn execution_cycles(instructions: u64) -> u128 {
+    UPDATE_BASE_FEE_CYCLES + instructions as u128 * CYCLES_PER_10_INSTRUCTIONS / 10
+}
+
+/// Log-linear histogram: four buckets per power of two, so a percentile is
+/// reported within 25% of the true value
+#[derive(Clone, CandidType, Deserialize, Default)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+}
+
+impl Histogram {
+    const BUCKETS: usize = 252;
+
+    fn bucket(v: u64) -> usize {
+        if v < 4 {
+            return v as usize;
+        }
+        let msb = 63 - v.leading_zeros() as usize;
+        let sub = ((v >> (msb - 2)) & 3) as usize;
+        (msb - 1) * 4 + sub
+    }
+
+    /// Largest value that lands in bucket `b`
+    fn upper_bound(b: usize) -> u64 {
+        if b < 4 {
+            return b as u64;
+        }
+        let shift = b / 4 - 1;
+        let lower = ((4 + b % 4) as u64) << shift;
+        lower.saturating_add((1u64 << shift) - 1)
+    }
+
+    pub fn record(&mut self, v: u64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; Self::BUCKETS];
+        }
+        self.buckets[Self::bucket(v)] += 1;
+    }
+
+    /// Upper bound of the bucket holding the p-th percentile (0 if empty)
+    pub fn percentile(&self, p: u64) -> u64 {
+        let total: u64 = self.buckets.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+        let rank = (total * p).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (b, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::upper_bound(b);
+            }
+        }
+        0
+    }
+}
+
 #[derive(Clone, CandidType, Deserialize, Default)]
 pub struct MiningMetrics {
     // Mining performance
@@ -20,12 +97,16 @@ pub struct MiningMetrics {
 
     // Instructions
     pub total_instructions: u64,
+    /// Estimated from instructions, see execution_cycles
+    pub total_cycles: u128,
     pub min_instructions_per_hash: u64,
     pub max_instructions_per_hash: u64,
 
     // Cache performance
     pub cache_hits: u64,
     pub cache_misses: u64,
+    pub cache_evictions: u64,
+    pub cache_expirations: u64,
 
     // Early termination
     pub early_terminations: u64,
@@ -38,6 +119,13 @@ pub struct MiningMetrics {
     // Solutions found
     pub solutions_found: u64,
     pub last_solution_time: u64,
+
+    // Distributions for percentiles
+    pub chunk_time_histogram: Histogram,
+    pub chunk_instructions_histogram: Histogram,
+
+    /// (timestamp, hashes) of the chunks inside the hashrate window
+    pub recent_chunks: Vec<(u64, u64)>,
 }
 
 impl MiningMetrics {
@@ -53,6 +141,13 @@ impl MiningMetrics {
         self.total_hashes_computed += hashes;
         self.total_mining_time_ns += time_ns;
         self.total_instructions += instructions;
+        self.total_cycles += execution_cycles(instructions);
+
+        let now = ic_cdk::api::time();
+        self.recent_chunks.push((now, hashes));
+        let cutoff = now.saturating_sub(hashrate_window_ns());
+        let expired = self.recent_chunks.partition_point(|(t, _)| *t <= cutoff);
+        self.recent_chunks.drain(..expired);
 
         if found_solution {
             self.successful_chunks += 1;
@@ -65,6 +160,12 @@ impl MiningMetrics {
             self.failed_chunks += 1;
         }
 
+        // Chunks mined within a single message report no elapsed time
+        if time_ns > 0 {
+            self.chunk_time_histogram.record(time_ns);
+        }
+        self.chunk_instructions_histogram.record(instructions);
+
         // Update timing stats
         if self.fastest_chunk_ns == 0 || time_ns < self.fastest_chunk_ns {
             self.fastest_chunk_ns = time_ns;
@@ -97,6 +198,14 @@ impl MiningMetrics {
         self.cache_misses += 1;
     }
 
+    pub fn record_cache_eviction(&mut self) {
+        self.cache_evictions += 1;
+    }
+
+    pub fn record_cache_expirations(&mut self, count: u64) {
+        self.cache_expirations += count;
+    }
+
     pub fn record_adaptive_change(&mut self, new_chunk_size: u64) {
         self.adaptive_chunk_changes += 1;
         // Running average
@@ -147,6 +256,29 @@ impl MiningMetrics {
             0.0
         };
 
+        let avg_cycles_per_chunk = self
+        .total_cycles
+        .checked_div(self.total_chunks_mined as u128)
+        .unwrap_or(0);
+        let cycles_per_hash = self
+        .total_cycles
+        .checked_div(self.total_hashes_computed as u128)
+        .unwrap_or(0);
+
+        // Wall-clock throughput over the window, whatever the difficulty or
+        // chunk size were before it
+        let window_ns = hashrate_window_ns();
+        let cutoff = ic_cdk::api::time().saturating_sub(window_ns);
+        let recent_hashes: u128 = self
+        .recent_chunks
+        .iter()
+        .filter(|(t, _)| *t > cutoff)
+        .map(|(_, h)| *h as u128)
+        .sum();
+        let recent_hashes_per_second = (recent_hashes * NS_PER_SEC as u128)
+        .checked_div(window_ns as u128)
+        .unwrap_or(0) as u64;
+
         MetricsSummary {
             total_chunks: self.total_chunks_mined,
             total_hashes: self.total_hashes_computed,
@@ -157,6 +289,17 @@ impl MiningMetrics {
             avg_hashes_per_chunk,
             avg_instructions_per_hash,
             hashes_per_second,
+            recent_hashes_per_second,
+            hashrate_window_secs: window_ns / NS_PER_SEC,
+            p50_chunk_time_ms: self.chunk_time_histogram.percentile(50) / 1_000_000,
+            p95_chunk_time_ms: self.chunk_time_histogram.percentile(95) / 1_000_000,
+            p99_chunk_time_ms: self.chunk_time_histogram.percentile(99) / 1_000_000,
+            p50_chunk_instructions: self.chunk_instructions_histogram.percentile(50),
+            p95_chunk_instructions: self.chunk_instructions_histogram.percentile(95),
+            p99_chunk_instructions: self.chunk_instructions_histogram.percentile(99),
+            total_cycles: self.total_cycles,
+            avg_cycles_per_chunk,
+            cycles_per_hash,
         }
     }
 
@@ -175,12 +318,134 @@ pub struct MetricsSummary {
     pub avg_time_per_chunk_ms: u64,
     pub avg_hashes_per_chunk: u64,
     pub avg_instructions_per_hash: u64,
+    /// Lifetime, over time spent mining
     pub hashes_per_second: u64,
+    /// Hashes over the last hashrate_window_secs of wall-clock time
+    pub recent_hashes_per_second: u64,
+    pub hashrate_window_secs: u64,
+    // Bucket upper bounds, within 25% of the true percentile
+    pub p50_chunk_time_ms: u64,
+    pub p95_chunk_time_ms: u64,
+    pub p99_chunk_time_ms: u64,
+    pub p50_chunk_instructions: u64,
+    pub p95_chunk_instructions: u64,
+    pub p99_chunk_instructions: u64,
+    // Estimated execution cost (13-node subnet pricing)
+    pub total_cycles: u128,
+    pub avg_cycles_per_chunk: u128,
+    pub cycles_per_hash: u128,
+}
+
+/// Where the miner pushes its MetricsSummary (aggregator's report_metrics)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MetricsSink {
+    pub canister: Principal,
+    /// Push every this many seconds
+    pub interval_secs: Option<u64>,
+    /// Push after every this many chunks
+    pub every_chunks: Option<u64>,
+}
+
+/// Thresholds checked after every recorded chunk; unset rules are off
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct AlertRules {
+    /// recent_hashes_per_second below this
+    pub min_hashrate: Option<u64>,
+    pub max_instructions_per_hash: Option<u64>,
+    /// Chunks abandoned by early termination, as a share of all chunks
+    pub max_failure_rate_percent: Option<f64>,
+    /// (canister, method) notified with the Alert when a rule is breached
+    pub callback: Option<(Principal, String)>,
+}
+
+#[derive(Clone, Copy, PartialEq, CandidType, Deserialize)]
+pub enum AlertKind {
+    LowHashrate,
+    HighInstructionsPerHash,
+    HighFailureRate,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub value: f64,
+    pub threshold: f64,
+    /// When the rule was first breached
+    pub since: u64,
+}
+
+/// Activity during one snapshot interval, ending at `timestamp`
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MetricsSnapshot {
+    pub timestamp: u64,
+    pub chunks: u64,
+    pub hashes: u64,
+    pub solutions_found: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Over the time spent mining in the interval
+    pub hashes_per_second: u64,
+    pub instructions_per_hash: u64,
+}
+
+impl MetricsSnapshot {
+    fn between(prev: &MiningMetrics, now: &MiningMetrics, timestamp: u64) -> Self {
+        let hashes = now.total_hashes_computed - prev.total_hashes_computed;
+        let mining_ns = now.total_mining_time_ns - prev.total_mining_time_ns;
+        let instructions = now.total_instructions - prev.total_instructions;
+
+        Self {
+            timestamp,
+            chunks: now.total_chunks_mined - prev.total_chunks_mined,
+            hashes,
+            solutions_found: now.solutions_found - prev.solutions_found,
+            cache_hits: now.cache_hits - prev.cache_hits,
+            cache_misses: now.cache_misses - prev.cache_misses,
+            hashes_per_second: (hashes as u128 * 1_000_000_000)
+            .checked_div(mining_ns as u128)
+            .unwrap_or(0) as u64,
+            instructions_per_hash: instructions.checked_div(hashes).unwrap_or(0),
+        }
+    }
 }
 
 // Global metrics instance
 thread_local! {
     static METRICS: RefCell<MiningMetrics> = RefCell::new(MiningMetrics::default());
+    static JOB_METRICS: RefCell<BTreeMap<u64, MiningMetrics>> = const { RefCell::new(BTreeMap::new()) };
+    static SINK: RefCell<Option<MetricsSink>> = const { RefCell::new(None) };
+    static SINK_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    static CHUNKS_SINCE_PUSH: Cell<u64> = const { Cell::new(0) };
+    static HASHRATE_WINDOW_SECS: Cell<u64> = const { Cell::new(DEFAULT_HASHRATE_WINDOW_SECS) };
+    static ALERT_RULES: RefCell<AlertRules> = RefCell::new(AlertRules::default());
+    static ACTIVE_ALERTS: RefCell<Vec<Alert>> = const { RefCell::new(Vec::new()) };
+    static HISTORY: RefCell<VecDeque<MetricsSnapshot>> = const { RefCell::new(VecDeque::new()) };
+    // Totals at the previous snapshot (zeroed by reset_metrics)
+    static LAST_SNAPSHOT: RefCell<MiningMetrics> = RefCell::new(MiningMetrics::default());
+}
+
+fn hashrate_window_ns() -> u64 {
+    HASHRATE_WINDOW_SECS.with(|w| w.get()).saturating_mul(NS_PER_SEC)
+}
+
+/// Record a snapshot every SNAPSHOT_INTERVAL_SECS (armed from init and
+/// post_upgrade)
+pub fn start_snapshot_timer() {
+    canister_timers::set_timer_interval(Duration::from_secs(SNAPSHOT_INTERVAL_SECS), take_snapshot);
+}
+
+fn take_snapshot() {
+    let now = METRICS.with(|m| m.borrow().clone());
+    let prev = LAST_SNAPSHOT.with(|l| l.replace(now.clone()));
+
+    let snapshot = MetricsSnapshot::between(&prev, &now, ic_cdk::api::time());
+    HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        if h.len() >= MAX_SNAPSHOTS {
+            h.pop_front();
+        }
+        h.push_back(snapshot);
+    });
 }
 
 // ------------------------------------------------------------
@@ -203,6 +468,123 @@ pub fn record_chunk_result(
             early_terminated,
         )
     });
+
+    evaluate_alerts();
+
+    let every = SINK.with(|s| s.borrow().as_ref().and_then(|s| s.every_chunks));
+    if let Some(every) = every {
+        let n = CHUNKS_SINCE_PUSH.with(|c| c.get()) + 1;
+        if n >= every {
+            push_to_sink();
+            CHUNKS_SINCE_PUSH.with(|c| c.set(0));
+        } else {
+            CHUNKS_SINCE_PUSH.with(|c| c.set(n));
+        }
+    }
+}
+
+/// Re-check every rule against the current summary. Newly breached rules
+/// become active (and are sent to the callback); recovered ones are cleared.
+fn evaluate_alerts() {
+    let rules = ALERT_RULES.with(|r| r.borrow().clone());
+    let summary = METRICS.with(|m| m.borrow().summary());
+
+    let checks = [
+        (
+            AlertKind::LowHashrate,
+            summary.recent_hashes_per_second as f64,
+            rules.min_hashrate.map(|t| t as f64),
+            true,
+        ),
+        (
+            AlertKind::HighInstructionsPerHash,
+            summary.avg_instructions_per_hash as f64,
+            rules.max_instructions_per_hash.map(|t| t as f64),
+            false,
+        ),
+        (
+            AlertKind::HighFailureRate,
+            summary.early_termination_rate,
+            rules.max_failure_rate_percent,
+            false,
+        ),
+    ];
+
+    let now = ic_cdk::api::time();
+    let mut raised = Vec::new();
+    ACTIVE_ALERTS.with(|a| {
+        let mut active = a.borrow_mut();
+        for (kind, value, threshold, below) in checks {
+            let breached = threshold.is_some_and(|t| if below { value < t } else { value > t });
+            let existing = active.iter().position(|alert| alert.kind == kind);
+
+            match (breached, existing) {
+                (true, Some(i)) => active[i].value = value,
+                (true, None) => {
+                    let alert = Alert { kind, value, threshold: threshold.unwrap_or(0.0), since: now };
+                    raised.push(alert.clone());
+                    active.push(alert);
+                }
+                (false, Some(i)) => {
+                    active.remove(i);
+                }
+                (false, None) => {}
+            }
+        }
+    });
+
+    if let Some((canister, method)) = rules.callback {
+        for alert in raised {
+            if let Err(code) = ic_cdk::api::call::notify(canister, &method, (alert,)) {
+                ic_cdk::println!("Alert callback to {} failed: {:?}", canister, code);
+            }
+        }
+    }
+}
+
+/// Fire-and-forget, so a slow or missing aggregator never holds up mining
+fn push_to_sink() {
+    let Some(sink) = SINK.with(|s| s.borrow().clone()) else { return };
+    let summary = METRICS.with(|m| m.borrow().summary());
+
+    if let Err(code) = ic_cdk::api::call::notify(sink.canister, "report_metrics", (summary,)) {
+        ic_cdk::println!("Metrics push to {} failed: {:?}", sink.canister, code);
+    }
+}
+
+/// Apply `f` to the job's metrics, dropping the oldest job ids beyond
+/// MAX_JOB_METRICS
+fn with_job(job_id: u64, f: impl FnOnce(&mut MiningMetrics)) {
+    JOB_METRICS.with(|j| {
+        let mut jobs = j.borrow_mut();
+        f(jobs.entry(job_id).or_default());
+        while jobs.len() > MAX_JOB_METRICS {
+            jobs.pop_first();
+        }
+    });
+}
+
+/// Same as `record_chunk_result`, also counted under `job_id`; the global
+/// metrics stay the roll-up of every job
+pub fn record_job_chunk_result(
+    job_id: u64,
+    hashes: u64,
+    time_ns: u64,
+    instructions: u64,
+    found_solution: bool,
+) {
+    record_chunk_result(hashes, time_ns, instructions, found_solution, false);
+    with_job(job_id, |m| m.record_chunk(hashes, time_ns, instructions, found_solution, false));
+}
+
+pub fn record_job_cache_lookup(job_id: u64, hit: bool) {
+    if hit {
+        record_cache_hit();
+        with_job(job_id, MiningMetrics::record_cache_hit);
+    } else {
+        record_cache_miss();
+        with_job(job_id, MiningMetrics::record_cache_miss);
+    }
 }
 
 pub fn record_cache_hit() {
@@ -213,6 +595,14 @@ pub fn record_cache_miss() {
     METRICS.with(|m| m.borrow_mut().record_cache_miss());
 }
 
+pub fn record_cache_eviction() {
+    METRICS.with(|m| m.borrow_mut().record_cache_eviction());
+}
+
+pub fn record_cache_expirations(count: u64) {
+    METRICS.with(|m| m.borrow_mut().record_cache_expirations(count));
+}
+
 pub fn record_adaptive_change(new_chunk_size: u64) {
     METRICS.with(|m| m.borrow_mut().record_adaptive_change(new_chunk_size));
 }
@@ -227,9 +617,97 @@ pub fn get_metrics_summary() -> MetricsSummary {
     METRICS.with(|m| m.borrow().summary())
 }
 
+/// Push get_metrics_summary to `sink.canister` on a timer and/or every N
+/// chunks; None stops pushing. Controllers only.
+#[update]
+pub fn set_metrics_sink(sink: Option<MetricsSink>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_metrics_sink", format!("{:?}", sink));
+    if let Some(s) = &sink {
+        if s.interval_secs == Some(0) || s.every_chunks == Some(0) {
+            ic_cdk::trap("interval_secs and every_chunks must be at least 1");
+        }
+    }
+
+    if let Some(id) = SINK_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+    CHUNKS_SINCE_PUSH.with(|c| c.set(0));
+
+    if let Some(secs) = sink.as_ref().and_then(|s| s.interval_secs) {
+        let id = set_timer_interval(Duration::from_secs(secs), push_to_sink);
+        SINK_TIMER.with(|t| t.set(Some(id)));
+    }
+    SINK.with(|s| *s.borrow_mut() = sink);
+}
+
+#[query]
+pub fn get_metrics_sink() -> Option<MetricsSink> {
+    SINK.with(|s| s.borrow().clone())
+}
+
+/// Replace the alert rules (admins only); active alerts are
+/// re-evaluated on the next chunk
+#[update]
+pub fn set_alert_rules(rules: AlertRules) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_alert_rules", format!("{:?}", rules));
+    ALERT_RULES.with(|r| *r.borrow_mut() = rules);
+}
+
+#[query]
+pub fn get_alert_rules() -> AlertRules {
+    ALERT_RULES.with(|r| r.borrow().clone())
+}
+
+/// Put back rules saved across an upgrade
+pub(crate) fn restore_alert_rules(rules: AlertRules) {
+    ALERT_RULES.with(|r| *r.borrow_mut() = rules);
+}
+
+/// Rules currently breached
+#[query]
+pub fn get_active_alerts() -> Vec<Alert> {
+    ACTIVE_ALERTS.with(|a| a.borrow().clone())
+}
+
+/// Window for `recent_hashes_per_second`
+#[update]
+pub fn set_hashrate_window_secs(secs: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_hashrate_window_secs", format!("{:?}", secs));
+    if secs == 0 {
+        ic_cdk::trap("window must be at least 1 second");
+    }
+    HASHRATE_WINDOW_SECS.with(|w| w.set(secs));
+}
+
+/// Summary of the chunks mined for one coordinator job; None if the job
+/// never ran here or has aged out
+#[query]
+pub fn get_job_metrics(job_id: u64) -> Option<MetricsSummary> {
+    JOB_METRICS.with(|j| j.borrow().get(&job_id).map(MiningMetrics::summary))
+}
+
+/// Per-minute snapshots with from_ts <= timestamp <= to_ts (last 24h kept)
+#[query]
+pub fn get_metrics_history(from_ts: u64, to_ts: u64) -> Vec<MetricsSnapshot> {
+    HISTORY.with(|h| {
+        h.borrow()
+        .iter()
+        .filter(|s| s.timestamp >= from_ts && s.timestamp <= to_ts)
+        .cloned()
+        .collect()
+    })
+}
+
 #[update]
 pub fn reset_metrics() {
+    canister_auth::require_admin();
+    canister_auth::audit::record("reset_metrics", String::new());
     METRICS.with(|m| m.borrow_mut().reset());
+    LAST_SNAPSHOT.with(|l| *l.borrow_mut() = MiningMetrics::default());
+    JOB_METRICS.with(|j| j.borrow_mut().clear());
 }
 
 /// Export metrics as CSV string for analysis
@@ -247,28 +725,38 @@ solutions_found,{}\n\
 cache_hits,{}\n\
 cache_misses,{}\n\
 cache_hit_rate_percent,{:.2}\n\
+cache_evictions,{}\n\
+cache_expirations,{}\n\
 early_terminations,{}\n\
 early_termination_rate_percent,{:.2}\n\
 avg_time_per_chunk_ms,{}\n\
 avg_hashes_per_chunk,{}\n\
 avg_instructions_per_hash,{}\n\
 hashes_per_second,{}\n\
+recent_hashes_per_second,{}\n\
 min_instructions_per_hash,{}\n\
-max_instructions_per_hash,{}\n",
+max_instructions_per_hash,{}\n\
+total_cycles,{}\n\
+cycles_per_hash,{}\n",
 metrics.total_chunks_mined,
 metrics.total_hashes_computed,
 metrics.solutions_found,
 metrics.cache_hits,
 metrics.cache_misses,
 summary.cache_hit_rate,
+metrics.cache_evictions,
+metrics.cache_expirations,
 metrics.early_terminations,
 summary.early_termination_rate,
 summary.avg_time_per_chunk_ms,
 summary.avg_hashes_per_chunk,
 summary.avg_instructions_per_hash,
 summary.hashes_per_second,
+summary.recent_hashes_per_second,
 metrics.min_instructions_per_hash,
 metrics.max_instructions_per_hash,
+summary.total_cycles,
+summary.cycles_per_hash,
         )
     })
 }
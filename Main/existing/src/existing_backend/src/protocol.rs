@@ -0,0 +1,62 @@
+// protocol.rs - versioned coordinator <-> miner messages, modelled on
+// Stratum's notify/submit. Records only grow optional fields, so older
+// peers keep decoding them; `version` is bumped for anything else.
+use candid::{CandidType, Deserialize};
+
+use crate::{MinerError, PowAlgorithm};
+
+/// Highest message version this miner understands
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Most ranges one mine_chunks call may carry
+pub const MAX_BATCH_CHUNKS: usize = 16;
+
+/// Most shares one JobSubmit carries; the lowest hashes are kept
+pub const MAX_SHARES_PER_CHUNK: usize = 8;
+
+/// Work for one chunk of a job (Stratum's mining.notify)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JobNotify {
+    pub version: u32,
+    pub job_id: u64,
+    /// Hash of the block this work builds on, when there is a chain
+    pub prev_hash: Option<String>,
+    pub block_data: String,
+    /// Nonces `extranonce_start .. extranonce_start + extranonce_size`
+    pub extranonce_start: u64,
+    pub extranonce_size: u64,
+    /// 32 bytes, big-endian
+    pub target: Vec<u8>,
+    /// None mines with SHA-256
+    pub algorithm: Option<PowAlgorithm>,
+    /// Drop every job older than this one (lower job_id)
+    pub clean_jobs: bool,
+    /// Echoed back in the submit
+    pub session_token: Option<u64>,
+    /// 32 bytes, big-endian, easier than `target`: hashes meeting it but
+    /// not `target` come back as shares. None asks for no shares
+    pub share_target: Option<Vec<u8>>,
+}
+
+/// The outcome of a JobNotify (Stratum's mining.submit)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JobSubmit {
+    pub version: u32,
+    pub job_id: u64,
+    pub found: bool,
+    /// The solution if found, else the first nonce not searched
+    pub nonce: u64,
+    pub hash: String,
+    pub attempts: u64,
+    pub instructions: u64,
+    pub session_token: Option<u64>,
+    /// (nonce, hash) of the best shares found in the range, lowest first
+    pub shares: Option<Vec<(u64, String)>>,
+}
+
+pub fn check_version(version: u32) -> Result<(), MinerError> {
+    if version > PROTOCOL_VERSION {
+        return Err(MinerError::UnsupportedVersion { version, supported: PROTOCOL_VERSION });
+    }
+    Ok(())
+}
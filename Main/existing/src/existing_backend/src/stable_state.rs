@@ -0,0 +1,189 @@
+// stable_state.rs - keeps the owner/admin set, the coordinator, the audit
+// log, the advanced mining task, the cached global config, the input limits
+// and the alert rules in stable memory across upgrades, as a versioned
+// canister_state envelope
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use canister_auth::audit::AuditEntry;
+use canister_state::{Migration, StateError};
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableCell, Storable};
+
+use crate::advanced::{self, AdvancedTask};
+use crate::cache::{memory, Memory};
+use crate::limits::{self, InputLimits};
+use crate::metrics::{self, AlertRules};
+
+const STATE_MEMORY: MemoryId = MemoryId::new(3);
+
+/// Bump when `SavedState` changes, and register a migration from the old
+/// version
+const SCHEMA_VERSION: u32 = 2;
+const MIGRATIONS: &[(u32, Migration)] = &[(1, from_v1)];
+
+#[derive(CandidType, Deserialize)]
+struct SavedState {
+    owner: Option<Principal>,
+    admins: Vec<Principal>,
+    coordinator: Option<Principal>,
+    audit: Vec<AuditEntry>,
+    task: Option<AdvancedTask>,
+    global_config: Option<canister_config::Snapshot>,
+    /// Since version 2; None keeps the defaults
+    limits: Option<InputLimits>,
+    /// Since version 2
+    alert_rules: Option<AlertRules>,
+}
+
+/// Version 1, before the input limits and alert rules were kept
+#[derive(CandidType, Deserialize)]
+struct SavedStateV1 {
+    owner: Option<Principal>,
+    admins: Vec<Principal>,
+    coordinator: Option<Principal>,
+    audit: Vec<AuditEntry>,
+    task: Option<AdvancedTask>,
+    global_config: Option<canister_config::Snapshot>,
+}
+
+fn from_v1(bytes: Vec<u8>) -> Result<Vec<u8>, String> {
+    let v1 = Decode!(&bytes, SavedStateV1).map_err(|e| e.to_string())?;
+    let v2 = SavedState {
+        owner: v1.owner,
+        admins: v1.admins,
+        coordinator: v1.coordinator,
+        audit: v1.audit,
+        task: v1.task,
+        global_config: v1.global_config,
+        limits: None,
+        alert_rules: None,
+    };
+    Encode!(&v2).map_err(|e| e.to_string())
+}
+
+/// What the cell held before state was versioned
+#[derive(CandidType, Deserialize)]
+struct LegacyAuth {
+    owner: Option<Principal>,
+    admins: Vec<Principal>,
+    coordinator: Option<Principal>,
+    audit: Option<Vec<AuditEntry>>,
+}
+
+impl From<LegacyAuth> for SavedState {
+    fn from(l: LegacyAuth) -> Self {
+        SavedState {
+            owner: l.owner,
+            admins: l.admins,
+            coordinator: l.coordinator,
+            audit: l.audit.unwrap_or_default(),
+            task: None,
+            global_config: None,
+            limits: None,
+            alert_rules: None,
+        }
+    }
+}
+
+/// Raw cell contents; empty until the first upgrade
+#[derive(Default)]
+struct StateBytes(Vec<u8>);
+
+impl Storable for StateBytes {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StateBytes(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static STATE: RefCell<StableCell<StateBytes, Memory>> = RefCell::new(
+        StableCell::init(memory(STATE_MEMORY), StateBytes::default()).expect("init state cell")
+    );
+}
+
+/// Write the owner/admin set, coordinator, audit log, advanced task, global
+/// config, input limits and alert rules (pre_upgrade)
+pub fn save(coordinator: Option<Principal>) {
+    let (owner, admins) = canister_auth::snapshot();
+    let saved = SavedState {
+        owner,
+        admins,
+        coordinator,
+        audit: canister_auth::audit::snapshot(),
+        task: advanced::get_advanced_status(),
+        global_config: Some(canister_config::snapshot()),
+        limits: Some(limits::get_input_limits()),
+        alert_rules: Some(metrics::get_alert_rules()),
+    };
+    let bytes = canister_state::encode(SCHEMA_VERSION, &saved);
+    STATE.with(|s| {
+        if s.borrow_mut().set(StateBytes(bytes)).is_err() {
+            ic_cdk::trap("failed to save miner state");
+        }
+    });
+}
+
+/// Restore the saved state and return the saved coordinator; None if no
+/// owner was saved (post_upgrade)
+pub fn load() -> Option<Option<Principal>> {
+    let saved = STATE.with(|s| {
+        let s = s.borrow();
+        let bytes = &s.get().0;
+        if bytes.is_empty() {
+            return None;
+        }
+        match canister_state::decode::<SavedState>(bytes, SCHEMA_VERSION, MIGRATIONS) {
+            Ok(saved) => Some(saved),
+            // Saved before state was versioned
+            Err(StateError::Unversioned) => match Decode!(bytes, LegacyAuth) {
+                Ok(legacy) => Some(legacy.into()),
+                Err(e) => ic_cdk::trap(&format!("failed to restore miner state: {}", e)),
+            },
+            Err(e) => ic_cdk::trap(&format!("failed to restore miner state: {}", e)),
+        }
+    })?;
+    saved.owner?;
+
+    canister_auth::restore(saved.owner, saved.admins);
+    canister_auth::audit::restore(saved.audit);
+    advanced::restore_task(saved.task);
+    canister_config::restore(saved.global_config.unwrap_or_default());
+    if let Some(l) = saved.limits {
+        limits::restore(l);
+    }
+    metrics::restore_alert_rules(saved.alert_rules.unwrap_or_default());
+    Some(saved.coordinator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_1_state_migrates_without_limits_or_alert_rules() {
+        let owner = Principal::from_slice(&[1]);
+        let v1 = SavedStateV1 {
+            owner: Some(owner),
+            admins: vec![owner],
+            coordinator: None,
+            audit: Vec::new(),
+            task: None,
+            global_config: None,
+        };
+        let bytes = canister_state::encode(1, &v1);
+        let saved = canister_state::decode::<SavedState>(&bytes, SCHEMA_VERSION, MIGRATIONS).unwrap();
+        assert_eq!(saved.owner, Some(owner));
+        assert_eq!(saved.admins, vec![owner]);
+        assert!(saved.limits.is_none());
+        assert!(saved.alert_rules.is_none());
+    }
+}
@@ -0,0 +1,376 @@
+// pow_client - typed async access to the validator, miner, coordinator and
+// chain_controller canisters over ic-agent, for off-chain tooling and load
+// generators. Each canister gets a small handle; the .did files stay the
+// source of truth for what these methods send and receive.
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::Principal;
+use ic_agent::{Agent, AgentError};
+
+pub mod types;
+
+pub use crate::types::*;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Transport, certificate or canister reject
+    Agent(AgentError),
+    /// The reply didn't match the expected Candid types
+    Candid(candid::Error),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Agent(e) => write!(f, "agent: {}", e),
+            ClientError::Candid(e) => write!(f, "candid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<AgentError> for ClientError {
+    fn from(e: AgentError) -> Self {
+        ClientError::Agent(e)
+    }
+}
+
+impl From<candid::Error> for ClientError {
+    fn from(e: candid::Error) -> Self {
+        ClientError::Candid(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// One canister on one agent
+#[derive(Clone)]
+struct Canister {
+    agent: Agent,
+    id: Principal,
+}
+
+impl Canister {
+    async fn query<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(&self, method: &str, args: A) -> Result<R> {
+        let reply = self
+        .agent
+        .query(&self.id, method)
+        .with_arg(candid::encode_args(args)?)
+        .call()
+        .await?;
+        Ok(candid::decode_args(&reply)?)
+    }
+
+    async fn update<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(&self, method: &str, args: A) -> Result<R> {
+        let reply = self
+        .agent
+        .update(&self.id, method)
+        .with_arg(candid::encode_args(args)?)
+        .call_and_wait()
+        .await?;
+        Ok(candid::decode_args(&reply)?)
+    }
+}
+
+// ------------------------------------------------------------
+// Validator
+// ------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct ValidatorClient(Canister);
+
+impl ValidatorClient {
+    pub fn new(agent: Agent, canister: Principal) -> Self {
+        ValidatorClient(Canister { agent, id: canister })
+    }
+
+    pub async fn verify_pow(
+        &self,
+        block_data: &str,
+        nonce: u64,
+        difficulty: u32,
+        algorithm: Option<PowAlgorithm>,
+        target: Option<Vec<u8>>,
+        nonce_encoding: Option<NonceEncoding>,
+    ) -> Result<ValidationResult> {
+        let args = (block_data, nonce, difficulty, algorithm, target, nonce_encoding);
+        let (r,) = self.0.query("verify_pow", args).await?;
+        Ok(r)
+    }
+
+    pub async fn verify_block(&self, block: &Block) -> Result<ValidationResult> {
+        let (r,) = self.0.query("verify_block", (block,)).await?;
+        Ok(r)
+    }
+
+    /// Free only up to the validator's free_blocks; an agent can't attach
+    /// cycles, so larger segments must go through a cycles wallet
+    pub async fn verify_chain_segment(&self, blocks: &[Block]) -> Result<ValidationResult> {
+        let (r,) = self.0.update("verify_chain_segment", (blocks,)).await?;
+        Ok(r)
+    }
+
+    pub async fn batch_verify_blocks(
+        &self,
+        blocks: &[Block],
+    ) -> Result<std::result::Result<Vec<ValidationResult>, ValidatorError>> {
+        let (r,) = self.0.update("batch_verify_blocks", (blocks,)).await?;
+        Ok(r)
+    }
+
+    pub async fn compute_hash(
+        &self,
+        block_data: &str,
+        nonce: u64,
+        algorithm: Option<PowAlgorithm>,
+        nonce_encoding: Option<NonceEncoding>,
+    ) -> Result<String> {
+        let (r,) = self.0.query("compute_hash", (block_data, nonce, algorithm, nonce_encoding)).await?;
+        Ok(r)
+    }
+
+    pub async fn difficulty_to_target(&self, difficulty: u32) -> Result<Vec<u8>> {
+        let (r,) = self.0.query("difficulty_to_target", (difficulty,)).await?;
+        Ok(r)
+    }
+
+    /// Token units the block at `height` may mint, before fees
+    pub async fn get_block_reward(&self, height: u64) -> Result<u64> {
+        let (r,) = self.0.query("get_block_reward", (height,)).await?;
+        Ok(r)
+    }
+}
+
+// ------------------------------------------------------------
+// Miner
+// ------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct MinerClient(Canister);
+
+impl MinerClient {
+    pub fn new(agent: Agent, canister: Principal) -> Self {
+        MinerClient(Canister { agent, id: canister })
+    }
+
+    /// (status, attempts)
+    pub async fn mine_chunk_naive(
+        &self,
+        block_data: &str,
+        difficulty: u32,
+        start_nonce: u64,
+        chunk_size: u64,
+        algorithm: Option<PowAlgorithm>,
+        target: Option<Vec<u8>>,
+        nonce_encoding: Option<NonceEncoding>,
+    ) -> Result<std::result::Result<(MiningStatus, u64), MinerError>> {
+        let args = (block_data, difficulty, start_nonce, chunk_size, algorithm, target, nonce_encoding);
+        let (r,) = self.0.update("mine_chunk_naive", args).await?;
+        Ok(r)
+    }
+
+    /// (status, attempts)
+    pub async fn mine_chunk_with_midstate(
+        &self,
+        block_data: &str,
+        difficulty: u32,
+        start_nonce: u64,
+        chunk_size: u64,
+        algorithm: Option<PowAlgorithm>,
+        target: Option<Vec<u8>>,
+        nonce_encoding: Option<NonceEncoding>,
+    ) -> Result<std::result::Result<(MiningStatus, u64), MinerError>> {
+        let args = (block_data, difficulty, start_nonce, chunk_size, algorithm, target, nonce_encoding);
+        let (r,) = self.0.update("mine_chunk_with_midstate", args).await?;
+        Ok(r)
+    }
+
+    /// Only the miner's coordinator may call this
+    pub async fn mine_job(&self, job: &JobNotify) -> Result<std::result::Result<JobSubmit, MinerError>> {
+        let (r,) = self.0.update("mine_job", (job,)).await?;
+        Ok(r)
+    }
+
+    /// Several (start, size) ranges of one job in one call; only the
+    /// miner's coordinator may call this
+    pub async fn mine_chunks(
+        &self,
+        job_id: u64,
+        ranges: &[(u64, u64)],
+        block_data: &str,
+        target: &[u8],
+        algorithm: Option<PowAlgorithm>,
+        session_token: Option<u64>,
+        share_target: Option<&[u8]>,
+    ) -> Result<std::result::Result<Vec<JobSubmit>, MinerError>> {
+        let args = (job_id, ranges, block_data, target, algorithm, session_token, share_target);
+        let (r,) = self.0.update("mine_chunks", args).await?;
+        Ok(r)
+    }
+
+    pub async fn is_cached(&self, block_data: &str, difficulty: u32) -> Result<bool> {
+        let (r,) = self.0.query("is_cached", (block_data, difficulty)).await?;
+        Ok(r)
+    }
+}
+
+// ------------------------------------------------------------
+// Coordinator
+// ------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct CoordinatorClient(Canister);
+
+impl CoordinatorClient {
+    pub fn new(agent: Agent, canister: Principal) -> Self {
+        CoordinatorClient(Canister { agent, id: canister })
+    }
+
+    /// Returns the job id
+    pub async fn start_dynamic_mining(&self, job: DynamicJob) -> Result<u64> {
+        let args = (
+            job.miners,
+            job.block_data,
+            job.difficulty,
+            job.start_nonce,
+            job.chunk_size,
+            job.weight,
+            job.deadline_ns,
+            job.max_total_attempts,
+            job.end_nonce,
+            job.algorithm,
+            job.target,
+            job.share_difficulty,
+        );
+        let (r,) = self.0.update("start_dynamic_mining", args).await?;
+        Ok(r)
+    }
+
+    pub async fn get_shares(&self, job_id: u64) -> Result<ShareTally> {
+        let (r,) = self.0.query("get_shares", (job_id,)).await?;
+        Ok(r)
+    }
+
+    /// None until the job is solved
+    pub async fn get_payouts(&self, job_id: u64) -> Result<Option<PayoutBreakdown>> {
+        let (r,) = self.0.query("get_payouts", (job_id,)).await?;
+        Ok(r)
+    }
+
+    /// None lists every miner with a record, lowest score first
+    pub async fn get_reputation(&self, miner: Option<Principal>) -> Result<Vec<MinerReputation>> {
+        let (r,) = self.0.query("get_reputation", (miner,)).await?;
+        Ok(r)
+    }
+
+    /// None if no operator has bonded the miner
+    pub async fn get_bond(&self, miner: Principal) -> Result<Option<Bond>> {
+        let (r,) = self.0.query("get_bond", (miner,)).await?;
+        Ok(r)
+    }
+
+    pub async fn stop_job(&self, job_id: u64) -> Result<bool> {
+        let (r,) = self.0.update("stop_job", (job_id,)).await?;
+        Ok(r)
+    }
+
+    pub async fn stop_dynamic_mining(&self) -> Result<()> {
+        self.0.update("stop_dynamic_mining", ()).await
+    }
+
+    pub async fn list_jobs(&self) -> Result<Vec<SchedulerStats>> {
+        let (r,) = self.0.query("list_jobs", ()).await?;
+        Ok(r)
+    }
+
+    /// None picks the newest job
+    pub async fn get_scheduler_stats(&self, job_id: Option<u64>) -> Result<Option<SchedulerStats>> {
+        let (r,) = self.0.query("get_scheduler_stats", (job_id,)).await?;
+        Ok(r)
+    }
+
+    /// Err if no chain is linked or a linked canister couldn't be reached
+    pub async fn get_chain_status(&self) -> Result<std::result::Result<ChainStatus, String>> {
+        let (r,) = self.0.query("get_chain_status", ()).await?;
+        Ok(r)
+    }
+
+    pub async fn get_miner_stats(&self) -> Result<Vec<MinerStats>> {
+        let (r,) = self.0.query("get_miner_stats", ()).await?;
+        Ok(r)
+    }
+
+    pub async fn assign_one_chunk(
+        &self,
+        miner: Principal,
+        block_data: &str,
+        difficulty: u32,
+        start_nonce: u64,
+        chunk_size: u64,
+    ) -> Result<Option<MiningResult>> {
+        let args = (miner, block_data, difficulty, start_nonce, chunk_size);
+        let (r,) = self.0.update("assign_one_chunk", args).await?;
+        Ok(r)
+    }
+}
+
+// ------------------------------------------------------------
+// Chain controller
+// ------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct ChainClient(Canister);
+
+impl ChainClient {
+    pub fn new(agent: Agent, canister: Principal) -> Self {
+        ChainClient(Canister { agent, id: canister })
+    }
+
+    pub async fn get_tip(&self) -> Result<ChainTip> {
+        let (r,) = self.0.query("get_tip", ()).await?;
+        Ok(r)
+    }
+
+    /// The tip with the validator's verdict, in one composite query
+    pub async fn get_tip_with_validation(&self) -> Result<ValidatedTip> {
+        let (r,) = self.0.query("get_tip_with_validation", ()).await?;
+        Ok(r)
+    }
+
+    pub async fn get_block_with_validation(&self, height: u64) -> Result<Option<ValidatedBlock>> {
+        let (r,) = self.0.query("get_block_with_validation", (height,)).await?;
+        Ok(r)
+    }
+
+    pub async fn get_block(&self, height: u64) -> Result<Option<BlockHeader>> {
+        let (r,) = self.0.query("get_block", (height,)).await?;
+        Ok(r)
+    }
+
+    /// Inclusive, at most 1000 per call
+    pub async fn get_blocks(&self, from: u64, to: u64) -> Result<Vec<BlockHeader>> {
+        let (r,) = self.0.query("get_blocks", (from, to)).await?;
+        Ok(r)
+    }
+
+    pub async fn get_block_by_hash(&self, block_hash: &str) -> Result<Option<BlockHeader>> {
+        let (r,) = self.0.query("get_block_by_hash", (block_hash,)).await?;
+        Ok(r)
+    }
+
+    pub async fn recent_blocks(&self, n: u64) -> Result<Vec<BlockHeader>> {
+        let (r,) = self.0.query("recent_blocks", (n,)).await?;
+        Ok(r)
+    }
+
+    pub async fn search_blocks(&self, filter: &BlockFilter, cursor: Option<u64>, limit: Option<u64>) -> Result<BlockPage> {
+        let (r,) = self.0.query("search_blocks", (filter, cursor, limit)).await?;
+        Ok(r)
+    }
+
+    /// Returns the new tip; rejected blocks come back as an agent error
+    pub async fn submit_block(&self, block: &Block) -> Result<ChainTip> {
+        let (r,) = self.0.update("submit_block", (block,)).await?;
+        Ok(r)
+    }
+}
@@ -0,0 +1,297 @@
+// types.rs - Rust mirrors of the canister .did types. Field names and
+// order follow the .did files; optional fields are Option so newer
+// canisters that add them keep decoding.
+use candid::{CandidType, Deserialize, Principal};
+
+// ------------------------------------------------------------
+// Shared
+// ------------------------------------------------------------
+
+/// None on the wire means Sha256
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum PowAlgorithm {
+    Sha256,
+    Blake3,
+    DoubleSha256,
+    Keccak256,
+    Scrypt { log_n: u8 },
+}
+
+/// None on the wire means LeBytes; Decimal and Hex are ASCII without
+/// leading zeros
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum NonceEncoding {
+    LeBytes,
+    BeBytes,
+    Decimal,
+    Hex,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ValidatorError {
+    RateLimited { retry_after_ms: u64 },
+    InputTooLarge { field: String, value: u64, max: u64 },
+    PaymentRequired { required_cycles: u64, attached_cycles: u64 },
+}
+
+/// A mined block, as the validator verifies it and chain_controller's
+/// submit_block accepts it (which ignores `algorithm` and `target`)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Block {
+    pub height: u64,
+    pub prev_hash: String,
+    pub block_data: String,
+    pub nonce: u64,
+    pub difficulty: u32,
+    pub hash: String,
+    pub timestamp: u64,
+    pub miner: Option<Principal>,
+    pub algorithm: Option<PowAlgorithm>,
+    /// 32 bytes, big-endian; checked instead of difficulty when set
+    pub target: Option<Vec<u8>>,
+    pub nonce_encoding: Option<NonceEncoding>,
+    /// Payload fees committed in a template's block_data
+    pub total_fee: Option<u64>,
+    /// Tokens minted to the miner; at most the height's reward plus fees
+    pub coinbase: Option<u64>,
+}
+
+// ------------------------------------------------------------
+// Miner
+// ------------------------------------------------------------
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum MiningStatus {
+    Found { hash: String, nonce: u64 },
+    Continue { next_nonce: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum MinerError {
+    RateLimited { retry_after_ms: u64 },
+    NotCoordinator,
+    InputTooLarge { field: String, value: u64, max: u64 },
+    InvalidTarget { len: u64 },
+    UnsupportedVersion { version: u32, supported: u32 },
+    Cancelled,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JobNotify {
+    pub version: u32,
+    pub job_id: u64,
+    pub prev_hash: Option<String>,
+    pub block_data: String,
+    pub extranonce_start: u64,
+    pub extranonce_size: u64,
+    pub target: Vec<u8>,
+    pub algorithm: Option<PowAlgorithm>,
+    pub clean_jobs: bool,
+    pub session_token: Option<u64>,
+    pub share_target: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JobSubmit {
+    pub version: u32,
+    pub job_id: u64,
+    pub found: bool,
+    pub nonce: u64,
+    pub hash: String,
+    pub attempts: u64,
+    pub instructions: u64,
+    pub session_token: Option<u64>,
+    /// (nonce, hash), lowest hash first
+    pub shares: Option<Vec<(u64, String)>>,
+}
+
+// ------------------------------------------------------------
+// Coordinator
+// ------------------------------------------------------------
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MiningResult {
+    pub found: bool,
+    pub nonce: u64,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SchedulerStats {
+    pub job_id: u64,
+    pub weight: u32,
+    pub running: bool,
+    pub expired: bool,
+    pub total_attempts: u64,
+    pub total_miners: u64,
+    pub idle_miners: u64,
+    pub busy_miners: u64,
+    pub failed_miners: u64,
+    pub total_chunks_assigned: u64,
+    pub next_nonce: u64,
+    pub solution: Option<(u64, String)>,
+    pub uptime_seconds: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MinerStats {
+    pub miner: Principal,
+    pub chunks_assigned: u64,
+    pub chunks_completed: u64,
+    pub solutions_found: u64,
+    pub failures: u32,
+    pub total_attempts: u64,
+    pub estimated_hashrate: u64,
+    pub last_seen: u64,
+    pub invalid_solutions: u64,
+}
+
+/// Arguments of start_dynamic_mining; the None fields take the
+/// coordinator's defaults
+#[derive(Clone, Debug, Default)]
+pub struct DynamicJob {
+    pub miners: Vec<Principal>,
+    pub block_data: String,
+    pub difficulty: u32,
+    pub start_nonce: u64,
+    pub chunk_size: u64,
+    pub weight: Option<u32>,
+    /// Absolute IC time
+    pub deadline_ns: Option<u64>,
+    pub max_total_attempts: Option<u64>,
+    /// Exclusive
+    pub end_nonce: Option<u64>,
+    pub algorithm: Option<PowAlgorithm>,
+    pub target: Option<Vec<u8>>,
+    /// Below `difficulty`; miners send back shares against it
+    pub share_difficulty: Option<u32>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MinerShares {
+    pub miner: Principal,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub work: f64,
+    pub last_share_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ShareTally {
+    pub job_id: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub work: f64,
+    pub miners: Vec<MinerShares>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MinerPayout {
+    pub miner: Principal,
+    pub shares: u64,
+    pub work: f64,
+    /// Out of 1_000_000_000
+    pub parts: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PayoutBreakdown {
+    pub job_id: u64,
+    pub block_hash: String,
+    pub finder: Principal,
+    pub window: u64,
+    pub shares: u64,
+    pub settled_at: u64,
+    pub payouts: Vec<MinerPayout>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ReputationOverride {
+    Banned,
+    Trusted,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MinerReputation {
+    pub miner: Principal,
+    pub score: u32,
+    pub pinned: Option<ReputationOverride>,
+    pub slashes: u64,
+    pub banned: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Bond {
+    pub miner: Principal,
+    pub operator: Principal,
+    /// Cycles
+    pub amount: u64,
+    pub forfeited: u64,
+    pub updated_at: u64,
+}
+
+// ------------------------------------------------------------
+// Chain controller
+// ------------------------------------------------------------
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChainTip {
+    pub height: u64,
+    pub block_hash: String,
+    pub difficulty: u32,
+    pub last_update_ns: u64,
+    pub paused: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub block_hash: String,
+    pub prev_hash: String,
+    pub difficulty: u32,
+    pub timestamp_ns: u64,
+    pub cumulative_work: u128,
+    pub miner: Option<Principal>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct BlockFilter {
+    pub miner: Option<Principal>,
+    pub min_difficulty: Option<u32>,
+    pub max_difficulty: Option<u32>,
+    pub from_ns: Option<u64>,
+    pub to_ns: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BlockPage {
+    pub blocks: Vec<BlockHeader>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ValidatedTip {
+    pub tip: ChainTip,
+    pub validation: ValidationResult,
+    pub validator: Principal,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ValidatedBlock {
+    pub header: BlockHeader,
+    pub validation: ValidationResult,
+}
+
+/// The coordinator's view of its linked chain
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ChainStatus {
+    pub tip: ChainTip,
+    pub validation: ValidationResult,
+    pub next_difficulty: u32,
+}
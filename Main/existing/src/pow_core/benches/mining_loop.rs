@@ -0,0 +1,65 @@
+// Native throughput of the SHA-256 mining loop, so hot-loop changes can be
+// compared without deploying to a replica:
+//
+//     cargo bench -p pow_core --bench mining_loop
+//
+// Each iteration searches CHUNK nonces against a target no hash meets, the
+// way a miner's chunk runs when it finds nothing. Numbers here are native:
+// sha2 uses SHA-NI where the CPU has it, which the lane-parallel path can't
+// match, and the lanes only get wide vectors with
+// RUSTFLAGS="-C target-cpu=native". Wasm has neither SHA instructions nor
+// AVX, so the canisters' instruction benchmarks remain the ground truth.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pow_core::target::{self, Check};
+use pow_core::{hash_preimage, HashMidState, NonceEncoding, PowAlgorithm, Preimage, Sha256Lanes, LANES};
+
+const CHUNK: u64 = 4096;
+
+/// A one-block tail, a two-block tail (block_data % 64 above 47), and a
+/// longer block whose prefix the midstate absorbs once
+const BLOCK_DATA_LENS: [usize; 3] = [32, 50, 200];
+
+fn naive(block_data: &str, check: &Check, start: u64) -> u64 {
+    let mut preimage = Preimage::new(block_data.as_bytes(), NonceEncoding::LeBytes);
+    (start..start + CHUNK)
+    .filter(|&n| check.meets(&hash_preimage(PowAlgorithm::Sha256, preimage.with_nonce(n))))
+    .count() as u64
+}
+
+fn midstate(block_data: &str, check: &Check, start: u64) -> u64 {
+    let mut mid = HashMidState::for_algorithm(PowAlgorithm::Sha256, block_data);
+    (start..start + CHUNK)
+    .filter(|&n| check.meets(&mid.finalize_with_nonce(n)))
+    .count() as u64
+}
+
+fn lanes(block_data: &str, check: &Check, start: u64) -> u64 {
+    let lanes = Sha256Lanes::new(block_data, NonceEncoding::LeBytes).expect("fixed-width nonce");
+    (start..start + CHUNK)
+    .step_by(LANES)
+    .map(|n| lanes.hash(n).iter().filter(|h| check.meets(h)).count() as u64)
+    .sum()
+}
+
+fn sha256_chunk(c: &mut Criterion) {
+    let check = Check::new(&target::from_difficulty(256));
+    let mut group = c.benchmark_group("sha256_chunk");
+    group.throughput(Throughput::Elements(CHUNK));
+
+    for len in BLOCK_DATA_LENS {
+        let block_data = "b".repeat(len);
+        group.bench_with_input(BenchmarkId::new("naive", len), &block_data, |b, data| {
+            b.iter(|| naive(black_box(data), &check, black_box(0)))
+        });
+        group.bench_with_input(BenchmarkId::new("midstate", len), &block_data, |b, data| {
+            b.iter(|| midstate(black_box(data), &check, black_box(0)))
+        });
+        group.bench_with_input(BenchmarkId::new("simd_lanes", len), &block_data, |b, data| {
+            b.iter(|| lanes(black_box(data), &check, black_box(0)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sha256_chunk);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+// emission.rs - how many tokens a block at a given height may mint. The
+// reward halves every `halving_interval` blocks and never drops below the
+// tail emission, so the validator that checks a coinbase and the
+// chain_controller that pays it read the same schedule.
+use candid::CandidType;
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmissionSchedule {
+    /// Token units minted by each block before the first halving
+    pub initial_reward: u64,
+    /// Blocks between halvings
+    pub halving_interval: u64,
+    /// Floor the reward stays at once halvings would take it lower; 0 for a
+    /// capped supply
+    pub tail_emission: u64,
+}
+
+impl Default for EmissionSchedule {
+    /// 50 tokens of 8 decimals, halving every 210,000 blocks, no tail
+    fn default() -> Self {
+        Self {
+            initial_reward: 5_000_000_000,
+            halving_interval: 210_000,
+            tail_emission: 0,
+        }
+    }
+}
+
+impl EmissionSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.halving_interval == 0 {
+            return Err("halving_interval must be at least 1".to_string());
+        }
+        if self.tail_emission > self.initial_reward {
+            return Err("tail_emission can't exceed initial_reward".to_string());
+        }
+        Ok(())
+    }
+
+    /// The reward of the block at `height`; genesis counts as height 0
+    pub fn reward_at(&self, height: u64) -> u64 {
+        let halvings = height / self.halving_interval.max(1);
+        let halved = if halvings >= 64 { 0 } else { self.initial_reward >> halvings };
+        halved.max(self.tail_emission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_on_schedule_down_to_the_tail() {
+        let s = EmissionSchedule { initial_reward: 1_000, halving_interval: 10, tail_emission: 0 };
+        assert_eq!(s.reward_at(0), 1_000);
+        assert_eq!(s.reward_at(9), 1_000);
+        assert_eq!(s.reward_at(10), 500);
+        assert_eq!(s.reward_at(35), 125);
+        assert_eq!(s.reward_at(100), 0);
+        assert_eq!(s.reward_at(u64::MAX), 0);
+
+        let tail = EmissionSchedule { tail_emission: 100, ..s };
+        assert_eq!(tail.reward_at(20), 250);
+        assert_eq!(tail.reward_at(40), 100);
+        assert_eq!(tail.reward_at(u64::MAX), 100);
+    }
+
+    #[test]
+    fn rejects_bad_schedules() {
+        assert!(EmissionSchedule::default().validate().is_ok());
+        let no_interval = EmissionSchedule { halving_interval: 0, ..Default::default() };
+        assert!(no_interval.validate().is_err());
+        let tail_above = EmissionSchedule { initial_reward: 1, tail_emission: 2, halving_interval: 1 };
+        assert!(tail_above.validate().is_err());
+    }
+}
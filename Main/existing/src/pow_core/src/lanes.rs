@@ -0,0 +1,135 @@
+// lanes.rs - SHA-256 over LANES consecutive nonces at once. The state is
+// kept lane-major ([word][lane]) so every step of the compression is the
+// same operation across LANES independent u32s, which the compiler turns
+// into vector instructions (SSE/AVX natively, simd128 on wasm when enabled).
+use crate::midstate::Sha256Tail;
+use crate::NonceEncoding;
+
+/// Nonces hashed per call
+pub const LANES: usize = 8;
+
+type Lanes = [u32; LANES];
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 of block_data || nonce for nonces n..n + LANES, sharing
+/// HashMidState's precomputed tail. Only fixed-width (8-byte) nonce
+/// encodings keep the nonce at one offset in every lane.
+#[derive(Clone)]
+pub struct Sha256Lanes {
+    tail: Sha256Tail,
+    encoding: NonceEncoding,
+}
+
+impl Sha256Lanes {
+    /// None for Decimal and Hex nonces
+    pub fn new(block_data: &str, encoding: NonceEncoding) -> Option<Self> {
+        encoding
+        .is_fixed_width()
+        .then(|| Sha256Lanes { tail: Sha256Tail::new(block_data.as_bytes()), encoding })
+    }
+
+    /// Hashes of nonces `first`, `first + 1`, ... (wrapping at u64::MAX)
+    pub fn hash(&self, first: u64) -> [[u8; 32]; LANES] {
+        let tail = &self.tail;
+        let mut tails = [tail.tail; LANES];
+        for (lane, t) in tails.iter_mut().enumerate() {
+            let nonce = self.encoding.fixed_bytes(first.wrapping_add(lane as u64));
+            t[tail.nonce_at..tail.nonce_at + 8].copy_from_slice(&nonce);
+        }
+
+        let mut state: [Lanes; 8] = std::array::from_fn(|i| [tail.state[i]; LANES]);
+        for block in 0..tail.tail_len / 64 {
+            let mut w = [[0u32; LANES]; 16];
+            for (i, word) in w.iter_mut().enumerate() {
+                let at = block * 64 + i * 4;
+                for (lane, t) in tails.iter().enumerate() {
+                    word[lane] = u32::from_be_bytes([t[at], t[at + 1], t[at + 2], t[at + 3]]);
+                }
+            }
+            compress(&mut state, w);
+        }
+
+        std::array::from_fn(|lane| {
+            let mut out = [0u8; 32];
+            for (bytes, word) in out.chunks_exact_mut(4).zip(&state) {
+                bytes.copy_from_slice(&word[lane].to_be_bytes());
+            }
+            out
+        })
+    }
+}
+
+/// One SHA-256 compression in every lane; `w` holds the block's 16 words
+/// and is extended in place as a rolling schedule
+fn compress(state: &mut [Lanes; 8], mut w: [Lanes; 16]) {
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for (i, k) in K.iter().enumerate() {
+        if i >= 16 {
+            let (w15, w2, w16, w7) = (w[(i + 1) % 16], w[(i + 14) % 16], w[i % 16], w[(i + 9) % 16]);
+            for l in 0..LANES {
+                let s0 = w15[l].rotate_right(7) ^ w15[l].rotate_right(18) ^ (w15[l] >> 3);
+                let s1 = w2[l].rotate_right(17) ^ w2[l].rotate_right(19) ^ (w2[l] >> 10);
+                w[i % 16][l] = w16[l].wrapping_add(s0).wrapping_add(w7[l]).wrapping_add(s1);
+            }
+        }
+        let wi = w[i % 16];
+        for l in 0..LANES {
+            let s1 = e[l].rotate_right(6) ^ e[l].rotate_right(11) ^ e[l].rotate_right(25);
+            let ch = (e[l] & f[l]) ^ (!e[l] & g[l]);
+            let t1 = h[l].wrapping_add(s1).wrapping_add(ch).wrapping_add(*k).wrapping_add(wi[l]);
+            let s0 = a[l].rotate_right(2) ^ a[l].rotate_right(13) ^ a[l].rotate_right(22);
+            let maj = (a[l] & b[l]) ^ (a[l] & c[l]) ^ (b[l] & c[l]);
+            h[l] = g[l];
+            g[l] = f[l];
+            f[l] = e[l];
+            e[l] = d[l].wrapping_add(t1);
+            d[l] = c[l];
+            c[l] = b[l];
+            b[l] = a[l];
+            a[l] = t1.wrapping_add(s0.wrapping_add(maj));
+        }
+    }
+    for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        for l in 0..LANES {
+            word[l] = word[l].wrapping_add(add[l]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pow_hash, PowAlgorithm};
+
+    #[test]
+    fn lanes_match_one_at_a_time() {
+        for len in [0, 1, 55, 56, 57, 63, 64, 100, 119, 120, 200] {
+            let block_data = "y".repeat(len);
+            for encoding in [NonceEncoding::LeBytes, NonceEncoding::BeBytes] {
+                let lanes = Sha256Lanes::new(&block_data, encoding).unwrap();
+                for first in [0, 1000, u64::MAX - 3] {
+                    for (lane, hash) in lanes.hash(first).iter().enumerate() {
+                        let nonce = first.wrapping_add(lane as u64);
+                        assert_eq!(*hash, pow_hash(PowAlgorithm::Sha256, encoding, &block_data, nonce));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn variable_width_nonces_have_no_lanes() {
+        assert!(Sha256Lanes::new("x", NonceEncoding::Decimal).is_none());
+        assert!(Sha256Lanes::new("x", NonceEncoding::Hex).is_none());
+    }
+}
@@ -0,0 +1,323 @@
+// pow_core - the proof-of-work rules the miner, coordinator and validator
+// must agree on: the hash algorithms, how block_data and a nonce become the
+// hashed preimage, and when a hash meets a difficulty or target. Kept free
+// of ic-cdk so one copy serves every canister and tests run natively.
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+pub mod emission;
+mod lanes;
+mod midstate;
+pub mod retarget;
+pub mod simulate;
+pub mod target;
+
+pub use lanes::{Sha256Lanes, LANES};
+pub use midstate::HashMidState;
+
+/// Hash a block is mined with; calls that omit it mean Sha256
+#[derive(candid::CandidType, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PowAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    /// SHA-256(SHA-256(..)), byte-reversed as Bitcoin displays and compares
+    /// it
+    DoubleSha256,
+    /// Ethereum's Keccak-256 (original padding, not SHA3-256)
+    Keccak256,
+    /// Memory-hard: scrypt with N = 2^log_n, r = 1, p = 1 (as Litecoin),
+    /// using the preimage as both password and salt. Each hash touches
+    /// 128 * N bytes of heap.
+    Scrypt { log_n: u8 },
+}
+
+/// scrypt requires N < 2^(16 * r), so with r = 1 the scratchpad tops out at
+/// 4 MiB
+pub const MAX_SCRYPT_LOG_N: u8 = 15;
+
+impl PowAlgorithm {
+    /// Scrypt at Litecoin's N = 1024
+    pub const ALL: [PowAlgorithm; 5] = [
+        PowAlgorithm::Sha256,
+        PowAlgorithm::Blake3,
+        PowAlgorithm::DoubleSha256,
+        PowAlgorithm::Keccak256,
+        PowAlgorithm::Scrypt { log_n: 10 },
+    ];
+}
+
+/// How a nonce is appended to block_data before hashing; calls that omit it
+/// mean LeBytes
+#[derive(candid::CandidType, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonceEncoding {
+    /// 8 bytes, little-endian
+    #[default]
+    LeBytes,
+    /// 8 bytes, big-endian
+    BeBytes,
+    /// ASCII decimal without leading zeros ("0" for zero)
+    Decimal,
+    /// ASCII lowercase hex without leading zeros or "0x" ("0" for zero)
+    Hex,
+}
+
+/// The longest encoded nonce: u64::MAX in decimal
+pub const MAX_NONCE_LEN: usize = 20;
+
+impl NonceEncoding {
+    /// Whether every nonce encodes to the same 8 bytes' width
+    pub fn is_fixed_width(self) -> bool {
+        matches!(self, NonceEncoding::LeBytes | NonceEncoding::BeBytes)
+    }
+
+    /// The 8 bytes of a fixed-width encoding; callers check is_fixed_width
+    fn fixed_bytes(self, nonce: u64) -> [u8; 8] {
+        match self {
+            NonceEncoding::BeBytes => nonce.to_be_bytes(),
+            _ => nonce.to_le_bytes(),
+        }
+    }
+
+    /// `nonce` as this encoding, written into `buf`
+    pub fn encode(self, nonce: u64, buf: &mut [u8; MAX_NONCE_LEN]) -> &[u8] {
+        match self {
+            NonceEncoding::LeBytes | NonceEncoding::BeBytes => {
+                buf[..8].copy_from_slice(&self.fixed_bytes(nonce));
+                &buf[..8]
+            }
+            NonceEncoding::Decimal => ascii_digits(nonce, 10, buf),
+            NonceEncoding::Hex => ascii_digits(nonce, 16, buf),
+        }
+    }
+}
+
+/// Digits are written from the end of `buf` back, so no reversal is needed
+fn ascii_digits(mut n: u64, radix: u64, buf: &mut [u8; MAX_NONCE_LEN]) -> &[u8] {
+    let mut at = buf.len();
+    loop {
+        at -= 1;
+        buf[at] = b"0123456789abcdef"[(n % radix) as usize];
+        n /= radix;
+        if n == 0 {
+            return &buf[at..];
+        }
+    }
+}
+
+/// Callers check `log_n` against MAX_SCRYPT_LOG_N first
+fn scrypt_hash(log_n: u8, preimage: &[u8]) -> [u8; 32] {
+    let params = scrypt::Params::new(log_n, 1, 1, 32).expect("scrypt log_n out of range");
+    let mut out = [0u8; 32];
+    scrypt::scrypt(preimage, preimage, &params, &mut out).expect("32-byte scrypt output");
+    out
+}
+
+/// block_data || encoded nonce in one buffer; each attempt rewrites the
+/// nonce in place, and the capacity covers the longest encoding, so a loop
+/// over nonces never allocates
+#[derive(Clone)]
+pub struct Preimage {
+    bytes: Vec<u8>,
+    prefix_len: usize,
+    encoding: NonceEncoding,
+}
+
+impl Preimage {
+    pub fn new(block_data: &[u8], encoding: NonceEncoding) -> Self {
+        let mut bytes = Vec::with_capacity(block_data.len() + MAX_NONCE_LEN);
+        bytes.extend_from_slice(block_data);
+        Preimage { bytes, prefix_len: block_data.len(), encoding }
+    }
+
+    pub fn with_nonce(&mut self, nonce: u64) -> &[u8] {
+        let mut buf = [0u8; MAX_NONCE_LEN];
+        self.bytes.truncate(self.prefix_len);
+        self.bytes.extend_from_slice(self.encoding.encode(nonce, &mut buf));
+        &self.bytes
+    }
+}
+
+/// H(preimage), from a fresh hasher
+pub fn hash_preimage(algorithm: PowAlgorithm, preimage: &[u8]) -> [u8; 32] {
+    match algorithm {
+        PowAlgorithm::Sha256 => Sha256::digest(preimage).into(),
+        PowAlgorithm::Blake3 => blake3::hash(preimage).into(),
+        PowAlgorithm::DoubleSha256 => midstate::sha256d_finish(Sha256::digest(preimage).into()),
+        PowAlgorithm::Keccak256 => Keccak256::digest(preimage).into(),
+        PowAlgorithm::Scrypt { log_n } => scrypt_hash(log_n, preimage),
+    }
+}
+
+/// H(block_data || encoded nonce), hashed from scratch
+pub fn pow_hash(algorithm: PowAlgorithm, encoding: NonceEncoding, block_data: &str, nonce: u64) -> [u8; 32] {
+    hash_preimage(algorithm, Preimage::new(block_data.as_bytes(), encoding).with_nonce(nonce))
+}
+
+pub fn hash_to_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes)
+}
+
+/// Whether `hash` starts with at least `difficulty` zero bits. Difficulties
+/// past 256 count as 256, as in target::from_difficulty.
+pub fn meets_difficulty(hash: &[u8; 32], difficulty: u32) -> bool {
+    let mut remaining = difficulty.min(256);
+    for b in hash.iter() {
+        if remaining == 0 { return true; }
+        let z = b.leading_zeros();
+        if z >= remaining { return true; }
+        if z < 8 { return false; }
+        remaining -= 8;
+    }
+    remaining == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [NonceEncoding; 4] =
+        [NonceEncoding::LeBytes, NonceEncoding::BeBytes, NonceEncoding::Decimal, NonceEncoding::Hex];
+
+    /// A hash with exactly `zeros` leading zero bits followed by ones
+    fn hash_with_zeros(zeros: u32) -> [u8; 32] {
+        let mut hash = [0xffu8; 32];
+        for (i, b) in hash.iter_mut().enumerate() {
+            let bit = i as u32 * 8;
+            if zeros >= bit + 8 {
+                *b = 0;
+            } else if zeros > bit {
+                *b = 0xff >> (zeros - bit);
+            }
+        }
+        hash
+    }
+
+    #[test]
+    fn meets_difficulty_at_every_boundary() {
+        for zeros in 0..=256 {
+            let hash = hash_with_zeros(zeros);
+            for difficulty in 0..=260 {
+                assert_eq!(
+                    meets_difficulty(&hash, difficulty),
+                    difficulty.min(256) <= zeros,
+                    "{} zero bits at difficulty {}",
+                    zeros,
+                    difficulty
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_first_clear_bit_matters() {
+        // Setting any bit after the leading zeros doesn't change the count
+        for zeros in 0..256u32 {
+            let mut hash = [0u8; 32];
+            hash[zeros as usize / 8] = 0x80 >> (zeros % 8);
+            assert!(meets_difficulty(&hash, zeros));
+            assert!(!meets_difficulty(&hash, zeros + 1));
+            hash[31] |= 1;
+            assert!(meets_difficulty(&hash, zeros));
+        }
+    }
+
+    #[test]
+    fn difficulty_and_target_agree() {
+        for zeros in 0..=256 {
+            let hash = hash_with_zeros(zeros);
+            for difficulty in 0..=260 {
+                let target = target::from_difficulty(difficulty);
+                assert_eq!(
+                    meets_difficulty(&hash, difficulty),
+                    target::meets(&hash, &target),
+                    "{} zero bits at difficulty {}",
+                    zeros,
+                    difficulty
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn preimage_appends_nonce_little_endian() {
+        let mut preimage = Preimage::new(b"block", NonceEncoding::LeBytes);
+        assert_eq!(preimage.with_nonce(0x0102), b"block\x02\x01\0\0\0\0\0\0");
+        assert_eq!(preimage.with_nonce(u64::MAX), b"block\xff\xff\xff\xff\xff\xff\xff\xff");
+        assert_eq!(Preimage::new(b"", NonceEncoding::LeBytes).with_nonce(1), &[1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn nonce_encodings() {
+        let mut buf = [0u8; MAX_NONCE_LEN];
+        let cases: [(NonceEncoding, u64, &[u8]); 10] = [
+            (NonceEncoding::LeBytes, 0x0102, b"\x02\x01\0\0\0\0\0\0"),
+            (NonceEncoding::BeBytes, 0x0102, b"\0\0\0\0\0\0\x01\x02"),
+            (NonceEncoding::Decimal, 0, b"0"),
+            (NonceEncoding::Decimal, 9, b"9"),
+            (NonceEncoding::Decimal, 10, b"10"),
+            (NonceEncoding::Decimal, u64::MAX, b"18446744073709551615"),
+            (NonceEncoding::Hex, 0, b"0"),
+            (NonceEncoding::Hex, 0xabc, b"abc"),
+            (NonceEncoding::Hex, 16, b"10"),
+            (NonceEncoding::Hex, u64::MAX, b"ffffffffffffffff"),
+        ];
+        for (encoding, nonce, expected) in cases {
+            assert_eq!(encoding.encode(nonce, &mut buf), expected, "{:?} {}", encoding, nonce);
+        }
+    }
+
+    #[test]
+    fn preimage_shrinks_and_grows_with_the_nonce() {
+        // A shorter decimal nonce must not leave digits of the previous one
+        let mut preimage = Preimage::new(b"block", NonceEncoding::Decimal);
+        assert_eq!(preimage.with_nonce(12345), b"block12345");
+        assert_eq!(preimage.with_nonce(7), b"block7");
+        assert_eq!(preimage.with_nonce(u64::MAX), b"block18446744073709551615");
+        let mut hex = Preimage::new(b"", NonceEncoding::Hex);
+        assert_eq!(hex.with_nonce(255), b"ff");
+    }
+
+    #[test]
+    fn pow_hash_known_answers() {
+        // sha256(b"abc" || 0u64 le), checked against an independent hasher
+        let expected: [u8; 32] = Sha256::digest(b"abc\0\0\0\0\0\0\0\0").into();
+        assert_eq!(pow_hash(PowAlgorithm::Sha256, NonceEncoding::LeBytes, "abc", 0), expected);
+
+        let mut double: [u8; 32] = Sha256::digest(expected).into();
+        double.reverse();
+        assert_eq!(pow_hash(PowAlgorithm::DoubleSha256, NonceEncoding::LeBytes, "abc", 0), double);
+
+        let decimal: [u8; 32] = Sha256::digest(b"abc42").into();
+        assert_eq!(pow_hash(PowAlgorithm::Sha256, NonceEncoding::Decimal, "abc", 42), decimal);
+        assert_eq!(hash_to_hex(&[0x00, 0xab, 0x0f]), "00ab0f");
+    }
+
+    #[test]
+    fn midstate_matches_from_scratch() {
+        // Lengths either side of SHA-256's one- and two-block tails
+        for len in [0, 1, 47, 48, 55, 56, 63, 64, 65, 119, 120, 128, 200] {
+            let block_data = "x".repeat(len);
+            for algorithm in PowAlgorithm::ALL {
+                let algorithm = match algorithm {
+                    PowAlgorithm::Scrypt { .. } => PowAlgorithm::Scrypt { log_n: 1 },
+                    other => other,
+                };
+                for encoding in ENCODINGS {
+                    let mut mid = HashMidState::with_encoding(algorithm, encoding, &block_data);
+                    for nonce in [0, 1, 255, 256, u64::MAX] {
+                        assert_eq!(
+                            mid.finalize_with_nonce(nonce),
+                            pow_hash(algorithm, encoding, &block_data, nonce),
+                            "{:?} {:?} at length {} nonce {}",
+                            algorithm,
+                            encoding,
+                            len,
+                            nonce
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// midstate.rs - hashing many nonces of one block_data without redoing the
+// shared prefix each time
+use sha2::digest::generic_array::GenericArray;
+use sha2::digest::FixedOutput;
+use sha2::Digest;
+use sha3::Keccak256;
+
+use crate::{hash_preimage, NonceEncoding, PowAlgorithm, Preimage, MAX_NONCE_LEN};
+
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Run SHA-256's compression over whole 64-byte blocks
+fn compress_blocks(state: &mut [u32; 8], bytes: &[u8]) {
+    for block in bytes.chunks_exact(64) {
+        sha2::compress256(state, std::slice::from_ref(GenericArray::from_slice(block)));
+    }
+}
+
+fn state_to_digest(state: [u32; 8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (bytes, word) in out.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// SHA-256 of block_data || nonce with everything but the nonce done up
+/// front: the state after block_data's whole blocks, and the padded final
+/// block(s) with 8 bytes left for the nonce. An attempt writes the nonce in
+/// place and compresses one or two blocks; no hasher is built or copied.
+#[derive(Clone)]
+pub(crate) struct Sha256Tail {
+    pub(crate) state: [u32; 8],
+    pub(crate) tail: [u8; 128],
+    pub(crate) tail_len: usize,
+    pub(crate) nonce_at: usize,
+}
+
+impl Sha256Tail {
+    pub(crate) fn new(block_data: &[u8]) -> Self {
+        let whole = block_data.len() - block_data.len() % 64;
+        let mut state = SHA256_IV;
+        compress_blocks(&mut state, &block_data[..whole]);
+
+        let rest = &block_data[whole..];
+        let nonce_at = rest.len();
+        let mut tail = [0u8; 128];
+        tail[..nonce_at].copy_from_slice(rest);
+        // The 0x80 terminator follows the nonce; the length in bits ends
+        // the last block
+        tail[nonce_at + 8] = 0x80;
+        let tail_len = if nonce_at + 8 + 1 + 8 <= 64 { 64 } else { 128 };
+        let bits = (block_data.len() as u64 + 8) * 8;
+        tail[tail_len - 8..tail_len].copy_from_slice(&bits.to_be_bytes());
+
+        Sha256Tail { state, tail, tail_len, nonce_at }
+    }
+
+    fn hash(&mut self, nonce: [u8; 8]) -> [u8; 32] {
+        self.tail[self.nonce_at..self.nonce_at + 8].copy_from_slice(&nonce);
+        let mut state = self.state;
+        compress_blocks(&mut state, &self.tail[..self.tail_len]);
+        state_to_digest(state)
+    }
+}
+
+/// Second round of DoubleSha256 over the first digest. 32 bytes always pad
+/// to the same single block.
+pub(crate) fn sha256d_finish(first: [u8; 32]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    block[..32].copy_from_slice(&first);
+    block[32] = 0x80;
+    block[62] = 0x01; // 256 bits
+    let mut state = SHA256_IV;
+    compress_blocks(&mut state, &block);
+    let mut hash = state_to_digest(state);
+    hash.reverse();
+    hash
+}
+
+/// Hash state after absorbing block_data, so the prefix is only hashed once.
+/// SHA-256 with an 8-byte nonce keeps precomputed final blocks (see
+/// Sha256Tail); Blake3 and Keccak copy the absorbed hasher per nonce, which
+/// stays on the stack; and scrypt, which has no reusable state, or SHA-256
+/// with a nonce whose width varies, keeps the preimage buffer and hashes it
+/// from scratch. There's one per chunk, so the Blake3 variant's size doesn't
+/// matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum MidState {
+    Sha256(Sha256Tail),
+    Blake3(blake3::Hasher),
+    DoubleSha256(Sha256Tail),
+    Keccak256(Keccak256),
+    Scratch { algorithm: PowAlgorithm, preimage: Preimage },
+}
+
+#[derive(Clone)]
+pub struct HashMidState {
+    state: MidState,
+    encoding: NonceEncoding,
+}
+
+impl HashMidState {
+    pub fn new(block_data: &str) -> Self {
+        Self::for_algorithm(PowAlgorithm::Sha256, block_data)
+    }
+
+    /// Nonces appended as little-endian bytes
+    pub fn for_algorithm(algorithm: PowAlgorithm, block_data: &str) -> Self {
+        Self::with_encoding(algorithm, NonceEncoding::LeBytes, block_data)
+    }
+
+    pub fn with_encoding(algorithm: PowAlgorithm, encoding: NonceEncoding, block_data: &str) -> Self {
+        let fixed = encoding.is_fixed_width();
+        let state = match algorithm {
+            PowAlgorithm::Sha256 if fixed => MidState::Sha256(Sha256Tail::new(block_data.as_bytes())),
+            PowAlgorithm::DoubleSha256 if fixed => MidState::DoubleSha256(Sha256Tail::new(block_data.as_bytes())),
+            PowAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(block_data.as_bytes());
+                MidState::Blake3(hasher)
+            }
+            PowAlgorithm::Keccak256 => {
+                let mut hasher = Keccak256::new();
+                hasher.update(block_data.as_bytes());
+                MidState::Keccak256(hasher)
+            }
+            _ => MidState::Scratch { algorithm, preimage: Preimage::new(block_data.as_bytes(), encoding) },
+        };
+        Self { state, encoding }
+    }
+
+    pub fn finalize_with_nonce(&mut self, nonce: u64) -> [u8; 32] {
+        let mut buf = [0u8; MAX_NONCE_LEN];
+        match &mut self.state {
+            MidState::Sha256(tail) => tail.hash(self.encoding.fixed_bytes(nonce)),
+            MidState::Blake3(hasher) => {
+                let mut h = hasher.clone();
+                h.update(self.encoding.encode(nonce, &mut buf));
+                h.finalize().into()
+            }
+            MidState::DoubleSha256(tail) => sha256d_finish(tail.hash(self.encoding.fixed_bytes(nonce))),
+            MidState::Keccak256(hasher) => {
+                let mut h = hasher.clone();
+                h.update(self.encoding.encode(nonce, &mut buf));
+                h.finalize_fixed().into()
+            }
+            MidState::Scratch { algorithm, preimage } => hash_preimage(*algorithm, preimage.with_nonce(nonce)),
+        }
+    }
+}
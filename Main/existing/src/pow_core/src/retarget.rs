@@ -0,0 +1,87 @@
+// retarget.rs - difficulty adjustment rules. Difficulty is in leading-zero
+// bits, so a block at difficulty d takes 2^d hashes on average and one bit
+// doubles or halves the work.
+
+/// The validator's rule: average the block times since the last
+/// adjustment; outside half to double the target move `max_step` bits,
+/// otherwise one bit toward the target. Never below 1.
+pub fn step(current: u32, target_block_time_secs: u64, block_times_secs: &[u64], max_step: u32) -> u32 {
+    if block_times_secs.is_empty() {
+        return current;
+    }
+    let avg_time = block_times_secs.iter().sum::<u64>() / block_times_secs.len() as u64;
+
+    if avg_time < target_block_time_secs / 2 {
+        // Much too fast
+        current.saturating_add(max_step)
+    } else if avg_time < target_block_time_secs {
+        current.saturating_add(1)
+    } else if avg_time > target_block_time_secs * 2 {
+        // Much too slow
+        current.saturating_sub(max_step).max(1)
+    } else if avg_time > target_block_time_secs {
+        current.saturating_sub(1).max(1)
+    } else {
+        current
+    }
+}
+
+/// Linearly weighted moving average over the window's (difficulty, solve
+/// time) pairs, oldest first: the newest solve time weighs `window` times
+/// the oldest. Each solve time is clamped to 1..=6x the target so one
+/// outlier can't swing it. The average work is scaled by target / weighted
+/// solve time and rounded to the nearest whole bit, at least 1.
+pub fn lwma(target_block_time_secs: u64, window: &[(u32, f64)]) -> Option<u32> {
+    if window.is_empty() {
+        return None;
+    }
+    let target = target_block_time_secs as f64;
+    let n = window.len() as f64;
+    let avg_work = window.iter().map(|(d, _)| 2f64.powi(*d as i32)).sum::<f64>() / n;
+    let weighted_secs: f64 = window
+    .iter()
+    .enumerate()
+    .map(|(i, (_, secs))| (i + 1) as f64 * secs.clamp(1.0, 6.0 * target))
+    .sum();
+    let weights = n * (n + 1.0) / 2.0;
+
+    let next_work = avg_work * target * weights / weighted_secs;
+    Some(next_work.log2().round().clamp(1.0, 256.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_moves_toward_the_target() {
+        // Target 60s, max step 2
+        assert_eq!(step(20, 60, &[], 2), 20);
+        assert_eq!(step(20, 60, &[10, 20], 2), 22);
+        assert_eq!(step(20, 60, &[40, 50], 2), 21);
+        assert_eq!(step(20, 60, &[60, 60], 2), 20);
+        assert_eq!(step(20, 60, &[70, 90], 2), 19);
+        assert_eq!(step(20, 60, &[200, 300], 2), 18);
+        assert_eq!(step(2, 60, &[500], 4), 1);
+        assert_eq!(step(1, 60, &[90], 2), 1);
+    }
+
+    #[test]
+    fn lwma_holds_on_target_and_follows_the_newest_blocks() {
+        let on_target = vec![(20, 60.0); 30];
+        assert_eq!(lwma(60, &on_target), Some(20));
+
+        // Twice as fast throughout: one more bit
+        assert_eq!(lwma(60, &vec![(20, 30.0); 30]), Some(21));
+
+        // The same slow blocks count for more when they're the newest
+        let mut recent_slow = vec![(20, 60.0); 30];
+        recent_slow[20..].iter_mut().for_each(|b| b.1 = 240.0);
+        let mut early_slow = vec![(20, 60.0); 30];
+        early_slow[..10].iter_mut().for_each(|b| b.1 = 240.0);
+        assert!(lwma(60, &recent_slow) < lwma(60, &early_slow));
+
+        assert_eq!(lwma(60, &[]), None);
+        assert_eq!(lwma(60, &[(1, 10_000.0)]), Some(1));
+    }
+}
@@ -0,0 +1,253 @@
+// simulate.rs - run a retarget rule against a hashrate profile for many
+// blocks and measure how steady the block times come out. Solve times are
+// drawn from the exponential distribution PoW gives (mean 2^d / hashrate)
+// with a seeded generator, so a run is reproducible.
+use candid::CandidType;
+use serde::Deserialize;
+
+use crate::retarget;
+
+/// Most blocks one simulation may run
+pub const MAX_SIM_BLOCKS: u64 = 20_000;
+/// Largest LWMA window and step interval
+pub const MAX_SIM_WINDOW: u64 = 1_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RetargetAlgorithm {
+    /// retarget::step every `interval` blocks over that interval's times
+    Step { interval: u64, max_step: u32 },
+    /// retarget::lwma every block over the last `window` blocks
+    Lwma { window: u64 },
+}
+
+/// Hashrate from `from_secs` of simulated time until the next segment
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct HashrateSegment {
+    pub from_secs: u64,
+    pub hashes_per_sec: f64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct SimulationParams {
+    pub algorithm: RetargetAlgorithm,
+    pub target_block_time_secs: u64,
+    pub initial_difficulty: u32,
+    pub blocks: u64,
+    /// Sorted by from_secs; the first starts at 0
+    pub hashrate: Vec<HashrateSegment>,
+    pub seed: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct SimulationReport {
+    pub blocks: u64,
+    pub mean_block_secs: f64,
+    pub block_secs_variance: f64,
+    pub max_block_secs: f64,
+    /// Blocks after which the difficulty changed
+    pub difficulty_changes: u64,
+    /// Changes that went the other way from the one before: how much the
+    /// rule oscillates instead of settling
+    pub direction_reversals: u64,
+    pub min_difficulty: u32,
+    pub max_difficulty: u32,
+    pub final_difficulty: u32,
+}
+
+impl SimulationParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.blocks == 0 || self.blocks > MAX_SIM_BLOCKS {
+            return Err(format!("blocks must be 1..={}", MAX_SIM_BLOCKS));
+        }
+        if self.target_block_time_secs == 0 {
+            return Err("target_block_time_secs must be at least 1".to_string());
+        }
+        if !(1..=256).contains(&self.initial_difficulty) {
+            return Err("initial_difficulty must be 1..=256".to_string());
+        }
+        match self.algorithm {
+            RetargetAlgorithm::Step { interval, max_step } => {
+                if interval == 0 || interval > MAX_SIM_WINDOW || max_step == 0 {
+                    return Err(format!("step needs interval 1..={} and max_step >= 1", MAX_SIM_WINDOW));
+                }
+            }
+            RetargetAlgorithm::Lwma { window } => {
+                if window == 0 || window > MAX_SIM_WINDOW {
+                    return Err(format!("lwma window must be 1..={}", MAX_SIM_WINDOW));
+                }
+            }
+        }
+        match self.hashrate.first() {
+            Some(first) if first.from_secs == 0 => {}
+            _ => return Err("hashrate must start with a segment at 0 secs".to_string()),
+        }
+        if !self.hashrate.windows(2).all(|w| w[0].from_secs < w[1].from_secs) {
+            return Err("hashrate segments must be in increasing from_secs order".to_string());
+        }
+        if !self.hashrate.iter().all(|s| s.hashes_per_sec.is_finite() && s.hashes_per_sec > 0.0) {
+            return Err("hashes_per_sec must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// splitmix64: tiny, seedable, and good enough for sampling solve times
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1]
+    fn unit(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The hashrate in force at `secs`, held for the whole block that starts then
+fn hashrate_at(profile: &[HashrateSegment], secs: f64) -> f64 {
+    profile
+    .iter()
+    .take_while(|s| s.from_secs as f64 <= secs)
+    .last()
+    .map_or(profile[0].hashes_per_sec, |s| s.hashes_per_sec)
+}
+
+pub fn simulate(params: &SimulationParams) -> Result<SimulationReport, String> {
+    params.validate()?;
+    let mut rng = Rng(params.seed);
+    let mut difficulty = params.initial_difficulty;
+    let mut now = 0.0;
+    // (difficulty, solve secs) of every block so far
+    let mut history: Vec<(u32, f64)> = Vec::with_capacity(params.blocks as usize);
+
+    let (mut sum, mut sum_sq, mut max_secs) = (0.0, 0.0, 0.0f64);
+    let (mut changes, mut reversals, mut last_direction) = (0u64, 0u64, 0i8);
+    let (mut min_d, mut max_d) = (difficulty, difficulty);
+
+    for height in 1..=params.blocks {
+        let mean = 2f64.powi(difficulty as i32) / hashrate_at(&params.hashrate, now);
+        let secs = -rng.unit().ln() * mean;
+        now += secs;
+        sum += secs;
+        sum_sq += secs * secs;
+        max_secs = max_secs.max(secs);
+        history.push((difficulty, secs));
+
+        let next = match params.algorithm {
+            RetargetAlgorithm::Step { interval, max_step } if height % interval == 0 => {
+                let times: Vec<u64> = history[history.len() - interval as usize..]
+                .iter()
+                .map(|(_, s)| s.round() as u64)
+                .collect();
+                retarget::step(difficulty, params.target_block_time_secs, &times, max_step)
+            }
+            RetargetAlgorithm::Step { .. } => difficulty,
+            RetargetAlgorithm::Lwma { window } => {
+                let from = history.len().saturating_sub(window as usize);
+                retarget::lwma(params.target_block_time_secs, &history[from..]).unwrap_or(difficulty)
+            }
+        };
+
+        if next != difficulty {
+            let direction = if next > difficulty { 1 } else { -1 };
+            changes += 1;
+            if last_direction == -direction {
+                reversals += 1;
+            }
+            last_direction = direction;
+            difficulty = next;
+            min_d = min_d.min(difficulty);
+            max_d = max_d.max(difficulty);
+        }
+    }
+
+    let n = params.blocks as f64;
+    let mean = sum / n;
+    Ok(SimulationReport {
+        blocks: params.blocks,
+        mean_block_secs: mean,
+        block_secs_variance: (sum_sq / n - mean * mean).max(0.0),
+        max_block_secs: max_secs,
+        difficulty_changes: changes,
+        direction_reversals: reversals,
+        min_difficulty: min_d,
+        max_difficulty: max_d,
+        final_difficulty: difficulty,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(algorithm: RetargetAlgorithm, hashrate: Vec<HashrateSegment>) -> SimulationParams {
+        SimulationParams {
+            algorithm,
+            target_block_time_secs: 60,
+            initial_difficulty: 16,
+            blocks: 5_000,
+            hashrate,
+            seed: 7,
+        }
+    }
+
+    fn constant(hashes_per_sec: f64) -> Vec<HashrateSegment> {
+        vec![HashrateSegment { from_secs: 0, hashes_per_sec }]
+    }
+
+    #[test]
+    fn settles_near_the_target_block_time() {
+        // 2^20 / 60 hashes per second makes difficulty 20 land on 60s blocks
+        let rate = 2f64.powi(20) / 60.0;
+        for algorithm in [RetargetAlgorithm::Step { interval: 10, max_step: 2 }, RetargetAlgorithm::Lwma { window: 45 }] {
+            let report = simulate(&params(algorithm, constant(rate))).unwrap();
+            assert!((19..=21).contains(&report.final_difficulty), "{:?}: {:?}", algorithm, report);
+            assert!((30.0..120.0).contains(&report.mean_block_secs), "{:?}: {:?}", algorithm, report);
+        }
+    }
+
+    #[test]
+    fn follows_a_hashrate_jump() {
+        let rate = 2f64.powi(20) / 60.0;
+        let profile = vec![
+            HashrateSegment { from_secs: 0, hashes_per_sec: rate },
+            HashrateSegment { from_secs: 100_000, hashes_per_sec: rate * 16.0 },
+        ];
+        let report = simulate(&params(RetargetAlgorithm::Lwma { window: 45 }, profile)).unwrap();
+        assert!((23..=25).contains(&report.final_difficulty), "{:?}", report);
+        assert!(report.max_difficulty >= 24);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let p = params(RetargetAlgorithm::Step { interval: 5, max_step: 1 }, constant(1_000.0));
+        assert_eq!(simulate(&p), simulate(&p));
+        let other = SimulationParams { seed: 8, ..p.clone() };
+        assert_ne!(simulate(&p), simulate(&other));
+    }
+
+    #[test]
+    fn rejects_bad_params() {
+        let ok = params(RetargetAlgorithm::Lwma { window: 10 }, constant(1.0));
+        assert!(ok.validate().is_ok());
+        let cases = [
+            SimulationParams { blocks: 0, ..ok.clone() },
+            SimulationParams { blocks: MAX_SIM_BLOCKS + 1, ..ok.clone() },
+            SimulationParams { algorithm: RetargetAlgorithm::Lwma { window: 0 }, ..ok.clone() },
+            SimulationParams { algorithm: RetargetAlgorithm::Step { interval: 1, max_step: 0 }, ..ok.clone() },
+            SimulationParams { hashrate: vec![], ..ok.clone() },
+            SimulationParams { hashrate: vec![HashrateSegment { from_secs: 5, hashes_per_sec: 1.0 }], ..ok.clone() },
+            SimulationParams { hashrate: constant(0.0), ..ok.clone() },
+            SimulationParams { target_block_time_secs: 0, ..ok.clone() },
+        ];
+        for p in cases {
+            assert!(simulate(&p).is_err(), "{:?}", p);
+        }
+    }
+}
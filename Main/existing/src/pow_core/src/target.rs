@@ -0,0 +1,160 @@
+// target.rs - 256-bit PoW targets. A hash wins when, read as a big-endian
+// number, it is at most the target; a leading-zero difficulty d is the
+// target 2^(256 - d) - 1.
+
+pub type Target = [u8; 32];
+
+/// The target met by exactly the hashes with `difficulty` leading zero bits
+pub fn from_difficulty(difficulty: u32) -> Target {
+    let zeros = difficulty.min(256) as usize;
+    let mut t = [0xffu8; 32];
+    for (i, b) in t.iter_mut().enumerate() {
+        let bit = i * 8;
+        if zeros >= bit + 8 {
+            *b = 0;
+        } else if zeros > bit {
+            *b = 0xff >> (zeros - bit);
+        }
+    }
+    t
+}
+
+/// Leading zero bits every hash meeting `target` has; the inverse of
+/// from_difficulty for the targets it produces
+pub fn to_difficulty(target: &Target) -> u32 {
+    let mut zeros = 0;
+    for b in target {
+        zeros += b.leading_zeros();
+        if *b != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+/// Byte arrays compare lexicographically, which is big-endian order
+pub fn meets(hash: &[u8; 32], target: &Target) -> bool {
+    hash <= target
+}
+
+/// A target compiled once per chunk for the hot loop. A leading-zero target
+/// is its zero-byte prefix plus a mask over the next byte, held as native
+/// 64-bit words so a check is an AND per word that has mask bits - one for
+/// any difficulty up to 64, and only that one for almost every hash. Any
+/// other target keeps the full ordered compare.
+#[derive(Clone, Copy)]
+pub enum Check {
+    Prefix { mask: [u64; 4], words: usize },
+    Full(Target),
+}
+
+impl Check {
+    pub fn new(target: &Target) -> Self {
+        let difficulty = to_difficulty(target);
+        if from_difficulty(difficulty) != *target {
+            return Check::Full(*target);
+        }
+        // The bits a hash must have clear are exactly the ones the target lacks
+        let mut mask = [0u64; 4];
+        for (m, t) in mask.iter_mut().zip(target.chunks_exact(8)) {
+            *m = !u64::from_ne_bytes(t.try_into().unwrap());
+        }
+        Check::Prefix { mask, words: (difficulty as usize).div_ceil(64) }
+    }
+
+    #[inline]
+    pub fn meets(&self, hash: &[u8; 32]) -> bool {
+        match self {
+            Check::Prefix { mask, words } => hash
+            .chunks_exact(8)
+            .zip(&mask[..*words])
+            .all(|(h, m)| u64::from_ne_bytes(h.try_into().unwrap()) & m == 0),
+            Check::Full(target) => meets(hash, target),
+        }
+    }
+}
+
+/// A 32-byte big-endian target
+pub fn parse(bytes: &[u8]) -> Result<Target, String> {
+    bytes
+    .try_into()
+    .map_err(|_| format!("target must be 32 bytes, got {}", bytes.len()))
+}
+
+/// Average attempts per solution, 2^256 / (target + 1)
+pub fn expected_attempts(target: &Target) -> f64 {
+    // (target + 1) / 2^256, accumulated from the most significant byte
+    let mut share = 2f64.powi(-256);
+    let mut scale = 1.0;
+    for b in target {
+        scale /= 256.0;
+        share += *b as f64 * scale;
+    }
+    1.0 / share
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `target` plus or minus one, or `target` itself at the ends of the
+    /// range
+    fn step(target: &Target, up: bool) -> Target {
+        let mut t = *target;
+        for b in t.iter_mut().rev() {
+            let (v, wrapped) = if up { b.overflowing_add(1) } else { b.overflowing_sub(1) };
+            *b = v;
+            if !wrapped {
+                return t;
+            }
+        }
+        *target
+    }
+
+    #[test]
+    fn difficulty_round_trips() {
+        for d in 0..=256 {
+            assert_eq!(to_difficulty(&from_difficulty(d)), d);
+        }
+        assert_eq!(from_difficulty(300), [0; 32]);
+        assert_eq!(from_difficulty(0), [0xff; 32]);
+    }
+
+    #[test]
+    fn target_is_inclusive() {
+        for d in 0..=256 {
+            let target = from_difficulty(d);
+            assert!(meets(&target, &target), "difficulty {}", d);
+            assert!(meets(&step(&target, false), &target));
+            if target != [0xff; 32] {
+                assert!(!meets(&step(&target, true), &target), "difficulty {}", d);
+            }
+        }
+    }
+
+    #[test]
+    fn check_agrees_with_meets() {
+        let mut targets: Vec<Target> = (0..=256).map(from_difficulty).collect();
+        // Targets no difficulty produces go through the full compare
+        let mut odd = from_difficulty(20);
+        odd[10] = 0x7f;
+        targets.push(odd);
+        targets.push([0x12; 32]);
+
+        for target in &targets {
+            let check = Check::new(target);
+            let exact = from_difficulty(to_difficulty(target)) == *target;
+            assert_eq!(matches!(check, Check::Prefix { .. }), exact);
+            for hash in [*target, step(target, false), step(target, true), [0; 32], [0xff; 32]] {
+                assert_eq!(check.meets(&hash), meets(&hash, target), "{:?} vs {:?}", hash, target);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_requires_32_bytes() {
+        assert_eq!(parse(&[7; 32]), Ok([7; 32]));
+        assert!(parse(&[0; 31]).is_err());
+        assert!(parse(&[0; 33]).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// alert.rs - push CRITICAL cycle events to a webhook via HTTPS outcalls,
+// and to canisters subscribed to the cycle_alert topic
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse,
+    TransformArgs, TransformContext,
+};
+use ic_cdk::api::time;
+
+/// Cycles attached to each outcall; unused cycles are refunded
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 2_048;
+/// Subscribers get (canister, cycles, critical_watermark, level)
+pub const TOPIC: &str = "cycle_alert";
+
+/// `template` is the request body. `{canister}`, `{cycles}`,
+/// `{critical_watermark}` and `{level}` are substituted.
+///
+/// Every subnet replica sends the request, so the endpoint should tolerate
+/// duplicates (e.g. use `{canister}` in a PagerDuty dedup_key).
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub template: String,
+    pub content_type: String,
+    /// Don't alert about the same canister more often than this
+    pub min_interval_secs: u64,
+}
+
+thread_local! {
+    static WEBHOOK: RefCell<Option<WebhookConfig>> = const { RefCell::new(None) };
+    static LAST_ALERT: RefCell<BTreeMap<Principal, u64>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn set_webhook(config: Option<WebhookConfig>) {
+    WEBHOOK.with(|w| *w.borrow_mut() = config);
+}
+
+pub fn get_webhook() -> Option<WebhookConfig> {
+    WEBHOOK.with(|w| w.borrow().clone())
+}
+
+fn render(template: &str, canister: Principal, cycles: u128, critical_watermark: u128, level: &str) -> String {
+    template
+    .replace("{canister}", &canister.to_text())
+    .replace("{cycles}", &cycles.to_string())
+    .replace("{critical_watermark}", &critical_watermark.to_string())
+    .replace("{level}", level)
+}
+
+/// Notify subscribers and fire the webhook for a canister below its
+/// critical watermark, unless it was alerted about within the webhook's
+/// `min_interval_secs`
+pub fn critical(canister: Principal, cycles: u128, critical_watermark: u128) {
+    let webhook = get_webhook();
+    if webhook.is_none() && canister_notify::subscribers(Some(TOPIC)).is_empty() {
+        return;
+    }
+    let min_interval_secs = webhook.as_ref().map(|c| c.min_interval_secs).unwrap_or(0);
+
+    let now = time();
+    let due = LAST_ALERT.with(|l| {
+        let mut l = l.borrow_mut();
+        let last = l.get(&canister).copied().unwrap_or(0);
+        if last != 0 && now.saturating_sub(last) < min_interval_secs.saturating_mul(1_000_000_000) {
+            return false;
+        }
+        l.insert(canister, now);
+        true
+    });
+    if !due {
+        return;
+    }
+
+    canister_notify::publish(TOPIC, (canister, cycles, critical_watermark, "CRITICAL".to_string()));
+
+    let Some(config) = webhook else { return };
+    let body = render(&config.template, canister, cycles, critical_watermark, "CRITICAL");
+    ic_cdk::spawn(async move {
+        if let Err(e) = post(&config, body).await {
+            ic_cdk::println!("[REFUELER] webhook for {} failed: {}", canister, e);
+        }
+    });
+}
+
+/// Send a test message through the configured webhook
+pub async fn test(canister: Principal) -> Result<u32, String> {
+    let config = get_webhook().ok_or("no webhook configured")?;
+    let body = render(&config.template, canister, 0, 0, "TEST");
+    post(&config, body).await
+}
+
+async fn post(config: &WebhookConfig, body: String) -> Result<u32, String> {
+    let request = CanisterHttpRequestArgument {
+        url: config.url.clone(),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: config.content_type.clone(),
+        }],
+        body: Some(body.into_bytes()),
+        transform: Some(TransformContext::from_name("transform_webhook".to_string(), vec![])),
+    };
+
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+    .await
+    .map_err(|(code, msg)| format!("{:?} {}", code, msg))?;
+
+    let status: u32 = response.status.0.try_into().unwrap_or(0);
+    if !(200..300).contains(&status) {
+        return Err(format!("HTTP {}", status));
+    }
+    Ok(status)
+}
+
+/// Replicas only have to agree on the status code
+pub fn strip_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
@@ -0,0 +1,106 @@
+// forecast.rs - cycle balance history, burn rate and time-to-critical
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+use candid::{CandidType, Deserialize, Principal};
+
+use crate::Transfer;
+
+/// Samples older than this don't count towards the burn rate
+const WINDOW_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_SAMPLES: usize = 1_440;
+
+#[derive(Clone, Copy, CandidType, Deserialize)]
+pub struct Sample {
+    pub timestamp: u64,
+    pub cycles: u128,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Forecast {
+    pub canister: Principal,
+    pub cycles: u128,
+    /// Cycles burned per day over the sampled window, top-ups excluded
+    pub burn_per_day: u128,
+    /// None while the canister isn't burning anything
+    pub hours_to_critical: Option<u64>,
+    pub samples: u32,
+    pub window_secs: u64,
+}
+
+pub type History = BTreeMap<Principal, VecDeque<Sample>>;
+
+thread_local! {
+    static HISTORY: RefCell<History> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub fn record(canister: Principal, timestamp: u64, cycles: u128) {
+    HISTORY.with(|h| {
+        let mut h = h.borrow_mut();
+        let samples = h.entry(canister).or_default();
+        samples.push_back(Sample { timestamp, cycles });
+
+        let cutoff = timestamp.saturating_sub(WINDOW_NS);
+        while samples.len() > MAX_SAMPLES || samples.front().is_some_and(|s| s.timestamp < cutoff) {
+            samples.pop_front();
+        }
+    });
+}
+
+pub fn snapshot() -> History {
+    HISTORY.with(|h| h.borrow().clone())
+}
+
+pub fn restore(history: History) {
+    HISTORY.with(|h| *h.borrow_mut() = history);
+}
+
+pub fn forget(canister: Principal) {
+    HISTORY.with(|h| h.borrow_mut().remove(&canister));
+}
+
+pub fn history(canister: Principal) -> Vec<Sample> {
+    HISTORY.with(|h| {
+        h.borrow()
+        .get(&canister)
+        .map(|s| s.iter().copied().collect())
+        .unwrap_or_default()
+    })
+}
+
+/// Burned = balance drop between samples plus whatever we deposited in
+/// between, so top-ups don't read as negative burn
+pub fn forecast(canister: Principal, critical_watermark: u128, transfers: &[Transfer]) -> Option<Forecast> {
+    let samples = history(canister);
+    let (first, last) = (samples.first()?, samples.last()?);
+
+    let mut burned: u128 = 0;
+    for w in samples.windows(2) {
+        let deposited: u128 = transfers
+        .iter()
+        .filter(|t| t.canister == canister && t.timestamp > w[0].timestamp && t.timestamp <= w[1].timestamp)
+        .map(|t| t.amount)
+        .sum();
+        burned += (w[0].cycles + deposited).saturating_sub(w[1].cycles);
+    }
+
+    let elapsed = last.timestamp.saturating_sub(first.timestamp);
+    let burn_per_day = if elapsed == 0 { 0 } else { burned * WINDOW_NS as u128 / elapsed as u128 };
+
+    let hours_to_critical = if last.cycles <= critical_watermark {
+        Some(0)
+    } else {
+        ((last.cycles - critical_watermark) * 24)
+        .checked_div(burn_per_day)
+        .map(|h| h as u64)
+    };
+
+    Some(Forecast {
+        canister,
+        cycles: last.cycles,
+        burn_per_day,
+        hours_to_critical,
+        samples: samples.len() as u32,
+        window_secs: elapsed / 1_000_000_000,
+    })
+}
@@ -0,0 +1,285 @@
+// icp.rs - convert held ICP into cycles for the refueler itself through the
+// ledger and the Cycles Minting Canister (transfer + notify_top_up)
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::call;
+use ic_cdk::api::{canister_balance128, id, time};
+
+/// Memo the CMC expects on top-up transfers ("TPUP")
+const MEMO_TOP_UP_CANISTER: u64 = 0x5055_5054;
+const MAX_LOG_ENTRIES: usize = 1_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IcpReserveConfig {
+    pub ledger: Principal,
+    pub cmc: Principal,
+    /// Convert ICP when the refueler's own balance drops below this
+    pub reserve_low: u128,
+    /// ICP (e8s) converted per top-up, capped by the ICP held
+    pub topup_e8s: u64,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Conversion {
+    pub timestamp: u64,
+    pub e8s: u64,
+    pub block_index: Option<u64>,
+    /// Cycles minted, once the CMC has been notified
+    pub cycles: Option<u128>,
+    pub error: Option<String>,
+}
+
+/// A top-up transfer the CMC hasn't minted cycles for yet. Its notify is
+/// retried every sweep, and no new ICP is sent until it resolves.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Unnotified {
+    pub block_index: u64,
+    pub e8s: u64,
+    pub transferred_at: u64,
+    pub last_error: String,
+}
+
+// ------------------------------------------------------------
+// ICRC-1 ledger / CMC types (only what we use)
+// ------------------------------------------------------------
+
+#[derive(Clone, CandidType, Deserialize)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+struct NotifyTopUpArg {
+    block_index: u64,
+    canister_id: Principal,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum NotifyError {
+    Refunded { reason: String, block_index: Option<u64> },
+    Processing,
+    TransactionTooOld(u64),
+    InvalidTransaction(String),
+    Other { error_code: u64, error_message: String },
+}
+
+thread_local! {
+    static CONFIG: RefCell<Option<IcpReserveConfig>> = const { RefCell::new(None) };
+    static LOG: RefCell<Vec<Conversion>> = const { RefCell::new(Vec::new()) };
+    static UNNOTIFIED: RefCell<Option<Unnotified>> = const { RefCell::new(None) };
+}
+
+pub fn set_config(config: Option<IcpReserveConfig>) {
+    CONFIG.with(|c| *c.borrow_mut() = config);
+}
+
+pub fn get_config() -> Option<IcpReserveConfig> {
+    CONFIG.with(|c| c.borrow().clone())
+}
+
+pub fn log() -> Vec<Conversion> {
+    LOG.with(|l| l.borrow().clone())
+}
+
+pub fn restore_log(log: Vec<Conversion>) {
+    LOG.with(|l| *l.borrow_mut() = log);
+}
+
+pub fn unnotified() -> Option<Unnotified> {
+    UNNOTIFIED.with(|u| u.borrow().clone())
+}
+
+pub fn restore_unnotified(pending: Option<Unnotified>) {
+    UNNOTIFIED.with(|u| *u.borrow_mut() = pending);
+}
+
+fn push_log(entry: Conversion) {
+    LOG.with(|l| {
+        let mut l = l.borrow_mut();
+        if l.len() >= MAX_LOG_ENTRIES {
+            l.remove(0);
+        }
+        l.push(entry);
+    });
+}
+
+/// The CMC credits a top-up to the canister encoded in the subaccount
+fn principal_to_subaccount(p: &Principal) -> Vec<u8> {
+    let bytes = p.as_slice();
+    let mut sub = vec![0u8; 32];
+    sub[0] = bytes.len() as u8;
+    sub[1..1 + bytes.len()].copy_from_slice(bytes);
+    sub
+}
+
+/// Top the refueler up from ICP if its own balance is below the reserve.
+/// Called at the start of every sweep, before the fleet is refuelled. A
+/// transfer the CMC wasn't notified of is retried first, and while it's
+/// unresolved no new ICP is sent.
+pub async fn replenish_if_low() {
+    let Some(config) = get_config() else { return };
+    if let Some(pending) = unnotified() {
+        if !retry_notify(&config, pending).await {
+            return;
+        }
+    }
+    if canister_balance128() >= config.reserve_low {
+        return;
+    }
+
+    let entry = match convert(&config).await {
+        Ok(entry) => entry,
+        Err(entry) => entry,
+    };
+    match &entry.error {
+        None => ic_cdk::println!(
+            "[REFUELER] converted {} e8s into {} cycles",
+            entry.e8s,
+            entry.cycles.unwrap_or(0)
+        ),
+        Some(e) => ic_cdk::println!("[REFUELER] ICP conversion failed: {}", e),
+    }
+    push_log(entry);
+}
+
+/// Notify the CMC of an earlier transfer again; true once it's resolved,
+/// minted or not
+async fn retry_notify(config: &IcpReserveConfig, pending: Unnotified) -> bool {
+    let mut entry = Conversion {
+        timestamp: time(),
+        e8s: pending.e8s,
+        block_index: Some(pending.block_index),
+        cycles: None,
+        error: None,
+    };
+    match notify(config, pending.block_index).await {
+        Ok(cycles) => {
+            ic_cdk::println!("[REFUELER] block {} minted {} cycles on retry", pending.block_index, cycles);
+            entry.cycles = Some(cycles);
+        }
+        Err((e, retry)) if retry => {
+            ic_cdk::println!("[REFUELER] notify_top_up for block {} still failing: {}", pending.block_index, e);
+            UNNOTIFIED.with(|u| *u.borrow_mut() = Some(Unnotified { last_error: e, ..pending }));
+            return false;
+        }
+        // Refunded, too old or invalid: retrying can't mint it
+        Err((e, _)) => entry.error = Some(e),
+    }
+    UNNOTIFIED.with(|u| *u.borrow_mut() = None);
+    push_log(entry);
+    true
+}
+
+/// Cycles minted for `block_index`, or the error and whether a later retry
+/// may still mint them
+async fn notify(config: &IcpReserveConfig, block_index: u64) -> Result<u128, (String, bool)> {
+    let arg = NotifyTopUpArg { block_index, canister_id: id() };
+    match call::<(NotifyTopUpArg,), (Result<Nat, NotifyError>,)>(config.cmc, "notify_top_up", (arg,)).await {
+        Ok((Ok(cycles),)) => Ok(cycles.0.try_into().unwrap_or(u128::MAX)),
+        Ok((Err(e @ (NotifyError::Processing | NotifyError::Other { .. })),)) => {
+            Err((format!("notify_top_up: {:?}", e), true))
+        }
+        Ok((Err(e),)) => Err((format!("notify_top_up: {:?}", e), false)),
+        Err((code, msg)) => Err((format!("notify_top_up: {:?} {}", code, msg), true)),
+    }
+}
+
+async fn convert(config: &IcpReserveConfig) -> Result<Conversion, Conversion> {
+    let me = id();
+    let mut entry = Conversion {
+        timestamp: time(),
+        e8s: 0,
+        block_index: None,
+        cycles: None,
+        error: None,
+    };
+    let fail = |mut entry: Conversion, e: String| {
+        entry.error = Some(e);
+        Err(entry)
+    };
+
+    let own = Account { owner: me, subaccount: None };
+    let balance: u64 = match call::<(Account,), (Nat,)>(config.ledger, "icrc1_balance_of", (own,)).await {
+        Ok((b,)) => b.0.try_into().unwrap_or(u64::MAX),
+        Err((code, msg)) => return fail(entry, format!("icrc1_balance_of: {:?} {}", code, msg)),
+    };
+    let fee: u64 = match call::<(), (Nat,)>(config.ledger, "icrc1_fee", ()).await {
+        Ok((f,)) => f.0.try_into().unwrap_or(u64::MAX),
+        Err((code, msg)) => return fail(entry, format!("icrc1_fee: {:?} {}", code, msg)),
+    };
+
+    entry.e8s = config.topup_e8s.min(balance.saturating_sub(fee));
+    if entry.e8s == 0 {
+        return fail(entry, format!("not enough ICP (balance {} e8s)", balance));
+    }
+
+    let transfer = TransferArg {
+        from_subaccount: None,
+        to: Account {
+            owner: config.cmc,
+            subaccount: Some(principal_to_subaccount(&me)),
+        },
+        amount: Nat::from(entry.e8s),
+        fee: Some(Nat::from(fee)),
+        memo: Some(MEMO_TOP_UP_CANISTER.to_le_bytes().to_vec()),
+        created_at_time: Some(entry.timestamp),
+    };
+    let block_index: u64 = match call::<(TransferArg,), (Result<Nat, TransferError>,)>(
+        config.ledger,
+        "icrc1_transfer",
+        (transfer,),
+    )
+    .await
+    {
+        Ok((Ok(idx),)) => idx.0.try_into().unwrap_or(u64::MAX),
+        Ok((Err(e),)) => return fail(entry, format!("icrc1_transfer: {:?}", e)),
+        Err((code, msg)) => return fail(entry, format!("icrc1_transfer: {:?} {}", code, msg)),
+    };
+    entry.block_index = Some(block_index);
+
+    match notify(config, block_index).await {
+        Ok(cycles) => {
+            entry.cycles = Some(cycles);
+            Ok(entry)
+        }
+        // The ICP has moved; keep the block so the next sweep notifies the
+        // CMC again instead of sending more
+        Err((e, retry)) => {
+            if retry {
+                UNNOTIFIED.with(|u| {
+                    *u.borrow_mut() = Some(Unnotified {
+                        block_index,
+                        e8s: entry.e8s,
+                        transferred_at: entry.timestamp,
+                        last_error: e.clone(),
+                    })
+                });
+            }
+            fail(entry, e)
+        }
+    }
+}
@@ -1,12 +1,34 @@
 use candid::{CandidType, Deserialize};
-use ic_cdk::{update, query};
+use ic_cdk::{init, post_upgrade, pre_upgrade, update, query};
+use ic_cdk::api::call::RejectionCode;
 use ic_cdk::api::time;
 use ic_cdk::api::management_canister::main::{
-    canister_status, CanisterIdRecord, CanisterStatusResponse,
+    canister_status, deposit_cycles, start_canister, CanisterIdRecord, CanisterStatusResponse,
+    CanisterStatusType,
 };
 use candid::Principal;
+use canister_auth::audit::AuditEntry;
+use canister_notify::{DeadLetter, RetryPolicy, Subscriber};
+use canister_state::{Migration, StateError};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
+use futures::future::join_all;
+
+mod alert;
+mod forecast;
+mod icp;
+mod refuel_log;
+
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+
+use crate::alert::WebhookConfig;
+use crate::forecast::{Forecast, History, Sample};
+use crate::icp::{Conversion, IcpReserveConfig, Unnotified};
+use crate::refuel_log::RefuelEvent;
 
 // ------------------------------------------------------------
 // Configuration
@@ -15,6 +37,22 @@ use std::cell::RefCell;
 const DEFAULT_LOW_WATERMARK: u128 = 2_000_000_000_000; // 2T cycles
 const DEFAULT_CRITICAL_WATERMARK: u128 = 500_000_000_000; // 0.5T
 
+const DEFAULT_TOPUP_AMOUNT: u128 = 1_000_000_000_000; // 1T
+const DEFAULT_PER_CANISTER_DAILY_CAP: u128 = 5_000_000_000_000; // 5T
+const DEFAULT_GLOBAL_DAILY_CAP: u128 = 20_000_000_000_000; // 20T
+
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 60;
+const MIN_CHECK_INTERVAL_SECS: u64 = 5;
+const MAX_CONCURRENT_CHECKS: usize = 10;
+
+/// Warn once memory use passes this share of the memory allocation
+const MEMORY_ALERT_PERCENT: u64 = 90;
+/// Consecutive sweeps a canister must be reported missing before it is
+/// unwatched
+const MISSING_SWEEPS_TO_UNWATCH: u32 = 3;
+
 // ------------------------------------------------------------
 // Public state
 // ------------------------------------------------------------
@@ -24,6 +62,26 @@ pub struct WatchedCanister {
     pub canister: Principal,
     pub low_watermark: u128,
     pub critical_watermark: u128,
+    pub policy: RefuelPolicy,
+    /// Set for canisters registered by `watch_all_controlled_by`
+    pub discovered_via: Option<Principal>,
+}
+
+/// Per-canister overrides of the global `RefuelConfig`
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct RefuelPolicy {
+    /// Cycles per top-up (default: config.topup_amount)
+    pub topup_amount: Option<u128>,
+    /// Top-ups allowed in a rolling 24h (default: unlimited)
+    pub max_topups_per_day: Option<u32>,
+    /// Cycles this canister may receive in a rolling 24h
+    /// (default: config.per_canister_daily_cap)
+    pub hard_cap: Option<u128>,
+    /// Only warn when low, never deposit
+    pub alert_only: bool,
+    /// start_canister it when a sweep finds it Stopped (the refueler must
+    /// be a controller)
+    pub auto_start: bool,
 }
 
 #[derive(Clone, CandidType, Deserialize)]
@@ -35,6 +93,42 @@ pub struct CanisterHealth {
     pub is_low: bool,
     pub is_critical: bool,
     pub last_checked: u64,
+    pub status: Option<CanisterStatusType>,
+    pub memory_size: u64,
+    /// None for best-effort canisters, which have no fixed allocation
+    pub memory_allocation: Option<u64>,
+    /// memory_size is at least MEMORY_ALERT_PERCENT of the allocation
+    pub memory_high: bool,
+    /// A Stopped canister was started by this sweep
+    pub restarted: bool,
+    /// Set when the status call failed; the other fields are then unknown
+    pub error: Option<String>,
+}
+
+/// How much a low canister gets, and how much may go out per rolling 24h
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RefuelConfig {
+    pub topup_amount: u128,
+    pub per_canister_daily_cap: u128,
+    pub global_daily_cap: u128,
+}
+
+impl Default for RefuelConfig {
+    fn default() -> Self {
+        Self {
+            topup_amount: DEFAULT_TOPUP_AMOUNT,
+            per_canister_daily_cap: DEFAULT_PER_CANISTER_DAILY_CAP,
+            global_daily_cap: DEFAULT_GLOBAL_DAILY_CAP,
+        }
+    }
+}
+
+/// A completed deposit_cycles call
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Transfer {
+    pub canister: Principal,
+    pub amount: u128,
+    pub timestamp: u64,
 }
 
 #[derive(Clone, CandidType, Deserialize)]
@@ -43,6 +137,37 @@ pub struct RefuelerState {
     pub watched: Vec<WatchedCanister>,
     pub last_report: Vec<CanisterHealth>,
     pub last_tick: u64,
+    pub config: RefuelConfig,
+    /// Transfers from the last 24h, used for the daily caps
+    pub transfers: Vec<Transfer>,
+}
+
+impl RefuelerState {
+    /// Cycles the next top-up of `canister` may carry under its policy and
+    /// the global cap; 0 if it has used up its top-ups for the day
+    fn allowance(&self, canister: Principal, policy: &RefuelPolicy, now: u64) -> u128 {
+        let since = now.saturating_sub(DAY_NS);
+        let recent = self.transfers.iter().filter(|t| t.timestamp > since);
+
+        let (count, mine, total) = recent.fold((0u32, 0u128, 0u128), |(count, mine, total), t| {
+            if t.canister == canister {
+                (count + 1, mine + t.amount, total + t.amount)
+            } else {
+                (count, mine, total + t.amount)
+            }
+        });
+
+        if policy.max_topups_per_day.is_some_and(|max| count >= max) {
+            return 0;
+        }
+
+        let cap = policy.hard_cap.unwrap_or(self.config.per_canister_daily_cap);
+        policy
+        .topup_amount
+        .unwrap_or(self.config.topup_amount)
+        .min(cap.saturating_sub(mine))
+        .min(self.config.global_daily_cap.saturating_sub(total))
+    }
 }
 
 thread_local! {
@@ -52,26 +177,238 @@ thread_local! {
             watched: Vec::new(),
                                                         last_report: Vec::new(),
                                                         last_tick: 0,
+                                                        config: RefuelConfig::default(),
+                                                        transfers: Vec::new(),
         }
     );
+
+    static CHECK_INTERVAL_SECS: Cell<u64> = const { Cell::new(DEFAULT_CHECK_INTERVAL_SECS) };
+    static CHECK_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    // A sweep can outlast the interval; never run two at once
+    static SWEEPING: Cell<bool> = const { Cell::new(false) };
+    /// Consecutive sweeps each canister came back DestinationInvalid
+    static MISSING: RefCell<HashMap<Principal, u32>> = RefCell::new(HashMap::new());
 }
 
 // ------------------------------------------------------------
-// Control API
+// Init / upgrades - configuration, admins, reports and history survive
+// ------------------------------------------------------------
+
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (
+    RefuelerState,
+    u64,
+    Option<WebhookConfig>,
+    History,
+    (Option<Principal>, Vec<Principal>),
+    (Option<IcpReserveConfig>, Vec<Conversion>),
+    Vec<RefuelEvent>,
+    Option<Vec<AuditEntry>>,
+    Option<canister_notify::Snapshot>,
+    Option<Unnotified>,
+);
+
+/// The installing principal becomes the owner; `admins` are added alongside
+#[init]
+fn init(admins: Option<Vec<Principal>>) {
+    canister_auth::init(ic_cdk::caller(), admins.unwrap_or_default());
+}
+
+fn saved_state() -> Saved {
+    (
+        STATE.with(|s| s.borrow().clone()),
+        CHECK_INTERVAL_SECS.with(|i| i.get()),
+        alert::get_webhook(),
+        forecast::snapshot(),
+        canister_auth::snapshot(),
+        (icp::get_config(), icp::log()),
+        refuel_log::snapshot(),
+        Some(canister_auth::audit::snapshot()),
+        Some(canister_notify::snapshot()),
+        icp::unnotified(),
+    )
+}
+
+fn restore_state(saved: Saved) {
+    let (state, interval, webhook, history, (owner, admins), (icp_config, icp_log), refuels, audit, notify, unnotified) =
+        saved;
+    STATE.with(|s| *s.borrow_mut() = state);
+    CHECK_INTERVAL_SECS.with(|i| i.set(interval));
+    alert::set_webhook(webhook);
+    forecast::restore(history);
+    canister_auth::restore(owner, admins);
+    icp::set_config(icp_config);
+    icp::restore_log(icp_log);
+    icp::restore_unnotified(unnotified);
+    refuel_log::restore(refuels);
+    canister_auth::audit::restore(audit.unwrap_or_default());
+    canister_notify::restore(notify.unwrap_or_default());
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved_state()) {
+        ic_cdk::trap(&format!("failed to save refueler state: {}", e));
+    }
+}
+
+/// Upgrade args add admins; they never replace the saved owner
+#[post_upgrade]
+fn post_upgrade(admins: Option<Vec<Principal>>) {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => Ok(saved),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<(Saved,)>().map(|(saved,)| saved),
+        Err(e) => ic_cdk::trap(&format!("failed to restore refueler state: {}", e)),
+    };
+
+    match restored {
+        Ok(saved) => {
+            restore_state(saved);
+            admins.unwrap_or_default().into_iter().for_each(canister_auth::add_admin);
+        }
+        Err(e) => {
+            ic_cdk::println!("[REFUELER] no saved state restored: {}", e);
+            init(admins);
+        }
+    }
+
+    // Timers don't survive upgrades
+    if STATE.with(|s| s.borrow().running) {
+        arm_check_timer();
+    }
+}
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+/// Hand the canister to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// `(page, total_len)` of a versioned snapshot of all refueler state; offset
+/// 0 takes the snapshot, then page until offset reaches total_len (admin only)
+#[update]
+pub fn export_state(offset: u64, limit: u64) -> (Vec<u8>, u64) {
+    canister_auth::require_admin();
+    if offset == 0 {
+        canister_auth::audit::record("export_state", String::new());
+    }
+    canister_state::transfer::export_page(SCHEMA_VERSION, saved_state, offset, limit)
+}
+
+/// Replace all state with an export_state snapshot, sent in order with
+/// `last` set on the final chunk. The caller stays an admin (admin only).
+#[update]
+pub fn import_state(chunk: Vec<u8>, last: bool) -> Result<(), String> {
+    canister_auth::require_admin();
+    let admin = ic_cdk::caller();
+    let imported = canister_state::transfer::import_chunk::<Saved>(chunk, last, SCHEMA_VERSION, MIGRATIONS)
+    .map_err(|e| e.to_string())?;
+    if let Some(saved) = imported {
+        restore_state(saved);
+        canister_auth::add_admin(admin);
+        canister_auth::audit::record("import_state", String::new());
+        if STATE.with(|s| s.borrow().running) {
+            arm_check_timer();
+        } else {
+            disarm_check_timer();
+        }
+    }
+    Ok(())
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+/// Alias of `list_admins` kept for existing callers
+#[query]
+pub fn get_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+// ------------------------------------------------------------
+// Control API (admin only)
 // ------------------------------------------------------------
 
 #[update]
 pub fn start_refueler() {
+    canister_auth::require_admin();
+    canister_auth::audit::record("start_refueler", String::new());
+
     STATE.with(|s| {
         s.borrow_mut().running = true;
     });
+    arm_check_timer();
 }
 
 #[update]
 pub fn stop_refueler() {
+    canister_auth::require_admin();
+    canister_auth::audit::record("stop_refueler", String::new());
+
     STATE.with(|s| {
         s.borrow_mut().running = false;
     });
+    disarm_check_timer();
+}
+
+/// Seconds between status sweeps (min 5); takes effect immediately
+#[update]
+pub fn set_check_interval(seconds: u64) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_check_interval", format!("{:?}", seconds));
+
+    if seconds < MIN_CHECK_INTERVAL_SECS {
+        ic_cdk::trap(&format!("check interval must be at least {}s", MIN_CHECK_INTERVAL_SECS));
+    }
+
+    CHECK_INTERVAL_SECS.with(|i| i.set(seconds));
+
+    if CHECK_TIMER.with(|t| t.get()).is_some() {
+        arm_check_timer();
+    }
+}
+
+#[query]
+pub fn get_check_interval() -> u64 {
+    CHECK_INTERVAL_SECS.with(|i| i.get())
 }
 
 #[update]
@@ -80,6 +417,9 @@ pub fn watch_canister(
     low_watermark: Option<u128>,
     critical_watermark: Option<u128>,
 ) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("watch_canister", format!("{:?}", (&canister, &low_watermark, &critical_watermark)));
+
     let low = low_watermark.unwrap_or(DEFAULT_LOW_WATERMARK);
     let critical = critical_watermark.unwrap_or(DEFAULT_CRITICAL_WATERMARK);
 
@@ -94,22 +434,230 @@ pub fn watch_canister(
             canister,
             low_watermark: low,
             critical_watermark: critical,
+            policy: RefuelPolicy::default(),
+            discovered_via: None,
         });
     });
 }
 
+/// Sync the canisters controlled by `controller` from a list the caller keeps
+/// (e.g. the coordinator's provisioned miners). Those whose status shows
+/// `controller` among their controllers are watched with default watermarks;
+/// ones discovered by an earlier sync but missing now are unwatched.
+/// Returns the newly watched canisters.
+#[update]
+pub async fn watch_all_controlled_by(controller: Principal, canisters: Vec<Principal>) -> Vec<Principal> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("watch_all_controlled_by", format!("{:?}", (&controller, &canisters)));
+
+    let mut controlled = Vec::new();
+    for canister in canisters {
+        match canister_status(CanisterIdRecord { canister_id: canister }).await {
+            Ok((status,)) if status.settings.controllers.contains(&controller) => controlled.push(canister),
+            Ok(_) => {}
+            Err((code, msg)) => ic_cdk::println!(
+                "[REFUELER] skipping {} : status failed {:?} {}",
+                canister, code, msg
+            ),
+        }
+    }
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+
+        st.watched.retain(|w| w.discovered_via != Some(controller) || controlled.contains(&w.canister));
+
+        let mut added = Vec::new();
+        for canister in controlled {
+            if st.watched.iter().any(|w| w.canister == canister) {
+                continue;
+            }
+            st.watched.push(WatchedCanister {
+                canister,
+                low_watermark: DEFAULT_LOW_WATERMARK,
+                critical_watermark: DEFAULT_CRITICAL_WATERMARK,
+                policy: RefuelPolicy::default(),
+                discovered_via: Some(controller),
+            });
+            added.push(canister);
+        }
+        added
+    })
+}
+
+/// Returns false if the canister isn't watched
+#[update]
+pub fn set_policy(canister: Principal, policy: RefuelPolicy) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_policy", format!("{:?}", (&canister, &policy)));
+
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        match st.watched.iter_mut().find(|w| w.canister == canister) {
+            Some(w) => {
+                w.policy = policy;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
 #[update]
 pub fn unwatch_canister(canister: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("unwatch_canister", format!("{:?}", canister));
+    unwatch(canister);
+}
+
+fn unwatch(canister: Principal) {
     STATE.with(|s| {
         let mut st = s.borrow_mut();
         st.watched.retain(|w| w.canister != canister);
     });
+    forecast::forget(canister);
+}
+
+#[update]
+pub fn set_refuel_config(config: RefuelConfig) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_refuel_config", format!("{:?}", config));
+
+    STATE.with(|s| {
+        s.borrow_mut().config = config;
+    });
+}
+
+/// Where CRITICAL events are POSTed; None turns webhook alerts off
+#[update]
+pub fn set_webhook(config: Option<WebhookConfig>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_webhook", format!("{:?}", config));
+
+    if let Some(c) = &config {
+        if !c.url.starts_with("https://") {
+            ic_cdk::trap("webhook url must be https");
+        }
+    }
+    alert::set_webhook(config);
+}
+
+#[query]
+pub fn get_webhook() -> Option<WebhookConfig> {
+    alert::get_webhook()
+}
+
+/// Convert held ICP into cycles for the refueler when its own balance drops
+/// below `reserve_low`; None turns conversion off
+#[update]
+pub fn set_icp_reserve(config: Option<IcpReserveConfig>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_icp_reserve", format!("{:?}", config));
+
+    icp::set_config(config);
+}
+
+#[query]
+pub fn get_icp_reserve() -> Option<IcpReserveConfig> {
+    icp::get_config()
+}
+
+#[query]
+pub fn get_icp_conversions() -> Vec<Conversion> {
+    icp::log()
+}
+
+/// The top-up transfer whose notify_top_up is still being retried, if any
+#[query]
+pub fn get_unnotified_top_up() -> Option<Unnotified> {
+    icp::unnotified()
+}
+
+/// POST a TEST event for `canister`; returns the HTTP status
+#[update]
+pub async fn test_webhook(canister: Principal) -> Result<u32, String> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("test_webhook", format!("{:?}", canister));
+
+    alert::test(canister).await
+}
+
+#[query]
+fn transform_webhook(args: TransformArgs) -> HttpResponse {
+    alert::strip_response(args)
+}
+
+/// Have `canister.method` called with (canister, cycles, critical_watermark,
+/// level) on every CRITICAL event, rate-limited like the webhook. A canister
+/// may subscribe itself; anyone else must be an admin.
+#[update]
+pub fn subscribe_alerts(canister: Principal, method: String) -> bool {
+    if ic_cdk::caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("subscribe_alerts", format!("{:?}", (&canister, &method)));
+    }
+
+    canister_notify::subscribe(alert::TOPIC, canister, method)
+}
+
+#[update]
+pub fn unsubscribe_alerts(canister: Principal) -> bool {
+    if ic_cdk::caller() != canister {
+        canister_auth::require_admin();
+        canister_auth::audit::record("unsubscribe_alerts", format!("{:?}", canister));
+    }
+
+    canister_notify::unsubscribe(Some(alert::TOPIC), canister)
+}
+
+#[query]
+pub fn get_alert_subscribers() -> Vec<Subscriber> {
+    canister_notify::subscribers(Some(alert::TOPIC))
+}
+
+/// Retries with backoff for alerts subscribers fail to take
+#[update]
+pub fn set_notify_policy(policy: RetryPolicy) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_notify_policy", format!("{:?}", policy));
+    if policy.max_attempts == 0 {
+        ic_cdk::trap("max_attempts must be at least 1");
+    }
+
+    canister_notify::set_policy(policy);
+}
+
+/// Resend every dead-lettered alert; returns how many
+#[update]
+pub fn retry_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_dead_letters", String::new());
+    canister_notify::retry_dead_letters()
+}
+
+/// Returns how many were dropped
+#[update]
+pub fn clear_dead_letters() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("clear_dead_letters", String::new());
+    canister_notify::clear_dead_letters()
 }
 
 // ------------------------------------------------------------
 // Read-only API
 // ------------------------------------------------------------
 
+#[query]
+pub fn get_notify_policy() -> RetryPolicy {
+    canister_notify::policy()
+}
+
+/// Alerts that ran out of retries, oldest first
+#[query]
+pub fn get_dead_letters() -> Vec<DeadLetter> {
+    canister_notify::dead_letters()
+}
+
 #[query]
 pub fn get_refueler_state() -> RefuelerState {
     STATE.with(|s| s.borrow().clone())
@@ -120,20 +668,60 @@ pub fn last_report() -> Vec<CanisterHealth> {
     STATE.with(|s| s.borrow().last_report.clone())
 }
 
+/// Burn rate over the last 24h of sweeps and hours until the critical
+/// watermark at that rate; None if the canister isn't watched or sampled yet
+#[query]
+pub fn get_forecast(canister: Principal) -> Option<Forecast> {
+    STATE.with(|s| {
+        let st = s.borrow();
+        let entry = st.watched.iter().find(|w| w.canister == canister)?;
+        forecast::forecast(canister, entry.critical_watermark, &st.transfers)
+    })
+}
+
+#[query]
+pub fn get_balance_history(canister: Principal) -> Vec<Sample> {
+    forecast::history(canister)
+}
+
+/// The latest 5,000 top-up attempts, oldest first. A canister held back by
+/// its daily cap logs that once until its next attempt.
+#[query]
+pub fn get_refuel_history(offset: u64, limit: u64) -> Vec<RefuelEvent> {
+    refuel_log::page(offset, limit)
+}
+
+#[query]
+pub fn get_refuel_history_len() -> u64 {
+    refuel_log::len()
+}
+
 // ------------------------------------------------------------
-// Heartbeat
+// Check timer - only armed while the refueler is running
 // ------------------------------------------------------------
 
-#[ic_cdk::heartbeat]
-fn heartbeat() {
-    let should_run = STATE.with(|s| s.borrow().running);
+fn arm_check_timer() {
+    disarm_check_timer();
 
-    if !should_run {
+    let interval = Duration::from_secs(CHECK_INTERVAL_SECS.with(|i| i.get()));
+    let id = set_timer_interval(interval, check_tick);
+    CHECK_TIMER.with(|t| t.set(Some(id)));
+}
+
+fn disarm_check_timer() {
+    if let Some(id) = CHECK_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+}
+
+fn check_tick() {
+    if SWEEPING.with(|s| s.replace(true)) {
         return;
     }
 
     ic_cdk::spawn(async {
         run_once().await;
+        SWEEPING.with(|s| s.set(false));
     });
 }
 
@@ -148,61 +736,251 @@ async fn run_once() {
         return;
     }
 
-    let mut report = Vec::new();
+    // Refill our own reserve first so the fleet top-ups below can be paid
+    icp::replenish_if_low().await;
 
-    for entry in watched.iter() {
-        let rec = CanisterIdRecord {
-            canister_id: entry.canister,
-        };
+    let mut report = Vec::with_capacity(watched.len());
 
-        let status: Result<(CanisterStatusResponse,), _> =
-        canister_status(rec).await;
+    // At most MAX_CONCURRENT_CHECKS status calls in flight at once
+    for batch in watched.chunks(MAX_CONCURRENT_CHECKS) {
+        report.extend(join_all(batch.iter().map(check_canister)).await.into_iter().flatten());
+    }
 
-        match status {
-            Ok((st,)) => {
-                let cycles = st.cycles;
+    STATE.with(|s| {
+        let mut st = s.borrow_mut();
+        st.last_report = report;
+        st.last_tick = time();
+    });
+}
 
-                let is_critical = cycles < entry.critical_watermark;
-                let is_low = cycles < entry.low_watermark;
+/// Status, alert and top-up for one canister. None if it has been missing
+/// (DestinationInvalid) for MISSING_SWEEPS_TO_UNWATCH sweeps in a row and
+/// was unwatched.
+async fn check_canister(entry: &WatchedCanister) -> Option<CanisterHealth> {
+    let rec = CanisterIdRecord {
+        canister_id: entry.canister,
+    };
 
-                if is_critical {
-                    ic_cdk::println!(
-                        "[REFUELER] CRITICAL cycles for {} : {}",
-                        entry.canister,
-                        cycles
-                    );
-                } else if is_low {
-                    ic_cdk::println!(
-                        "[REFUELER] LOW cycles for {} : {}",
-                        entry.canister,
-                        cycles
-                    );
-                }
+    let status: Result<(CanisterStatusResponse,), _> =
+    canister_status(rec).await;
+
+    if !matches!(&status, Err((RejectionCode::DestinationInvalid, _))) {
+        MISSING.with(|m| m.borrow_mut().remove(&entry.canister));
+    }
 
-                report.push(CanisterHealth {
-                    canister: entry.canister,
-                    cycles: cycles.0.clone().try_into().unwrap_or(0u128),
-                    low_watermark: entry.low_watermark,
-                    critical_watermark: entry.critical_watermark,
-                    is_low,
-                    is_critical,
-                    last_checked: time(),
-                });
+    match status {
+        Ok((st,)) => {
+            let cycles = st.cycles;
+            // Taken before any top-up so deposits land after the sample
+            let checked_at = time();
+            let balance: u128 = cycles.0.clone().try_into().unwrap_or(0u128);
+            forecast::record(entry.canister, checked_at, balance);
+
+            let is_critical = cycles < entry.critical_watermark;
+            let is_low = cycles < entry.low_watermark;
+
+            let memory_size: u64 = st.memory_size.0.try_into().unwrap_or(u64::MAX);
+            let memory_allocation: Option<u64> = st
+            .settings
+            .memory_allocation
+            .0
+            .try_into()
+            .ok()
+            .filter(|a| *a > 0);
+            let memory_high = memory_allocation
+            .is_some_and(|a| memory_size as u128 * 100 >= a as u128 * MEMORY_ALERT_PERCENT as u128);
+            if memory_high {
+                ic_cdk::println!(
+                    "[REFUELER] HIGH memory for {} : {} of {} bytes",
+                    entry.canister,
+                    memory_size,
+                    memory_allocation.unwrap_or(0)
+                );
             }
 
-            Err(e) => {
+            let mut restarted = false;
+            if st.status == CanisterStatusType::Stopped {
+                ic_cdk::println!("[REFUELER] {} is stopped", entry.canister);
+                if entry.policy.auto_start {
+                    match start_canister(CanisterIdRecord { canister_id: entry.canister }).await {
+                        Ok(()) => {
+                            ic_cdk::println!("[REFUELER] started {}", entry.canister);
+                            restarted = true;
+                        }
+                        Err((code, msg)) => ic_cdk::println!(
+                            "[REFUELER] failed to start {} : {:?} {}",
+                            entry.canister,
+                            code,
+                            msg
+                        ),
+                    }
+                }
+            }
+
+            if is_critical {
+                ic_cdk::println!(
+                    "[REFUELER] CRITICAL cycles for {} : {}",
+                    entry.canister,
+                    cycles
+                );
+                alert::critical(entry.canister, balance, entry.critical_watermark);
+            } else if is_low {
                 ic_cdk::println!(
-                    "[REFUELER] failed to query status for {} : {:?}",
+                    "[REFUELER] LOW cycles for {} : {}",
                     entry.canister,
-                    e
+                    cycles
                 );
             }
+
+            if is_low && !entry.policy.alert_only {
+                top_up(entry.canister, &entry.policy, balance).await;
+            }
+
+            Some(CanisterHealth {
+                canister: entry.canister,
+                cycles: balance,
+                low_watermark: entry.low_watermark,
+                critical_watermark: entry.critical_watermark,
+                is_low,
+                is_critical,
+                last_checked: checked_at,
+                status: Some(st.status),
+                memory_size,
+                memory_allocation,
+                memory_high,
+                restarted,
+                error: None,
+            })
+        }
+
+        Err((RejectionCode::DestinationInvalid, msg)) if missing_again(entry.canister) => {
+            ic_cdk::println!("[REFUELER] {} no longer exists, unwatching", entry.canister);
+            unwatch(entry.canister);
+            refuel_log::record(RefuelEvent {
+                canister: entry.canister,
+                amount: 0,
+                balance_before: 0,
+                balance_after: 0,
+                success: false,
+                error: Some(format!(
+                    "unwatched after {} sweeps found it missing: {}",
+                    MISSING_SWEEPS_TO_UNWATCH, msg
+                )),
+                timestamp: time(),
+            });
+            None
+        }
+
+        Err((code, msg)) => {
+            ic_cdk::println!(
+                "[REFUELER] failed to query status for {} : {:?} {}",
+                entry.canister,
+                code,
+                msg
+            );
+
+            Some(CanisterHealth {
+                canister: entry.canister,
+                cycles: 0,
+                low_watermark: entry.low_watermark,
+                critical_watermark: entry.critical_watermark,
+                is_low: false,
+                is_critical: false,
+                last_checked: time(),
+                status: None,
+                memory_size: 0,
+                memory_allocation: None,
+                memory_high: false,
+                restarted: false,
+                error: Some(format!("{:?}: {}", code, msg)),
+            })
         }
     }
+}
 
-    STATE.with(|s| {
+/// Count one more sweep that found `canister` missing; true once that has
+/// happened MISSING_SWEEPS_TO_UNWATCH times in a row
+fn missing_again(canister: Principal) -> bool {
+    MISSING.with(|m| {
+        let mut m = m.borrow_mut();
+        let sweeps = m.entry(canister).or_default();
+        *sweeps += 1;
+        if *sweeps < MISSING_SWEEPS_TO_UNWATCH {
+            return false;
+        }
+        m.remove(&canister);
+        true
+    })
+}
+
+/// Send the policy's top-up, trimmed to what the daily caps still allow.
+/// The allowance is reserved before the await so concurrent sweeps can't
+/// both spend it.
+async fn top_up(canister: Principal, policy: &RefuelPolicy, balance: u128) {
+    let now = time();
+    let amount = STATE.with(|s| {
         let mut st = s.borrow_mut();
-        st.last_report = report;
-        st.last_tick = time();
+        st.transfers.retain(|t| t.timestamp > now.saturating_sub(DAY_NS));
+
+        let amount = st.allowance(canister, policy, now);
+        if amount > 0 {
+            st.transfers.push(Transfer { canister, amount, timestamp: now });
+        }
+        amount
     });
+
+    let mut event = RefuelEvent {
+        canister,
+        amount,
+        balance_before: balance,
+        balance_after: balance,
+        success: false,
+        error: None,
+        timestamp: now,
+    };
+
+    if amount == 0 {
+        ic_cdk::println!("[REFUELER] daily cap reached, not topping up {}", canister);
+        event.error = Some("daily cap reached".to_string());
+        refuel_log::record_skipped(event);
+        return;
+    }
+
+    match deposit_cycles(CanisterIdRecord { canister_id: canister }, amount).await {
+        Ok(()) => {
+            ic_cdk::println!("[REFUELER] deposited {} cycles to {}", amount, canister);
+            event.balance_after = balance.saturating_add(amount);
+            event.success = true;
+        }
+        Err((code, msg)) => {
+            event.error = Some(format!("{:?}: {}", code, msg));
+            ic_cdk::println!("[REFUELER] deposit to {} failed: {:?} {}", canister, code, msg);
+            // Nothing was sent, so give the allowance back
+            STATE.with(|s| {
+                let mut st = s.borrow_mut();
+                if let Some(pos) = st
+                .transfers
+                .iter()
+                .rposition(|t| t.canister == canister && t.timestamp == now && t.amount == amount)
+                {
+                    st.transfers.remove(pos);
+                }
+            });
+        }
+    }
+    refuel_log::record(event);
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
 }
@@ -0,0 +1,77 @@
+// refuel_log.rs - record of the latest top-up attempts and their outcomes
+use std::cell::RefCell;
+
+use candid::{CandidType, Deserialize, Principal};
+
+const MAX_PAGE: u64 = 500;
+/// Oldest events are dropped past this
+const MAX_LOG_ENTRIES: usize = 5_000;
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct RefuelEvent {
+    pub canister: Principal,
+    /// Cycles sent (0 when the daily caps allowed nothing)
+    pub amount: u128,
+    pub balance_before: u128,
+    /// balance_before + amount on success; deposit_cycles doesn't report the
+    /// resulting balance, the next sweep samples the real one
+    pub balance_after: u128,
+    pub success: bool,
+    pub error: Option<String>,
+    pub timestamp: u64,
+}
+
+thread_local! {
+    static LOG: RefCell<Vec<RefuelEvent>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn record(event: RefuelEvent) {
+    LOG.with(|l| {
+        let mut l = l.borrow_mut();
+        if l.len() >= MAX_LOG_ENTRIES {
+            l.remove(0);
+        }
+        l.push(event);
+    });
+}
+
+/// Record a top-up that wasn't attempted, unless the canister's latest
+/// event already says the same, so a canister held back by its cap logs
+/// once per cap window rather than every sweep
+pub fn record_skipped(event: RefuelEvent) {
+    let repeated = LOG.with(|l| {
+        l.borrow()
+        .iter()
+        .rev()
+        .find(|e| e.canister == event.canister)
+        .is_some_and(|e| e.amount == 0 && e.error == event.error)
+    });
+    if !repeated {
+        record(event);
+    }
+}
+
+/// Oldest kept event first; `limit` is capped at MAX_PAGE
+pub fn page(offset: u64, limit: u64) -> Vec<RefuelEvent> {
+    LOG.with(|l| {
+        l.borrow()
+        .iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PAGE) as usize)
+        .cloned()
+        .collect()
+    })
+}
+
+pub fn len() -> u64 {
+    LOG.with(|l| l.borrow().len() as u64)
+}
+
+pub fn snapshot() -> Vec<RefuelEvent> {
+    LOG.with(|l| l.borrow().clone())
+}
+
+pub fn restore(mut events: Vec<RefuelEvent>) {
+    events.drain(..events.len().saturating_sub(MAX_LOG_ENTRIES));
+    LOG.with(|l| *l.borrow_mut() = events);
+}
@@ -0,0 +1,418 @@
+// book.rs - per-miner balances and the transfers that pay them out. A
+// solved job's reward is credited once, split by the coordinator's PPLNS
+// breakdown; a transfer moves a miner's whole available balance in flight
+// until the ledger confirms it.
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::time;
+
+use crate::ledger::Outcome;
+
+/// The coordinator's breakdowns split PAYOUT_SCALE parts
+pub const PAYOUT_SCALE: u64 = 1_000_000_000;
+
+/// Mirrors the coordinator's MinerPayout (unused fields left out)
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerPayout {
+    pub miner: Principal,
+    pub parts: u64,
+}
+
+/// Mirrors the coordinator's PayoutBreakdown (unused fields left out)
+#[derive(Clone, CandidType, Deserialize)]
+pub struct PayoutBreakdown {
+    pub job_id: u64,
+    pub block_hash: String,
+    pub payouts: Vec<MinerPayout>,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct MinerBalance {
+    pub miner: Principal,
+    /// Credited and not yet in a transfer
+    pub available: Nat,
+    /// In transfers the ledger hasn't confirmed
+    pub in_flight: Nat,
+    pub paid: Nat,
+    /// Solved jobs that credited this miner
+    pub jobs: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TransferKind {
+    /// Requested by the miner through claim()
+    Claim,
+    /// Pushed because the balance reached the auto-payout threshold
+    Auto,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferStatus {
+    Queued,
+    Paid { block_index: Nat },
+    /// Out of attempts; retry_failed queues it again
+    Failed { error: String },
+    /// The dedup window passed after an attempt that may have gone through.
+    /// Nothing is sent until reconcile_transfer records what the ledger
+    /// holds under its memo.
+    Unreconciled,
+}
+
+#[derive(Clone, CandidType, Deserialize)]
+pub struct Transfer {
+    pub id: u64,
+    pub miner: Principal,
+    pub amount: Nat,
+    pub kind: TransferKind,
+    /// Sent with every attempt so the ledger dedupes a resend
+    pub created_at_time: u64,
+    /// An attempt ended without a definite answer, so it may have paid
+    pub maybe_sent: bool,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub status: TransferStatus,
+}
+
+/// Kept across upgrades
+#[derive(Clone, Default, CandidType, Deserialize)]
+pub struct Book {
+    credited: BTreeSet<u64>,
+    /// Solved jobs whose breakdown hasn't been fetched yet
+    pending_jobs: BTreeSet<u64>,
+    balances: BTreeMap<Principal, MinerBalance>,
+    transfers: BTreeMap<u64, Transfer>,
+    next_transfer_id: u64,
+}
+
+thread_local! {
+    static BOOK: RefCell<Book> = RefCell::new(Book::default());
+    /// Transfers with a call in flight, so two rounds never send one twice
+    static SENDING: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+pub fn snapshot() -> Book {
+    BOOK.with(|b| b.borrow().clone())
+}
+
+pub fn restore(book: Book) {
+    BOOK.with(|b| *b.borrow_mut() = book);
+}
+
+// ------------------------------------------------------------
+// Crediting
+// ------------------------------------------------------------
+
+/// Remember a solved job to fetch its breakdown; false if it's already
+/// credited or waiting
+pub fn queue_job(job_id: u64) -> bool {
+    BOOK.with(|b| {
+        let mut b = b.borrow_mut();
+        !b.credited.contains(&job_id) && b.pending_jobs.insert(job_id)
+    })
+}
+
+pub fn pending_jobs() -> Vec<u64> {
+    BOOK.with(|b| b.borrow().pending_jobs.iter().copied().collect())
+}
+
+/// Split `reward` over the breakdown's parts, rounding dust to the largest
+/// cut, and add each miner's share to its balance. A job credits once.
+/// A malformed breakdown is dropped, not retried.
+pub fn credit(breakdown: &PayoutBreakdown, reward: &Nat) -> Result<(), String> {
+    BOOK.with(|b| {
+        let mut b = b.borrow_mut();
+        b.pending_jobs.remove(&breakdown.job_id);
+        let parts: u64 = breakdown.payouts.iter().map(|p| p.parts).sum();
+        if parts != PAYOUT_SCALE {
+            return Err(format!("job {} breakdown splits {} parts, not {}", breakdown.job_id, parts, PAYOUT_SCALE));
+        }
+        if !b.credited.insert(breakdown.job_id) {
+            return Ok(());
+        }
+
+        let scale = Nat::from(PAYOUT_SCALE);
+        let mut cuts: Vec<(Principal, Nat)> = breakdown
+        .payouts
+        .iter()
+        .map(|p| (p.miner, reward.clone() * Nat::from(p.parts) / scale.clone()))
+        .collect();
+        let given = cuts.iter().fold(Nat::from(0u32), |sum, (_, cut)| sum + cut.clone());
+        if let Some((_, largest)) = cuts.iter_mut().max_by(|a, b| a.1.cmp(&b.1)) {
+            *largest += reward.clone() - given;
+        }
+
+        for (miner, cut) in cuts {
+            let balance = b.balances.entry(miner).or_insert_with(|| MinerBalance {
+                miner,
+                available: Nat::from(0u32),
+                in_flight: Nat::from(0u32),
+                paid: Nat::from(0u32),
+                jobs: 0,
+            });
+            balance.available += cut;
+            balance.jobs += 1;
+        }
+        Ok(())
+    })
+}
+
+// ------------------------------------------------------------
+// Transfers
+// ------------------------------------------------------------
+
+/// Move all of `miner`'s available balance into a new queued transfer;
+/// None if there's nothing to pay
+pub fn open_transfer(miner: Principal, kind: TransferKind) -> Option<u64> {
+    BOOK.with(|b| {
+        let mut b = b.borrow_mut();
+        let balance = b.balances.get_mut(&miner)?;
+        if balance.available == 0u32 {
+            return None;
+        }
+        let amount = std::mem::replace(&mut balance.available, Nat::from(0u32));
+        balance.in_flight += amount.clone();
+
+        let id = b.next_transfer_id;
+        b.next_transfer_id += 1;
+        b.transfers.insert(id, Transfer {
+            id,
+            miner,
+            amount,
+            kind,
+            created_at_time: time(),
+            maybe_sent: false,
+            attempts: 0,
+            last_error: None,
+            status: TransferStatus::Queued,
+        });
+        Some(id)
+    })
+}
+
+/// Miners whose available balance has reached `threshold`
+pub fn due(threshold: &Nat) -> Vec<Principal> {
+    BOOK.with(|b| {
+        b.borrow()
+        .balances
+        .values()
+        .filter(|m| m.available > 0u32 && m.available >= *threshold)
+        .map(|m| m.miner)
+        .collect()
+    })
+}
+
+/// Up to `limit` queued transfers nobody is sending, oldest first, marked
+/// as being sent
+pub fn take_queued(limit: usize) -> Vec<Transfer> {
+    BOOK.with(|b| {
+        SENDING.with(|s| {
+            let mut s = s.borrow_mut();
+            let out: Vec<Transfer> = b
+            .borrow()
+            .transfers
+            .values()
+            .filter(|t| matches!(t.status, TransferStatus::Queued) && !s.contains(&t.id))
+            .take(limit)
+            .cloned()
+            .collect();
+            s.extend(out.iter().map(|t| t.id));
+            out
+        })
+    })
+}
+
+/// Mark one transfer as being sent; None if it isn't queued or is already
+/// being sent
+pub fn take(id: u64) -> Option<Transfer> {
+    let t = BOOK.with(|b| b.borrow().transfers.get(&id).cloned())?;
+    if !matches!(t.status, TransferStatus::Queued) || !SENDING.with(|s| s.borrow_mut().insert(id)) {
+        return None;
+    }
+    Some(t)
+}
+
+fn mark_paid(b: &mut Book, id: u64, block_index: Nat) -> TransferStatus {
+    let Some(t) = b.transfers.get_mut(&id) else {
+        return TransferStatus::Failed { error: "unknown transfer".to_string() };
+    };
+    t.status = TransferStatus::Paid { block_index };
+    t.last_error = None;
+    if let Some(m) = b.balances.get_mut(&t.miner) {
+        m.in_flight -= t.amount.clone();
+        m.paid += t.amount.clone();
+    }
+    t.status.clone()
+}
+
+/// Apply the ledger's answer to a transfer taken for sending. It fails for
+/// good after `max_attempts`, keeping its amount in flight.
+pub fn record_outcome(id: u64, outcome: Outcome, max_attempts: u32) -> TransferStatus {
+    SENDING.with(|s| s.borrow_mut().remove(&id));
+    BOOK.with(|b| {
+        let b = &mut *b.borrow_mut();
+        let Some(t) = b.transfers.get_mut(&id) else {
+            return TransferStatus::Failed { error: "unknown transfer".to_string() };
+        };
+        t.attempts += 1;
+
+        let error = match outcome {
+            Outcome::Paid(block_index) => return mark_paid(b, id, block_index),
+            Outcome::Rejected(e) => e,
+            // A fresh created_at_time is only safe if no attempt could have
+            // gone through; otherwise the ledger would pay it twice
+            Outcome::TooOld if t.maybe_sent => {
+                t.status = TransferStatus::Unreconciled;
+                t.last_error = Some("TooOld after an unconfirmed attempt".to_string());
+                return t.status.clone();
+            }
+            Outcome::TooOld => {
+                t.created_at_time = time();
+                "TooOld".to_string()
+            }
+            Outcome::Unknown(e) => {
+                t.maybe_sent = true;
+                e
+            }
+        };
+        if t.attempts >= max_attempts {
+            t.status = TransferStatus::Failed { error: error.clone() };
+        }
+        t.last_error = Some(error);
+        t.status.clone()
+    })
+}
+
+/// Settle an unreconciled transfer from what the ledger holds under its
+/// memo: the block that paid it, or None to send it again under a fresh
+/// created_at_time
+pub fn reconcile(id: u64, paid_in: Option<Nat>) -> Result<TransferStatus, String> {
+    BOOK.with(|b| {
+        let b = &mut *b.borrow_mut();
+        let t = b.transfers.get_mut(&id).ok_or(format!("unknown transfer {}", id))?;
+        if !matches!(t.status, TransferStatus::Unreconciled) {
+            return Err(format!("transfer {} is not unreconciled", id));
+        }
+        Ok(match paid_in {
+            Some(block_index) => mark_paid(b, id, block_index),
+            None => {
+                t.status = TransferStatus::Queued;
+                t.created_at_time = time();
+                t.maybe_sent = false;
+                t.attempts = 0;
+                t.status.clone()
+            }
+        })
+    })
+}
+
+/// Queue every failed transfer again with a fresh attempt count; returns
+/// how many
+pub fn requeue_failed() -> u64 {
+    BOOK.with(|b| {
+        let mut n = 0;
+        for t in b.borrow_mut().transfers.values_mut() {
+            if matches!(t.status, TransferStatus::Failed { .. }) {
+                t.status = TransferStatus::Queued;
+                t.attempts = 0;
+                n += 1;
+            }
+        }
+        n
+    })
+}
+
+// ------------------------------------------------------------
+// Reads
+// ------------------------------------------------------------
+
+pub fn balance(miner: Principal) -> Option<MinerBalance> {
+    BOOK.with(|b| b.borrow().balances.get(&miner).cloned())
+}
+
+/// Largest available balance first
+pub fn balances() -> Vec<MinerBalance> {
+    let mut out: Vec<MinerBalance> = BOOK.with(|b| b.borrow().balances.values().cloned().collect());
+    out.sort_by(|a, b| b.available.cmp(&a.available).then(a.miner.cmp(&b.miner)));
+    out
+}
+
+pub fn transfer(id: u64) -> Option<Transfer> {
+    BOOK.with(|b| b.borrow().transfers.get(&id).cloned())
+}
+
+/// Newest first, optionally only one miner's, at most `limit`
+pub fn transfers(miner: Option<Principal>, limit: usize) -> Vec<Transfer> {
+    BOOK.with(|b| {
+        b.borrow()
+        .transfers
+        .values()
+        .rev()
+        .filter(|t| miner.is_none_or(|m| t.miner == m))
+        .take(limit)
+        .cloned()
+        .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn miner(n: u8) -> Principal {
+        Principal::from_slice(&[n])
+    }
+
+    fn breakdown(job_id: u64, parts: &[(u8, u64)]) -> PayoutBreakdown {
+        PayoutBreakdown {
+            job_id,
+            block_hash: String::new(),
+            payouts: parts.iter().map(|&(m, parts)| MinerPayout { miner: miner(m), parts }).collect(),
+        }
+    }
+
+    fn available(m: u8) -> Nat {
+        balance(miner(m)).map_or(Nat::from(0u32), |b| b.available)
+    }
+
+    #[test]
+    fn credit_splits_pro_rata_with_dust_to_the_largest_cut() {
+        let third = PAYOUT_SCALE / 3;
+        let b = breakdown(1, &[(1, third), (2, third), (3, PAYOUT_SCALE - 2 * third)]);
+        credit(&b, &Nat::from(100u32)).unwrap();
+
+        // 33 each, and the 1 left over goes to the largest share
+        assert_eq!(available(1), Nat::from(33u32));
+        assert_eq!(available(2), Nat::from(33u32));
+        assert_eq!(available(3), Nat::from(34u32));
+        assert_eq!(balance(miner(3)).unwrap().jobs, 1);
+    }
+
+    #[test]
+    fn credit_pays_each_job_once() {
+        let b = breakdown(7, &[(1, PAYOUT_SCALE)]);
+        credit(&b, &Nat::from(10u32)).unwrap();
+        credit(&b, &Nat::from(10u32)).unwrap();
+        assert_eq!(available(1), Nat::from(10u32));
+        assert_eq!(balance(miner(1)).unwrap().jobs, 1);
+    }
+
+    #[test]
+    fn credit_refuses_a_breakdown_off_scale() {
+        queue_job(3);
+        let b = breakdown(3, &[(1, PAYOUT_SCALE / 2), (2, PAYOUT_SCALE / 2 + 1)]);
+        assert!(credit(&b, &Nat::from(10u32)).is_err());
+        assert_eq!(available(1), Nat::from(0u32));
+        // Dropped, not left pending
+        assert!(pending_jobs().is_empty());
+    }
+
+    #[test]
+    fn credit_sums_to_the_reward() {
+        let parts = [(1, 1), (2, 999), (3, PAYOUT_SCALE - 1_000)];
+        credit(&breakdown(2, &parts), &Nat::from(12_345_678_901u64)).unwrap();
+        let sum = available(1) + available(2) + available(3);
+        assert_eq!(sum, Nat::from(12_345_678_901u64));
+    }
+}
@@ -0,0 +1,55 @@
+// ledger.rs - the slice of the ICRC-1 interface rewards are paid through
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::call;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+pub enum Outcome {
+    Paid(Nat),
+    /// The ledger refused this attempt, so it moved nothing
+    Rejected(String),
+    /// created_at_time is past the ledger's dedup window. This attempt moved
+    /// nothing, but the ledger can no longer tell whether an earlier one did.
+    TooOld,
+    /// The call failed or the ledger was busy; it may or may not have gone
+    /// through, so it's resent unchanged for the ledger to dedupe
+    Unknown(String),
+}
+
+pub async fn transfer(ledger: Principal, arg: TransferArg) -> Outcome {
+    match call::<(TransferArg,), (Result<Nat, TransferError>,)>(ledger, "icrc1_transfer", (arg,)).await {
+        Ok((Ok(block_index),)) => Outcome::Paid(block_index),
+        // An earlier attempt already went through
+        Ok((Err(TransferError::Duplicate { duplicate_of }),)) => Outcome::Paid(duplicate_of),
+        Ok((Err(TransferError::TemporarilyUnavailable),)) => Outcome::Unknown("ledger temporarily unavailable".to_string()),
+        Ok((Err(TransferError::TooOld),)) => Outcome::TooOld,
+        Ok((Err(e),)) => Outcome::Rejected(format!("{:?}", e)),
+        Err((code, msg)) => Outcome::Unknown(format!("icrc1_transfer: {:?} {}", code, msg)),
+    }
+}
@@ -0,0 +1,401 @@
+// rewards/src/lib.rs - pays miners from an ICRC-1 ledger. The coordinator
+// notifies on_solution for every solved job; its PPLNS breakdown is fetched
+// and the job's reward credited to each miner's balance. Balances go out in
+// batched transfers once they reach the auto-payout threshold, or when the
+// miner calls claim(). Transfers that fail are retried with the same memo
+// and created_at_time, so the ledger never pays one twice; one that outlives
+// the ledger's dedup window after an unconfirmed attempt waits for an admin
+// to reconcile it instead.
+use candid::{CandidType, Deserialize, Nat, Principal};
+use canister_auth::audit::AuditEntry;
+use canister_state::{Migration, StateError};
+use canister_timers::{clear_timer, set_timer_interval, TimerId};
+use futures::future::join_all;
+use ic_cdk::api::call::call;
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+mod book;
+mod ledger;
+
+use crate::book::{MinerBalance, PayoutBreakdown, Transfer, TransferKind, TransferStatus};
+use crate::ledger::{Account, TransferArg};
+
+/// Breakdowns fetched from the coordinator per settlement round
+const MAX_FETCH_PER_ROUND: usize = 50;
+/// Most transfers one get_transfers call returns
+const MAX_TRANSFERS_PAGE: u64 = 500;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RewardsConfig {
+    /// Only it may call on_solution; breakdowns come from its get_payouts
+    pub coordinator: Principal,
+    pub ledger: Principal,
+    /// Token units split over each solved job's breakdown
+    pub reward_per_job: Nat,
+    /// None lets the ledger charge its default fee
+    pub fee: Option<Nat>,
+    /// This canister's subaccount rewards are paid from
+    pub from_subaccount: Option<Vec<u8>>,
+    /// Push a balance once it reaches this; None leaves every payout to
+    /// claim()
+    pub auto_payout_threshold: Option<Nat>,
+    /// Transfers sent per settlement round
+    pub batch_size: u32,
+    pub settle_interval_secs: u64,
+    /// Attempts before a transfer is marked failed
+    pub max_attempts: u32,
+}
+
+thread_local! {
+    static CONFIG: RefCell<Option<RewardsConfig>> = const { RefCell::new(None) };
+    static SETTLE_TIMER: Cell<Option<TimerId>> = const { Cell::new(None) };
+    static SETTLING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn config() -> Option<RewardsConfig> {
+    CONFIG.with(|c| c.borrow().clone())
+}
+
+// ------------------------------------------------------------
+// Init / upgrades
+// ------------------------------------------------------------
+
+/// The installing principal becomes the owner; `admins` are added alongside
+#[init]
+fn init(admins: Option<Vec<Principal>>) {
+    canister_auth::init(caller(), admins.unwrap_or_default());
+}
+
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (
+    Option<RewardsConfig>,
+    book::Book,
+    (Option<Principal>, Vec<Principal>),
+    Option<Vec<AuditEntry>>,
+);
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let saved: Saved = (
+        config(),
+        book::snapshot(),
+        canister_auth::snapshot(),
+        Some(canister_auth::audit::snapshot()),
+    );
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved) {
+        ic_cdk::trap(&format!("failed to save rewards state: {}", e));
+    }
+}
+
+/// Upgrade args add admins; they never replace the saved owner
+#[post_upgrade]
+fn post_upgrade(admins: Option<Vec<Principal>>) {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => saved,
+        Err(StateError::Unversioned) => ic_cdk::trap("rewards state was never saved unversioned"),
+        Err(e) => ic_cdk::trap(&format!("failed to restore rewards state: {}", e)),
+    };
+    let (cfg, saved_book, (owner, saved_admins), audit) = restored;
+    CONFIG.with(|c| *c.borrow_mut() = cfg);
+    book::restore(saved_book);
+    canister_auth::restore(owner, saved_admins);
+    canister_auth::audit::restore(audit.unwrap_or_default());
+    admins.unwrap_or_default().into_iter().for_each(canister_auth::add_admin);
+
+    // Timers don't survive upgrades
+    arm_settle_timer();
+}
+
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
+
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
+
+/// Hand the rewards canister to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+// ------------------------------------------------------------
+// Configuration (admin only)
+// ------------------------------------------------------------
+
+/// None stops fetching breakdowns and sending transfers; balances are kept
+#[update]
+pub fn set_config(config: Option<RewardsConfig>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_config", format!("{:?}", config));
+    if let Some(c) = &config {
+        if c.reward_per_job == 0u32 {
+            ic_cdk::trap("reward_per_job must be positive");
+        }
+        if c.batch_size == 0 || c.settle_interval_secs == 0 || c.max_attempts == 0 {
+            ic_cdk::trap("batch_size, settle_interval_secs and max_attempts must be at least 1");
+        }
+    }
+
+    CONFIG.with(|c| *c.borrow_mut() = config);
+    arm_settle_timer();
+}
+
+#[query]
+pub fn get_config() -> Option<RewardsConfig> {
+    config()
+}
+
+/// Queue solved jobs whose notification was missed; returns how many were
+/// new
+#[update]
+pub fn sync_jobs(job_ids: Vec<u64>) -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("sync_jobs", format!("{:?}", job_ids));
+    job_ids.into_iter().filter(|&id| book::queue_job(id)).count() as u64
+}
+
+/// Run a settlement round now instead of waiting for the timer; returns
+/// the transfers sent
+#[update]
+pub async fn settle_now() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("settle_now", String::new());
+    settle_round().await
+}
+
+/// Queue every failed transfer again; returns how many
+#[update]
+pub fn retry_failed() -> u64 {
+    canister_auth::require_admin();
+    canister_auth::audit::record("retry_failed", String::new());
+    book::requeue_failed()
+}
+
+/// Settle an unreconciled transfer after looking its memo up on the ledger:
+/// `paid_in` is the ledger block that paid it, or None if none did, which
+/// queues it again under a fresh created_at_time
+#[update]
+pub fn reconcile_transfer(id: u64, paid_in: Option<Nat>) -> TransferStatus {
+    canister_auth::require_admin();
+    canister_auth::audit::record("reconcile_transfer", format!("{:?}", (id, &paid_in)));
+    book::reconcile(id, paid_in).unwrap_or_else(|e| ic_cdk::trap(&e))
+}
+
+/// The memo every attempt of transfer `id` carries
+#[query]
+pub fn get_transfer_memo(id: u64) -> Vec<u8> {
+    memo(id)
+}
+
+// ------------------------------------------------------------
+// Coordinator notifications
+// ------------------------------------------------------------
+
+/// Solution topic handler: `(job_id, nonce, hash, miner)`. Subscribe with
+/// the coordinator's subscribe(this canister, "on_solution", null).
+#[update]
+pub fn on_solution(job_id: u64, _nonce: u64, _hash: String, _miner: Principal) {
+    match config() {
+        Some(c) if c.coordinator == caller() => {}
+        _ => ic_cdk::trap("caller is not the configured coordinator"),
+    }
+    book::queue_job(job_id);
+}
+
+// ------------------------------------------------------------
+// Claims
+// ------------------------------------------------------------
+
+/// Pay the caller's whole available balance to its default account. Returns
+/// the ledger block index; if the ledger doesn't confirm the transfer it
+/// stays queued and is retried every round.
+#[update]
+pub async fn claim() -> Result<Nat, String> {
+    let cfg = config().ok_or("rewards are not configured")?;
+    let id = book::open_transfer(caller(), TransferKind::Claim).ok_or("nothing to claim")?;
+    let t = book::take(id).ok_or("transfer is already being sent")?;
+
+    match send(&cfg, t).await {
+        TransferStatus::Paid { block_index } => Ok(block_index),
+        TransferStatus::Failed { error } => Err(format!("transfer {} failed: {}", id, error)),
+        TransferStatus::Queued => Err(format!("transfer {} not confirmed yet; it will be retried", id)),
+        TransferStatus::Unreconciled => Err(format!("transfer {} is waiting to be reconciled with the ledger", id)),
+    }
+}
+
+// ------------------------------------------------------------
+// Settlement
+// ------------------------------------------------------------
+
+fn arm_settle_timer() {
+    if let Some(id) = SETTLE_TIMER.with(|t| t.take()) {
+        clear_timer(id);
+    }
+    let Some(cfg) = config() else { return };
+
+    let id = set_timer_interval(Duration::from_secs(cfg.settle_interval_secs), settle_tick);
+    SETTLE_TIMER.with(|t| t.set(Some(id)));
+}
+
+fn settle_tick() {
+    if SETTLING.with(|s| s.replace(true)) {
+        return;
+    }
+
+    ic_cdk::spawn(async {
+        settle_round().await;
+        SETTLING.with(|s| s.set(false));
+    });
+}
+
+/// Credit newly settled jobs, open transfers for balances over the
+/// threshold, and send one batch of queued transfers
+async fn settle_round() -> u64 {
+    let Some(cfg) = config() else { return 0 };
+    fetch_breakdowns(&cfg).await;
+
+    if let Some(threshold) = &cfg.auto_payout_threshold {
+        for miner in book::due(threshold) {
+            book::open_transfer(miner, TransferKind::Auto);
+        }
+    }
+
+    let batch = book::take_queued(cfg.batch_size as usize);
+    let sent = batch.len() as u64;
+    join_all(batch.into_iter().map(|t| send(&cfg, t))).await;
+    sent
+}
+
+/// A job without a breakdown yet stays pending for the next round
+async fn fetch_breakdowns(cfg: &RewardsConfig) {
+    let jobs: Vec<u64> = book::pending_jobs().into_iter().take(MAX_FETCH_PER_ROUND).collect();
+    let calls = jobs
+    .iter()
+    .map(|&job_id| call::<(u64,), (Option<PayoutBreakdown>,)>(cfg.coordinator, "get_payouts", (job_id,)));
+
+    for (job_id, result) in jobs.iter().zip(join_all(calls).await) {
+        match result {
+            Ok((Some(breakdown),)) => {
+                if let Err(e) = book::credit(&breakdown, &cfg.reward_per_job) {
+                    ic_cdk::println!("❌ Dropped breakdown of job {}: {}", job_id, e);
+                }
+            }
+            Ok((None,)) => {}
+            Err((code, msg)) => ic_cdk::println!("get_payouts({}) failed: {:?} {}", job_id, code, msg),
+        }
+    }
+}
+
+/// The memo every attempt of a transfer carries
+fn memo(id: u64) -> Vec<u8> {
+    let mut h = Sha256::new();
+    h.update(b"rewards");
+    h.update(id.to_be_bytes());
+    h.finalize().to_vec()
+}
+
+async fn send(cfg: &RewardsConfig, t: Transfer) -> TransferStatus {
+    let arg = TransferArg {
+        from_subaccount: cfg.from_subaccount.clone(),
+        to: Account { owner: t.miner, subaccount: None },
+        amount: t.amount.clone(),
+        fee: cfg.fee.clone(),
+        memo: Some(memo(t.id)),
+        created_at_time: Some(t.created_at_time),
+    };
+    let outcome = ledger::transfer(cfg.ledger, arg).await;
+
+    let status = book::record_outcome(t.id, outcome, cfg.max_attempts);
+    match &status {
+        TransferStatus::Paid { block_index } => {
+            ic_cdk::println!("💰 Paid {} to {} (ledger block {})", t.amount, t.miner, block_index)
+        }
+        TransferStatus::Failed { error } => ic_cdk::println!("❌ Transfer {} failed for good: {}", t.id, error),
+        TransferStatus::Unreconciled => ic_cdk::println!("⚠️ Transfer {} needs reconciling with the ledger", t.id),
+        TransferStatus::Queued => {}
+    }
+    status
+}
+
+// ------------------------------------------------------------
+// Read API
+// ------------------------------------------------------------
+
+#[query]
+pub fn get_balance(miner: Principal) -> Option<MinerBalance> {
+    book::balance(miner)
+}
+
+/// Largest available balance first
+#[query]
+pub fn get_balances() -> Vec<MinerBalance> {
+    book::balances()
+}
+
+#[query]
+pub fn get_transfer(id: u64) -> Option<Transfer> {
+    book::transfer(id)
+}
+
+/// Newest first, optionally only one miner's (at most 500)
+#[query]
+pub fn get_transfers(miner: Option<Principal>, limit: u64) -> Vec<Transfer> {
+    book::transfers(miner, limit.min(MAX_TRANSFERS_PAGE) as usize)
+}
+
+/// Solved jobs whose breakdown hasn't been credited yet
+#[query]
+pub fn get_pending_jobs() -> Vec<u64> {
+    book::pending_jobs()
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
@@ -0,0 +1,245 @@
+// bitcoin.rs - raw 80-byte Bitcoin block headers: parsing, double-SHA256
+// proof of work against the compact `bits` target, and header-chain linkage
+use candid::{CandidType, Deserialize};
+use ic_cdk::query;
+use sha2::{Digest, Sha256};
+
+use pow_core::target::{self, Target};
+use crate::{limits, ValidationResult};
+
+const HEADER_LEN: usize = 80;
+
+/// A header in wire order, as hex (160 characters) or bytes
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RawHeader {
+    Hex(String),
+    Bytes(Vec<u8>),
+}
+
+/// Hashes are hex in the byte-reversed order block explorers show
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BitcoinHeader {
+    pub version: i32,
+    pub prev_block: String,
+    pub merkle_root: String,
+    /// Unix seconds
+    pub time: u32,
+    /// Compact target
+    pub bits: u32,
+    pub nonce: u32,
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HeaderCheck {
+    pub valid: bool,
+    pub reason: Option<String>,
+    /// None if the input wasn't an 80-byte header
+    pub header: Option<BitcoinHeader>,
+}
+
+// ------------------------------------------------------------
+// Parsing and proof of work
+// ------------------------------------------------------------
+
+fn header_bytes(raw: &RawHeader) -> Result<[u8; HEADER_LEN], String> {
+    let bytes = match raw {
+        RawHeader::Hex(h) if h.len() != HEADER_LEN * 2 => {
+            return Err(format!("header must be {} hex characters, got {}", HEADER_LEN * 2, h.len()));
+        }
+        RawHeader::Hex(h) => hex::decode(h).map_err(|e| format!("bad header hex: {}", e))?,
+        RawHeader::Bytes(b) => b.clone(),
+    };
+    let len = bytes.len();
+    bytes
+    .try_into()
+    .map_err(|_| format!("header must be {} bytes, got {}", HEADER_LEN, len))
+}
+
+/// Hex of a little-endian hash, most significant byte first
+fn display_hex(bytes: &[u8]) -> String {
+    bytes.iter().rev().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().expect("4-byte field"))
+}
+
+fn parse(bytes: &[u8; HEADER_LEN]) -> BitcoinHeader {
+    let hash: [u8; 32] = Sha256::digest(Sha256::digest(bytes)).into();
+    BitcoinHeader {
+        version: le_u32(&bytes[0..4]) as i32,
+        prev_block: display_hex(&bytes[4..36]),
+        merkle_root: display_hex(&bytes[36..68]),
+        time: le_u32(&bytes[68..72]),
+        bits: le_u32(&bytes[72..76]),
+        nonce: le_u32(&bytes[76..80]),
+        hash: display_hex(&hash),
+    }
+}
+
+/// Expand compact `bits` (mantissa * 256^(exponent - 3)) into a target.
+/// Negative, zero and over-256-bit targets are rejected.
+pub fn bits_to_target(bits: u32) -> Result<Target, String> {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 {
+        return Err(format!("bits {:08x} encode a negative target", bits));
+    }
+    let mut t = [0u8; 32];
+    for (i, b) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+        // Mantissa byte i weighs 256^(exponent - 1 - i); bytes below 1 drop off
+        let pos = (32 + i).checked_sub(exponent);
+        match pos {
+            _ if *b == 0 => {}
+            None => return Err(format!("bits {:08x} overflow 256 bits", bits)),
+            Some(p) if p < 32 => t[p] = *b,
+            Some(_) => {}
+        }
+    }
+    if t == [0u8; 32] {
+        return Err(format!("bits {:08x} encode a zero target", bits));
+    }
+    Ok(t)
+}
+
+/// The parsed header, or why its proof of work fails
+fn check(raw: &RawHeader) -> Result<BitcoinHeader, (String, Option<BitcoinHeader>)> {
+    let bytes = header_bytes(raw).map_err(|e| (e, None))?;
+    let header = parse(&bytes);
+    let target = match bits_to_target(header.bits) {
+        Ok(t) => t,
+        Err(e) => return Err((e, Some(header))),
+    };
+    // The display order is the big-endian number compared to the target
+    let mut hash: [u8; 32] = Sha256::digest(Sha256::digest(bytes)).into();
+    hash.reverse();
+    if !target::meets(&hash, &target) {
+        let reason = format!("hash {} is above the target for bits {:08x}", header.hash, header.bits);
+        return Err((reason, Some(header)));
+    }
+    Ok(header)
+}
+
+// ------------------------------------------------------------
+// Endpoints
+// ------------------------------------------------------------
+
+#[query]
+pub fn parse_bitcoin_header(header: RawHeader) -> Result<BitcoinHeader, String> {
+    header_bytes(&header).map(|b| parse(&b))
+}
+
+#[query]
+pub fn verify_bitcoin_header(header: RawHeader) -> HeaderCheck {
+    match check(&header) {
+        Ok(header) => HeaderCheck { valid: true, reason: None, header: Some(header) },
+        Err((reason, header)) => HeaderCheck { valid: false, reason: Some(reason), header },
+    }
+}
+
+/// Every header's proof of work, and each one's prev_block naming the hash
+/// of the header before it. Retargeting rules aren't checked.
+#[query]
+pub fn verify_bitcoin_header_chain(headers: Vec<RawHeader>) -> ValidationResult {
+    if let Err(e) = limits::check_batch(headers.len()) {
+        return e.into();
+    }
+    if headers.is_empty() {
+        return crate::invalid("Empty header chain".to_string());
+    }
+
+    let mut prev: Option<BitcoinHeader> = None;
+    for (i, raw) in headers.iter().enumerate() {
+        let header = match check(raw) {
+            Ok(h) => h,
+            Err((reason, _)) => return crate::invalid(format!("Header {}: {}", i, reason)),
+        };
+        if let Some(p) = &prev {
+            if header.prev_block != p.hash {
+                return crate::invalid(format!(
+                    "Chain break at header {}: prev_block {} doesn't match {}",
+                    i, header.prev_block, p.hash
+                ));
+            }
+        }
+        prev = Some(header);
+    }
+
+    ValidationResult {
+        valid: true,
+        reason: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS: &str = "01000000\
+        0000000000000000000000000000000000000000000000000000000000000000\
+        3ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a\
+        29ab5f49ffff001d1dac2b7c";
+    const BLOCK_1: &str = "01000000\
+        6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000\
+        982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e\
+        61bc6649ffff001d01e36299";
+
+    fn raw(hex: &str) -> RawHeader {
+        RawHeader::Hex(hex.to_string())
+    }
+
+    #[test]
+    fn genesis_header_parses_to_its_explorer_fields() {
+        let h = check(&raw(GENESIS)).unwrap_or_else(|(e, _)| panic!("{}", e));
+        assert_eq!(h.hash, "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+        assert_eq!(h.prev_block, "0".repeat(64));
+        assert_eq!(h.merkle_root, "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b");
+        assert_eq!((h.version, h.time, h.bits, h.nonce), (1, 1231006505, 0x1d00ffff, 2083236893));
+    }
+
+    #[test]
+    fn block_1_links_to_genesis() {
+        let h = check(&raw(BLOCK_1)).unwrap_or_else(|(e, _)| panic!("{}", e));
+        assert_eq!(h.hash, "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048");
+        assert_eq!(h.prev_block, "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+
+        assert!(verify_bitcoin_header_chain(vec![raw(GENESIS), raw(BLOCK_1)]).valid);
+        let reversed = verify_bitcoin_header_chain(vec![raw(BLOCK_1), raw(GENESIS)]);
+        assert!(!reversed.valid);
+        assert!(reversed.reason.unwrap().starts_with("Chain break at header 1"));
+    }
+
+    #[test]
+    fn a_changed_nonce_fails_proof_of_work() {
+        let mut bytes = header_bytes(&raw(GENESIS)).unwrap();
+        bytes[76] ^= 1;
+        let (reason, header) = check(&RawHeader::Bytes(bytes.to_vec())).unwrap_err();
+        assert!(reason.contains("above the target"));
+        assert!(header.is_some());
+    }
+
+    #[test]
+    fn bits_expand_to_the_difficulty_1_target() {
+        let mut want = [0u8; 32];
+        want[4] = 0xff;
+        want[5] = 0xff;
+        assert_eq!(bits_to_target(0x1d00ffff).unwrap(), want);
+
+        // Regtest's easiest target fills the top byte
+        assert_eq!(bits_to_target(0x207fffff).unwrap()[..3], [0x7f, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn bits_reject_negative_zero_and_overflowing_targets() {
+        assert!(bits_to_target(0x1d80ffff).unwrap_err().contains("negative"));
+        assert!(bits_to_target(0x1d000000).unwrap_err().contains("zero"));
+        assert!(bits_to_target(0x2201ffff).unwrap_err().contains("overflow"));
+    }
+
+    #[test]
+    fn headers_must_be_80_bytes() {
+        assert!(header_bytes(&raw(&GENESIS[..158])).is_err());
+        assert!(header_bytes(&RawHeader::Bytes(vec![0; 81])).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+// emission.rs - the admin-set block reward schedule coinbases are checked
+// against
+use std::cell::Cell;
+
+use pow_core::emission::EmissionSchedule;
+
+thread_local! {
+    static SCHEDULE: Cell<EmissionSchedule> = Cell::new(EmissionSchedule::default());
+}
+
+pub fn get() -> EmissionSchedule {
+    SCHEDULE.with(|s| s.get())
+}
+
+pub fn set(schedule: EmissionSchedule) {
+    if let Err(e) = schedule.validate() {
+        ic_cdk::trap(&e);
+    }
+    SCHEDULE.with(|s| s.set(schedule));
+}
+
+/// A coinbase may claim up to the height's reward plus the block's fees
+pub fn check_coinbase(height: u64, coinbase: u64, total_fee: Option<u64>) -> Result<(), String> {
+    let reward = get().reward_at(height);
+    let max = reward.saturating_add(total_fee.unwrap_or(0));
+    if coinbase > max {
+        return Err(format!(
+            "coinbase {} exceeds reward {} plus fees {} at height {}",
+            coinbase,
+            reward,
+            total_fee.unwrap_or(0),
+            height
+        ));
+    }
+    Ok(())
+}
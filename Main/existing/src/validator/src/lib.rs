@@ -1,8 +1,23 @@
 // validator/src/lib.rs - Complete PoW validation
 use candid::{CandidType, Deserialize};
-use ic_cdk::{caller, query, update};
-use sha2::{Digest, Sha256};
+use ic_cdk::{caller, init, post_upgrade, pre_upgrade, query, update};
 use candid::Principal;
+use canister_auth::audit::AuditEntry;
+use canister_auth::rate_limit::{self, RateLimit, RateLimited};
+use canister_config::VersionedConfig;
+use canister_state::{Migration, StateError};
+
+mod bitcoin;
+mod emission;
+mod limits;
+mod pricing;
+
+use limits::InputLimits;
+use pricing::VerificationPrice;
+use pow_core::emission::EmissionSchedule;
+use pow_core::simulate::{self, SimulationParams, SimulationReport};
+use pow_core::target::{self, Target};
+use pow_core::{hash_to_hex, meets_difficulty, pow_hash, NonceEncoding, PowAlgorithm, MAX_SCRYPT_LOG_N};
 
 
 // ------------------------------------------------------------
@@ -19,6 +34,18 @@ pub struct Block {
     pub hash: String,
     pub timestamp: u64,
     pub miner: Option<Principal>,
+    /// None for SHA-256
+    pub algorithm: Option<PowAlgorithm>,
+    /// 32-byte big-endian target checked instead of `difficulty`
+    pub target: Option<Vec<u8>>,
+    /// None for little-endian bytes
+    pub nonce_encoding: Option<NonceEncoding>,
+    /// Payload fees; when set, must match the fee committed in a
+    /// template's `height:prev:ids:total_fee:root` block_data
+    pub total_fee: Option<u64>,
+    /// Tokens the block mints to its miner; at most get_block_reward(height)
+    /// plus total_fee
+    pub coinbase: Option<u64>,
 }
 
 #[derive(Clone, CandidType, Deserialize)]
@@ -27,43 +54,230 @@ pub struct ValidationResult {
     pub reason: Option<String>,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ValidatorError {
+    /// This caller is over its call budget
+    RateLimited { retry_after_ms: u64 },
+    /// `field` is over the admin-set limit (see get_input_limits)
+    InputTooLarge { field: String, value: u64, max: u64 },
+    /// Too many blocks to verify for free; attach `required_cycles`
+    PaymentRequired { required_cycles: u64, attached_cycles: u64 },
+}
+
+impl From<RateLimited> for ValidatorError {
+    fn from(e: RateLimited) -> Self {
+        ValidatorError::RateLimited { retry_after_ms: e.retry_after_ms }
+    }
+}
+
+/// The single-result endpoints report a refused input as invalid
+impl From<ValidatorError> for ValidationResult {
+    fn from(e: ValidatorError) -> Self {
+        ValidationResult {
+            valid: false,
+            reason: Some(format!("{:?}", e)),
+        }
+    }
+}
+
 // ------------------------------------------------------------
-// Hash verification
+// Init / admin management
 // ------------------------------------------------------------
 
-fn hash_block(block_data: &str, nonce: u64) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(block_data.as_bytes());
-    hasher.update(nonce.to_le_bytes());
-    hasher.finalize().into()
+/// The installing principal becomes the owner; `admins` are added alongside
+#[init]
+fn init(admins: Option<Vec<Principal>>) {
+    canister_auth::init(caller(), admins.unwrap_or_default());
 }
 
-fn hash_to_hex(bytes: &[u8; 32]) -> String {
-    hex::encode(bytes)
+/// Bump when `Saved` changes, and register a migration from the old version
+const SCHEMA_VERSION: u32 = 1;
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+type Saved = (
+    (Option<Principal>, Vec<Principal>),
+    Option<InputLimits>,
+    Option<Vec<AuditEntry>>,
+    Option<canister_config::Snapshot>,
+    Option<EmissionSchedule>,
+    Option<VerificationPrice>,
+);
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let saved: Saved = (
+        canister_auth::snapshot(),
+        Some(limits::get()),
+        Some(canister_auth::audit::snapshot()),
+        Some(canister_config::snapshot()),
+        Some(emission::get()),
+        Some(pricing::get()),
+    );
+    if let Err(e) = canister_state::save(SCHEMA_VERSION, &saved) {
+        ic_cdk::trap(&format!("failed to save admin set: {}", e));
+    }
 }
 
-fn meets_difficulty(hash: &[u8; 32], difficulty: u32) -> bool {
-    let mut remaining = difficulty;
-
-    for b in hash.iter() {
-        if remaining == 0 {
-            return true;
+/// Upgrade args add admins; they never replace the saved owner
+#[post_upgrade]
+fn post_upgrade(admins: Option<Vec<Principal>>) {
+    let restored = match canister_state::load::<Saved>(SCHEMA_VERSION, MIGRATIONS) {
+        Ok(saved) => Ok(saved),
+        // Saved before state was versioned
+        Err(StateError::Unversioned) => ic_cdk::storage::stable_restore::<Saved>(),
+        Err(e) => ic_cdk::trap(&format!("failed to restore validator state: {}", e)),
+    };
+    match restored {
+        Ok(((owner, saved), input_limits, audit, global_config, schedule, price)) if owner.is_some() => {
+            canister_auth::restore(owner, saved);
+            canister_auth::audit::restore(audit.unwrap_or_default());
+            canister_config::restore(global_config.unwrap_or_default());
+            admins.unwrap_or_default().into_iter().for_each(canister_auth::add_admin);
+            if let Some(l) = input_limits {
+                limits::set(l);
+            }
+            if let Some(s) = schedule {
+                emission::set(s);
+            }
+            if let Some(p) = price {
+                pricing::set(p);
+            }
         }
+        _ => init(admins),
+    }
+}
 
-        let z = b.leading_zeros();
+#[update]
+pub fn add_admin(p: Principal) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("add_admin", format!("{:?}", p));
+    canister_auth::add_admin(p);
+}
 
-        if z >= remaining {
-            return true;
-        }
+#[update]
+pub fn remove_admin(p: Principal) -> bool {
+    canister_auth::require_admin();
+    canister_auth::audit::record("remove_admin", format!("{:?}", p));
+    canister_auth::remove_admin(p)
+}
 
-        if z < 8 {
-            return false;
-        }
+/// Hand the validator to `new_owner` (owner only)
+#[update]
+pub fn transfer_ownership(new_owner: Principal) {
+    canister_auth::require_owner();
+    canister_auth::audit::record("transfer_ownership", format!("{:?}", new_owner));
+    canister_auth::transfer_ownership(new_owner);
+}
+
+#[query]
+pub fn get_owner() -> Option<Principal> {
+    canister_auth::owner()
+}
+
+#[query]
+pub fn list_admins() -> Vec<Principal> {
+    canister_auth::list_admins()
+}
+
+/// Privileged calls, oldest first (at most 500 per page)
+#[query]
+pub fn get_audit_log(offset: u64, limit: u64) -> Vec<AuditEntry> {
+    canister_auth::audit::page(offset, limit)
+}
+
+#[query]
+pub fn get_audit_log_len() -> u64 {
+    canister_auth::audit::len()
+}
+
+/// Per-caller budget for batch_verify_pow; admins bypass it and None turns
+/// it off (admin only)
+#[update]
+pub fn set_rate_limit(limit: Option<RateLimit>) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_rate_limit", format!("{:?}", limit));
+    rate_limit::set_limit(limit);
+}
 
-        remaining -= 8;
+#[query]
+pub fn get_rate_limit() -> Option<RateLimit> {
+    rate_limit::limit()
+}
+
+/// Ceilings on block_data, difficulty and batch sizes (admin only)
+#[update]
+pub fn set_input_limits(limits: InputLimits) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_input_limits", format!("{:?}", limits));
+    limits::set(limits);
+}
+
+#[query]
+pub fn get_input_limits() -> InputLimits {
+    limits::get()
+}
+
+/// Cycles verify_chain_segment and batch_verify_blocks charge per block
+/// above the free size (admin only)
+#[update]
+pub fn set_verification_price(price: VerificationPrice) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_verification_price", format!("{:?}", price));
+    pricing::set(price);
+}
+
+#[query]
+pub fn get_verification_price() -> VerificationPrice {
+    pricing::get()
+}
+
+/// Initial reward, halving interval and tail emission that coinbases are
+/// checked against (admin only)
+#[update]
+pub fn set_emission_schedule(schedule: EmissionSchedule) {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_emission_schedule", format!("{:?}", schedule));
+    emission::set(schedule);
+}
+
+#[query]
+pub fn get_emission_schedule() -> EmissionSchedule {
+    emission::get()
+}
+
+/// Token units the block at `height` may mint, before fees
+#[query]
+pub fn get_block_reward(height: u64) -> u64 {
+    emission::get().reward_at(height)
+}
+
+// ------------------------------------------------------------
+// Hash verification
+// ------------------------------------------------------------
+
+/// The explicit target if given, else the one `difficulty` implies, and how
+/// to name it in a rejection
+fn resolve_target(difficulty: u32, target: Option<&[u8]>) -> Result<(Target, String), String> {
+    match target {
+        Some(t) => Ok((target::parse(t)?, format!("target {}", hex::encode(t)))),
+        None => Ok((target::from_difficulty(difficulty), format!("difficulty {}", difficulty))),
+    }
+}
+
+/// The total_fee field of template-shaped block_data
+fn committed_fee(block_data: &str) -> Option<u64> {
+    let parts: Vec<&str> = block_data.rsplitn(4, ':').collect();
+    match parts.as_slice() {
+        [_, fee, _, _] => fee.parse().ok(),
+        _ => None,
     }
+}
 
-    remaining == 0
+fn invalid(reason: String) -> ValidationResult {
+    ValidationResult {
+        valid: false,
+        reason: Some(reason),
+    }
 }
 
 // ------------------------------------------------------------
@@ -71,10 +285,35 @@ fn meets_difficulty(hash: &[u8; 32], difficulty: u32) -> bool {
 // ------------------------------------------------------------
 
 #[query]
-pub fn verify_pow(block_data: String, nonce: u64, difficulty: u32) -> ValidationResult {
-    let hash = hash_block(&block_data, nonce);
+pub fn verify_pow(
+    block_data: String,
+    nonce: u64,
+    difficulty: u32,
+    algorithm: Option<PowAlgorithm>,
+    target: Option<Vec<u8>>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> ValidationResult {
+    check_pow(&block_data, nonce, difficulty, algorithm.unwrap_or_default(), target.as_deref(), nonce_encoding.unwrap_or_default())
+}
 
-    if meets_difficulty(&hash, difficulty) {
+fn check_pow(
+    block_data: &str,
+    nonce: u64,
+    difficulty: u32,
+    algorithm: PowAlgorithm,
+    target: Option<&[u8]>,
+    encoding: NonceEncoding,
+) -> ValidationResult {
+    if let Err(e) = limits::check_block(block_data, difficulty, algorithm) {
+        return e.into();
+    }
+    let (target, required) = match resolve_target(difficulty, target) {
+        Ok(t) => t,
+        Err(e) => return invalid(e),
+    };
+    let hash = pow_hash(algorithm, encoding, block_data, nonce);
+
+    if target::meets(&hash, &target) {
         ValidationResult {
             valid: true,
             reason: None,
@@ -83,8 +322,8 @@ pub fn verify_pow(block_data: String, nonce: u64, difficulty: u32) -> Validation
         ValidationResult {
             valid: false,
             reason: Some(format!(
-                "Hash does not meet difficulty {}. Hash: {}",
-                difficulty,
+                "Hash does not meet {}. Hash: {}",
+                required,
                 hash_to_hex(&hash)
             )),
         }
@@ -93,30 +332,52 @@ pub fn verify_pow(block_data: String, nonce: u64, difficulty: u32) -> Validation
 
 #[query]
 pub fn verify_block(block: Block) -> ValidationResult {
-    // Verify PoW
-    let computed_hash = hash_block(&block.block_data, block.nonce);
-    let computed_hash_hex = hash_to_hex(&computed_hash);
+    check_block(&block)
+}
 
-    // Check hash matches
-    if computed_hash_hex != block.hash {
+fn check_block(block: &Block) -> ValidationResult {
+    if let Err(e) = limits::check_block(&block.block_data, block.difficulty, block.algorithm.unwrap_or_default()) {
+        return e.into();
+    }
+    let (target, required) = match resolve_target(block.difficulty, block.target.as_deref()) {
+        Ok(t) => t,
+        Err(e) => return invalid(e),
+    };
+    if let Some(fee) = block.total_fee {
+        match committed_fee(&block.block_data) {
+            Some(committed) if committed == fee => {}
+            Some(committed) => return invalid(format!("total_fee {} but block_data commits to {}", fee, committed)),
+            None => return invalid("total_fee given but block_data has no fee field".to_string()),
+        }
+    }
+    if let Some(Err(e)) = block.coinbase.map(|c| emission::check_coinbase(block.height, c, block.total_fee)) {
+        return invalid(e);
+    }
+
+    // Verify PoW
+    let computed_hash = pow_hash(
+        block.algorithm.unwrap_or_default(),
+        block.nonce_encoding.unwrap_or_default(),
+        &block.block_data,
+        block.nonce,
+    );
+
+    // Check hash matches; hex is only built into a String for the rejection
+    let mut computed_hex = [0u8; 64];
+    hex::encode_to_slice(computed_hash, &mut computed_hex).expect("64 hex digits");
+    if block.hash.as_bytes() != computed_hex {
         return ValidationResult {
             valid: false,
             reason: Some(format!(
                 "Hash mismatch. Expected: {}, Computed: {}",
-                block.hash, computed_hash_hex
+                block.hash, hash_to_hex(&computed_hash)
             )),
         };
     }
 
     // Check difficulty
-    if !meets_difficulty(&computed_hash, block.difficulty) {
-        return ValidationResult {
-            valid: false,
-            reason: Some(format!(
-                "Hash does not meet difficulty requirement {}",
-                block.difficulty
-            )),
-        };
+    if !target::meets(&computed_hash, &target) {
+        return invalid(format!("Hash does not meet {} requirement", required));
     }
 
     // Check timestamp is reasonable (within 1 hour of now)
@@ -137,8 +398,24 @@ pub fn verify_block(block: Block) -> ValidationResult {
     }
 }
 
-#[query]
+/// An update so that segments above the free size can pay in cycles, and
+/// rate-limited like batch_verify_blocks
+#[update]
 pub fn verify_chain_segment(blocks: Vec<Block>) -> ValidationResult {
+    let admitted = rate_limit::check()
+    .map_err(ValidatorError::from)
+    .and_then(|_| limits::check_batch(blocks.len()))
+    .and_then(|_| pricing::charge(blocks.len()));
+    if let Err(e) = admitted {
+        return e.into();
+    }
+    check_segment(&blocks)
+}
+
+fn check_segment(blocks: &[Block]) -> ValidationResult {
+    if let Err(e) = limits::check_batch(blocks.len()) {
+        return e.into();
+    }
     if blocks.is_empty() {
         return ValidationResult {
             valid: false,
@@ -147,14 +424,18 @@ pub fn verify_chain_segment(blocks: Vec<Block>) -> ValidationResult {
     }
 
     // Verify each block individually
-    for block in &blocks {
-        let result = verify_block(block.clone());
+    for block in blocks {
+        let result = check_block(block);
         if !result.valid {
             return result;
         }
     }
 
-    // Verify chain linkage
+    check_links(blocks)
+}
+
+/// Each block follows the one before it in hash and height
+fn check_links(blocks: &[Block]) -> ValidationResult {
     for i in 1..blocks.len() {
         if blocks[i].prev_hash != blocks[i - 1].hash {
             return ValidationResult {
@@ -198,33 +479,26 @@ pub fn calculate_difficulty_adjustment(
     if actual_block_times_seconds.is_empty() {
         return current_difficulty;
     }
+    // 0 aims for the global config's block time
+    let target_block_time_seconds = match target_block_time_seconds {
+        0 => canister_config::current().target_block_time_secs,
+        t => t,
+    };
+
+    pow_core::retarget::step(
+        current_difficulty,
+        target_block_time_seconds,
+        &actual_block_times_seconds,
+        canister_config::current().max_difficulty_step,
+    )
+}
 
-    // Average actual block time
-    let sum: u64 = actual_block_times_seconds.iter().sum();
-    let avg_time = sum / actual_block_times_seconds.len() as u64;
-
-    // Adjust difficulty
-    // If blocks too fast → increase difficulty
-    // If blocks too slow → decrease difficulty
-
-    const MAX_ADJUSTMENT: u32 = 2; // Limit adjustment per period
-
-    if avg_time < target_block_time_seconds / 2 {
-        // Much too fast - increase difficulty
-        current_difficulty.saturating_add(MAX_ADJUSTMENT)
-    } else if avg_time < target_block_time_seconds {
-        // Slightly too fast - increase difficulty
-        current_difficulty.saturating_add(1)
-    } else if avg_time > target_block_time_seconds * 2 {
-        // Much too slow - decrease difficulty
-        current_difficulty.saturating_sub(MAX_ADJUSTMENT).max(1)
-    } else if avg_time > target_block_time_seconds {
-        // Slightly too slow - decrease difficulty
-        current_difficulty.saturating_sub(1).max(1)
-    } else {
-        // Just right
-        current_difficulty
-    }
+/// Run a retarget rule against a hashrate profile for up to
+/// MAX_SIM_BLOCKS blocks and report how steady the block times are, to
+/// tune step/LWMA parameters before deploying them
+#[query]
+pub fn simulate_difficulty(params: SimulationParams) -> Result<SimulationReport, String> {
+    simulate::simulate(&params)
 }
 
 // ------------------------------------------------------------
@@ -239,17 +513,24 @@ pub struct BatchValidationResult {
     pub invalid_indices: Vec<usize>,
 }
 
-#[query]
+/// An update rather than a query so the caller's rate-limit budget sticks
+#[update]
 pub fn batch_verify_pow(
     blocks: Vec<(String, u64, u32)>, // (block_data, nonce, difficulty)
-) -> BatchValidationResult {
+) -> Result<BatchValidationResult, ValidatorError> {
+    rate_limit::check()?;
+    limits::check_batch(blocks.len())?;
+    for (block_data, _, difficulty) in &blocks {
+        limits::check_block(block_data, *difficulty, PowAlgorithm::Sha256)?;
+    }
+
     let total = blocks.len();
     let mut valid = 0;
     let mut invalid = 0;
     let mut invalid_indices = Vec::new();
 
     for (i, (block_data, nonce, difficulty)) in blocks.iter().enumerate() {
-        let result = verify_pow(block_data.clone(), *nonce, *difficulty);
+        let result = check_pow(block_data, *nonce, *difficulty, PowAlgorithm::Sha256, None, NonceEncoding::LeBytes);
 
         if result.valid {
             valid += 1;
@@ -259,12 +540,22 @@ pub fn batch_verify_pow(
         }
     }
 
-    BatchValidationResult {
+    Ok(BatchValidationResult {
         total,
         valid,
         invalid,
         invalid_indices,
-    }
+    })
+}
+
+/// Each block checked on its own, as verify_block would; paid like
+/// verify_chain_segment
+#[update]
+pub fn batch_verify_blocks(blocks: Vec<Block>) -> Result<Vec<ValidationResult>, ValidatorError> {
+    rate_limit::check()?;
+    limits::check_batch(blocks.len())?;
+    pricing::charge(blocks.len())?;
+    Ok(blocks.iter().map(check_block).collect())
 }
 
 // ------------------------------------------------------------
@@ -272,8 +563,17 @@ pub fn batch_verify_pow(
 // ------------------------------------------------------------
 
 #[query]
-pub fn compute_hash(block_data: String, nonce: u64) -> String {
-    let hash = hash_block(&block_data, nonce);
+pub fn compute_hash(
+    block_data: String,
+    nonce: u64,
+    algorithm: Option<PowAlgorithm>,
+    nonce_encoding: Option<NonceEncoding>,
+) -> String {
+    let algorithm = algorithm.unwrap_or_default();
+    if let Err(e) = limits::check_block(&block_data, 0, algorithm) {
+        ic_cdk::trap(&format!("{:?}", e));
+    }
+    let hash = pow_hash(algorithm, nonce_encoding.unwrap_or_default(), &block_data, nonce);
     hash_to_hex(&hash)
 }
 
@@ -288,3 +588,145 @@ pub fn check_difficulty_level(hash_hex: String, difficulty: u32) -> bool {
     }
     false
 }
+
+/// The target met by hashes with `difficulty` leading zero bits
+#[query]
+pub fn difficulty_to_target(difficulty: u32) -> Vec<u8> {
+    target::from_difficulty(difficulty).to_vec()
+}
+
+/// Leading zero bits guaranteed by a 32-byte target
+#[query]
+pub fn target_to_difficulty(target: Vec<u8>) -> Result<u32, String> {
+    target::parse(&target).map(|t| target::to_difficulty(&t))
+}
+
+#[query]
+pub fn check_target(hash_hex: String, target: Vec<u8>) -> bool {
+    match (hex::decode(&hash_hex), target::parse(&target)) {
+        (Ok(bytes), Ok(target)) => bytes
+        .try_into()
+        .map(|hash: [u8; 32]| target::meets(&hash, &target))
+        .unwrap_or(false),
+        _ => false,
+    }
+}
+
+// ------------------------------------------------------------
+// Instruction benchmarks
+// ------------------------------------------------------------
+
+/// Instructions to verify one synthetic segment the way verify_chain_segment
+/// used to (a clone of every block) and the way it does now (by reference)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SegmentBench {
+    pub blocks: u64,
+    pub block_data_len: u64,
+    pub valid: bool,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// A linked difficulty-0 segment, so every block verifies
+fn synthetic_segment(len: u64, block_data_len: u64) -> Vec<Block> {
+    let mut prev_hash = "0".repeat(64);
+    (0..len)
+    .map(|height| {
+        let mut block_data = format!("{}:", height);
+        block_data.push_str(&"x".repeat((block_data_len as usize).saturating_sub(block_data.len())));
+        let hash = hash_to_hex(&pow_hash(PowAlgorithm::Sha256, NonceEncoding::LeBytes, &block_data, 0));
+        Block {
+            height,
+            prev_hash: std::mem::replace(&mut prev_hash, hash.clone()),
+            block_data,
+            nonce: 0,
+            difficulty: 0,
+            hash,
+            timestamp: 0,
+            miner: None,
+            algorithm: None,
+            target: None,
+            nonce_encoding: None,
+            total_fee: None,
+            coinbase: None,
+        }
+    })
+    .collect()
+}
+
+/// Verify a synthetic segment of `blocks` blocks (default 100), each with
+/// `block_data_len` bytes of data, through both paths (admin only)
+#[update]
+pub fn bench_chain_segment_instructions(blocks: Option<u64>, block_data_len: u64) -> SegmentBench {
+    canister_auth::require_admin();
+    let len = blocks.unwrap_or(100);
+    if let Err(e) = limits::check_batch(len as usize) {
+        ic_cdk::trap(&format!("{:?}", e));
+    }
+    if block_data_len > limits::get().max_block_data_len {
+        ic_cdk::trap("block_data_len is over max_block_data_len");
+    }
+    let segment = synthetic_segment(len, block_data_len);
+    let counter = ic_cdk::api::instruction_counter;
+
+    let i0 = counter();
+    let before = segment.iter().all(|block| verify_block(block.clone()).valid) && check_links(&segment).valid;
+    let i1 = counter();
+    let after = check_segment(&segment).valid;
+    let i2 = counter();
+
+    SegmentBench {
+        blocks: len,
+        block_data_len,
+        valid: before && after,
+        before: i1 - i0,
+        after: i2 - i1,
+    }
+}
+
+// ------------------------------------------------------------
+// Global config
+// ------------------------------------------------------------
+
+/// Follow the config canister's shared parameters (None goes back to the
+/// defaults); returns the version fetched (admin only)
+#[update]
+pub async fn set_config_canister(canister: Option<Principal>) -> Result<u64, String> {
+    canister_auth::require_admin();
+    canister_auth::audit::record("set_config_canister", format!("{:?}", canister));
+    canister_config::connect(canister).await
+}
+
+#[query]
+pub fn get_config_canister() -> Option<Principal> {
+    canister_config::source()
+}
+
+/// The cached config; version 0 means the built-in defaults
+#[query]
+pub fn get_global_config() -> VersionedConfig {
+    canister_config::cached()
+}
+
+/// Pushed by the config canister on every change
+#[update]
+pub fn on_config_update(update: VersionedConfig) -> bool {
+    canister_config::accept_update(update)
+}
+
+// ------------------------------------------------------------
+// Candid interface
+// ------------------------------------------------------------
+
+// Must stay last: it only sees the endpoints expanded before it. Types
+// from endpoints in submodules have to be in scope here.
+use crate::bitcoin::{BitcoinHeader, HeaderCheck, RawHeader};
+
+candid::export_service!();
+
+/// The interface generated from the endpoints, to diff against or
+/// regenerate the checked-in .did file
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
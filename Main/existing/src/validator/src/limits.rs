@@ -0,0 +1,61 @@
+// limits.rs - admin-set ceilings on validation inputs
+use std::cell::Cell;
+
+use candid::{CandidType, Deserialize};
+
+use crate::{PowAlgorithm, ValidatorError, MAX_SCRYPT_LOG_N};
+
+const DEFAULT_LIMITS: InputLimits = InputLimits {
+    max_block_data_len: 16 * 1024,
+    max_batch_size: 1_000,
+    max_difficulty: 256,
+};
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct InputLimits {
+    /// Bytes
+    pub max_block_data_len: u64,
+    /// Entries per batch_verify_pow / blocks per verify_chain_segment
+    pub max_batch_size: u64,
+    /// Leading zero bits; a SHA-256 hash has 256
+    pub max_difficulty: u32,
+}
+
+thread_local! {
+    static LIMITS: Cell<InputLimits> = const { Cell::new(DEFAULT_LIMITS) };
+}
+
+pub fn get() -> InputLimits {
+    LIMITS.with(|l| l.get())
+}
+
+pub fn set(limits: InputLimits) {
+    if limits.max_block_data_len == 0 || limits.max_batch_size == 0 {
+        ic_cdk::trap("max_block_data_len and max_batch_size must be at least 1");
+    }
+    if limits.max_difficulty > 256 {
+        ic_cdk::trap("max_difficulty can't exceed 256");
+    }
+    LIMITS.with(|l| l.set(limits));
+}
+
+fn exceeds(field: &str, value: u64, max: u64) -> Result<(), ValidatorError> {
+    if value > max {
+        return Err(ValidatorError::InputTooLarge { field: field.to_string(), value, max });
+    }
+    Ok(())
+}
+
+pub fn check_block(block_data: &str, difficulty: u32, algorithm: PowAlgorithm) -> Result<(), ValidatorError> {
+    let l = get();
+    exceeds("block_data", block_data.len() as u64, l.max_block_data_len)?;
+    exceeds("difficulty", difficulty as u64, l.max_difficulty as u64)?;
+    if let PowAlgorithm::Scrypt { log_n } = algorithm {
+        exceeds("scrypt_log_n", log_n as u64, MAX_SCRYPT_LOG_N as u64)?;
+    }
+    Ok(())
+}
+
+pub fn check_batch(len: usize) -> Result<(), ValidatorError> {
+    exceeds("batch_size", len as u64, get().max_batch_size)
+}
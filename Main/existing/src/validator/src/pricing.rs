@@ -0,0 +1,53 @@
+// pricing.rs - cycles charged for the multi-block endpoints. Requests of up
+// to `free_blocks` blocks are free; larger ones must attach
+// `cycles_per_block` for every block or they're refused before any hashing.
+// Admins never pay.
+use std::cell::Cell;
+
+use candid::{CandidType, Deserialize};
+use ic_cdk::api::call::{msg_cycles_accept, msg_cycles_available};
+use ic_cdk::caller;
+
+use crate::ValidatorError;
+
+const DEFAULT_PRICE: VerificationPrice = VerificationPrice {
+    cycles_per_block: 1_000_000,
+    free_blocks: 10,
+};
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize)]
+pub struct VerificationPrice {
+    pub cycles_per_block: u64,
+    /// Largest request served without payment
+    pub free_blocks: u64,
+}
+
+thread_local! {
+    static PRICE: Cell<VerificationPrice> = const { Cell::new(DEFAULT_PRICE) };
+}
+
+pub fn get() -> VerificationPrice {
+    PRICE.with(|p| p.get())
+}
+
+pub fn set(price: VerificationPrice) {
+    PRICE.with(|p| p.set(price));
+}
+
+/// Take payment for verifying `blocks` blocks from the attached cycles;
+/// anything attached beyond the price goes back to the caller. Call only
+/// once the input has passed every other check, since accepted cycles
+/// aren't refunded on an invalid result.
+pub fn charge(blocks: usize) -> Result<u64, ValidatorError> {
+    let price = get();
+    if blocks as u64 <= price.free_blocks || canister_auth::is_admin(&caller()) {
+        return Ok(0);
+    }
+
+    let required = price.cycles_per_block.saturating_mul(blocks as u64);
+    let attached = msg_cycles_available();
+    if attached < required {
+        return Err(ValidatorError::PaymentRequired { required_cycles: required, attached_cycles: attached });
+    }
+    Ok(msg_cycles_accept(required))
+}